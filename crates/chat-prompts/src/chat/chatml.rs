@@ -1,5 +1,10 @@
-use super::BuildChatPrompt;
-use crate::error::{PromptError, Result};
+use super::{BuildChatPrompt, ToolCallParser};
+use crate::{
+    error::{PromptError, Result},
+    tool_call::{
+        function_from_value, parse_tool_call_value, tool_call_regex, ParseResult, ToolCallFormat,
+    },
+};
 use endpoints::chat::{
     ChatCompletionAssistantMessage, ChatCompletionRequestMessage, ChatCompletionSystemMessage,
     ChatCompletionToolMessage, ChatCompletionUserMessage, ChatCompletionUserMessageContent,
@@ -369,6 +374,74 @@ impl BuildChatPrompt for ChatMLToolPrompt {
     }
 }
 
+impl ToolCallParser for ChatMLToolPrompt {
+    fn tool_call_format(&self) -> ToolCallFormat {
+        ToolCallFormat {
+            start: "<tool_call>",
+            end: Some("</tool_call>"),
+            named: false,
+            arguments_key: "arguments",
+        }
+    }
+
+    fn parse_tool_calls(&self, output: &str) -> Result<ParseResult> {
+        let re = tool_call_regex(r"(?s)<tool_call>(.*?)</tool_call>")?;
+
+        let mut tool_calls = vec![];
+        for cap in re.captures_iter(output) {
+            // remove the escaped line breaks from the captured group
+            let matched = cap[1].replace("\\n", "");
+            let value = parse_tool_call_value(matched.trim())?;
+            tool_calls.push(function_from_value(&value, "arguments")?);
+        }
+
+        Ok(ParseResult {
+            raw: output.to_owned(),
+            content: None,
+            tool_calls,
+        })
+    }
+}
+
+#[test]
+fn test_parse_chatml_tool_calls() {
+    use crate::tool_call::{ToolCallDelta, ToolCallStream};
+
+    let output = "<tool_call>\n{\"arguments\": {\"location\": \"Beijing, China\", \"format\": \"celsius\"}, \"name\": \"get_current_weather\"}\n</tool_call>";
+
+    let parsed = ChatMLToolPrompt.parse_tool_calls(output).unwrap();
+    assert_eq!(parsed.tool_calls.len(), 1);
+    assert_eq!(parsed.tool_calls[0].name, "get_current_weather");
+    let arguments: serde_json::Value =
+        serde_json::from_str(&parsed.tool_calls[0].arguments).unwrap();
+    assert_eq!(
+        arguments,
+        serde_json::json!({"location": "Beijing, China", "format": "celsius"})
+    );
+
+    // the arguments come before the name, so they are sent out once the name is known
+    let mut stream = ToolCallStream::new(ChatMLToolPrompt.tool_call_format());
+    let mut deltas = vec![];
+    for token in output.split_inclusive(' ') {
+        deltas.extend(stream.push(token));
+    }
+    deltas.extend(stream.finish());
+    assert_eq!(
+        deltas[0],
+        ToolCallDelta::Start {
+            index: 0,
+            name: "get_current_weather".to_string()
+        }
+    );
+    assert_eq!(
+        deltas[1],
+        ToolCallDelta::Arguments {
+            index: 0,
+            fragment: "{\"location\": \"Beijing, China\", \"format\": \"celsius\"}".to_string()
+        }
+    );
+}
+
 /// Generate prompts for InternLM-2.5 models in tool use scenario.
 pub struct InternLM2ToolPrompt;
 impl InternLM2ToolPrompt {
@@ -585,3 +658,63 @@ impl BuildChatPrompt for InternLM2ToolPrompt {
         Ok(prompt)
     }
 }
+
+impl ToolCallParser for InternLM2ToolPrompt {
+    fn tool_call_format(&self) -> ToolCallFormat {
+        ToolCallFormat {
+            start: "<|action_start|><|plugin|>",
+            end: Some("<|action_end|>"),
+            named: false,
+            arguments_key: "parameters",
+        }
+    }
+
+    fn parse_tool_calls(&self, output: &str) -> Result<ParseResult> {
+        let blocks: Vec<&str> = output.trim().split("<|action_start|><|plugin|>").collect();
+
+        let mut tool_calls = vec![];
+        let mut content = String::new();
+        for block in blocks {
+            let block = block.trim();
+            if !block.is_empty() {
+                if block.ends_with("<|action_end|>") {
+                    let value = parse_tool_call_value(block.trim_end_matches("<|action_end|>"))?;
+                    tool_calls.push(function_from_value(&value, "parameters")?);
+                } else {
+                    content.push_str(block);
+                    content.push('\n');
+                }
+            }
+        }
+
+        let content = match content.is_empty() {
+            true => None,
+            false => Some(content.trim().to_owned()),
+        };
+
+        Ok(ParseResult {
+            raw: output.to_owned(),
+            content,
+            tool_calls,
+        })
+    }
+}
+
+#[test]
+fn test_parse_internlm2_tool_calls() {
+    let output = "I need to use the get_current_weather API to get the current weather in Beijing.<|action_start|><|plugin|>\n{\"name\": \"get_current_weather\", \"parameters\": {\"location\": \"Beijing, China\", \"format\": \"celsius\"}}<|action_end|>";
+
+    let parsed = InternLM2ToolPrompt.parse_tool_calls(output).unwrap();
+    assert_eq!(
+        parsed.content.as_deref(),
+        Some("I need to use the get_current_weather API to get the current weather in Beijing.")
+    );
+    assert_eq!(parsed.tool_calls.len(), 1);
+    assert_eq!(parsed.tool_calls[0].name, "get_current_weather");
+    let arguments: serde_json::Value =
+        serde_json::from_str(&parsed.tool_calls[0].arguments).unwrap();
+    assert_eq!(
+        arguments,
+        serde_json::json!({"location": "Beijing, China", "format": "celsius"})
+    );
+}
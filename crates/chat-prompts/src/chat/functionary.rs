@@ -1,8 +1,11 @@
-use super::BuildChatPrompt;
-use crate::error::{PromptError, Result};
+use super::{BuildChatPrompt, ToolCallParser};
+use crate::{
+    error::{PromptError, Result},
+    tool_call::{tool_call_regex, ParseResult, ToolCallFormat},
+};
 use endpoints::chat::{
     ChatCompletionAssistantMessage, ChatCompletionRequestMessage, ChatCompletionToolMessage,
-    ChatCompletionUserMessage, ChatCompletionUserMessageContent, ContentPart, Tool,
+    ChatCompletionUserMessage, ChatCompletionUserMessageContent, ContentPart, Function, Tool,
 };
 
 use tera::{Context, Tera};
@@ -167,6 +170,87 @@ impl BuildChatPrompt for FunctionaryV32ToolPrompt {
     }
 }
 
+impl ToolCallParser for FunctionaryV32ToolPrompt {
+    fn tool_call_format(&self) -> ToolCallFormat {
+        ToolCallFormat {
+            start: ">>>",
+            end: Some("<|eot_id|>"),
+            named: true,
+            arguments_key: "",
+        }
+    }
+
+    fn parse_tool_calls(&self, output: &str) -> Result<ParseResult> {
+        let re = tool_call_regex(r">>>\s*(\w+)\s*(\{.*\})<\|eot_id\|>")?;
+
+        let tool_calls = re
+            .captures_iter(output)
+            .map(|cap| Function {
+                name: cap[1].to_string(),
+                arguments: cap[2].to_string(),
+            })
+            .collect();
+
+        Ok(ParseResult {
+            raw: output.to_owned(),
+            content: None,
+            tool_calls,
+        })
+    }
+}
+
+#[test]
+fn test_parse_functionary_v32_tool_calls() {
+    use crate::tool_call::{ToolCallDelta, ToolCallStream};
+
+    let output = ">>>get_current_weather\n{\"location\": \"Beijing, China\", \"format\": \"celsius\"}<|eot_id|>";
+
+    let parsed = FunctionaryV32ToolPrompt.parse_tool_calls(output).unwrap();
+    assert_eq!(
+        parsed.tool_calls,
+        vec![Function {
+            name: "get_current_weather".to_string(),
+            arguments: "{\"location\": \"Beijing, China\", \"format\": \"celsius\"}".to_string(),
+        }]
+    );
+
+    let mut stream = ToolCallStream::new(FunctionaryV32ToolPrompt.tool_call_format());
+    let mut deltas = vec![];
+    for token in output.split_inclusive(' ') {
+        deltas.extend(stream.push(token));
+    }
+    deltas.extend(stream.finish());
+    assert_eq!(
+        deltas,
+        vec![
+            ToolCallDelta::Start {
+                index: 0,
+                name: "get_current_weather".to_string()
+            },
+            ToolCallDelta::Arguments {
+                index: 0,
+                fragment: "{\"location\": ".to_string()
+            },
+            ToolCallDelta::Arguments {
+                index: 0,
+                fragment: "\"Beijing, ".to_string()
+            },
+            ToolCallDelta::Arguments {
+                index: 0,
+                fragment: "China\", ".to_string()
+            },
+            ToolCallDelta::Arguments {
+                index: 0,
+                fragment: "\"format\": ".to_string()
+            },
+            ToolCallDelta::Arguments {
+                index: 0,
+                fragment: "\"celsius\"}".to_string()
+            },
+        ]
+    );
+}
+
 /// Generate prompts for `functionary-v3.1` models.
 #[derive(Debug, Default, Clone)]
 pub struct FunctionaryV31ToolPrompt;
@@ -329,3 +413,45 @@ impl BuildChatPrompt for FunctionaryV31ToolPrompt {
         Ok(prompt)
     }
 }
+
+impl ToolCallParser for FunctionaryV31ToolPrompt {
+    fn tool_call_format(&self) -> ToolCallFormat {
+        ToolCallFormat {
+            start: "<function=",
+            end: Some("</function>"),
+            named: true,
+            arguments_key: "",
+        }
+    }
+
+    fn parse_tool_calls(&self, output: &str) -> Result<ParseResult> {
+        let re = tool_call_regex(r"<function=(\w+)>\s*(\{.*?\})</function>")?;
+
+        let tool_calls = re
+            .captures_iter(output)
+            .map(|cap| Function {
+                name: cap[1].to_string(),
+                arguments: cap[2].to_string(),
+            })
+            .collect();
+
+        Ok(ParseResult {
+            raw: output.to_owned(),
+            content: None,
+            tool_calls,
+        })
+    }
+}
+
+#[test]
+fn test_parse_functionary_v31_tool_calls() {
+    let output = r#"<function=get_current_weather>{"location": "Beijing, China", "format": "celsius"}</function><function=predict_weather>{"location": "Beijing, China", "format": "celsius"}</function>"#;
+
+    let parsed = FunctionaryV31ToolPrompt.parse_tool_calls(output).unwrap();
+    assert_eq!(parsed.tool_calls.len(), 2);
+    assert_eq!(parsed.tool_calls[0].name, "get_current_weather");
+    assert_eq!(
+        parsed.tool_calls[1].arguments,
+        r#"{"location": "Beijing, China", "format": "celsius"}"#
+    );
+}
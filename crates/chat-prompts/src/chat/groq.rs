@@ -1,5 +1,10 @@
-use super::BuildChatPrompt;
-use crate::error::{PromptError, Result};
+use super::{BuildChatPrompt, ToolCallParser};
+use crate::{
+    error::{PromptError, Result},
+    tool_call::{
+        function_from_value, parse_tool_call_value, tool_call_regex, ParseResult, ToolCallFormat,
+    },
+};
 use endpoints::chat::{
     ChatCompletionAssistantMessage, ChatCompletionRequestMessage, ChatCompletionToolMessage,
     ChatCompletionUserMessage, ChatCompletionUserMessageContent, ContentPart, Tool,
@@ -150,3 +155,49 @@ impl BuildChatPrompt for GroqLlama3ToolPrompt {
         Ok(prompt)
     }
 }
+
+impl ToolCallParser for GroqLlama3ToolPrompt {
+    fn tool_call_format(&self) -> ToolCallFormat {
+        ToolCallFormat {
+            start: "<tool_call>",
+            end: Some("</tool_call>"),
+            named: false,
+            arguments_key: "arguments",
+        }
+    }
+
+    fn parse_tool_calls(&self, output: &str) -> Result<ParseResult> {
+        let re = tool_call_regex(r"(?s)<tool_call>((.|\r|\n)*?)</tool_call>")?;
+
+        let mut tool_calls = vec![];
+        for cap in re.captures_iter(output) {
+            let value = parse_tool_call_value(cap[1].trim())?;
+            tool_calls.push(function_from_value(&value, "arguments")?);
+        }
+
+        Ok(ParseResult {
+            raw: output.to_owned(),
+            content: None,
+            tool_calls,
+        })
+    }
+}
+
+#[test]
+fn test_parse_groq_llama3_tool_calls() {
+    let output = "<tool_call>\n{\"id\": 0, \"name\": \"get_current_weather\", \"arguments\": {\"location\": \"Beijing, China\", \"format\": \"celsius\"}}\n</tool_call>";
+
+    let parsed = GroqLlama3ToolPrompt.parse_tool_calls(output).unwrap();
+    assert_eq!(parsed.tool_calls.len(), 1);
+    assert_eq!(parsed.tool_calls[0].name, "get_current_weather");
+    let arguments: serde_json::Value =
+        serde_json::from_str(&parsed.tool_calls[0].arguments).unwrap();
+    assert_eq!(
+        arguments,
+        serde_json::json!({"location": "Beijing, China", "format": "celsius"})
+    );
+
+    assert!(GroqLlama3ToolPrompt
+        .parse_tool_calls("<tool_call>\n{\"id\": 0, \"name\": \n</tool_call>")
+        .is_err());
+}
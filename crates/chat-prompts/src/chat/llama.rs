@@ -1,5 +1,10 @@
-use super::BuildChatPrompt;
-use crate::error::{PromptError, Result};
+use super::{BuildChatPrompt, ToolCallParser};
+use crate::{
+    error::{PromptError, Result},
+    tool_call::{
+        function_from_value, parse_tool_call_value, tool_call_regex, ParseResult, ToolCallFormat,
+    },
+};
 use endpoints::chat::{
     ChatCompletionAssistantMessage, ChatCompletionRequestMessage, ChatCompletionSystemMessage,
     ChatCompletionToolMessage, ChatCompletionUserMessage, ChatCompletionUserMessageContent,
@@ -673,3 +678,72 @@ impl BuildChatPrompt for Llama3ToolPrompt {
         Ok(prompt)
     }
 }
+
+impl ToolCallParser for Llama3ToolPrompt {
+    fn tool_call_format(&self) -> ToolCallFormat {
+        ToolCallFormat {
+            start: "",
+            end: None,
+            named: false,
+            arguments_key: "parameters",
+        }
+    }
+
+    fn parse_tool_calls(&self, output: &str) -> Result<ParseResult> {
+        let re = tool_call_regex(r"^\{(.|\r|\n)*\}$")?;
+
+        let mut tool_calls = vec![];
+        if re.is_match(output) {
+            let value = parse_tool_call_value(output)?;
+            tool_calls.push(function_from_value(&value, "parameters")?);
+        }
+
+        Ok(ParseResult {
+            raw: output.to_owned(),
+            content: None,
+            tool_calls,
+        })
+    }
+}
+
+#[test]
+fn test_parse_llama3_tool_calls() {
+    use crate::tool_call::{ToolCallDelta, ToolCallStream};
+
+    let output = r#"{"name": "get_current_weather", "parameters": {"location": "Beijing, China", "format": "celsius"}}"#;
+
+    let parsed = Llama3ToolPrompt.parse_tool_calls(output).unwrap();
+    assert_eq!(parsed.tool_calls.len(), 1);
+    assert_eq!(parsed.tool_calls[0].name, "get_current_weather");
+    let arguments: serde_json::Value =
+        serde_json::from_str(&parsed.tool_calls[0].arguments).unwrap();
+    assert_eq!(
+        arguments,
+        serde_json::json!({"location": "Beijing, China", "format": "celsius"})
+    );
+
+    let mut stream = ToolCallStream::new(Llama3ToolPrompt.tool_call_format());
+    let mut arguments = String::new();
+    for token in output.split_inclusive(' ') {
+        for delta in stream.push(token) {
+            if let ToolCallDelta::Arguments { fragment, .. } = delta {
+                arguments.push_str(&fragment);
+            }
+        }
+    }
+    assert!(stream.finish().is_empty());
+    assert_eq!(
+        arguments,
+        r#"{"location": "Beijing, China", "format": "celsius"}"#
+    );
+
+    // the output not starting with a JSON object is plain text
+    let output = "The current weather in Beijing is sunny.";
+    let parsed = Llama3ToolPrompt.parse_tool_calls(output).unwrap();
+    assert!(parsed.tool_calls.is_empty());
+
+    let mut stream = ToolCallStream::new(Llama3ToolPrompt.tool_call_format());
+    let mut deltas = stream.push(output);
+    deltas.extend(stream.finish());
+    assert_eq!(deltas, vec![ToolCallDelta::Content(output.to_string())]);
+}
@@ -1,5 +1,8 @@
-use super::BuildChatPrompt;
-use crate::error::{PromptError, Result};
+use super::{BuildChatPrompt, ToolCallParser};
+use crate::{
+    error::{PromptError, Result},
+    tool_call::{function_from_value, tool_call_regex, ParseResult, ToolCallFormat},
+};
 use endpoints::chat::{
     ChatCompletionAssistantMessage, ChatCompletionRequestMessage, ChatCompletionSystemMessage,
     ChatCompletionToolMessage, ChatCompletionUserMessage, ChatCompletionUserMessageContent,
@@ -377,6 +380,93 @@ impl BuildChatPrompt for MistralToolPrompt {
     }
 }
 
+impl ToolCallParser for MistralToolPrompt {
+    fn tool_call_format(&self) -> ToolCallFormat {
+        ToolCallFormat {
+            start: "[TOOL_CALLS]",
+            end: Some("]"),
+            named: false,
+            arguments_key: "arguments",
+        }
+    }
+
+    fn parse_tool_calls(&self, output: &str) -> Result<ParseResult> {
+        let re = tool_call_regex(r"\[\{.*?\}\]")?;
+
+        let mut tool_calls = vec![];
+        for cap in re.captures_iter(output) {
+            let values = serde_json::from_str::<Vec<serde_json::Value>>(&cap[0]).map_err(|e| {
+                PromptError::BadToolCalls(format!(
+                    "Failed to deserialize generated tool calls. Reason: {}",
+                    e
+                ))
+            })?;
+
+            for value in values.iter() {
+                tool_calls.push(function_from_value(value, "arguments")?);
+            }
+        }
+
+        Ok(ParseResult {
+            raw: output.to_owned(),
+            content: None,
+            tool_calls,
+        })
+    }
+}
+
+#[test]
+fn test_parse_mistral_tool_calls() {
+    use crate::tool_call::{ToolCallDelta, ToolCallStream};
+
+    let output = r#"[TOOL_CALLS] [{"name": "get_current_weather", "arguments": {"location": "Beijing, China", "format": "celsius"}}, {"name": "predict_weather", "arguments": {"location": "Beijing, China", "format": "celsius"}}]"#;
+
+    let parsed = MistralToolPrompt.parse_tool_calls(output).unwrap();
+    assert!(parsed.content.is_none());
+    assert_eq!(parsed.tool_calls.len(), 2);
+    assert_eq!(parsed.tool_calls[0].name, "get_current_weather");
+    assert_eq!(parsed.tool_calls[1].name, "predict_weather");
+    let arguments: serde_json::Value =
+        serde_json::from_str(&parsed.tool_calls[0].arguments).unwrap();
+    assert_eq!(
+        arguments,
+        serde_json::json!({"location": "Beijing, China", "format": "celsius"})
+    );
+
+    let mut stream = ToolCallStream::new(MistralToolPrompt.tool_call_format());
+    let mut deltas = vec![];
+    for token in output.split_inclusive(' ') {
+        deltas.extend(stream.push(token));
+    }
+    deltas.extend(stream.finish());
+    assert_eq!(
+        deltas
+            .iter()
+            .filter(|delta| matches!(delta, ToolCallDelta::Start { .. }))
+            .count(),
+        2
+    );
+    // the `[TOOL_CALLS]` marker is not sent as the content
+    assert!(!deltas
+        .iter()
+        .any(|delta| matches!(delta, ToolCallDelta::Content(_))));
+
+    let mut stream = ToolCallStream::new(MistralToolPrompt.tool_call_format());
+    let mut deltas = stream.push("See [1] for the details.");
+    deltas.extend(stream.finish());
+    assert_eq!(
+        deltas,
+        vec![ToolCallDelta::Content(
+            "See [1] for the details.".to_string()
+        )]
+    );
+
+    let parsed = MistralToolPrompt
+        .parse_tool_calls("The weather in Beijing is sunny.")
+        .unwrap();
+    assert!(parsed.tool_calls.is_empty());
+}
+
 /// Generate prompts for `Mistral-Small-24B-Instruct` model
 #[derive(Debug, Default, Clone)]
 pub struct MistralSmallChatPrompt;
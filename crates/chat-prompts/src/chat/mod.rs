@@ -24,7 +24,11 @@ pub mod vicuna;
 pub mod wizard;
 pub mod zephyr;
 
-use crate::{error::Result, PromptTemplateType};
+use crate::{
+    error::Result,
+    tool_call::{ParseResult, ToolCallFormat},
+    PromptTemplateType,
+};
 use baichuan::*;
 use belle::*;
 use chatml::*;
@@ -66,6 +70,15 @@ pub trait BuildChatPrompt: Send {
     }
 }
 
+/// Trait for parsing the tool calls in the outputs of the models.
+pub trait ToolCallParser: Send {
+    /// Get the layout of the tool calls in the output, which is used to detect the tool calls while the tokens are generated in the stream mode.
+    fn tool_call_format(&self) -> ToolCallFormat;
+
+    /// Parse the tool calls in the output.
+    fn parse_tool_calls(&self, output: &str) -> Result<ParseResult>;
+}
+
 #[enum_dispatch::enum_dispatch(BuildChatPrompt)]
 pub enum ChatPrompt {
     Llama2ChatPrompt,
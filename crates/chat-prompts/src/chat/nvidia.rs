@@ -1,5 +1,10 @@
-use super::BuildChatPrompt;
-use crate::error::{PromptError, Result};
+use super::{BuildChatPrompt, ToolCallParser};
+use crate::{
+    error::{PromptError, Result},
+    tool_call::{
+        function_from_value, parse_tool_call_value, tool_call_regex, ParseResult, ToolCallFormat,
+    },
+};
 use endpoints::chat::{
     ChatCompletionAssistantMessage, ChatCompletionRequestMessage, ChatCompletionSystemMessage,
     ChatCompletionToolMessage, ChatCompletionUserMessage, ChatCompletionUserMessageContent,
@@ -329,3 +334,45 @@ impl BuildChatPrompt for NemotronToolPrompt {
         Ok(prompt)
     }
 }
+
+impl ToolCallParser for NemotronToolPrompt {
+    fn tool_call_format(&self) -> ToolCallFormat {
+        ToolCallFormat {
+            start: "<toolcall>",
+            end: Some("</toolcall>"),
+            named: false,
+            arguments_key: "arguments",
+        }
+    }
+
+    fn parse_tool_calls(&self, output: &str) -> Result<ParseResult> {
+        let re = tool_call_regex(r"(?s)<toolcall>\s*(.*?)\s*</toolcall>")?;
+
+        let mut tool_calls = vec![];
+        for cap in re.captures_iter(output) {
+            let value = parse_tool_call_value(cap[1].trim())?;
+            tool_calls.push(function_from_value(&value, "arguments")?);
+        }
+
+        Ok(ParseResult {
+            raw: output.to_owned(),
+            content: None,
+            tool_calls,
+        })
+    }
+}
+
+#[test]
+fn test_parse_nemotron_tool_calls() {
+    let output = r#"<toolcall> {"name": "get_current_weather", "arguments": {"location": "Beijing, China", "format": "celsius"}} </toolcall>"#;
+
+    let parsed = NemotronToolPrompt.parse_tool_calls(output).unwrap();
+    assert_eq!(parsed.tool_calls.len(), 1);
+    assert_eq!(parsed.tool_calls[0].name, "get_current_weather");
+    let arguments: serde_json::Value =
+        serde_json::from_str(&parsed.tool_calls[0].arguments).unwrap();
+    assert_eq!(
+        arguments,
+        serde_json::json!({"location": "Beijing, China", "format": "celsius"})
+    );
+}
@@ -19,6 +19,8 @@ pub enum PromptError {
     UnknownRole(ChatCompletionRole),
     #[error("Unknown prompt template type: {0}")]
     UnknownPromptTemplateType(String),
+    #[error("Bad tool calls. {0}")]
+    BadToolCalls(String),
     #[error("Failed to build prompt. Reason: {0}")]
     Operation(String),
 }
@@ -4,10 +4,22 @@
 
 pub mod chat;
 pub mod error;
+pub mod tool_call;
 pub mod utils;
 
+use chat::{
+    chatml::{ChatMLToolPrompt, InternLM2ToolPrompt},
+    functionary::{FunctionaryV31ToolPrompt, FunctionaryV32ToolPrompt},
+    groq::GroqLlama3ToolPrompt,
+    llama::Llama3ToolPrompt,
+    mistral::MistralToolPrompt,
+    nvidia::NemotronToolPrompt,
+    ToolCallParser,
+};
 use clap::ValueEnum;
-use endpoints::chat::ChatCompletionRequestMessage;
+use endpoints::chat::{
+    ChatCompletionRequestMessage, ChatCompletionUserMessageContent, ContentPart, TextContentPart,
+};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
@@ -169,6 +181,21 @@ impl PromptTemplateType {
         }
     }
 
+    /// Get the tool call parser of the prompt template. Returns `None` if the prompt template does not support tool use.
+    pub fn tool_call_parser(&self) -> Option<Box<dyn ToolCallParser>> {
+        match self {
+            PromptTemplateType::MistralTool => Some(Box::new(MistralToolPrompt)),
+            PromptTemplateType::ChatMLTool => Some(Box::new(ChatMLToolPrompt)),
+            PromptTemplateType::GroqLlama3Tool => Some(Box::new(GroqLlama3ToolPrompt)),
+            PromptTemplateType::Llama3Tool => Some(Box::new(Llama3ToolPrompt)),
+            PromptTemplateType::InternLM2Tool => Some(Box::new(InternLM2ToolPrompt)),
+            PromptTemplateType::NemotronTool => Some(Box::new(NemotronToolPrompt)),
+            PromptTemplateType::FunctionaryV32 => Some(Box::new(FunctionaryV32ToolPrompt)),
+            PromptTemplateType::FunctionaryV31 => Some(Box::new(FunctionaryV31ToolPrompt)),
+            _ => None,
+        }
+    }
+
     /// Check if the prompt template supports image input.
     pub fn is_image_supported(&self) -> bool {
         matches!(
@@ -296,11 +323,17 @@ impl std::fmt::Display for PromptTemplateType {
     }
 }
 
+/// The default instruction for merging RAG context into chat messages. The `{context}` placeholder is replaced by the numbered pieces of context.
+pub const DEFAULT_RAG_PROMPT: &str = "Use the following pieces of context to answer the user's question.\nIf you don't know the answer, just say that you don't know, don't try to make up an answer.\n----------------\n{context}";
+
+/// The placeholder of the RAG context in the instruction.
+pub const RAG_CONTEXT_PLACEHOLDER: &str = "{context}";
+
 /// Trait for merging RAG context into chat messages
 pub trait MergeRagContext: Send {
-    /// Merge RAG context into chat messages.
+    /// Merge RAG context into chat messages with the instruction of [DEFAULT_RAG_PROMPT].
     ///
-    /// Note that the default implementation simply merges the RAG context into the system message. That is, to use the default implementation, `has_system_prompt` should be set to `true` and `policy` set to `MergeRagContextPolicy::SystemMessage`.
+    /// See [MergeRagContext::build_with_prompt] for the details.
     ///
     /// # Arguments
     ///
@@ -317,57 +350,153 @@ pub trait MergeRagContext: Send {
         has_system_prompt: bool,
         policy: MergeRagContextPolicy,
     ) -> error::Result<()> {
-        if (policy == MergeRagContextPolicy::SystemMessage) && has_system_prompt {
-            if messages.is_empty() {
-                return Err(error::PromptError::NoMessages);
-            }
+        Self::build_with_prompt(
+            messages,
+            context,
+            has_system_prompt,
+            policy,
+            DEFAULT_RAG_PROMPT,
+        )
+    }
 
-            if context.is_empty() {
-                return Err(error::PromptError::Operation(
-                    "No context provided.".to_string(),
-                ));
-            }
+    /// Merge RAG context into chat messages with the given instruction.
+    ///
+    /// All the pieces of context are merged, each of which is marked with its number, e.g., `[1]`, in the order of `context`. The merged context replaces the `{context}` placeholder of `rag_prompt`, or is appended to `rag_prompt` if the placeholder is absent.
+    ///
+    /// With `MergeRagContextPolicy::SystemMessage`, the instruction is appended to the system message, which is inserted if absent. With `MergeRagContextPolicy::LastUserMessage`, the instruction is prepended to the text of the last user message. If the chat template has no system prompt, the context is always merged into the last user message.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The chat messages to merge the context into.
+    ///
+    /// * `context` - The RAG context to merge into the chat messages.
+    ///
+    /// * `has_system_prompt` - Whether the chat template has a system prompt.
+    ///
+    /// * `policy` - The policy for merging RAG context into chat messages.
+    ///
+    /// * `rag_prompt` - The instruction of the RAG context, which contains the `{context}` placeholder.
+    fn build_with_prompt(
+        messages: &mut Vec<endpoints::chat::ChatCompletionRequestMessage>,
+        context: &[String],
+        has_system_prompt: bool,
+        policy: MergeRagContextPolicy,
+        rag_prompt: &str,
+    ) -> error::Result<()> {
+        if messages.is_empty() {
+            return Err(error::PromptError::NoMessages);
+        }
 
-            let context = context[0].trim_end();
+        if context.is_empty() {
+            return Err(error::PromptError::Operation(
+                "No context provided.".to_string(),
+            ));
+        }
 
-            // update or insert system message
-            match messages[0] {
-                ChatCompletionRequestMessage::System(ref message) => {
-                    // compose new system message content
-                    let content = format!("{original_system_message}\nUse the following pieces of context to answer the user's question.\nIf you don't know the answer, just say that you don't know, don't try to make up an answer.\n----------------\n{context}", original_system_message=message.content().trim(), context=context.trim_end());
-                    // create system message
-                    let system_message = ChatCompletionRequestMessage::new_system_message(
-                        content,
-                        messages[0].name().cloned(),
-                    );
-                    // replace the original system message
-                    messages[0] = system_message;
-                }
-                _ => {
-                    // prepare system message
-                    let content = format!("Use the following pieces of context to answer the user's question.\nIf you don't know the answer, just say that you don't know, don't try to make up an answer.\n----------------\n{}", context.trim_end());
+        let instruction = render_rag_prompt(rag_prompt, context);
+
+        let policy = match has_system_prompt {
+            true => policy,
+            false => MergeRagContextPolicy::LastUserMessage,
+        };
+
+        match policy {
+            MergeRagContextPolicy::SystemMessage => {
+                // update or insert system message
+                match messages[0] {
+                    ChatCompletionRequestMessage::System(ref message) => {
+                        // compose new system message content
+                        let content = format!(
+                            "{original_system_message}\n{instruction}",
+                            original_system_message = message.content().trim(),
+                        );
+                        // create system message
+                        let system_message = ChatCompletionRequestMessage::new_system_message(
+                            content,
+                            messages[0].name().cloned(),
+                        );
+                        // replace the original system message
+                        messages[0] = system_message;
+                    }
+                    _ => {
+                        // create system message
+                        let system_message =
+                            ChatCompletionRequestMessage::new_system_message(instruction, None);
+                        // insert system message
+                        messages.insert(0, system_message);
+                    }
+                };
+            }
+            MergeRagContextPolicy::LastUserMessage => {
+                let index = messages
+                    .iter()
+                    .rposition(|message| matches!(message, ChatCompletionRequestMessage::User(_)))
+                    .ok_or(error::PromptError::NoUserMessage)?;
 
-                    // create system message
-                    let system_message = ChatCompletionRequestMessage::new_system_message(
+                if let ChatCompletionRequestMessage::User(ref message) = messages[index] {
+                    // compose new user message content
+                    let content = match message.content() {
+                        ChatCompletionUserMessageContent::Text(text) => {
+                            ChatCompletionUserMessageContent::Text(format!(
+                                "{instruction}\n\n{text}"
+                            ))
+                        }
+                        ChatCompletionUserMessageContent::Parts(parts) => {
+                            let mut parts = parts.clone();
+                            // prepend the instruction to the first text part, or insert a text part if absent
+                            match parts
+                                .iter()
+                                .position(|part| matches!(part, ContentPart::Text(_)))
+                            {
+                                Some(i) => {
+                                    if let ContentPart::Text(ref part) = parts[i] {
+                                        parts[i] = ContentPart::Text(TextContentPart::new(
+                                            format!("{instruction}\n\n{}", part.text()),
+                                        ));
+                                    }
+                                }
+                                None => parts.insert(
+                                    0,
+                                    ContentPart::Text(TextContentPart::new(instruction)),
+                                ),
+                            }
+                            ChatCompletionUserMessageContent::Parts(parts)
+                        }
+                    };
+                    // replace the last user message
+                    messages[index] = ChatCompletionRequestMessage::new_user_message(
                         content,
-                        messages[0].name().cloned(),
+                        message.name().cloned(),
                     );
-                    // insert system message
-                    messages.insert(0, system_message);
                 }
-            };
+            }
         }
 
         Ok(())
     }
 }
 
+/// Render the instruction of the RAG context with the numbered pieces of context.
+fn render_rag_prompt(rag_prompt: &str, context: &[String]) -> String {
+    let context = context
+        .iter()
+        .enumerate()
+        .map(|(i, piece)| format!("[{}] {}", i + 1, piece.trim()))
+        .collect::<Vec<String>>()
+        .join("\n\n");
+
+    match rag_prompt.contains(RAG_CONTEXT_PLACEHOLDER) {
+        true => rag_prompt.replace(RAG_CONTEXT_PLACEHOLDER, &context),
+        false => format!("{}\n{}", rag_prompt.trim_end(), context),
+    }
+}
+
 /// Define the strategy for merging RAG context into chat messages.
 #[derive(Clone, Debug, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
 pub enum MergeRagContextPolicy {
     /// Merge RAG context into the system message.
     ///
-    /// Note that this policy is only applicable when the chat template has a system message.
+    /// Note that this policy is only applicable when the chat template has a system message. Otherwise, the context is merged into the last user message.
     #[default]
     SystemMessage,
     /// Merge RAG context into the last user message.
@@ -381,3 +510,130 @@ impl std::fmt::Display for MergeRagContextPolicy {
         }
     }
 }
+
+#[test]
+fn test_merge_rag_context_into_system_message() {
+    struct RagPrompt;
+    impl MergeRagContext for RagPrompt {}
+
+    let mut messages = vec![
+        ChatCompletionRequestMessage::new_system_message("You are a helpful assistant.", None),
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+            None,
+        ),
+    ];
+    let context = vec![
+        "LlamaEdge is an API server.\n".to_string(),
+        "LlamaEdge runs on WasmEdge.".to_string(),
+    ];
+    RagPrompt::build(
+        &mut messages,
+        &context,
+        true,
+        MergeRagContextPolicy::SystemMessage,
+    )
+    .unwrap();
+
+    assert_eq!(messages.len(), 2);
+    match &messages[0] {
+        ChatCompletionRequestMessage::System(message) => assert_eq!(
+            message.content(),
+            "You are a helpful assistant.\nUse the following pieces of context to answer the user's question.\nIf you don't know the answer, just say that you don't know, don't try to make up an answer.\n----------------\n[1] LlamaEdge is an API server.\n\n[2] LlamaEdge runs on WasmEdge."
+        ),
+        _ => panic!("The first message should be a system message."),
+    }
+}
+
+#[test]
+fn test_merge_rag_context_into_last_user_message() {
+    struct RagPrompt;
+    impl MergeRagContext for RagPrompt {}
+
+    let context = vec!["LlamaEdge is an API server.".to_string()];
+
+    // fall back to the last user message if the template has no system prompt
+    let mut messages = vec![
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("Hello".to_string()),
+            None,
+        ),
+        ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("What is LlamaEdge?".to_string()),
+            Some("alice".to_string()),
+        ),
+    ];
+    RagPrompt::build_with_prompt(
+        &mut messages,
+        &context,
+        false,
+        MergeRagContextPolicy::SystemMessage,
+        "Context:\n{context}",
+    )
+    .unwrap();
+
+    assert_eq!(messages.len(), 2);
+    match &messages[1] {
+        ChatCompletionRequestMessage::User(message) => {
+            assert_eq!(
+                message.content(),
+                &ChatCompletionUserMessageContent::Text(
+                    "Context:\n[1] LlamaEdge is an API server.\n\nWhat is LlamaEdge?".to_string()
+                )
+            );
+            assert_eq!(message.name(), Some(&"alice".to_string()));
+        }
+        _ => panic!("The last message should be a user message."),
+    }
+
+    // the instruction is prepended to the first text part
+    let image = ContentPart::Image(endpoints::chat::ImageContentPart::new(
+        endpoints::chat::Image {
+            url: "https://example.com/image.png".to_string(),
+            detail: None,
+        },
+    ));
+    let mut messages = vec![ChatCompletionRequestMessage::new_user_message(
+        ChatCompletionUserMessageContent::Parts(vec![
+            image.clone(),
+            ContentPart::Text(TextContentPart::new("What is in the image?")),
+        ]),
+        None,
+    )];
+    RagPrompt::build_with_prompt(
+        &mut messages,
+        &context,
+        true,
+        MergeRagContextPolicy::LastUserMessage,
+        "Context:",
+    )
+    .unwrap();
+
+    match &messages[0] {
+        ChatCompletionRequestMessage::User(message) => assert_eq!(
+            message.content(),
+            &ChatCompletionUserMessageContent::Parts(vec![
+                image,
+                ContentPart::Text(TextContentPart::new(
+                    "Context:\n[1] LlamaEdge is an API server.\n\nWhat is in the image?"
+                )),
+            ])
+        ),
+        _ => panic!("The message should be a user message."),
+    }
+
+    // no user message to merge the context into
+    let mut messages = vec![ChatCompletionRequestMessage::new_system_message(
+        "You are a helpful assistant.",
+        None,
+    )];
+    assert_eq!(
+        RagPrompt::build(
+            &mut messages,
+            &context,
+            true,
+            MergeRagContextPolicy::LastUserMessage,
+        ),
+        Err(error::PromptError::NoUserMessage)
+    );
+}
@@ -0,0 +1,616 @@
+//! Define the types for parsing the tool calls generated by the models.
+
+use crate::error::{PromptError, Result};
+use endpoints::chat::Function;
+use serde_json::Value;
+
+/// The result of parsing the output of a model.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseResult {
+    /// The raw output of the model.
+    pub raw: String,
+    /// The text of the assistant message other than the tool calls.
+    pub content: Option<String>,
+    /// The functions to call.
+    pub tool_calls: Vec<Function>,
+}
+
+/// Deserialize the JSON text of a tool call.
+pub(crate) fn parse_tool_call_value(text: &str) -> Result<Value> {
+    serde_json::from_str::<Value>(text).map_err(|e| {
+        PromptError::BadToolCalls(format!(
+            "Failed to deserialize generated tool calls. Reason: {}",
+            e
+        ))
+    })
+}
+
+/// Get the function to call from a tool call in the form of `{"name": <function-name>, <arguments_key>: <args-dict>}`.
+pub(crate) fn function_from_value(value: &Value, arguments_key: &str) -> Result<Function> {
+    let name = match value.get("name") {
+        Some(name) => name.to_string().replace("\"", ""),
+        None => {
+            return Err(PromptError::BadToolCalls(format!(
+                "Failed to get the name of the function. Tool call: {:?}",
+                value
+            )))
+        }
+    };
+
+    let arguments = match value.get(arguments_key) {
+        Some(arguments) => arguments.to_string(),
+        None => {
+            return Err(PromptError::BadToolCalls(format!(
+                "Failed to get the arguments of the function. Tool call: {:?}",
+                value
+            )))
+        }
+    };
+
+    Ok(Function { name, arguments })
+}
+
+/// Create a regex pattern for matching the tool calls.
+pub(crate) fn tool_call_regex(pattern: &str) -> Result<regex::Regex> {
+    regex::Regex::new(pattern).map_err(|e| {
+        PromptError::BadToolCalls(format!("Failed to create a regex pattern. Reason: {}", e))
+    })
+}
+
+/// The layout of the tool calls generated by a prompt template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToolCallFormat {
+    /// The text opening a block of tool calls. An empty string means that the tool calls are only recognized at the very beginning of the output.
+    pub start: &'static str,
+    /// The text closing a block of tool calls.
+    pub end: Option<&'static str>,
+    /// `true` if the function name follows the start text as a header, and the arguments are the JSON object after it. Otherwise, each tool call is a JSON object with the `name` field and the arguments field.
+    pub named: bool,
+    /// The name of the field holding the arguments in the JSON object of a tool call.
+    pub arguments_key: &'static str,
+}
+
+/// A piece of the output recognized by [`ToolCallStream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolCallDelta {
+    /// Plain text of the assistant message.
+    Content(String),
+    /// The beginning of a new tool call.
+    Start { index: usize, name: String },
+    /// A fragment of the arguments of the tool call at `index`.
+    Arguments { index: usize, fragment: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ToolCallScan {
+    /// Looking for the start text of a block of tool calls.
+    Content,
+    /// Inside a block of tool calls, between two tool calls.
+    Block,
+    /// Reading the function name in the header of a tool call.
+    Header(String),
+    /// Reading the JSON object of a tool call.
+    Object(JsonObjectScan),
+    /// No tool call is expected any more.
+    Plain,
+}
+
+/// The position in the top-level fields of a JSON object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum JsonField {
+    Key,
+    Colon,
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct JsonObjectScan {
+    raw: String,
+    depth: usize,
+    in_string: bool,
+    escape: bool,
+    field: JsonField,
+    key: String,
+    value: String,
+    arguments: String,
+    started: bool,
+}
+impl JsonObjectScan {
+    fn new() -> Self {
+        Self {
+            raw: String::new(),
+            depth: 0,
+            in_string: false,
+            escape: false,
+            field: JsonField::Key,
+            key: String::new(),
+            value: String::new(),
+            arguments: String::new(),
+            started: false,
+        }
+    }
+}
+
+/// Detect the tool calls while the tokens are generated one by one in the stream mode.
+///
+/// The text which may turn out to be a part of a tool call is held back until it can be decided. The arguments of a tool call are sent out as soon as they are generated.
+#[derive(Debug)]
+pub struct ToolCallStream {
+    format: ToolCallFormat,
+    scan: ToolCallScan,
+    /// The text held back while matching the start or end text.
+    pending: String,
+    /// The raw text of the current block, used to restore the content if the block turns out not to be a tool call.
+    block: String,
+    /// The number of tool calls in the current block.
+    block_calls: usize,
+    /// The number of tool calls detected so far.
+    count: usize,
+    finished: bool,
+}
+impl ToolCallStream {
+    pub fn new(format: ToolCallFormat) -> Self {
+        Self {
+            format,
+            scan: ToolCallScan::Content,
+            pending: String::new(),
+            block: String::new(),
+            block_calls: 0,
+            count: 0,
+            finished: false,
+        }
+    }
+
+    /// Feed a newly generated token.
+    pub fn push(&mut self, token: &str) -> Vec<ToolCallDelta> {
+        let mut deltas = vec![];
+        for c in token.chars() {
+            self.push_char(c, &mut deltas);
+        }
+
+        merge_deltas(deltas)
+    }
+
+    /// Finish the stream and take out the text held back so far.
+    pub fn finish(&mut self) -> Vec<ToolCallDelta> {
+        self.finished = true;
+
+        let mut deltas = vec![];
+        match std::mem::replace(&mut self.scan, ToolCallScan::Plain) {
+            ToolCallScan::Content => {
+                let text = std::mem::take(&mut self.pending);
+                push_content(&mut deltas, text);
+            }
+            ToolCallScan::Block => {
+                if self.block_calls == 0 {
+                    let text = std::mem::take(&mut self.block) + &self.pending;
+                    push_content(&mut deltas, text);
+                }
+            }
+            ToolCallScan::Header(header) => {
+                let text = std::mem::take(&mut self.block) + &header;
+                push_content(&mut deltas, text);
+            }
+            ToolCallScan::Object(object) => {
+                if !object.started && self.block_calls == 0 {
+                    let text = std::mem::take(&mut self.block) + &object.raw;
+                    push_content(&mut deltas, text);
+                }
+            }
+            ToolCallScan::Plain => {}
+        }
+        self.pending.clear();
+
+        merge_deltas(deltas)
+    }
+
+    /// Check if the stream is finished.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Check if any tool call is detected.
+    pub fn has_tool_calls(&self) -> bool {
+        self.count > 0
+    }
+
+    fn push_char(&mut self, c: char, deltas: &mut Vec<ToolCallDelta>) {
+        match std::mem::replace(&mut self.scan, ToolCallScan::Plain) {
+            ToolCallScan::Content => self.scan_content(c, deltas),
+            ToolCallScan::Block => self.scan_block(c, deltas),
+            ToolCallScan::Header(header) => self.scan_header(header, c, deltas),
+            ToolCallScan::Object(object) => self.scan_object(object, c, deltas),
+            ToolCallScan::Plain => push_content(deltas, c.to_string()),
+        }
+    }
+
+    fn scan_content(&mut self, c: char, deltas: &mut Vec<ToolCallDelta>) {
+        self.scan = ToolCallScan::Content;
+
+        // the tool calls are only expected at the beginning of the output
+        if self.format.start.is_empty() {
+            if c.is_whitespace() {
+                self.pending.push(c);
+            } else if c == '{' {
+                self.pending.clear();
+                self.block.clear();
+                self.block_calls = 0;
+                self.scan = ToolCallScan::Block;
+                self.scan_block(c, deltas);
+            } else {
+                let text = std::mem::take(&mut self.pending);
+                push_content(deltas, text);
+                push_content(deltas, c.to_string());
+                self.scan = ToolCallScan::Plain;
+            }
+
+            return;
+        }
+
+        self.pending.push(c);
+
+        if self.pending.ends_with(self.format.start) {
+            let text = self.pending[..self.pending.len() - self.format.start.len()].to_string();
+            push_content(deltas, text);
+
+            self.pending.clear();
+            self.block = self.format.start.to_string();
+            self.block_calls = 0;
+            self.scan = match self.format.named {
+                true => ToolCallScan::Header(String::new()),
+                false => ToolCallScan::Block,
+            };
+
+            return;
+        }
+
+        // hold back the longest tail which is a prefix of the start text
+        let held = self
+            .pending
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .find(|&idx| self.format.start.starts_with(&self.pending[idx..]))
+            .unwrap_or(self.pending.len());
+        let text: String = self.pending.drain(..held).collect();
+        push_content(deltas, text);
+    }
+
+    fn scan_block(&mut self, c: char, deltas: &mut Vec<ToolCallDelta>) {
+        self.scan = ToolCallScan::Block;
+
+        if let Some(end) = self.format.end {
+            let mut candidate = self.pending.clone();
+            candidate.push(c);
+
+            if end.starts_with(&candidate) {
+                if candidate == end {
+                    // the block is closed
+                    if self.block_calls == 0 {
+                        push_content(deltas, std::mem::take(&mut self.block) + &candidate);
+                    }
+                    self.pending.clear();
+                    self.block.clear();
+                    self.scan = match self.format.start.is_empty() {
+                        true => ToolCallScan::Plain,
+                        false => ToolCallScan::Content,
+                    };
+                } else {
+                    self.pending = candidate;
+                }
+
+                return;
+            }
+        }
+
+        if self.pending.is_empty() {
+            if !self.format.named && c == '{' {
+                let mut object = JsonObjectScan::new();
+                object.raw.push(c);
+                object.depth = 1;
+                self.scan = ToolCallScan::Object(object);
+
+                return;
+            }
+
+            // the tool calls may be wrapped in a JSON array, e.g., `[TOOL_CALLS] [{...}, {...}]`
+            let opens_array = c == '['
+                && self.block_calls == 0
+                && !self.block[self.format.start.len().min(self.block.len())..].contains('[');
+            if c.is_whitespace() || (!self.format.named && (c == ',' || opens_array)) {
+                self.block.push(c);
+
+                return;
+            }
+        }
+
+        // not a tool call
+        let pending = std::mem::take(&mut self.pending);
+        let block = std::mem::take(&mut self.block);
+        self.scan = match self.format.start.is_empty() {
+            true => ToolCallScan::Plain,
+            false => ToolCallScan::Content,
+        };
+        match self.block_calls {
+            0 => {
+                push_content(deltas, block);
+                for c in pending.chars() {
+                    self.push_char(c, deltas);
+                }
+                self.push_char(c, deltas);
+            }
+            _ => {
+                for c in pending.chars() {
+                    self.push_char(c, deltas);
+                }
+                self.push_char(c, deltas);
+            }
+        }
+    }
+
+    fn scan_header(&mut self, mut header: String, c: char, deltas: &mut Vec<ToolCallDelta>) {
+        let name = header
+            .trim_start()
+            .trim_end_matches(['>', ' ', '\t', '\r', '\n']);
+
+        if c.is_alphanumeric() || c == '_' || c == '-' {
+            if header.trim_start() == name {
+                header.push(c);
+                self.scan = ToolCallScan::Header(header);
+
+                return;
+            }
+        } else if c.is_whitespace() || c == '>' {
+            header.push(c);
+            self.scan = ToolCallScan::Header(header);
+
+            return;
+        } else if c == '{' && !name.is_empty() {
+            let index = self.count;
+            deltas.push(ToolCallDelta::Start {
+                index,
+                name: name.to_string(),
+            });
+
+            let mut object = JsonObjectScan::new();
+            object.raw.push(c);
+            object.depth = 1;
+            object.started = true;
+            deltas.push(ToolCallDelta::Arguments {
+                index,
+                fragment: c.to_string(),
+            });
+
+            self.count += 1;
+            self.block_calls += 1;
+            self.block.clear();
+            self.scan = ToolCallScan::Object(object);
+
+            return;
+        }
+
+        // not a tool call
+        let block = std::mem::take(&mut self.block);
+        push_content(deltas, block + &header);
+        self.scan = ToolCallScan::Content;
+        self.push_char(c, deltas);
+    }
+
+    fn scan_object(
+        &mut self,
+        mut object: JsonObjectScan,
+        c: char,
+        deltas: &mut Vec<ToolCallDelta>,
+    ) {
+        object.raw.push(c);
+
+        // the arguments of a named tool call are the whole object
+        if self.format.named {
+            let index = self.count - 1;
+            deltas.push(ToolCallDelta::Arguments {
+                index,
+                fragment: c.to_string(),
+            });
+        }
+
+        let top_level = object.depth == 1 && !object.in_string;
+
+        if object.in_string {
+            if object.escape {
+                object.escape = false;
+            } else if c == '\\' {
+                object.escape = true;
+            } else if c == '"' {
+                object.in_string = false;
+            }
+        } else {
+            match c {
+                '"' => object.in_string = true,
+                '{' | '[' => object.depth += 1,
+                '}' | ']' => object.depth -= 1,
+                _ => {}
+            }
+        }
+
+        if !self.format.named {
+            match object.field {
+                JsonField::Key => {
+                    if top_level && c == '"' {
+                        object.key.clear();
+                    } else if object.in_string {
+                        object.key.push(c);
+                    } else if c == '"' {
+                        object.field = JsonField::Colon;
+                    }
+                }
+                JsonField::Colon => {
+                    if c == ':' {
+                        object.field = JsonField::Value;
+                        object.value.clear();
+                    }
+                }
+                JsonField::Value => {
+                    if top_level && (c == ',' || c == '}') {
+                        self.end_json_value(&mut object, deltas);
+                        object.field = JsonField::Key;
+                    } else if !(object.value.is_empty() && c.is_whitespace()) {
+                        object.value.push(c);
+
+                        if object.key == self.format.arguments_key {
+                            match object.started {
+                                true => deltas.push(ToolCallDelta::Arguments {
+                                    index: self.count - 1,
+                                    fragment: c.to_string(),
+                                }),
+                                false => object.arguments.push(c),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if object.depth > 0 {
+            self.scan = ToolCallScan::Object(object);
+
+            return;
+        }
+
+        // the object is closed
+        self.scan = ToolCallScan::Block;
+        if !object.started {
+            // not a tool call
+            let block = std::mem::take(&mut self.block);
+            push_content(deltas, block + &object.raw);
+            self.scan = match self.format.start.is_empty() {
+                true => ToolCallScan::Plain,
+                false => ToolCallScan::Content,
+            };
+        }
+    }
+
+    fn end_json_value(&mut self, object: &mut JsonObjectScan, deltas: &mut Vec<ToolCallDelta>) {
+        if object.key != "name" || object.started {
+            return;
+        }
+
+        let value = object.value.trim_end();
+        let name = match serde_json::from_str::<String>(value) {
+            Ok(name) => name,
+            Err(_) => value.trim_matches('"').to_string(),
+        };
+
+        let index = self.count;
+        deltas.push(ToolCallDelta::Start { index, name });
+        if !object.arguments.is_empty() {
+            deltas.push(ToolCallDelta::Arguments {
+                index,
+                fragment: std::mem::take(&mut object.arguments),
+            });
+        }
+
+        object.started = true;
+        self.count += 1;
+        self.block_calls += 1;
+    }
+}
+
+fn push_content(deltas: &mut Vec<ToolCallDelta>, text: String) {
+    if !text.is_empty() {
+        deltas.push(ToolCallDelta::Content(text));
+    }
+}
+
+/// Merge the consecutive fragments of the same kind.
+fn merge_deltas(deltas: Vec<ToolCallDelta>) -> Vec<ToolCallDelta> {
+    let mut merged: Vec<ToolCallDelta> = vec![];
+    for delta in deltas {
+        match (merged.last_mut(), delta) {
+            (Some(ToolCallDelta::Content(text)), ToolCallDelta::Content(more)) => {
+                text.push_str(&more)
+            }
+            (
+                Some(ToolCallDelta::Arguments { index, fragment }),
+                ToolCallDelta::Arguments {
+                    index: next,
+                    fragment: more,
+                },
+            ) if *index == next => fragment.push_str(&more),
+            (_, delta) => merged.push(delta),
+        }
+    }
+
+    merged
+}
+
+#[test]
+fn test_tool_call_stream_split_markers() {
+    let format = ToolCallFormat {
+        start: "<tool_call>",
+        end: Some("</tool_call>"),
+        named: false,
+        arguments_key: "arguments",
+    };
+
+    let mut stream = ToolCallStream::new(format);
+    let mut deltas = vec![];
+    for token in [
+        "Sure.",
+        " <tool",
+        "_call>",
+        "{\"name\": \"get",
+        "_weather\", ",
+        "\"arguments\": {\"city",
+        "\": \"Tokyo\"}}",
+        "</tool_",
+        "call>",
+    ] {
+        deltas.extend(stream.push(token));
+    }
+    deltas.extend(stream.finish());
+
+    assert!(stream.has_tool_calls());
+    assert_eq!(
+        deltas,
+        vec![
+            ToolCallDelta::Content("Sure.".to_string()),
+            ToolCallDelta::Content(" ".to_string()),
+            ToolCallDelta::Start {
+                index: 0,
+                name: "get_weather".to_string()
+            },
+            ToolCallDelta::Arguments {
+                index: 0,
+                fragment: "{\"city".to_string()
+            },
+            ToolCallDelta::Arguments {
+                index: 0,
+                fragment: "\": \"Tokyo\"}".to_string()
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_tool_call_stream_false_positive() {
+    let format = ToolCallFormat {
+        start: "[",
+        end: Some("]"),
+        named: false,
+        arguments_key: "arguments",
+    };
+
+    let mut stream = ToolCallStream::new(format);
+    let mut deltas = stream.push("See [1] and [{\"a\": 1}]");
+    deltas.extend(stream.push(" and ["));
+    deltas.extend(stream.finish());
+
+    assert!(!stream.has_tool_calls());
+    assert_eq!(
+        deltas,
+        vec![
+            ToolCallDelta::Content("See [1] and [{\"a\": 1}]".to_string()),
+            ToolCallDelta::Content(" and ".to_string()),
+            ToolCallDelta::Content("[".to_string()),
+        ]
+    );
+}
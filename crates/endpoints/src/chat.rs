@@ -162,6 +162,9 @@ use serde::{
 use serde_json::Value;
 use std::{collections::HashMap, fmt};
 
+/// The maximum number of most likely tokens returned at each token position.
+pub const MAX_TOP_LOGPROBS: u8 = 20;
+
 /// Request builder for creating a new chat completion request.
 pub struct ChatCompletionRequestBuilder {
     req: ChatCompletionRequest,
@@ -302,6 +305,27 @@ impl ChatCompletionRequestBuilder {
         self
     }
 
+    /// Sets the seed for deterministic sampling.
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed of the random number generator used in sampling.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.req.seed = Some(seed);
+        self
+    }
+
+    /// Enables returning the log probabilities of the output tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `top_logprobs` - The number of most likely tokens to return at each token position. The value is clamped to `20`.
+    pub fn enable_logprobs(mut self, top_logprobs: u8) -> Self {
+        self.req.logprobs = Some(true);
+        self.req.top_logprobs = Some(top_logprobs.min(MAX_TOP_LOGPROBS));
+        self
+    }
+
     /// Sets the user.
     ///
     /// # Arguments
@@ -478,10 +502,21 @@ pub struct ChatCompletionRequest {
     pub frequency_penalty: Option<f64>,
     /// Modify the likelihood of specified tokens appearing in the completion.
     ///
-    /// Accepts a json object that maps tokens (specified by their token ID in the tokenizer) to an associated bias value from -100 to 100. Mathematically, the bias is added to the logits generated by the model prior to sampling. The exact effect will vary per model, but values between -1 and 1 should decrease or increase likelihood of selection; values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
+    /// Accepts a json object that maps tokens (specified by their token ID in the tokenizer, or by a string which is tokenized against the loaded model) to an associated bias value from -100 to 100. The bias of a string applies to each of its tokens. Mathematically, the bias is added to the logits generated by the model prior to sampling. The exact effect will vary per model, but values between -1 and 1 should decrease or increase likelihood of selection; values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
     /// Defaults to None.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logit_bias: Option<HashMap<String, f64>>,
+    /// Whether to return log probabilities of the output tokens or not. If true, returns the log probabilities of each output token returned in the `content` of `message`.
+    /// Defaults to false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// An integer between 0 and 20 specifying the number of most likely tokens to return at each token position, each with an associated log probability. `logprobs` must be set to `true` if this parameter is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+    /// If specified, the sampling is deterministic, such that repeated requests with the same `seed` and parameters should return the same result. Determinism is only guaranteed for the same `system_fingerprint` in the response. If `n` is greater than 1, the choices are sampled with `seed`, `seed + 1`, and so on.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
     /// A unique identifier representing your end-user.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
@@ -519,7 +554,7 @@ pub struct ChatCompletionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub context_window: Option<u64>,
 
-    /// The URL of the VectorDB server.
+    /// The URL of the VectorDB server, e.g., `http://localhost:6333` for Qdrant, or `local://` for the vector store embedded in LlamaEdge.
     #[cfg(feature = "rag")]
     #[serde(rename = "vdb_server_url", skip_serializing_if = "Option::is_none")]
     pub vdb_server_url: Option<String>,
@@ -589,6 +624,9 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                 let mut presence_penalty = None;
                 let mut frequency_penalty = None;
                 let mut logit_bias = None;
+                let mut logprobs: Option<bool> = None;
+                let mut top_logprobs: Option<u8> = None;
+                let mut seed = None;
                 let mut user = None;
                 let mut functions = None;
                 let mut function_call = None;
@@ -632,6 +670,9 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                         "presence_penalty" => presence_penalty = map.next_value()?,
                         "frequency_penalty" => frequency_penalty = map.next_value()?,
                         "logit_bias" => logit_bias = map.next_value()?,
+                        "logprobs" => logprobs = map.next_value()?,
+                        "top_logprobs" => top_logprobs = map.next_value()?,
+                        "seed" => seed = map.next_value()?,
                         "user" => user = map.next_value()?,
                         "functions" => functions = map.next_value()?,
                         "function_call" => function_call = map.next_value()?,
@@ -698,6 +739,22 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                     stream = Some(false);
                 }
 
+                // Check logprobs and top_logprobs
+                if let Some(top) = top_logprobs {
+                    if top > MAX_TOP_LOGPROBS {
+                        return Err(de::Error::custom(format!(
+                            "`top_logprobs` must be between 0 and {}, but got {}",
+                            MAX_TOP_LOGPROBS, top
+                        )));
+                    }
+
+                    if logprobs != Some(true) {
+                        return Err(de::Error::custom(
+                            "`logprobs` must be set to `true` if `top_logprobs` is used",
+                        ));
+                    }
+                }
+
                 #[cfg(all(feature = "rag", feature = "index"))]
                 if let Some(name) = &kw_index_name {
                     if name.is_empty() {
@@ -728,6 +785,9 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
                     presence_penalty,
                     frequency_penalty,
                     logit_bias,
+                    logprobs,
+                    top_logprobs,
+                    seed,
                     user,
                     functions,
                     function_call,
@@ -770,6 +830,9 @@ impl<'de> Deserialize<'de> for ChatCompletionRequest {
             "presence_penalty",
             "frequency_penalty",
             "logit_bias",
+            "logprobs",
+            "top_logprobs",
+            "seed",
             "user",
             "functions",
             "function_call",
@@ -819,6 +882,9 @@ impl Default for ChatCompletionRequest {
             presence_penalty: Some(0.0),
             frequency_penalty: Some(0.0),
             logit_bias: None,
+            logprobs: None,
+            top_logprobs: None,
+            seed: None,
             user: None,
             functions: None,
             function_call: None,
@@ -1529,17 +1595,82 @@ fn test_chat_deserialize_chat_request() {
     }
 }
 
+#[test]
+fn test_chat_deserialize_chat_request_logprobs() {
+    {
+        let json = r#"{"model":"model-id","messages":[{"role":"user","content":"Hello, world!"}],"logprobs":true,"top_logprobs":2}"#;
+
+        let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.logprobs, Some(true));
+        assert_eq!(request.top_logprobs, Some(2));
+    }
+
+    {
+        let json = r#"{"model":"model-id","messages":[{"role":"user","content":"Hello, world!"}],"top_logprobs":2}"#;
+
+        let result: Result<ChatCompletionRequest, _> = serde_json::from_str(json);
+        assert!(result.is_err());
+    }
+
+    {
+        let json = r#"{"model":"model-id","messages":[{"role":"user","content":"Hello, world!"}],"logprobs":true,"top_logprobs":21}"#;
+
+        let result: Result<ChatCompletionRequest, _> = serde_json::from_str(json);
+        assert!(result.is_err());
+    }
+
+    {
+        let messages = vec![ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("Hello, world!".to_string()),
+            None,
+        )];
+        let request = ChatCompletionRequestBuilder::new(&messages)
+            .enable_logprobs(30)
+            .build();
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains(r#""logprobs":true,"top_logprobs":20"#));
+    }
+}
+
+#[test]
+fn test_chat_deserialize_chat_request_seed() {
+    {
+        let json = r#"{"model":"model-id","messages":[{"role":"user","content":"Hello, world!"}],"temperature":0.8,"seed":42}"#;
+
+        let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.seed, Some(42));
+    }
+
+    {
+        let messages = vec![ChatCompletionRequestMessage::new_user_message(
+            ChatCompletionUserMessageContent::Text("Hello, world!".to_string()),
+            None,
+        )];
+        let request = ChatCompletionRequestBuilder::new(&messages)
+            .with_seed(42)
+            .build();
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains(r#""seed":42"#));
+    }
+}
+
 /// An object specifying the format that the model must output.
+///
+/// Setting to `{ "type": "json_object" }` enables JSON mode, which guarantees the message the model generates is valid JSON. Setting to `{ "type": "json_schema", "json_schema": {...} }` enables Structured Outputs which ensures the model will match your supplied JSON schema.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChatResponseFormat {
-    /// Must be one of `text`` or `json_object`. Defaults to `text`.
+    /// Must be one of `text`, `json_object` or `json_schema`. Defaults to `text`.
     #[serde(rename = "type")]
     pub ty: String,
+    /// The JSON schema the model output must conform to. Required if `type` is `json_schema`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub json_schema: Option<ChatResponseFormatJsonSchema>,
 }
 impl Default for ChatResponseFormat {
     fn default() -> Self {
         Self {
             ty: "text".to_string(),
+            json_schema: None,
         }
     }
 }
@@ -1548,15 +1679,77 @@ impl Default for ChatResponseFormat {
 fn test_chat_serialize_response_format() {
     let response_format = ChatResponseFormat {
         ty: "text".to_string(),
+        json_schema: None,
     };
     let json = serde_json::to_string(&response_format).unwrap();
     assert_eq!(json, r#"{"type":"text"}"#);
 
     let response_format = ChatResponseFormat {
         ty: "json_object".to_string(),
+        json_schema: None,
     };
     let json = serde_json::to_string(&response_format).unwrap();
     assert_eq!(json, r#"{"type":"json_object"}"#);
+
+    let response_format = ChatResponseFormat {
+        ty: "json_schema".to_string(),
+        json_schema: Some(ChatResponseFormatJsonSchema {
+            name: "weather".to_string(),
+            description: None,
+            schema: Some(serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "location": {"type": "string"}
+                },
+                "required": ["location"]
+            })),
+            strict: Some(true),
+        }),
+    };
+    let json = serde_json::to_string(&response_format).unwrap();
+    assert_eq!(
+        json,
+        r#"{"type":"json_schema","json_schema":{"name":"weather","schema":{"properties":{"location":{"type":"string"}},"required":["location"],"type":"object"},"strict":true}}"#
+    );
+}
+
+#[test]
+fn test_chat_deserialize_response_format() {
+    let json = r#"{"type":"json_object"}"#;
+    let response_format: ChatResponseFormat = serde_json::from_str(json).unwrap();
+    assert_eq!(response_format.ty, "json_object");
+    assert!(response_format.json_schema.is_none());
+
+    let json = r#"{"type":"json_schema","json_schema":{"name":"weather","description":"The weather of a city","schema":{"type":"object","properties":{"location":{"type":"string"}},"required":["location"]},"strict":true}}"#;
+    let response_format: ChatResponseFormat = serde_json::from_str(json).unwrap();
+    assert_eq!(response_format.ty, "json_schema");
+    let json_schema = response_format.json_schema.unwrap();
+    assert_eq!(json_schema.name, "weather");
+    assert_eq!(
+        json_schema.description,
+        Some("The weather of a city".to_string())
+    );
+    assert_eq!(
+        json_schema.schema.unwrap()["required"],
+        serde_json::json!(["location"])
+    );
+    assert_eq!(json_schema.strict, Some(true));
+}
+
+/// The JSON schema of the `json_schema` response format.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChatResponseFormatJsonSchema {
+    /// The name of the response format. Must be a-z, A-Z, 0-9, or contain underscores and dashes, with a maximum length of 64.
+    pub name: String,
+    /// A description of what the response format is for, used by the model to determine how to respond in the format.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The schema for the response format, described as a JSON Schema object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+    /// Whether to enable strict schema adherence when generating the output. The schema is always enforced by constraining the sampling of the model, so this flag is only kept for compatibility.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 /// Options for streaming response. Only set this when you set stream: `true`.
@@ -2981,6 +3174,9 @@ pub struct ChatCompletionObject {
     pub created: u64,
     /// The model used for the chat completion.
     pub model: String,
+    /// This fingerprint represents the backend configuration that the model runs with. Can be used in conjunction with the `seed` request parameter to understand when backend changes have been made that might impact determinism.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_fingerprint: Option<String>,
     /// A list of chat completion choices. Can be more than one if `n_choice` is greater than 1.
     pub choices: Vec<ChatCompletionObjectChoice>,
     /// Usage statistics for the completion request.
@@ -2994,6 +3190,7 @@ fn test_deserialize_chat_completion_object() {
   "object": "chat.completion",
   "created": 1699896916,
   "model": "gpt-3.5-turbo-0125",
+  "system_fingerprint": "fp_44709d6fcb",
   "choices": [
     {
       "index": 0,
@@ -3027,6 +3224,10 @@ fn test_deserialize_chat_completion_object() {
     assert_eq!(chatcmp_object.object, "chat.completion");
     assert_eq!(chatcmp_object.created, 1699896916);
     assert_eq!(chatcmp_object.model, "gpt-3.5-turbo-0125");
+    assert_eq!(
+        chatcmp_object.system_fingerprint,
+        Some("fp_44709d6fcb".to_string())
+    );
     assert_eq!(chatcmp_object.choices.len(), 1);
     assert_eq!(chatcmp_object.choices[0].index, 0);
     assert_eq!(
@@ -3102,8 +3303,66 @@ fn test_serialize_chat_completion_object_choice() {
 }
 
 /// Log probability information for the choice.
-#[derive(Debug, Deserialize, Serialize)]
-pub struct LogProbs;
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct LogProbs {
+    /// A list of message content tokens with log probability information.
+    pub content: Option<Vec<TokenLogProb>>,
+}
+
+#[test]
+fn test_serialize_logprobs() {
+    let logprobs = LogProbs {
+        content: Some(vec![TokenLogProb {
+            token: "Hi".to_string(),
+            logprob: -0.25,
+            bytes: Some(vec![72, 105]),
+            top_logprobs: vec![
+                TopLogProb {
+                    token: "Hi".to_string(),
+                    logprob: -0.25,
+                    bytes: Some(vec![72, 105]),
+                },
+                TopLogProb {
+                    token: "Hello".to_string(),
+                    logprob: -1.5,
+                    bytes: Some(vec![72, 101, 108, 108, 111]),
+                },
+            ],
+        }]),
+    };
+    let json = serde_json::to_string(&logprobs).unwrap();
+    assert_eq!(
+        json,
+        r#"{"content":[{"token":"Hi","logprob":-0.25,"bytes":[72,105],"top_logprobs":[{"token":"Hi","logprob":-0.25,"bytes":[72,105]},{"token":"Hello","logprob":-1.5,"bytes":[72,101,108,108,111]}]}]}"#
+    );
+
+    let value: LogProbs = serde_json::from_str(&json).unwrap();
+    assert_eq!(value, logprobs);
+}
+
+/// Log probability information of a token.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TokenLogProb {
+    /// The token.
+    pub token: String,
+    /// The log probability of this token.
+    pub logprob: f32,
+    /// A list of integers representing the UTF-8 bytes representation of the token. Useful in instances where characters are represented by multiple tokens and their byte representations must be combined to generate the correct text representation. Can be `None` if there is no bytes representation for the token.
+    pub bytes: Option<Vec<u8>>,
+    /// List of the most likely tokens and their log probability, at this token position. In rare cases, there may be fewer than the number of requested `top_logprobs` returned.
+    pub top_logprobs: Vec<TopLogProb>,
+}
+
+/// One of the most likely tokens and its log probability at a token position.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TopLogProb {
+    /// The token.
+    pub token: String,
+    /// The log probability of this token.
+    pub logprob: f32,
+    /// A list of integers representing the UTF-8 bytes representation of the token. Can be `None` if there is no bytes representation for the token.
+    pub bytes: Option<Vec<u8>>,
+}
 
 /// Represents a chat completion message generated by the model.
 #[derive(Debug, Serialize)]
@@ -1,6 +1,9 @@
 //! Define types for the `completions` endpoint.
 
-use super::common::{FinishReason, Usage};
+use super::{
+    chat::StreamOptions,
+    common::{FinishReason, Usage},
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -50,6 +53,10 @@ pub struct CompletionRequest {
     /// Defaults to 0.0.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub presence_penalty: Option<f32>,
+    /// If specified, the sampling is deterministic, such that repeated requests with the same `seed` and parameters should return the same result. The candidates of a prompt are sampled with `seed`, `seed + 1`, and so on.
+    /// Defaults to None.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
     /// Up to 4 sequences where the API will stop generating further tokens. The returned text will not contain the stop sequence.
     /// Defaults to None.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -58,6 +65,9 @@ pub struct CompletionRequest {
     /// Defaults to false.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
+    /// Options for streaming response. Only set this when you set `stream: true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
     /// The suffix that comes after a completion of inserted text.
     /// Defaults to None.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -97,8 +107,10 @@ fn test_serialize_completion_request() {
             max_tokens: Some(16),
             n: Some(1),
             presence_penalty: Some(0.0),
+            seed: Some(42),
             stop: Some(vec!["\n".to_string()]),
             stream: Some(false),
+            stream_options: None,
             suffix: Some("".to_string()),
             temperature: Some(1.0),
             top_p: Some(1.0),
@@ -106,7 +118,7 @@ fn test_serialize_completion_request() {
         };
 
         let actual = serde_json::to_string(&request).unwrap();
-        let expected = r#"{"model":"text-davinci-003","prompt":"Once upon a time","best_of":1,"echo":false,"frequency_penalty":0.0,"logit_bias":{},"logprobs":5,"max_tokens":16,"n":1,"presence_penalty":0.0,"stop":["\n"],"stream":false,"suffix":"","temperature":1.0,"top_p":1.0,"user":"user-123"}"#;
+        let expected = r#"{"model":"text-davinci-003","prompt":"Once upon a time","best_of":1,"echo":false,"frequency_penalty":0.0,"logit_bias":{},"logprobs":5,"max_tokens":16,"n":1,"presence_penalty":0.0,"seed":42,"stop":["\n"],"stream":false,"suffix":"","temperature":1.0,"top_p":1.0,"user":"user-123"}"#;
         assert_eq!(actual, expected);
     }
 
@@ -125,8 +137,10 @@ fn test_serialize_completion_request() {
             max_tokens: None,
             n: None,
             presence_penalty: None,
+            seed: None,
             stop: None,
             stream: None,
+            stream_options: None,
             suffix: None,
             temperature: None,
             top_p: None,
@@ -142,7 +156,7 @@ fn test_serialize_completion_request() {
 #[test]
 fn test_deserialize_completion_request() {
     {
-        let json = r#"{"model":"text-davinci-003","prompt":"Once upon a time","best_of":1,"echo":false,"frequency_penalty":0.0,"logit_bias":{},"logprobs":5,"max_tokens":16,"n":1,"presence_penalty":0.0,"stop":["\n"],"stream":false,"suffix":"","temperature":1.0,"top_p":1.0,"user":"user-123"}"#;
+        let json = r#"{"model":"text-davinci-003","prompt":"Once upon a time","best_of":1,"echo":false,"frequency_penalty":0.0,"logit_bias":{},"logprobs":5,"max_tokens":16,"n":1,"presence_penalty":0.0,"seed":42,"stop":["\n"],"stream":false,"suffix":"","temperature":1.0,"top_p":1.0,"user":"user-123"}"#;
         let request: CompletionRequest = serde_json::from_str(json).unwrap();
         assert_eq!(request.model, Some("text-davinci-003".to_string()));
         assert_eq!(
@@ -157,6 +171,7 @@ fn test_deserialize_completion_request() {
         assert_eq!(request.max_tokens, Some(16));
         assert_eq!(request.n, Some(1));
         assert_eq!(request.presence_penalty, Some(0.0));
+        assert_eq!(request.seed, Some(42));
         assert_eq!(request.stop, Some(vec!["\n".to_string()]));
         assert_eq!(request.stream, Some(false));
         assert_eq!(request.suffix, Some("".to_string()));
@@ -184,6 +199,7 @@ fn test_deserialize_completion_request() {
         assert_eq!(request.max_tokens, None);
         assert_eq!(request.n, None);
         assert_eq!(request.presence_penalty, None);
+        assert_eq!(request.seed, None);
         assert_eq!(request.stop, None);
         assert_eq!(request.stream, None);
         assert_eq!(request.suffix, None);
@@ -233,6 +249,62 @@ pub struct CompletionChoice {
     pub text: String,
 }
 
+/// Represents a streamed chunk of a completion response.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompletionChunk {
+    /// A unique identifier for the completion. Each chunk has the same id.
+    pub id: String,
+    /// The list of completion choices in the chunk.
+    pub choices: Vec<CompletionChunkChoice>,
+    /// The Unix timestamp (in seconds) of when the completion was created.
+    pub created: u64,
+    /// The model used for completion.
+    pub model: String,
+    /// The object type, which is always "text_completion".
+    pub object: String,
+    /// Usage statistics for the completion request.
+    ///
+    /// An optional field that will only be present when you set stream_options: {"include_usage": true} in your request. When present, it contains a null value except for the last chunk which contains the token usage statistics for the entire request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+#[test]
+fn test_serialize_completion_chunk() {
+    let chunk = CompletionChunk {
+        id: "cmpl-1d0ff773-e8ab-4254-a222-96e97e3c295a".to_string(),
+        choices: vec![CompletionChunkChoice {
+            finish_reason: None,
+            index: 0,
+            logprobs: None,
+            text: " there".to_string(),
+        }],
+        created: 1722433423,
+        model: "default".to_string(),
+        object: "text_completion".to_string(),
+        usage: None,
+    };
+
+    let json = serde_json::to_string(&chunk).unwrap();
+    assert_eq!(
+        json,
+        r#"{"id":"cmpl-1d0ff773-e8ab-4254-a222-96e97e3c295a","choices":[{"finish_reason":null,"index":0,"logprobs":null,"text":" there"}],"created":1722433423,"model":"default","object":"text_completion"}"#
+    );
+}
+
+/// Represents a completion choice in a streamed chunk of a completion response.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompletionChunkChoice {
+    /// The reason the model stopped generating tokens. `None` until the last chunk of the choice.
+    pub finish_reason: Option<FinishReason>,
+    /// The index of the choice in the list of choices.
+    pub index: u32,
+    /// The log probabilities of the tokens in the chunk.
+    pub logprobs: Option<LogprobResult>,
+    /// The text generated in the chunk.
+    pub text: String,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LogprobResult {
     pub tokens: Vec<String>,
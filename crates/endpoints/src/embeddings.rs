@@ -21,7 +21,7 @@ pub struct EmbeddingRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
 
-    /// The URL of the VectorDB server.
+    /// The URL of the VectorDB server, e.g., `http://localhost:6333` for Qdrant, or `local://` for the vector store embedded in LlamaEdge.
     #[cfg(feature = "rag")]
     #[serde(rename = "vdb_server_url", skip_serializing_if = "Option::is_none")]
     pub vdb_server_url: Option<String>,
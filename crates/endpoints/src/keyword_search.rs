@@ -13,6 +13,15 @@ pub struct DocumentInput {
     pub title: Option<String>,
 }
 
+// Request of building the built-in BM25 index of an uploaded file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bm25IndexRequest {
+    /// The id of the uploaded file, which is also the name of the index.
+    pub id: String,
+    /// The max tokens each chunk contains.
+    pub chunk_capacity: usize,
+}
+
 // Document processing result
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocumentResult {
@@ -20,4 +20,52 @@ pub struct Model {
     pub object: String,
     /// The organization that owns the model.
     pub owned_by: String,
+    /// The state of the model, which is reported by the servers loading models on demand.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<ModelState>,
+}
+
+/// The state of a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelState {
+    /// The model is loaded and ready to serve requests.
+    Loaded,
+    /// The model is not loaded, and is loaded on the first request to it.
+    Unloaded,
+    /// The model is being loaded.
+    Loading,
+}
+
+/// Represents the status of a model deletion operation.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeleteModelStatus {
+    /// The model identifier, which can be referenced in the API endpoints.
+    pub id: String,
+    /// The object type, which is always "model".
+    pub object: String,
+    /// The status of the deletion operation.
+    pub deleted: bool,
+}
+
+#[test]
+fn test_serialize_model() {
+    let model = Model {
+        id: "llama-3-8b".to_string(),
+        created: 1721232000,
+        object: "model".to_string(),
+        owned_by: "Not specified".to_string(),
+        state: Some(ModelState::Unloaded),
+    };
+    let json = serde_json::to_string(&model).unwrap();
+    assert_eq!(
+        json,
+        r#"{"id":"llama-3-8b","created":1721232000,"object":"model","owned_by":"Not specified","state":"unloaded"}"#
+    );
+
+    let model: Model = serde_json::from_str(
+        r#"{"id":"llama-3-8b","created":1721232000,"object":"model","owned_by":"Not specified"}"#,
+    )
+    .unwrap();
+    assert!(model.state.is_none());
 }
@@ -5,13 +5,17 @@ use crate::{
     metadata::ggml::GgmlMetadata,
     running_mode,
     utils::{
-        gen_chat_id, get_output_buffer, get_output_buffer_single, get_token_info_by_graph,
-        get_token_info_by_graph_name, set_tensor_data_u8,
+        chat_prompt_template, gen_chat_id, gen_system_fingerprint, gen_tool_call_id,
+        get_logprobs_by_graph, get_output_buffer, get_output_buffer_single,
+        get_token_info_by_graph, lease_chat_slot, parse_logit_bias, set_tensor_data_u8,
+        truncate_at_stop_sequence, truncate_logprobs_at_stop_sequence, with_chat_slot,
+        StopSequences,
     },
-    Graph, RunningMode, CACHED_UTF8_ENCODINGS, CHAT_GRAPHS, OUTPUT_TENSOR,
+    Graph, RunningMode, SlotLease, CHAT_GRAPHS, OUTPUT_TENSOR,
 };
 use chat_prompts::{
     chat::{BuildChatPrompt, ChatPrompt},
+    tool_call::{ToolCallDelta, ToolCallFormat, ToolCallStream},
     PromptTemplateType,
 };
 use either::{Either, Left, Right};
@@ -20,24 +24,25 @@ use endpoints::{
         ChatCompletionChunk, ChatCompletionChunkChoice, ChatCompletionChunkChoiceDelta,
         ChatCompletionObject, ChatCompletionObjectChoice, ChatCompletionObjectMessage,
         ChatCompletionRequest, ChatCompletionRequestMessage, ChatCompletionRole,
-        ChatCompletionUserMessageContent, ContentPart, Function, ToolCall, ToolCallForChunk,
-        ToolChoice,
+        ChatCompletionUserMessageContent, ChatResponseFormat, ContentPart, Function, LogProbs,
+        TokenLogProb, ToolCall, ToolCallForChunk, ToolChoice,
     },
     common::{FinishReason, Usage},
 };
 use error::{BackendError, LlamaCoreError};
 use futures::StreamExt;
 use std::{
-    collections::VecDeque,
+    collections::HashSet,
     fs::{self, File},
     path::Path,
     pin::Pin,
-    sync::Mutex,
     task::{Context, Poll},
     time::SystemTime,
 };
 
 /// Processes a chat-completion request and returns either a stream of ChatCompletionChunk instances or a ChatCompletionObject instance.
+///
+/// If `n` of the request is greater than 1, the choices of the stream are generated side by side, each on its own execution context of the model, and their `index`-tagged chunks are interleaved. Each choice ends with a chunk carrying its `finish_reason`.
 pub async fn chat(
     chat_request: &mut ChatCompletionRequest,
 ) -> Result<
@@ -51,26 +56,36 @@ pub async fn chat(
         debug!(target: "stdout", "stream mode: {:?}", chat_request.stream);
     }
 
-    let model_name = chat_request.model.clone();
+    // check if the tool messages respond to the tool calls in the conversation
+    validate_tool_call_ids(&chat_request.messages)?;
+
+    // lease a free execution slot of the model, which is released when the request is done
+    let slot = lease_chat_slot(chat_request.model.as_deref())?;
+
+    // the rest of the request runs on the model of the slot
+    chat_request.model = Some(slot.model_name().to_string());
 
-    let result = match chat_request.stream {
-        Some(true) => match chat_stream(chat_request).await {
+    match chat_request.stream {
+        // the stream holds the slot until it is dropped
+        Some(true) => match chat_stream(chat_request, slot).await {
             Ok(stream) => Ok(Left(stream)),
             Err(e) => Err(e),
         },
-        Some(false) | None => match chat_once(chat_request).await {
-            Ok(chat_completion_object) => Ok(Right(chat_completion_object)),
-            Err(e) => Err(e),
-        },
-    };
+        Some(false) | None => {
+            let result = match chat_once(chat_request, &slot).await {
+                Ok(chat_completion_object) => Ok(Right(chat_completion_object)),
+                Err(e) => Err(e),
+            };
 
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Reset the model metadata");
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Reset the model metadata");
 
-    // reset the model metadata
-    reset_model_metadata(model_name.as_ref())?;
+            // reset the model metadata
+            reset_model_metadata(&slot)?;
 
-    result
+            result
+        }
+    }
 }
 
 /// Processes a chat-completion request and returns ChatCompletionChunk instances in stream.
@@ -78,7 +93,9 @@ pub async fn chat(
 pub async fn chat_completions_stream(
     chat_request: &mut ChatCompletionRequest,
 ) -> Result<impl futures::TryStream<Ok = String, Error = LlamaCoreError>, LlamaCoreError> {
-    chat_stream(chat_request).await
+    let slot = lease_chat_slot(chat_request.model.as_deref())?;
+
+    chat_stream(chat_request, slot).await
 }
 
 /// Processes a chat-completion request and returns a ChatCompletionObject instance.
@@ -86,11 +103,19 @@ pub async fn chat_completions_stream(
 pub async fn chat_completions(
     chat_request: &mut ChatCompletionRequest,
 ) -> Result<ChatCompletionObject, LlamaCoreError> {
-    chat_once(chat_request).await
+    let slot = lease_chat_slot(chat_request.model.as_deref())?;
+
+    let result = chat_once(chat_request, &slot).await;
+
+    // reset the model metadata
+    reset_model_metadata(&slot)?;
+
+    result
 }
 
 async fn chat_stream(
     chat_request: &mut ChatCompletionRequest,
+    slot: SlotLease,
 ) -> Result<impl futures::TryStream<Ok = String, Error = LlamaCoreError>, LlamaCoreError> {
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Process chat completion request in the stream mode");
@@ -130,14 +155,14 @@ async fn chat_stream(
     info!(target: "stdout", "Check model metadata");
 
     // update metadata
-    let mut metadata = check_model_metadata(chat_request).await?;
+    let mut metadata = check_model_metadata(chat_request, &slot).await?;
 
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Build the chat prompt");
 
     // build prompt
     let (prompt, avaible_completion_tokens, tool_use) =
-        build_prompt(model_name.as_ref(), chat_request)?;
+        build_prompt(model_name.as_ref(), &slot, chat_request)?;
 
     #[cfg(feature = "logging")]
     {
@@ -150,67 +175,83 @@ async fn chat_stream(
     info!(target: "stdout", "Update the n_predict");
 
     // update metadata n_predict
-    update_n_predict(chat_request, &mut metadata, avaible_completion_tokens).await?;
+    update_n_predict(
+        chat_request,
+        &slot,
+        &mut metadata,
+        avaible_completion_tokens,
+    )
+    .await?;
 
     // set prompt
-    set_prompt(chat_request.model.as_ref(), &prompt)?;
+    set_prompt(&slot, &prompt)?;
 
-    let stream = match tool_use {
-        false => ChatStream::new(model_name, id, include_usage, None),
+    // detect the tool calls in the generated tokens
+    let tool_call_format = match tool_use {
         true => {
-            let chat_graphs = match CHAT_GRAPHS.get() {
-                Some(chat_graphs) => chat_graphs,
+            let prompt_template = chat_prompt_template(model_name.as_deref())?;
+
+            match prompt_template.tool_call_parser() {
+                Some(parser) => Some(parser.tool_call_format()),
                 None => {
-                    let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
+                    let err_msg = format!("Unsupported prompt template: {}. The tool use is only supported for 'mistral-tool', 'chatml-tool', 'groq-llama3-tool', 'llama-3-tool', 'internlm-2-tool', 'nemotron-tool', 'functionary-31', and 'functionary-32' prompt templates.", prompt_template);
 
                     #[cfg(feature = "logging")]
                     error!(target: "stdout", "{}", &err_msg);
 
-                    return Err(LlamaCoreError::Operation(err_msg.into()));
+                    return Err(LlamaCoreError::Operation(err_msg));
                 }
-            };
+            }
+        }
+        false => None,
+    };
 
-            let mut chat_graphs = chat_graphs.lock().map_err(|e| {
-                let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e);
+    // the number of choices to generate
+    let n_choice = chat_request.n_choice.unwrap_or(1).max(1);
 
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "n_choice: {}", n_choice);
 
-                LlamaCoreError::Operation(err_msg)
-            })?;
+    // the other choices run on the execution contexts forked from the slot. The choices are sampled with `seed`, `seed + 1`, ..., so that they differ from each other but are still reproducible.
+    let mut forks = Vec::with_capacity(n_choice as usize - 1);
+    for index in 1..n_choice {
+        if let Some(seed) = chat_request.seed {
+            metadata.seed = seed.wrapping_add(index);
+        }
 
-            match model_name {
-                Some(model_name) => match chat_graphs.contains_key(&model_name) {
-                    true => {
-                        let graph = chat_graphs.get_mut(&model_name).unwrap();
-                        chat_stream_by_graph(graph, id, include_usage)?
-                    }
-                    false => match chat_graphs.iter_mut().next() {
-                        Some((_, graph)) => chat_stream_by_graph(graph, id, include_usage)?,
-                        None => {
-                            let err_msg = "There is no model available in the chat graphs.";
+        let config = serde_json::to_string(&metadata).map_err(|e| {
+            let err_msg = format!("Fail to serialize metadata to a JSON string. {}", e);
 
-                            #[cfg(feature = "logging")]
-                            error!(target: "stdout", "{}", &err_msg);
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
 
-                            return Err(LlamaCoreError::Operation(err_msg.into()));
-                        }
-                    },
-                },
-                None => match chat_graphs.iter_mut().next() {
-                    Some((_, graph)) => chat_stream_by_graph(graph, id, include_usage)?,
-                    None => {
-                        let err_msg = "There is no model available in the chat graphs.";
+            LlamaCoreError::Operation(err_msg)
+        })?;
 
-                        #[cfg(feature = "logging")]
-                        error!(target: "stdout", "{}", &err_msg);
+        let mut graph = with_chat_slot(&slot, |graph| graph.fork_context())?;
+        set_tensor_data_u8(&mut graph, 1, config.as_bytes())?;
+        set_tensor_data_u8(&mut graph, 0, prompt.as_bytes())?;
+        forks.push(graph);
+    }
 
-                        return Err(LlamaCoreError::Operation(err_msg.into()));
-                    }
-                },
-            }
-        }
-    };
+    let stream = ChatStream::new(
+        slot,
+        forks,
+        id,
+        include_usage,
+        StreamChoices::new(
+            n_choice,
+            chat_request.stop.clone(),
+            tool_call_format,
+            requested_top_logprobs(chat_request),
+        ),
+    );
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Reset the model metadata");
+
+    // reset the model metadata
+    reset_model_metadata(&stream.slot)?;
 
     #[cfg(feature = "logging")]
     info!(target: "stdout", "End of the chat completion stream.");
@@ -218,15 +259,143 @@ async fn chat_stream(
     Ok(stream)
 }
 
-fn chat_stream_by_graph(
+async fn chat_once(
+    chat_request: &mut ChatCompletionRequest,
+    slot: &SlotLease,
+) -> Result<ChatCompletionObject, LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Processing chat completion request in non-stream mode");
+
+    let running_mode = running_mode()?;
+    if running_mode == RunningMode::Embeddings {
+        let err_msg = format!(
+            "The chat completion is not supported in the {} mode.",
+            running_mode
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::Operation(err_msg));
+    }
+
+    let model_name = chat_request.model.clone();
+    let id = match &chat_request.user {
+        Some(id) => id.clone(),
+        None => gen_chat_id(),
+    };
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "user: {}", &id);
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Check model metadata");
+
+    // update metadata
+    let mut metadata = check_model_metadata(chat_request, slot).await?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Build the chat prompt");
+
+    // build prompt
+    let (prompt, avaible_completion_tokens, tool_use) =
+        build_prompt(model_name.as_ref(), slot, chat_request)?;
+
+    #[cfg(feature = "logging")]
+    {
+        info!(target: "stdout", "prompt:\n{}", &prompt);
+        info!(target: "stdout", "available_completion_tokens: {}", avaible_completion_tokens);
+        info!(target: "stdout", "tool_use: {}", tool_use);
+    }
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Update n_predict");
+
+    // update metadata n_predict
+    update_n_predict(chat_request, slot, &mut metadata, avaible_completion_tokens).await?;
+
+    // the number of choices to generate
+    let n_choice = chat_request.n_choice.unwrap_or(1).max(1);
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "n_choice: {}", n_choice);
+
+    let mut res: Option<ChatCompletionObject> = None;
+    for index in 0..n_choice {
+        // the choices are sampled with `seed`, `seed + 1`, ..., so that they differ from each other but are still reproducible
+        if let (Some(seed), true) = (chat_request.seed, index > 0) {
+            metadata.seed = seed.wrapping_add(index);
+            update_model_metadata(slot, &metadata)?;
+        }
+
+        // feed the prompt to the model
+        set_prompt(slot, &prompt)?;
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Compute chat completion. choice: {}/{}", index + 1, n_choice);
+
+        // compute
+        let object = compute(
+            slot,
+            id.clone(),
+            tool_use,
+            chat_request.stop.as_deref().unwrap_or_default(),
+            requested_top_logprobs(chat_request),
+        )?;
+
+        res = match res {
+            Some(mut res) => {
+                // each choice is an independent sample of the same prompt, so only the completion tokens are accumulated
+                res.usage.completion_tokens += object.usage.completion_tokens;
+                res.usage.total_tokens += object.usage.completion_tokens;
+                res.choices
+                    .extend(object.choices.into_iter().map(|mut choice| {
+                        choice.index = index as u32;
+                        choice
+                    }));
+
+                Some(res)
+            }
+            None => Some(object),
+        };
+    }
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "End of the chat completion");
+
+    res.ok_or_else(|| {
+        let err_msg = "No chat completion choice is generated.";
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg.into())
+    })
+}
+
+fn compute(
+    slot: &SlotLease,
+    id: impl Into<String>,
+    tool_use: bool,
+    stop: &[String],
+    top_logprobs: Option<u8>,
+) -> Result<ChatCompletionObject, LlamaCoreError> {
+    with_chat_slot(slot, |graph| {
+        compute_by_graph(graph, id, tool_use, stop, top_logprobs)
+    })
+}
+
+fn compute_by_graph(
     graph: &mut Graph<GgmlMetadata>,
     id: impl Into<String>,
-    include_usage: bool,
-) -> Result<ChatStream, LlamaCoreError> {
+    tool_use: bool,
+    stop: &[String],
+    top_logprobs: Option<u8>,
+) -> Result<ChatCompletionObject, LlamaCoreError> {
     #[cfg(feature = "logging")]
-    info!(target: "stdout", "Handle chat request with available tools by the model named {}.", graph.name());
+    info!(target: "stdout", "Compute chat completion by the model named {}.", graph.name());
 
-    let id = id.into();
+    let system_fingerprint = gen_system_fingerprint(graph)?;
 
     match graph.compute() {
         Ok(_) => {
@@ -245,27 +414,41 @@ fn chat_stream_by_graph(
             })?;
 
             #[cfg(feature = "logging")]
-            info!(target: "stdout", "raw generation:\n{}", output);
+            info!(target: "stdout", "raw generation: {}", output);
 
             // post-process
-            let message = post_process(output, &graph.metadata.prompt_template).map_err(|e| {
-                LlamaCoreError::Operation(format!("Failed to post-process the output. {}", e))
-            })?;
+            let mut message =
+                post_process(output, &graph.metadata.prompt_template).map_err(|e| {
+                    LlamaCoreError::Operation(format!("Failed to post-process the output. {}", e))
+                })?;
 
             #[cfg(feature = "logging")]
             info!(target: "stdout", "post-processed generation:\n{}", &message);
 
+            // truncate the generation at the first stop sequence
+            if truncate_at_stop_sequence(&mut message, stop) {
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "Hit a stop sequence. The generation is truncated to:\n{}", &message);
+            }
+
             // retrieve the number of prompt and completion tokens
             let token_info = get_token_info_by_graph(graph)?;
 
             #[cfg(feature = "logging")]
             info!(target: "stdout", "prompt tokens: {}, completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
 
-            let usage = Some(Usage {
-                prompt_tokens: token_info.prompt_tokens,
-                completion_tokens: token_info.completion_tokens,
-                total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
-            });
+            // retrieve the log probabilities of the generated tokens
+            let logprobs = match top_logprobs {
+                Some(top_logprobs) => {
+                    let mut content = get_logprobs_by_graph(graph, top_logprobs, false)?;
+                    truncate_logprobs_at_stop_sequence(&mut content, stop);
+
+                    Some(LogProbs {
+                        content: Some(content),
+                    })
+                }
+                None => None,
+            };
 
             let created = SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -278,107 +461,90 @@ fn chat_stream_by_graph(
                     LlamaCoreError::Operation(err_msg)
                 })?;
 
-            if graph.metadata.prompt_template != PromptTemplateType::MistralTool
-                && graph.metadata.prompt_template != PromptTemplateType::ChatMLTool
-                && graph.metadata.prompt_template != PromptTemplateType::GroqLlama3Tool
-                && graph.metadata.prompt_template != PromptTemplateType::Llama3Tool
-                && graph.metadata.prompt_template != PromptTemplateType::InternLM2Tool
-            {
-                let err_msg = "The tool use is only supported for 'mistral-chat' and 'chatml' prompt templates.";
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                return Err(LlamaCoreError::Operation(err_msg.into()));
-            }
-
-            let parsed_result = parse_tool_calls(&message, graph.metadata.prompt_template)?;
-
-            let content = match parsed_result.content {
-                Some(content) => Some(content),
-                None => Some(parsed_result.raw),
-            };
-
-            let tool_calls: Vec<ToolCallForChunk> = parsed_result
-                .tool_calls
-                .into_iter()
-                .enumerate()
-                .map(|(index, tool_call)| ToolCallForChunk {
-                    index,
-                    id: tool_call.id,
-                    ty: tool_call.ty,
-                    function: tool_call.function,
-                })
-                .collect();
-
-            // tool_calls chunk
-            let tool_call_chunk = {
-                let chat_completion_chunk = ChatCompletionChunk {
-                    id: id.clone(),
-                    object: "chat.completion.chunk".to_string(),
-                    created: created.as_secs(),
-                    model: graph.name().to_owned(),
-                    system_fingerprint: "fp_44709d6fcb".to_string(),
-                    choices: vec![ChatCompletionChunkChoice {
-                        index: 0,
-                        delta: ChatCompletionChunkChoiceDelta {
-                            role: ChatCompletionRole::Assistant,
-                            content,
-                            tool_calls,
-                        },
-                        logprobs: None,
-                        finish_reason: None,
-                    }],
-                    usage: None,
-                };
-                let chunk_str = serde_json::to_string(&chat_completion_chunk).map_err(|e| {
-                    let err_msg =
-                        format!("Failed to serialize chat completion chunk. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    LlamaCoreError::Operation(err_msg)
-                })?;
-
-                format!("data: {}\n\n", chunk_str)
-            };
-
-            // uage chunk
-            let usage_chunk = {
-                let chat_completion_chunk = ChatCompletionChunk {
-                    id: id.clone(),
-                    object: "chat.completion.chunk".to_string(),
-                    created: created.as_secs(),
-                    model: graph.name().to_owned(),
-                    system_fingerprint: "fp_44709d6fcb".to_string(),
-                    choices: vec![],
-                    usage,
-                };
-                let chunk_str = serde_json::to_string(&chat_completion_chunk).map_err(|e| {
-                    let err_msg =
-                        format!("Failed to serialize chat completion chunk. Reason: {}", e);
+            match tool_use {
+                true => {
+                    if graph.metadata.prompt_template != PromptTemplateType::MistralTool
+                        && graph.metadata.prompt_template != PromptTemplateType::ChatMLTool
+                        && graph.metadata.prompt_template != PromptTemplateType::GroqLlama3Tool
+                        && graph.metadata.prompt_template != PromptTemplateType::Llama3Tool
+                        && graph.metadata.prompt_template != PromptTemplateType::InternLM2Tool
+                        && graph.metadata.prompt_template != PromptTemplateType::NemotronTool
+                        && graph.metadata.prompt_template != PromptTemplateType::FunctionaryV32
+                        && graph.metadata.prompt_template != PromptTemplateType::FunctionaryV31
+                    {
+                        let err_msg = format!("Unsupported prompt template: {}. The tool use is only supported for 'mistral-tool', 'chatml-tool', 'groq-llama3-tool', 'llama-3-tool', 'internlm-2-tool', 'nemotron-tool', 'functionary-31', and 'functionary-32' prompt templates.", graph.metadata.prompt_template);
 
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
+                        #[cfg(feature = "logging")]
+                        error!(target: "stdout", "{}", &err_msg);
 
-                    LlamaCoreError::Operation(err_msg)
-                })?;
+                        return Err(LlamaCoreError::Operation(err_msg));
+                    }
 
-                format!("data: {}\n\n", chunk_str)
-            };
+                    let parsed_result = parse_tool_calls(&message, graph.metadata.prompt_template)?;
 
-            // ending chunk
-            let ending_chunk = "data: [DONE]\n\n".to_string();
+                    let finish_reason = if parsed_result.tool_calls.is_empty() {
+                        FinishReason::stop
+                    } else {
+                        FinishReason::tool_calls
+                    };
 
-            let chunks = vec![tool_call_chunk, usage_chunk, ending_chunk];
+                    let content = match parsed_result.content {
+                        Some(content) => Some(content),
+                        None => Some(parsed_result.raw),
+                    };
 
-            Ok(ChatStream::new(
-                Some(graph.name().to_owned()),
-                id,
-                include_usage,
-                Some(chunks),
-            ))
+                    // create ChatCompletionResponse
+                    Ok(ChatCompletionObject {
+                        id: id.into(),
+                        object: String::from("chat.completion"),
+                        created: created.as_secs(),
+                        model: graph.name().to_owned(),
+                        system_fingerprint: Some(system_fingerprint),
+                        choices: vec![ChatCompletionObjectChoice {
+                            index: 0,
+                            message: ChatCompletionObjectMessage {
+                                role: ChatCompletionRole::Assistant,
+                                content,
+                                tool_calls: parsed_result.tool_calls,
+                                function_call: None,
+                            },
+                            finish_reason,
+                            logprobs,
+                        }],
+                        usage: Usage {
+                            prompt_tokens: token_info.prompt_tokens,
+                            completion_tokens: token_info.completion_tokens,
+                            total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
+                        },
+                    })
+                }
+                false => {
+                    // create ChatCompletionResponse
+                    Ok(ChatCompletionObject {
+                        id: id.into(),
+                        object: String::from("chat.completion"),
+                        created: created.as_secs(),
+                        model: graph.name().to_owned(),
+                        system_fingerprint: Some(system_fingerprint),
+                        choices: vec![ChatCompletionObjectChoice {
+                            index: 0,
+                            message: ChatCompletionObjectMessage {
+                                role: ChatCompletionRole::Assistant,
+                                content: Some(message),
+                                tool_calls: vec![],
+                                function_call: None,
+                            },
+                            finish_reason: FinishReason::stop,
+                            logprobs,
+                        }],
+                        usage: Usage {
+                            prompt_tokens: token_info.prompt_tokens,
+                            completion_tokens: token_info.completion_tokens,
+                            total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
+                        },
+                    })
+                }
+            }
         }
         Err(wasmedge_wasi_nn::Error::BackendError(wasmedge_wasi_nn::BackendError::ContextFull)) => {
             // Retrieve the output.
@@ -396,14 +562,21 @@ fn chat_stream_by_graph(
             })?;
 
             // post-process
-            let message = post_process(output, &graph.metadata.prompt_template).map_err(|e| {
-                let err_msg = format!("Failed to post-process the output. {}", e);
+            let mut message =
+                post_process(output, &graph.metadata.prompt_template).map_err(|e| {
+                    let err_msg = format!("Failed to post-process the output. {}", e);
 
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
 
-                LlamaCoreError::Operation(err_msg)
-            })?;
+                    LlamaCoreError::Operation(err_msg)
+                })?;
+
+            // truncate the generation at the first stop sequence
+            let finish_reason = match truncate_at_stop_sequence(&mut message, stop) {
+                true => FinishReason::stop,
+                false => FinishReason::length,
+            };
 
             // retrieve the number of prompt and completion tokens
             let token_info = get_token_info_by_graph(graph)?;
@@ -411,11 +584,18 @@ fn chat_stream_by_graph(
             #[cfg(feature = "logging")]
             info!(target: "stdout", "prompt tokens: {}, completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
 
-            let usage = Some(Usage {
-                prompt_tokens: token_info.prompt_tokens,
-                completion_tokens: token_info.completion_tokens,
-                total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
-            });
+            // retrieve the log probabilities of the generated tokens
+            let logprobs = match top_logprobs {
+                Some(top_logprobs) => {
+                    let mut content = get_logprobs_by_graph(graph, top_logprobs, false)?;
+                    truncate_logprobs_at_stop_sequence(&mut content, stop);
+
+                    Some(LogProbs {
+                        content: Some(content),
+                    })
+                }
+                None => None,
+            };
 
             let created = SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -428,78 +608,30 @@ fn chat_stream_by_graph(
                     LlamaCoreError::Operation(err_msg)
                 })?;
 
-            // context full chunk
-            let context_full_chunk = {
-                let chat_completion_chunk = ChatCompletionChunk {
-                    id: id.clone(),
-                    object: "chat.completion.chunk".to_string(),
-                    created: created.as_secs(),
-                    model: graph.name().to_owned(),
-                    system_fingerprint: "fp_44709d6fcb".to_string(),
-                    choices: vec![ChatCompletionChunkChoice {
-                        index: 0,
-                        delta: ChatCompletionChunkChoiceDelta {
-                            role: ChatCompletionRole::Assistant,
-                            content: Some(message),
-                            tool_calls: vec![],
-                        },
-                        logprobs: None,
-                        finish_reason: Some(FinishReason::length),
-                    }],
-                    usage: None,
-                };
-
-                // serialize chat completion chunk
-                let chunk_str = serde_json::to_string(&chat_completion_chunk).map_err(|e| {
-                    let err_msg =
-                        format!("Failed to serialize chat completion chunk. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    LlamaCoreError::Operation(err_msg)
-                })?;
-
-                format!("data: {}\n\n", chunk_str)
-            };
-
-            // usage chunk
-            let usage_chunk = {
-                let chat_completion_chunk = ChatCompletionChunk {
-                    id: id.clone(),
-                    object: "chat.completion.chunk".to_string(),
-                    created: created.as_secs(),
-                    model: graph.name().to_owned(),
-                    system_fingerprint: "fp_44709d6fcb".to_string(),
-                    choices: vec![],
-                    usage,
-                };
-
-                // serialize chat completion chunk
-                let chunk_str = serde_json::to_string(&chat_completion_chunk).map_err(|e| {
-                    let err_msg =
-                        format!("Failed to serialize chat completion chunk. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    LlamaCoreError::Operation(err_msg)
-                })?;
-
-                format!("data: {}\n\n", chunk_str)
-            };
-
-            // ending chunk
-            let ending_chunk = "data: [DONE]\n\n".to_string();
-
-            let chunks = vec![context_full_chunk, usage_chunk, ending_chunk];
-
-            Ok(ChatStream::new(
-                Some(graph.name().to_owned()),
-                id,
-                include_usage,
-                Some(chunks),
-            ))
+            // create ChatCompletionResponse
+            Ok(ChatCompletionObject {
+                id: id.into(),
+                object: String::from("chat.completion"),
+                created: created.as_secs(),
+                model: graph.name().to_owned(),
+                system_fingerprint: Some(system_fingerprint),
+                choices: vec![ChatCompletionObjectChoice {
+                    index: 0,
+                    message: ChatCompletionObjectMessage {
+                        role: ChatCompletionRole::Assistant,
+                        content: Some(message),
+                        tool_calls: vec![],
+                        function_call: None,
+                    },
+                    finish_reason,
+                    logprobs,
+                }],
+                usage: Usage {
+                    prompt_tokens: token_info.prompt_tokens,
+                    completion_tokens: token_info.completion_tokens,
+                    total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
+                },
+            })
         }
         Err(wasmedge_wasi_nn::Error::BackendError(
             wasmedge_wasi_nn::BackendError::PromptTooLong,
@@ -522,89 +654,9 @@ fn chat_stream_by_graph(
             })?;
 
             // post-process
-            let message = post_process(output, &graph.metadata.prompt_template).map_err(|e| {
-                let err_msg = format!("Failed to post-process the output. {}", e);
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                LlamaCoreError::Operation(err_msg)
-            })?;
-
-            // retrieve the number of prompt and completion token
-            let token_info = get_token_info_by_graph(graph)?;
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "prompt tokens: {}, completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
-
-            let usage = Some(Usage {
-                prompt_tokens: token_info.prompt_tokens,
-                completion_tokens: token_info.completion_tokens,
-                total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
-            });
-
-            let created = SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|e| {
-                    let err_msg = format!("Failed to get the current time. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    LlamaCoreError::Operation(err_msg)
-                })?;
-
-            // prompt too long chunk
-            let prompt_too_long_chunk = {
-                let chat_completion_chunk = ChatCompletionChunk {
-                    id: id.clone(),
-                    object: "chat.completion.chunk".to_string(),
-                    created: created.as_secs(),
-                    model: graph.name().to_owned(),
-                    system_fingerprint: "fp_44709d6fcb".to_string(),
-                    choices: vec![ChatCompletionChunkChoice {
-                        index: 0,
-                        delta: ChatCompletionChunkChoiceDelta {
-                            role: ChatCompletionRole::Assistant,
-                            content: Some(message),
-                            tool_calls: vec![],
-                        },
-                        logprobs: None,
-                        finish_reason: Some(FinishReason::length),
-                    }],
-                    usage: None,
-                };
-
-                // serialize chat completion chunk
-                let chunk_str = serde_json::to_string(&chat_completion_chunk).map_err(|e| {
-                    let err_msg =
-                        format!("Failed to serialize chat completion chunk. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    LlamaCoreError::Operation(err_msg)
-                })?;
-
-                format!("data: {}\n\n", chunk_str)
-            };
-
-            // usage chunk
-            let usage_chunk = {
-                let chat_completion_chunk = ChatCompletionChunk {
-                    id: id.clone(),
-                    object: "chat.completion.chunk".to_string(),
-                    created: created.as_secs(),
-                    model: graph.name().to_owned(),
-                    system_fingerprint: "fp_44709d6fcb".to_string(),
-                    choices: vec![],
-                    usage,
-                };
-
-                // serialize chat completion chunk
-                let chunk_str = serde_json::to_string(&chat_completion_chunk).map_err(|e| {
-                    let err_msg =
-                        format!("Failed to serialize chat completion chunk. Reason: {}", e);
+            let mut message =
+                post_process(output, &graph.metadata.prompt_template).map_err(|e| {
+                    let err_msg = format!("Failed to post-process the output. {}", e);
 
                     #[cfg(feature = "logging")]
                     error!(target: "stdout", "{}", &err_msg);
@@ -612,395 +664,30 @@ fn chat_stream_by_graph(
                     LlamaCoreError::Operation(err_msg)
                 })?;
 
-                format!("data: {}\n\n", chunk_str)
+            // truncate the generation at the first stop sequence
+            let finish_reason = match truncate_at_stop_sequence(&mut message, stop) {
+                true => FinishReason::stop,
+                false => FinishReason::length,
             };
 
-            // ending chunk
-            let ending_chunk = "data: [DONE]\n\n".to_string();
-
-            let chunks = vec![prompt_too_long_chunk, usage_chunk, ending_chunk];
-
-            Ok(ChatStream::new(
-                Some(graph.name().to_owned()),
-                id,
-                include_usage,
-                Some(chunks),
-            ))
-        }
-        Err(e) => {
-            let err_msg = format!("Failed to compute the chat completion. Reason: {}", e);
-
-            #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
-
-            Err(LlamaCoreError::Backend(BackendError::Compute(err_msg)))
-        }
-    }
-}
-
-async fn chat_once(
-    chat_request: &mut ChatCompletionRequest,
-) -> Result<ChatCompletionObject, LlamaCoreError> {
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Processing chat completion request in non-stream mode");
-
-    let running_mode = running_mode()?;
-    if running_mode == RunningMode::Embeddings {
-        let err_msg = format!(
-            "The chat completion is not supported in the {} mode.",
-            running_mode
-        );
-
-        #[cfg(feature = "logging")]
-        error!(target: "stdout", "{}", &err_msg);
-
-        return Err(LlamaCoreError::Operation(err_msg));
-    }
-
-    let model_name = chat_request.model.clone();
-    let id = match &chat_request.user {
-        Some(id) => id.clone(),
-        None => gen_chat_id(),
-    };
-
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "user: {}", &id);
-
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Check model metadata");
-
-    // update metadata
-    let mut metadata = check_model_metadata(chat_request).await?;
-
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Build the chat prompt");
-
-    // build prompt
-    let (prompt, avaible_completion_tokens, tool_use) =
-        build_prompt(model_name.as_ref(), chat_request)?;
-
-    #[cfg(feature = "logging")]
-    {
-        info!(target: "stdout", "prompt:\n{}", &prompt);
-        info!(target: "stdout", "available_completion_tokens: {}", avaible_completion_tokens);
-        info!(target: "stdout", "tool_use: {}", tool_use);
-    }
-
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Update n_predict");
-
-    // update metadata n_predict
-    update_n_predict(chat_request, &mut metadata, avaible_completion_tokens).await?;
-
-    // feed the prompt to the model
-    set_prompt(model_name.as_ref(), &prompt)?;
-
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Compute chat completion.");
-
-    // compute
-    let res = compute(model_name.as_ref(), id, tool_use);
-
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "End of the chat completion");
-
-    res
-}
-
-fn compute(
-    model_name: Option<&String>,
-    id: impl Into<String>,
-    tool_use: bool,
-) -> Result<ChatCompletionObject, LlamaCoreError> {
-    let chat_graphs = match CHAT_GRAPHS.get() {
-        Some(chat_graphs) => chat_graphs,
-        None => {
-            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
+            // retrieve the number of prompt and completion token
+            let token_info = get_token_info_by_graph(graph)?;
 
             #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
-
-            return Err(LlamaCoreError::Operation(err_msg.into()));
-        }
-    };
-
-    let mut chat_graphs = chat_graphs.lock().map_err(|e| {
-        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e);
-
-        #[cfg(feature = "logging")]
-        error!(target: "stdout", "{}", &err_msg);
-
-        LlamaCoreError::Operation(err_msg)
-    })?;
-
-    match model_name {
-        Some(model_name) => match chat_graphs.contains_key(model_name) {
-            true => {
-                let graph = chat_graphs.get_mut(model_name).unwrap();
-                compute_by_graph(graph, id, tool_use)
-            }
-            false => match chat_graphs.iter_mut().next() {
-                Some((_, graph)) => compute_by_graph(graph, id, tool_use),
-                None => {
-                    let err_msg = "There is no model available in the chat graphs.";
+            info!(target: "stdout", "prompt tokens: {}, completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
 
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
+            // retrieve the log probabilities of the generated tokens
+            let logprobs = match top_logprobs {
+                Some(top_logprobs) => {
+                    let mut content = get_logprobs_by_graph(graph, top_logprobs, false)?;
+                    truncate_logprobs_at_stop_sequence(&mut content, stop);
 
-                    Err(LlamaCoreError::Operation(err_msg.into()))
+                    Some(LogProbs {
+                        content: Some(content),
+                    })
                 }
-            },
-        },
-        None => match chat_graphs.iter_mut().next() {
-            Some((_, graph)) => compute_by_graph(graph, id, tool_use),
-            None => {
-                let err_msg = "There is no model available in the chat graphs.";
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                Err(LlamaCoreError::Operation(err_msg.into()))
-            }
-        },
-    }
-}
-
-fn compute_by_graph(
-    graph: &mut Graph<GgmlMetadata>,
-    id: impl Into<String>,
-    tool_use: bool,
-) -> Result<ChatCompletionObject, LlamaCoreError> {
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Compute chat completion by the model named {}.", graph.name());
-
-    match graph.compute() {
-        Ok(_) => {
-            // Retrieve the output.
-            let output_buffer = get_output_buffer(graph, OUTPUT_TENSOR)?;
-            let output = std::str::from_utf8(&output_buffer[..]).map_err(|e| {
-                let err_msg = format!(
-                    "Failed to decode the buffer of the inference result to a utf-8 string. {}",
-                    e
-                );
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                LlamaCoreError::Operation(err_msg)
-            })?;
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "raw generation: {}", output);
-
-            // post-process
-            let message = post_process(output, &graph.metadata.prompt_template).map_err(|e| {
-                LlamaCoreError::Operation(format!("Failed to post-process the output. {}", e))
-            })?;
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "post-processed generation:\n{}", &message);
-
-            // retrieve the number of prompt and completion tokens
-            let token_info = get_token_info_by_graph(graph)?;
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "prompt tokens: {}, completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
-
-            let created = SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|e| {
-                    let err_msg = format!("Failed to get the current time. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    LlamaCoreError::Operation(err_msg)
-                })?;
-
-            match tool_use {
-                true => {
-                    if graph.metadata.prompt_template != PromptTemplateType::MistralTool
-                        && graph.metadata.prompt_template != PromptTemplateType::ChatMLTool
-                        && graph.metadata.prompt_template != PromptTemplateType::GroqLlama3Tool
-                        && graph.metadata.prompt_template != PromptTemplateType::Llama3Tool
-                        && graph.metadata.prompt_template != PromptTemplateType::InternLM2Tool
-                        && graph.metadata.prompt_template != PromptTemplateType::NemotronTool
-                        && graph.metadata.prompt_template != PromptTemplateType::FunctionaryV32
-                        && graph.metadata.prompt_template != PromptTemplateType::FunctionaryV31
-                    {
-                        let err_msg = format!("Unsupported prompt template: {}. The tool use is only supported for 'mistral-tool', 'chatml-tool', 'groq-llama3-tool', 'llama-3-tool', 'internlm-2-tool', 'nemotron-tool', 'functionary-31', and 'functionary-32' prompt templates.", graph.metadata.prompt_template);
-
-                        #[cfg(feature = "logging")]
-                        error!(target: "stdout", "{}", &err_msg);
-
-                        return Err(LlamaCoreError::Operation(err_msg));
-                    }
-
-                    let parsed_result = parse_tool_calls(&message, graph.metadata.prompt_template)?;
-
-                    let finish_reason = if parsed_result.tool_calls.is_empty() {
-                        FinishReason::stop
-                    } else {
-                        FinishReason::tool_calls
-                    };
-
-                    let content = match parsed_result.content {
-                        Some(content) => Some(content),
-                        None => Some(parsed_result.raw),
-                    };
-
-                    // create ChatCompletionResponse
-                    Ok(ChatCompletionObject {
-                        id: id.into(),
-                        object: String::from("chat.completion"),
-                        created: created.as_secs(),
-                        model: graph.name().to_owned(),
-                        choices: vec![ChatCompletionObjectChoice {
-                            index: 0,
-                            message: ChatCompletionObjectMessage {
-                                role: ChatCompletionRole::Assistant,
-                                content,
-                                tool_calls: parsed_result.tool_calls,
-                                function_call: None,
-                            },
-                            finish_reason,
-                            logprobs: None,
-                        }],
-                        usage: Usage {
-                            prompt_tokens: token_info.prompt_tokens,
-                            completion_tokens: token_info.completion_tokens,
-                            total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
-                        },
-                    })
-                }
-                false => {
-                    // create ChatCompletionResponse
-                    Ok(ChatCompletionObject {
-                        id: id.into(),
-                        object: String::from("chat.completion"),
-                        created: created.as_secs(),
-                        model: graph.name().to_owned(),
-                        choices: vec![ChatCompletionObjectChoice {
-                            index: 0,
-                            message: ChatCompletionObjectMessage {
-                                role: ChatCompletionRole::Assistant,
-                                content: Some(message),
-                                tool_calls: vec![],
-                                function_call: None,
-                            },
-                            finish_reason: FinishReason::stop,
-                            logprobs: None,
-                        }],
-                        usage: Usage {
-                            prompt_tokens: token_info.prompt_tokens,
-                            completion_tokens: token_info.completion_tokens,
-                            total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
-                        },
-                    })
-                }
-            }
-        }
-        Err(wasmedge_wasi_nn::Error::BackendError(wasmedge_wasi_nn::BackendError::ContextFull)) => {
-            // Retrieve the output.
-            let output_buffer = get_output_buffer(graph, OUTPUT_TENSOR)?;
-            let output = std::str::from_utf8(&output_buffer[..]).map_err(|e| {
-                let err_msg = format!(
-                    "Failed to decode the buffer of the inference result to a utf-8 string. {}",
-                    e
-                );
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                LlamaCoreError::Operation(err_msg)
-            })?;
-
-            // post-process
-            let message = post_process(output, &graph.metadata.prompt_template).map_err(|e| {
-                let err_msg = format!("Failed to post-process the output. {}", e);
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                LlamaCoreError::Operation(err_msg)
-            })?;
-
-            // retrieve the number of prompt and completion tokens
-            let token_info = get_token_info_by_graph(graph)?;
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "prompt tokens: {}, completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
-
-            let created = SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|e| {
-                    let err_msg = format!("Failed to get the current time. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    LlamaCoreError::Operation(err_msg)
-                })?;
-
-            // create ChatCompletionResponse
-            Ok(ChatCompletionObject {
-                id: id.into(),
-                object: String::from("chat.completion"),
-                created: created.as_secs(),
-                model: graph.name().to_owned(),
-                choices: vec![ChatCompletionObjectChoice {
-                    index: 0,
-                    message: ChatCompletionObjectMessage {
-                        role: ChatCompletionRole::Assistant,
-                        content: Some(message),
-                        tool_calls: vec![],
-                        function_call: None,
-                    },
-                    finish_reason: FinishReason::length,
-                    logprobs: None,
-                }],
-                usage: Usage {
-                    prompt_tokens: token_info.prompt_tokens,
-                    completion_tokens: token_info.completion_tokens,
-                    total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
-                },
-            })
-        }
-        Err(wasmedge_wasi_nn::Error::BackendError(
-            wasmedge_wasi_nn::BackendError::PromptTooLong,
-        )) => {
-            #[cfg(feature = "logging")]
-            warn!(target: "stdout", "The prompt is too long. Please reduce the length of your input and try again.");
-
-            // Retrieve the output.
-            let output_buffer = get_output_buffer(graph, OUTPUT_TENSOR)?;
-            let output = std::str::from_utf8(&output_buffer[..]).map_err(|e| {
-                let err_msg = format!(
-                    "Failed to decode the buffer of the inference result to a utf-8 string. {}",
-                    e
-                );
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                LlamaCoreError::Operation(err_msg)
-            })?;
-
-            // post-process
-            let message = post_process(output, &graph.metadata.prompt_template).map_err(|e| {
-                let err_msg = format!("Failed to post-process the output. {}", e);
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                LlamaCoreError::Operation(err_msg)
-            })?;
-
-            // retrieve the number of prompt and completion token
-            let token_info = get_token_info_by_graph(graph)?;
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "prompt tokens: {}, completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
+                None => None,
+            };
 
             let created = SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
@@ -1008,723 +695,94 @@ fn compute_by_graph(
                     let err_msg = format!("Failed to get the current time. Reason: {}", e);
 
                     #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    LlamaCoreError::Operation(err_msg)
-                })?;
-
-            // create ChatCompletionResponse
-            Ok(ChatCompletionObject {
-                id: id.into(),
-                object: String::from("chat.completion"),
-                created: created.as_secs(),
-                model: graph.name().to_owned(),
-                choices: vec![ChatCompletionObjectChoice {
-                    index: 0,
-                    message: ChatCompletionObjectMessage {
-                        role: ChatCompletionRole::Assistant,
-                        content: Some(message),
-                        tool_calls: vec![],
-                        function_call: None,
-                    },
-                    finish_reason: FinishReason::length,
-                    logprobs: None,
-                }],
-                usage: Usage {
-                    prompt_tokens: token_info.prompt_tokens,
-                    completion_tokens: token_info.completion_tokens,
-                    total_tokens: token_info.completion_tokens + token_info.completion_tokens,
-                },
-            })
-        }
-        Err(e) => {
-            let err_msg = format!("Failed to compute the chat completion. Reason: {}", e);
-
-            #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
-
-            Err(LlamaCoreError::Backend(BackendError::Compute(err_msg)))
-        }
-    }
-}
-
-fn parse_tool_calls(
-    input: &str,
-    prompt_template: PromptTemplateType,
-) -> Result<ParseResult, LlamaCoreError> {
-    match prompt_template {
-        PromptTemplateType::MistralTool => match regex::Regex::new(r"\[\{.*?\}\]") {
-            Ok(re) => {
-                let mut values: Vec<serde_json::Value> = vec![];
-                for cap in re.captures_iter(input) {
-                    let matched = &cap[0];
-
-                    #[cfg(feature = "logging")]
-                    info!(target: "stdout", "captured: {}", matched);
-
-                    match serde_json::from_str::<Vec<serde_json::Value>>(matched) {
-                        Ok(group) => values.extend(group),
-                        Err(e) => {
-                            let err_msg = format!(
-                                "Failed to deserialize generated tool calls. Reason: {}",
-                                e
-                            );
-
-                            #[cfg(feature = "logging")]
-                            error!(target: "stdout", "{}", &err_msg);
-
-                            return Err(LlamaCoreError::Operation(err_msg));
-                        }
-                    }
-                }
-
-                let mut tool_calls: Vec<ToolCall> = vec![];
-                for value in values.iter() {
-                    let name = match value.get("name") {
-                        Some(name) => name.to_string().replace("\"", ""),
-                        None => {
-                            let err_msg = format!(
-                                "Failed to get the name of the function. Tool call: {:?}",
-                                value
-                            );
-
-                            #[cfg(feature = "logging")]
-                            error!(target: "stdout", "{}", &err_msg);
-
-                            return Err(LlamaCoreError::Operation(err_msg));
-                        }
-                    };
-
-                    let arguments = match value.get("arguments") {
-                        Some(arguments) => arguments.to_string(),
-                        None => {
-                            let err_msg = format!(
-                                "Failed to get the arguments of the function. Tool call: {:?}",
-                                value
-                            );
-
-                            #[cfg(feature = "logging")]
-                            error!(target: "stdout", "{}", &err_msg);
-
-                            return Err(LlamaCoreError::Operation(err_msg));
-                        }
-                    };
-
-                    let function = Function { name, arguments };
-
-                    let tool_call = ToolCall {
-                        id: "call_abc123".to_string(),
-                        ty: "function".to_string(),
-                        function,
-                    };
-
-                    tool_calls.push(tool_call);
-                }
-
-                let parsed = ParseResult {
-                    raw: input.to_owned(),
-                    content: None,
-                    tool_calls,
-                };
-
-                #[cfg(feature = "logging")]
-                info!(target: "stdout", "parsed result: {:?}", parsed);
-
-                Ok(parsed)
-            }
-            Err(e) => {
-                let err_msg = format!("Failed to create a regex pattern. Reason: {}", e);
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                Err(LlamaCoreError::Operation(err_msg))
-            }
-        },
-        PromptTemplateType::ChatMLTool => {
-            match regex::Regex::new(r"<tool_call>(.*?)</tool_call>") {
-                Ok(re) => {
-                    let mut values: Vec<serde_json::Value> = vec![];
-                    for cap in re.captures_iter(input) {
-                        let matched = cap[1].replace("\\n", ""); // Remove "\\n" from the captured group
-
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "captured: {}", &matched);
-
-                        match serde_json::from_str::<serde_json::Value>(&matched) {
-                            Ok(value) => values.push(value),
-                            Err(e) => {
-                                let err_msg = format!(
-                                    "Failed to deserialize generated tool calls. Reason: {}",
-                                    e
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        }
-                    }
-
-                    let mut tool_calls: Vec<ToolCall> = vec![];
-                    for value in values.iter() {
-                        let name = match value.get("name") {
-                            Some(name) => name.to_string().replace("\"", ""),
-                            None => {
-                                let err_msg = format!(
-                                    "Failed to get the name of the function. Tool call: {:?}",
-                                    value
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        };
-
-                        let arguments = match value.get("arguments") {
-                            Some(arguments) => arguments.to_string(),
-                            None => {
-                                let err_msg = format!(
-                                    "Failed to get the arguments of the function. Tool call: {:?}",
-                                    value
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        };
-
-                        let function = Function { name, arguments };
-
-                        let tool_call = ToolCall {
-                            id: "call_abc123".to_string(),
-                            ty: "function".to_string(),
-                            function,
-                        };
-
-                        tool_calls.push(tool_call);
-                    }
-
-                    let parsed = ParseResult {
-                        raw: input.to_owned(),
-                        content: None,
-                        tool_calls,
-                    };
-
-                    #[cfg(feature = "logging")]
-                    info!(target: "stdout", "parsed result: {:?}", parsed);
-
-                    Ok(parsed)
-                }
-                Err(e) => {
-                    let err_msg = format!("Failed to create a regex pattern. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    Err(LlamaCoreError::Operation(err_msg))
-                }
-            }
-        }
-        PromptTemplateType::GroqLlama3Tool => {
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "raw input: {}", input);
-
-            match regex::Regex::new(r"(?s)<tool_call>((.|\r|\n)*?)</tool_call>") {
-                Ok(re) => {
-                    let mut values: Vec<serde_json::Value> = vec![];
-                    for cap in re.captures_iter(input) {
-                        let matched = cap[1].trim();
-
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "captured: {}", matched);
-
-                        match serde_json::from_str::<serde_json::Value>(matched) {
-                            Ok(value) => values.push(value),
-                            Err(e) => {
-                                let err_msg = format!(
-                                    "Failed to deserialize generated tool calls. Reason: {}",
-                                    e
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        }
-                    }
-
-                    let mut tool_calls: Vec<ToolCall> = vec![];
-                    for value in values.iter() {
-                        let name = match value.get("name") {
-                            Some(name) => name.to_string().replace("\"", ""),
-                            None => {
-                                let err_msg = format!(
-                                    "Failed to get the name of the function. Tool call: {:?}",
-                                    value
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        };
-
-                        let arguments = match value.get("arguments") {
-                            Some(arguments) => arguments.to_string(),
-                            None => {
-                                let err_msg = format!(
-                                    "Failed to get the arguments of the function. Tool call: {:?}",
-                                    value
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        };
-
-                        let function = Function { name, arguments };
-
-                        let tool_call = ToolCall {
-                            id: "call_abc123".to_string(),
-                            ty: "function".to_string(),
-                            function,
-                        };
-
-                        tool_calls.push(tool_call);
-                    }
-
-                    let parsed = ParseResult {
-                        raw: input.to_owned(),
-                        content: None,
-                        tool_calls,
-                    };
-
-                    #[cfg(feature = "logging")]
-                    info!(target: "stdout", "parsed result: {:?}", parsed);
-
-                    Ok(parsed)
-                }
-                Err(e) => {
-                    let err_msg = format!("Failed to create a regex pattern. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    Err(LlamaCoreError::Operation(err_msg))
-                }
-            }
-        }
-        PromptTemplateType::Llama3Tool => {
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "raw input: {}", input);
-
-            let re = match regex::Regex::new(r"^\{(.|\r|\n)*\}$") {
-                Ok(re) => re,
-                Err(e) => {
-                    let err_msg = format!("Failed to create a regex pattern. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    return Err(LlamaCoreError::Operation(err_msg));
-                }
-            };
-
-            if re.is_match(input) {
-                match serde_json::from_str::<serde_json::Value>(input) {
-                    Ok(value) => {
-                        let values: Vec<serde_json::Value> = vec![value];
-
-                        let mut tool_calls: Vec<ToolCall> = vec![];
-                        for value in values.iter() {
-                            let name = match value.get("name") {
-                                Some(name) => name.to_string().replace("\"", ""),
-                                None => {
-                                    let err_msg = format!(
-                                        "Failed to get the name of the function. Tool call: {:?}",
-                                        value
-                                    );
-
-                                    #[cfg(feature = "logging")]
-                                    error!(target: "stdout", "{}", &err_msg);
-
-                                    return Err(LlamaCoreError::Operation(err_msg));
-                                }
-                            };
-
-                            let arguments = match value.get("parameters") {
-                                Some(arguments) => arguments.to_string(),
-                                None => {
-                                    let err_msg = format!(
-                                        "Failed to get the arguments of the function. Tool call: {:?}",
-                                        value
-                                    );
-
-                                    #[cfg(feature = "logging")]
-                                    error!(target: "stdout", "{}", &err_msg);
-
-                                    return Err(LlamaCoreError::Operation(err_msg));
-                                }
-                            };
-
-                            let function = Function { name, arguments };
-
-                            let tool_call = ToolCall {
-                                id: "call_abc123".to_string(),
-                                ty: "function".to_string(),
-                                function,
-                            };
-
-                            tool_calls.push(tool_call);
-                        }
-
-                        let parsed = ParseResult {
-                            raw: input.to_owned(),
-                            content: None,
-                            tool_calls,
-                        };
-
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "parsed result: {:?}", parsed);
-
-                        Ok(parsed)
-                    }
-                    Err(e) => {
-                        let err_msg =
-                            format!("Failed to deserialize generated tool calls. Reason: {}", e);
-
-                        #[cfg(feature = "logging")]
-                        error!(target: "stdout", "{}", &err_msg);
-
-                        Err(LlamaCoreError::Operation(err_msg))
-                    }
-                }
-            } else {
-                let parsed = ParseResult {
-                    raw: input.to_owned(),
-                    content: None,
-                    tool_calls: vec![],
-                };
-
-                #[cfg(feature = "logging")]
-                info!(target: "stdout", "parsed result: {:?}", parsed);
-
-                Ok(parsed)
-            }
-        }
-        PromptTemplateType::InternLM2Tool => {
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "raw input: {}", input);
-
-            let blocks: Vec<&str> = input.trim().split("<|action_start|><|plugin|>").collect();
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "blocks: {:?}", blocks);
-
-            let mut tool_calls: Vec<ToolCall> = vec![];
-            let mut content = String::new();
-            for block in blocks {
-                let block = block.trim();
-                if !block.is_empty() {
-                    if block.ends_with("<|action_end|>") {
-                        let value = block.trim().trim_end_matches("<|action_end|>");
-
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "tool call: {}", value);
-
-                        match serde_json::from_str::<serde_json::Value>(value) {
-                            Ok(value) => {
-                                let name = match value.get("name") {
-                                    Some(name) => name.to_string().replace("\"", ""),
-                                    None => {
-                                        let err_msg = format!(
-                                            "Failed to get the name of the function. Tool call: {:?}",
-                                            value
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        return Err(LlamaCoreError::Operation(err_msg));
-                                    }
-                                };
-
-                                let arguments = match value.get("parameters") {
-                                    Some(arguments) => arguments.to_string(),
-                                    None => {
-                                        let err_msg = format!(
-                                            "Failed to get the arguments of the function. Tool call: {:?}",
-                                            value
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        return Err(LlamaCoreError::Operation(err_msg));
-                                    }
-                                };
-
-                                let function = Function { name, arguments };
-
-                                let tool_call = ToolCall {
-                                    id: "call_abc123".to_string(),
-                                    ty: "function".to_string(),
-                                    function,
-                                };
-
-                                tool_calls.push(tool_call);
-                            }
-                            Err(e) => {
-                                let err_msg = format!(
-                                    "Failed to deserialize generated tool calls. Reason: {}",
-                                    e
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        }
-                    } else {
-                        content.push_str(block);
-                        content.push('\n');
-                    }
-                }
-            }
-
-            let parsed = match content.is_empty() {
-                true => ParseResult {
-                    raw: input.to_owned(),
-                    content: None,
-                    tool_calls,
-                },
-                false => ParseResult {
-                    raw: input.to_owned(),
-                    content: Some(content.trim().to_owned()),
-                    tool_calls,
-                },
-            };
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "parsed result: {:?}", parsed);
-
-            Ok(parsed)
-        }
-        PromptTemplateType::NemotronTool => {
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "raw input: {}", input);
-
-            match regex::Regex::new(r"(?s)<toolcall>\s*(.*?)\s*</toolcall>") {
-                Ok(re) => {
-                    let mut values: Vec<serde_json::Value> = vec![];
-                    for cap in re.captures_iter(input) {
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "captured: {}", &cap[0]);
-
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "extracted: {}", &cap[1]);
-
-                        let matched = cap[1].trim();
-
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "captured: {}", matched);
-
-                        match serde_json::from_str::<serde_json::Value>(matched) {
-                            Ok(value) => values.push(value),
-                            Err(e) => {
-                                let err_msg = format!(
-                                    "Failed to deserialize generated tool calls. Reason: {}",
-                                    e
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        }
-                    }
-
-                    let mut tool_calls: Vec<ToolCall> = vec![];
-                    for value in values.iter() {
-                        let name = match value.get("name") {
-                            Some(name) => name.to_string().replace("\"", ""),
-                            None => {
-                                let err_msg = format!(
-                                    "Failed to get the name of the function. Tool call: {:?}",
-                                    value
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        };
-
-                        let arguments = match value.get("arguments") {
-                            Some(arguments) => arguments.to_string(),
-                            None => {
-                                let err_msg = format!(
-                                    "Failed to get the arguments of the function. Tool call: {:?}",
-                                    value
-                                );
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                return Err(LlamaCoreError::Operation(err_msg));
-                            }
-                        };
-
-                        let function = Function { name, arguments };
-
-                        let tool_call = ToolCall {
-                            id: "call_abc123".to_string(),
-                            ty: "function".to_string(),
-                            function,
-                        };
-
-                        tool_calls.push(tool_call);
-                    }
-
-                    let parsed = ParseResult {
-                        raw: input.to_owned(),
-                        content: None,
-                        tool_calls,
-                    };
-
-                    #[cfg(feature = "logging")]
-                    info!(target: "stdout", "parsed result: {:?}", parsed);
-
-                    Ok(parsed)
-                }
-                Err(e) => {
-                    let err_msg = format!("Failed to create a regex pattern. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    Err(LlamaCoreError::Operation(err_msg))
-                }
-            }
-        }
-        PromptTemplateType::FunctionaryV32 => {
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "raw input: {}", input);
-
-            match regex::Regex::new(r">>>\s*(\w+)\s*\{(.*)\}<\|eot_id\|>") {
-                Ok(re) => {
-                    let mut tool_calls: Vec<ToolCall> = vec![];
-                    for cap in re.captures_iter(input) {
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "func_name: {}", &cap[1]);
-
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "arguments: {}", &cap[2]);
-
-                        let tool_call = ToolCall {
-                            id: "call_abc123".to_string(),
-                            ty: "function".to_string(),
-                            function: Function {
-                                name: cap[1].to_string(),
-                                arguments: cap[2].to_string(),
-                            },
-                        };
-
-                        tool_calls.push(tool_call);
-                    }
-
-                    let parsed = ParseResult {
-                        raw: input.to_owned(),
-                        content: None,
-                        tool_calls,
-                    };
-
-                    #[cfg(feature = "logging")]
-                    info!(target: "stdout", "parsed result: {:?}", parsed);
-
-                    Ok(parsed)
-                }
-                Err(e) => {
-                    let warn_msg = format!("Failed to create a regex pattern. Reason: {}", e);
-
-                    #[cfg(feature = "logging")]
-                    warn!(target: "stdout", "{}", &warn_msg);
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    LlamaCoreError::Operation(err_msg)
+                })?;
 
-                    Ok(ParseResult {
-                        raw: input.to_owned(),
-                        content: None,
+            // create ChatCompletionResponse
+            Ok(ChatCompletionObject {
+                id: id.into(),
+                object: String::from("chat.completion"),
+                created: created.as_secs(),
+                model: graph.name().to_owned(),
+                system_fingerprint: Some(system_fingerprint),
+                choices: vec![ChatCompletionObjectChoice {
+                    index: 0,
+                    message: ChatCompletionObjectMessage {
+                        role: ChatCompletionRole::Assistant,
+                        content: Some(message),
                         tool_calls: vec![],
-                    })
-                }
-            }
+                        function_call: None,
+                    },
+                    finish_reason,
+                    logprobs,
+                }],
+                usage: Usage {
+                    prompt_tokens: token_info.prompt_tokens,
+                    completion_tokens: token_info.completion_tokens,
+                    total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
+                },
+            })
         }
-        PromptTemplateType::FunctionaryV31 => {
+        Err(e) => {
+            let err_msg = format!("Failed to compute the chat completion. Reason: {}", e);
+
             #[cfg(feature = "logging")]
-            info!(target: "stdout", "raw input: {}", input);
+            error!(target: "stdout", "{}", &err_msg);
 
-            match regex::Regex::new(r"<function=(\w+)>\s*(\{.*?\})</function>") {
-                Ok(re) => {
-                    let mut tool_calls: Vec<ToolCall> = vec![];
-                    for cap in re.captures_iter(input) {
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "func_name: {}", &cap[1]);
+            Err(LlamaCoreError::Backend(BackendError::Compute(err_msg)))
+        }
+    }
+}
 
-                        #[cfg(feature = "logging")]
-                        info!(target: "stdout", "arguments: {}", &cap[2]);
-
-                        let tool_call = ToolCall {
-                            id: "call_abc123".to_string(),
-                            ty: "function".to_string(),
-                            function: Function {
-                                name: cap[1].to_string(),
-                                arguments: cap[2].to_string(),
-                            },
-                        };
+/// Check if every tool message with a `tool_call_id` in the conversation responds to a tool call generated by the model in a prior assistant message. The tool messages without `tool_call_id` are accepted, since some clients and prompt templates leave it out.
+fn validate_tool_call_ids(messages: &[ChatCompletionRequestMessage]) -> Result<(), LlamaCoreError> {
+    let mut tool_call_ids: HashSet<String> = HashSet::new();
 
-                        tool_calls.push(tool_call);
+    for message in messages {
+        match message {
+            ChatCompletionRequestMessage::Assistant(assistant_message) => {
+                if let Some(tool_calls) = assistant_message.tool_calls() {
+                    for tool_call in tool_calls {
+                        tool_call_ids.insert(tool_call.id.clone());
                     }
-
-                    let parsed = ParseResult {
-                        raw: input.to_owned(),
-                        content: None,
-                        tool_calls,
-                    };
-
-                    #[cfg(feature = "logging")]
-                    info!(target: "stdout", "parsed result: {:?}", parsed);
-
-                    Ok(parsed)
                 }
-                Err(e) => {
-                    let warn_msg = format!("Failed to create a regex pattern. Reason: {}", e);
+            }
+            ChatCompletionRequestMessage::Tool(tool_message) => {
+                if let Some(id) = tool_message.tool_call_id() {
+                    if !tool_call_ids.contains(&id) {
+                        let err_msg = format!(
+                            "The tool message responds to an unknown tool call id: {}. The `tool_call_id` must be the id of a tool call in a prior assistant message.",
+                            id
+                        );
 
-                    #[cfg(feature = "logging")]
-                    warn!(target: "stdout", "{}", &warn_msg);
+                        #[cfg(feature = "logging")]
+                        error!(target: "stdout", "{}", &err_msg);
 
-                    Ok(ParseResult {
-                        raw: input.to_owned(),
-                        content: None,
-                        tool_calls: vec![],
-                    })
+                        return Err(LlamaCoreError::BadRequest(err_msg));
+                    }
                 }
             }
+            _ => {}
         }
-        _ => {
+    }
+
+    Ok(())
+}
+
+fn parse_tool_calls(
+    input: &str,
+    prompt_template: PromptTemplateType,
+) -> Result<ParseResult, LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "raw input: {}", input);
+
+    let parser = match prompt_template.tool_call_parser() {
+        Some(parser) => parser,
+        None => {
             let err_msg = format!(
-                "The tool use is only supported for prompt templates: {}, {}, {}, {}, {}, {}, and {}.",
+                "The tool use is only supported for prompt templates: {}, {}, {}, {}, {}, {}, {}, and {}.",
                 PromptTemplateType::MistralTool,
                 PromptTemplateType::ChatMLTool,
                 PromptTemplateType::GroqLlama3Tool,
@@ -1732,18 +790,48 @@ fn parse_tool_calls(
                 PromptTemplateType::InternLM2Tool,
                 PromptTemplateType::NemotronTool,
                 PromptTemplateType::FunctionaryV32,
+                PromptTemplateType::FunctionaryV31,
             );
 
             #[cfg(feature = "logging")]
             error!(target: "stdout", "{}", &err_msg);
 
-            Err(LlamaCoreError::Operation(err_msg))
+            return Err(LlamaCoreError::Operation(err_msg));
         }
-    }
+    };
+
+    let parsed = parser.parse_tool_calls(input).map_err(|e| {
+        let err_msg = e.to_string();
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    let parsed = ParseResult {
+        raw: parsed.raw,
+        content: parsed.content,
+        tool_calls: parsed
+            .tool_calls
+            .into_iter()
+            .map(|function| ToolCall {
+                id: gen_tool_call_id(),
+                ty: "function".to_string(),
+                function,
+            })
+            .collect(),
+    };
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "parsed result: {:?}", parsed);
+
+    Ok(parsed)
 }
 
 async fn check_model_metadata(
     chat_request: &ChatCompletionRequest,
+    slot: &SlotLease,
 ) -> Result<GgmlMetadata, LlamaCoreError> {
     let mut should_update = false;
     let mut metadata = get_model_metadata(chat_request.model.as_ref())?;
@@ -1839,6 +927,68 @@ async fn check_model_metadata(
         }
     }
 
+    // check if necessary to update the json schema for the structured output
+    if let Some(response_format) = &chat_request.response_format {
+        if let Some(json_schema) = parse_response_format(response_format)? {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "json schema of the response format: {}", &json_schema);
+
+            if metadata.json_schema.as_ref() != Some(&json_schema) {
+                // the json schema is converted to a grammar by the backend, so the grammar set by the model config is disabled
+                metadata.grammar = String::new();
+
+                // update json schema
+                metadata.json_schema = Some(json_schema);
+
+                if !should_update {
+                    should_update = true;
+                }
+            }
+        }
+    }
+
+    // check if necessary to update seed
+    if let Some(seed) = chat_request.seed {
+        if metadata.seed != seed {
+            // update seed
+            metadata.seed = seed;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // check if necessary to update the logit bias
+    if let Some(logit_bias) = &chat_request.logit_bias {
+        let logit_bias = parse_logit_bias(logit_bias)?;
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "logit bias: {:?}", &logit_bias);
+
+        if metadata.logit_bias != logit_bias {
+            // update logit bias
+            metadata.logit_bias = logit_bias;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
+    // check if necessary to output the log probabilities
+    if let Some(top_logprobs) = requested_top_logprobs(chat_request) {
+        if !metadata.logprobs || metadata.top_logprobs != top_logprobs as u64 {
+            // update logprobs and top_logprobs
+            metadata.logprobs = true;
+            metadata.top_logprobs = top_logprobs as u64;
+
+            if !should_update {
+                should_update = true;
+            }
+        }
+    }
+
     // check if the `embedding` option is disabled
     if metadata.embeddings {
         metadata.embeddings = false;
@@ -1853,14 +1003,85 @@ async fn check_model_metadata(
         info!(target: "stdout", "Update the model metadata.");
 
         // update the target graph with the new metadata
-        update_model_metadata(chat_request.model.as_ref(), &metadata)?;
+        update_model_metadata(slot, &metadata)?;
     }
 
     Ok(metadata)
 }
 
+/// Returns the number of the most likely tokens to return at each token position if the log probabilities are requested; otherwise, returns `None`.
+fn requested_top_logprobs(chat_request: &ChatCompletionRequest) -> Option<u8> {
+    match chat_request.logprobs {
+        Some(true) => Some(chat_request.top_logprobs.unwrap_or_default()),
+        _ => None,
+    }
+}
+
+/// Convert the `response_format` of a chat request to the JSON schema which is used to constrain the generation. Returns `None` for the `text` format.
+fn parse_response_format(
+    response_format: &ChatResponseFormat,
+) -> Result<Option<String>, LlamaCoreError> {
+    match response_format.ty.as_str() {
+        "text" => Ok(None),
+        // any valid JSON object
+        "json_object" => Ok(Some(r#"{"type":"object"}"#.to_string())),
+        "json_schema" => {
+            let schema = match &response_format.json_schema {
+                Some(json_schema) => match &json_schema.schema {
+                    Some(schema) => schema,
+                    None => {
+                        let err_msg = format!(
+                            "The `schema` field is required in the `json_schema` response format named {}.",
+                            json_schema.name
+                        );
+
+                        #[cfg(feature = "logging")]
+                        error!(target: "stdout", "{}", &err_msg);
+
+                        return Err(LlamaCoreError::Operation(err_msg));
+                    }
+                },
+                None => {
+                    let err_msg = "The `json_schema` field is required if the type of the response format is `json_schema`.";
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return Err(LlamaCoreError::Operation(err_msg.into()));
+                }
+            };
+
+            let json_schema = serde_json::to_string(schema).map_err(|e| {
+                let err_msg = format!(
+                    "Failed to serialize the json schema of the response format. Reason: {}",
+                    e
+                );
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                LlamaCoreError::Operation(err_msg)
+            })?;
+
+            Ok(Some(json_schema))
+        }
+        ty => {
+            let err_msg = format!(
+                "Unsupported response format: {}. The response format must be one of `text`, `json_object` or `json_schema`.",
+                ty
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::Operation(err_msg))
+        }
+    }
+}
+
 async fn update_n_predict(
     chat_request: &ChatCompletionRequest,
+    slot: &SlotLease,
     metadata: &mut GgmlMetadata,
     available_completion_tokens: u64,
 ) -> Result<(), LlamaCoreError> {
@@ -1921,7 +1142,7 @@ async fn update_n_predict(
         info!(target: "stdout", "Update the model metadata.");
 
         // update the target graph with the new metadata
-        update_model_metadata(chat_request.model.as_ref(), metadata)?;
+        update_model_metadata(slot, metadata)?;
     }
 
     Ok(())
@@ -2133,6 +1354,8 @@ fn post_process(
 ///
 /// * `model_name`: The name of the model.
 ///
+/// * `slot`: The execution slot leased for the request.
+///
 /// * `chat_request`: The chat request.
 ///
 /// # Returns
@@ -2140,6 +1363,7 @@ fn post_process(
 /// A tuple containing the prompt, the number of available tokens for completions, and a boolean indicating whether tools are used.
 fn build_prompt(
     model_name: Option<&String>,
+    slot: &SlotLease,
     chat_request: &mut ChatCompletionRequest,
 ) -> Result<(String, u64, bool), LlamaCoreError> {
     let metadata = get_model_metadata(model_name)?;
@@ -2234,10 +1458,10 @@ fn build_prompt(
         };
 
         // set prompt
-        set_prompt(model_name, &prompt)?;
+        set_prompt(slot, &prompt)?;
 
         // Retrieve the number of prompt tokens.
-        let token_info = get_token_info_by_graph_name(model_name)?;
+        let token_info = with_chat_slot(slot, |graph| get_token_info_by_graph(graph))?;
 
         match token_info.prompt_tokens > max_prompt_tokens {
             true => {
@@ -2393,75 +1617,14 @@ async fn download_image(image_url: impl AsRef<str>) -> Result<String, LlamaCoreE
     Ok(img_path.as_path().to_string_lossy().to_string())
 }
 
-fn set_prompt(model_name: Option<&String>, prompt: impl AsRef<str>) -> Result<(), LlamaCoreError> {
-    let chat_graphs = match CHAT_GRAPHS.get() {
-        Some(chat_graphs) => chat_graphs,
-        None => {
-            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
-
-            #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
-
-            return Err(LlamaCoreError::Operation(err_msg.into()));
-        }
-    };
-
-    let mut chat_graphs = chat_graphs.lock().map_err(|e| {
-        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e);
-
-        #[cfg(feature = "logging")]
-        error!(target: "stdout", "{}", &err_msg);
-
-        LlamaCoreError::Operation(err_msg)
-    })?;
-
-    match model_name {
-        Some(model_name) => {
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "Set prompt to the chat model named {}", model_name);
-
-            match chat_graphs.contains_key(model_name) {
-                true => {
-                    let graph = chat_graphs.get_mut(model_name).unwrap();
-                    let tensor_data = prompt.as_ref().as_bytes().to_vec();
-                    set_tensor_data_u8(graph, 0, &tensor_data)
-                }
-                false => match chat_graphs.iter_mut().next() {
-                    Some((_, graph)) => {
-                        let tensor_data = prompt.as_ref().as_bytes().to_vec();
-                        set_tensor_data_u8(graph, 0, &tensor_data)
-                    }
-                    None => {
-                        let err_msg = "There is no model available in the chat graphs.";
-
-                        #[cfg(feature = "logging")]
-                        error!(target: "stdout", "{}", &err_msg);
-
-                        Err(LlamaCoreError::Operation(err_msg.into()))
-                    }
-                },
-            }
-        }
-        None => {
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "Set prompt to the default chat model.");
-
-            match chat_graphs.iter_mut().next() {
-                Some((_, graph)) => {
-                    let tensor_data = prompt.as_ref().as_bytes().to_vec();
-                    set_tensor_data_u8(graph, 0, &tensor_data)
-                }
-                None => {
-                    let err_msg = "There is no model available in the chat graphs while trying to set prompt to the default model.";
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", err_msg);
+fn set_prompt(slot: &SlotLease, prompt: impl AsRef<str>) -> Result<(), LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Set prompt to the slot {} of the chat model named {}", slot.index(), slot.model_name());
 
-                    Err(LlamaCoreError::Operation(err_msg.into()))
-                }
-            }
-        }
-    }
+    with_chat_slot(slot, |graph| {
+        let tensor_data = prompt.as_ref().as_bytes().to_vec();
+        set_tensor_data_u8(graph, 0, &tensor_data)
+    })
 }
 
 // fn set_tensor_data_u8(
@@ -2536,10 +1699,7 @@ fn get_model_metadata(model_name: Option<&String>) -> Result<GgmlMetadata, Llama
     }
 }
 
-fn update_model_metadata(
-    model_name: Option<&String>,
-    metadata: &GgmlMetadata,
-) -> Result<(), LlamaCoreError> {
+fn update_model_metadata(slot: &SlotLease, metadata: &GgmlMetadata) -> Result<(), LlamaCoreError> {
     let config = match serde_json::to_string(metadata) {
         Ok(config) => config,
         Err(e) => {
@@ -2552,1778 +1712,607 @@ fn update_model_metadata(
         }
     };
 
-    let chat_graphs = match CHAT_GRAPHS.get() {
-        Some(chat_graphs) => chat_graphs,
-        None => {
-            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
-
-            #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", err_msg);
-
-            return Err(LlamaCoreError::Operation(err_msg.into()));
-        }
-    };
-
-    let mut chat_graphs = chat_graphs.lock().map_err(|e| {
-        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. Reason: {}", e);
-
-        #[cfg(feature = "logging")]
-        error!(target: "stdout", "{}", &err_msg);
-
-        LlamaCoreError::Operation(err_msg)
-    })?;
-
-    match model_name {
-        Some(model_name) => {
-            match chat_graphs.contains_key(model_name) {
-                true => {
-                    let graph = chat_graphs.get_mut(model_name).unwrap();
-                    // update metadata
-                    set_tensor_data_u8(graph, 1, config.as_bytes())
-                }
-                false => match chat_graphs.iter_mut().next() {
-                    Some((_, graph)) => {
-                        // update metadata
-                        set_tensor_data_u8(graph, 1, config.as_bytes())
-                    }
-                    None => {
-                        let err_msg = "There is no model available in the chat graphs.";
-
-                        #[cfg(feature = "logging")]
-                        error!(target: "stdout", "{}", &err_msg);
-
-                        Err(LlamaCoreError::Operation(err_msg.into()))
-                    }
-                },
-            }
-        }
-        None => {
-            match chat_graphs.iter_mut().next() {
-                Some((_, graph)) => {
-                    // update metadata
-                    set_tensor_data_u8(graph, 1, config.as_bytes())
-                }
-                None => {
-                    let err_msg = "There is no model available in the chat graphs.";
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", err_msg);
-
-                    Err(LlamaCoreError::Operation(err_msg.into()))
-                }
-            }
-        }
-    }
-}
-
-fn reset_model_metadata(model_name: Option<&String>) -> Result<(), LlamaCoreError> {
-    // get metadata
-    let metadata = get_model_metadata(model_name)?;
-
-    // update model with the original metadata
-    update_model_metadata(model_name, &metadata)
+    // update metadata
+    with_chat_slot(slot, |graph| {
+        set_tensor_data_u8(graph, 1, config.as_bytes())
+    })
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum ContextFullState {
-    Message,
-    Usage,
-    Done,
-    EndOfSequence,
+/// Reset the metadata of the slot to the original metadata of the model.
+fn reset_model_metadata(slot: &SlotLease) -> Result<(), LlamaCoreError> {
+    with_chat_slot(slot, |graph| graph.update_metadata())
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum StreamState {
+    /// Generate the tokens of the choices in turn.
+    Generate,
+    /// All the choices are finished. Send the usage of the request.
     Usage,
+    /// Send the `[DONE]` message.
     Done,
+    /// The stream is finished.
     EndOfSequence,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum PromptTooLongState {
-    Message,
-    Usage,
-    Done,
-    EndOfSequence,
+/// A choice of a request in the stream mode.
+#[derive(Debug)]
+struct StreamChoice {
+    index: u32,
+    stop_sequences: StopSequences,
+    tool_calls: Option<ToolCallStream>,
+    /// The log probabilities of the generated tokens which are not sent yet. `None` if the log probabilities are not requested.
+    logprobs: Option<Vec<TokenLogProb>>,
+    /// The bytes of an incomplete utf-8 character held back.
+    utf8_bytes: Vec<u8>,
+    /// Whether the choice is finished.
+    finished: bool,
+}
+impl StreamChoice {
+    /// Take the log probabilities of the tokens held back so far.
+    fn take_logprobs(&mut self) -> Option<LogProbs> {
+        self.logprobs.as_mut().map(|logprobs| LogProbs {
+            content: Some(std::mem::take(logprobs)),
+        })
+    }
+}
+
+/// Track the choices of a request in the stream mode. The choices take turns generating one token at a time, so the chunks of the choices are interleaved, and each chunk is tagged with the index of its choice.
+#[derive(Debug)]
+struct StreamChoices {
+    choices: Vec<StreamChoice>,
+    /// The position of the choice which generates the next token.
+    next: usize,
+    /// The number of the most likely tokens to return at each token position. `None` if the log probabilities are not requested.
+    top_logprobs: Option<u8>,
+    /// The fingerprint of the backend configuration, which is generated with the first chunk.
+    system_fingerprint: Option<String>,
+    /// The number of prompt tokens, and the number of completion tokens of the finished choices.
+    usage: Usage,
+}
+impl StreamChoices {
+    fn new(
+        n: u64,
+        stop: Option<Vec<String>>,
+        tool_call_format: Option<ToolCallFormat>,
+        top_logprobs: Option<u8>,
+    ) -> Self {
+        let choices = (0..n)
+            .map(|index| StreamChoice {
+                index: index as u32,
+                stop_sequences: StopSequences::new(stop.clone()),
+                tool_calls: tool_call_format.map(ToolCallStream::new),
+                logprobs: top_logprobs.map(|_| vec![]),
+                utf8_bytes: vec![],
+                finished: false,
+            })
+            .collect();
+
+        Self {
+            choices,
+            next: 0,
+            top_logprobs,
+            system_fingerprint: None,
+            usage: Usage::default(),
+        }
+    }
+
+    /// Pick the next unfinished choice in turn. Returns `None` if all the choices are finished.
+    fn next_choice(&mut self) -> Option<usize> {
+        let n = self.choices.len();
+        let position = (0..n)
+            .map(|offset| (self.next + offset) % n)
+            .find(|&position| !self.choices[position].finished)?;
+        self.next = (position + 1) % n;
+
+        Some(position)
+    }
 }
 
+/// Stream of the chat completion chunks in the server-sent events format.
+///
+/// The first choice runs on the slot of the request, and each of the other choices runs on an execution context forked from the slot, so that the choices are generated side by side.
 struct ChatStream {
     id: String,
-    model: Option<String>,
+    slot: SlotLease,
+    /// The execution contexts of the choices other than the first one.
+    forks: Vec<Graph<GgmlMetadata>>,
     include_usage: bool,
-    context_full_state: ContextFullState,
-    prompt_too_long_state: PromptTooLongState,
-    stream_state: StreamState,
-    cache: Option<VecDeque<String>>,
+    state: StreamState,
+    choices: StreamChoices,
 }
 impl ChatStream {
     fn new(
-        model: Option<String>,
+        slot: SlotLease,
+        forks: Vec<Graph<GgmlMetadata>>,
         id: String,
         include_usage: bool,
-        cache: Option<Vec<String>>,
+        choices: StreamChoices,
     ) -> Self {
-        let stream_state = if include_usage {
-            StreamState::Usage
-        } else {
-            StreamState::Done
-        };
-
         ChatStream {
             id,
-            model,
+            slot,
+            forks,
             include_usage,
-            context_full_state: ContextFullState::Message,
-            prompt_too_long_state: PromptTooLongState::Message,
-            stream_state,
-            cache: cache.map(VecDeque::from),
+            state: StreamState::Generate,
+            choices,
         }
     }
 }
 impl Drop for ChatStream {
     fn drop(&mut self) {
-        if self.cache.is_none() {
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "Clean up the context of the stream work environment.");
-
-            match &self.model {
-                Some(model_name) => {
-                    match CHAT_GRAPHS.get() {
-                        Some(chat_graphs) => match chat_graphs.lock() {
-                            Ok(mut chat_graphs) => match chat_graphs.contains_key(model_name) {
-                                true => {
-                                    let graph = chat_graphs.get_mut(model_name).unwrap();
-
-                                    if let Err(e) = graph.finish_single() {
-                                        let err_msg = format!(
-                                            "Failed to clean up the context. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        #[cfg(not(feature = "logging"))]
-                                        println!(
-                                            "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
-                                            &err_msg
-                                        );
-                                    }
-                                }
-                                false => match chat_graphs.iter_mut().next() {
-                                    Some((_, graph)) => {
-                                        if let Err(e) = graph.finish_single() {
-                                            let err_msg = format!(
-                                                "Failed to clean up the context. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            #[cfg(not(feature = "logging"))]
-                                            println!(
-                                                "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
-                                                &err_msg
-                                            );
-                                        }
-                                    }
-                                    None => {
-                                        let err_msg =
-                                            "There is no model available in the chat graphs.";
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        #[cfg(not(feature = "logging"))]
-                                        println!(
-                                            "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
-                                            &err_msg
-                                        );
-                                    }
-                                },
-                            },
-                            Err(e) => {
-                                let err_msg =
-                                    format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e);
-
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
-
-                                #[cfg(not(feature = "logging"))]
-                                println!(
-                                "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
-                                &err_msg
-                            );
-                            }
-                        },
-                        None => {
-                            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
-
-                            #[cfg(feature = "logging")]
-                            error!(target: "stdout", "{}", &err_msg);
-
-                            #[cfg(not(feature = "logging"))]
-                            println!(
-                                "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
-                                &err_msg
-                            );
-                        }
-                    };
-                }
-                None => {
-                    match CHAT_GRAPHS.get() {
-                        Some(chat_graphs) => match chat_graphs.lock() {
-                            Ok(mut chat_graphs) => match chat_graphs.iter_mut().next() {
-                                Some((_, graph)) => {
-                                    if let Err(e) = graph.finish_single() {
-                                        let err_msg = format!(
-                                            "Failed to clean up the context. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        #[cfg(not(feature = "logging"))]
-                                        println!(
-                                        "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
-                                        &err_msg
-                                    );
-                                    }
-                                }
-                                None => {
-                                    let err_msg = "There is no model available in the chat graphs.";
-
-                                    #[cfg(feature = "logging")]
-                                    error!(target: "stdout", "{}", err_msg);
-
-                                    #[cfg(not(feature = "logging"))]
-                                    println!(
-                                    "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
-                                    err_msg
-                                );
-                                }
-                            },
-                            Err(e) => {
-                                let err_msg =
-                                    format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e);
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Clean up the context of the stream work environment.");
 
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
+        let finish = |graph: &mut Graph<GgmlMetadata>| {
+            graph
+                .finish_single()
+                .map_err(|e| LlamaCoreError::Backend(BackendError::FinishSingle(e.to_string())))
+        };
 
-                                #[cfg(not(feature = "logging"))]
-                                println!(
-                                "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
-                                &err_msg
-                            );
-                            }
-                        },
-                        None => {
-                            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
+        let mut results = vec![with_chat_slot(&self.slot, finish)];
+        results.extend(self.forks.iter_mut().map(finish));
+        for res in results {
+            if let Err(e) = res {
+                let err_msg = format!("Failed to clean up the context. Reason: {}", e);
 
-                            #[cfg(feature = "logging")]
-                            error!(target: "stdout", "{}", &err_msg);
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
 
-                            #[cfg(not(feature = "logging"))]
-                            println!(
-                                "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
-                                &err_msg
-                            );
-                        }
-                    };
-                }
+                #[cfg(not(feature = "logging"))]
+                println!(
+                    "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
+                    &err_msg
+                );
             }
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "Cleanup done!");
         }
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Cleanup done!");
     }
 }
 impl futures::Stream for ChatStream {
     type Item = Result<String, LlamaCoreError>;
 
     fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        if self.cache.is_none() {
-            let this = self.get_mut();
-            let x = compute_stream(
-                this.model.clone(),
-                this.id.clone(),
-                this.include_usage,
-                &mut this.prompt_too_long_state,
-                &mut this.context_full_state,
-                &mut this.stream_state,
-            );
+        let this = self.get_mut();
+        let x = compute_stream(
+            &this.slot,
+            &mut this.forks,
+            &this.id,
+            this.include_usage,
+            &mut this.state,
+            &mut this.choices,
+        );
 
-            match x {
-                Ok(x) => {
-                    #[cfg(feature = "logging")]
-                    info!(target: "stdout", "next item: {}", &x);
+        match x {
+            Ok(x) => {
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "next item: {}", &x);
 
-                    if x != "[GGML] End of sequence" && !x.is_empty() {
-                        Poll::Ready(Some(Ok(x)))
-                    } else {
-                        // stopped
-                        Poll::Ready(None)
-                    }
+                if x != "[GGML] End of sequence" && !x.is_empty() {
+                    Poll::Ready(Some(Ok(x)))
+                } else {
+                    // stopped
+                    Poll::Ready(None)
                 }
-                Err(e) => Poll::Ready(Some(Err(e))),
-            }
-        } else {
-            let this = self.get_mut();
-
-            let x = this.cache.as_mut().unwrap().pop_front();
-
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "Get the next item from the cache: {:?}", &x);
-
-            match x {
-                Some(x) => Poll::Ready(Some(Ok(x))),
-                None => Poll::Ready(None),
             }
+            Err(e) => Poll::Ready(Some(Err(e))),
         }
     }
 }
 
 fn compute_stream(
-    model_name: Option<String>,
-    id: String,
+    slot: &SlotLease,
+    forks: &mut [Graph<GgmlMetadata>],
+    id: &str,
     include_usage: bool,
-    prompt_too_long_state: &mut PromptTooLongState,
-    context_full_state: &mut ContextFullState,
-    stream_state: &mut StreamState,
+    state: &mut StreamState,
+    choices: &mut StreamChoices,
 ) -> Result<String, LlamaCoreError> {
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Compute the chat stream chunk.");
 
-    if *prompt_too_long_state == PromptTooLongState::EndOfSequence
-        || *context_full_state == ContextFullState::EndOfSequence
-        || *stream_state == StreamState::EndOfSequence
-    {
-        #[cfg(feature = "logging")]
-        info!(target: "stdout", "Return the chat stream chunk!");
+    loop {
+        match *state {
+            StreamState::Generate => {
+                let position = match choices.next_choice() {
+                    Some(position) => position,
+                    None => {
+                        *state = match include_usage {
+                            true => StreamState::Usage,
+                            false => StreamState::Done,
+                        };
+
+                        continue;
+                    }
+                };
+
+                let choice = &mut choices.choices[position];
+                let res = match position {
+                    0 => with_chat_slot(slot, |graph| {
+                        compute_choice_by_graph(
+                            graph,
+                            id,
+                            choices.top_logprobs,
+                            &mut choices.system_fingerprint,
+                            &mut choices.usage,
+                            choice,
+                        )
+                    }),
+                    _ => compute_choice_by_graph(
+                        &mut forks[position - 1],
+                        id,
+                        choices.top_logprobs,
+                        &mut choices.system_fingerprint,
+                        &mut choices.usage,
+                        choice,
+                    ),
+                };
+
+                // the choice may be finished without anything left to send, then the turn passes to the next choice
+                if let Some(chunk) = res? {
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "Return the chat stream chunk!");
+
+                    return Ok(chunk);
+                }
+            }
+            StreamState::Usage => {
+                *state = StreamState::Done;
+
+                let usage = Usage {
+                    prompt_tokens: choices.usage.prompt_tokens,
+                    completion_tokens: choices.usage.completion_tokens,
+                    total_tokens: choices.usage.prompt_tokens + choices.usage.completion_tokens,
+                };
+
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "token_info: {} prompt tokens, {} completion tokens", usage.prompt_tokens, usage.completion_tokens);
+
+                return serialize_chat_chunk(
+                    id,
+                    slot.model_name(),
+                    choices.system_fingerprint.as_deref().unwrap_or_default(),
+                    vec![],
+                    Some(usage),
+                );
+            }
+            StreamState::Done => {
+                *state = StreamState::EndOfSequence;
 
-        return Ok("[GGML] End of sequence".to_string());
+                return Ok("data: [DONE]\n\n".to_string());
+            }
+            StreamState::EndOfSequence => return Ok("[GGML] End of sequence".to_string()),
+        }
     }
+}
 
-    let chat_graphs = match CHAT_GRAPHS.get() {
-        Some(chat_graphs) => chat_graphs,
+/// Generate the next token of the given choice. Returns `None` if the choice is finished and nothing is left to send.
+fn compute_choice_by_graph(
+    graph: &mut Graph<GgmlMetadata>,
+    id: &str,
+    top_logprobs: Option<u8>,
+    system_fingerprint: &mut Option<String>,
+    usage: &mut Usage,
+    choice: &mut StreamChoice,
+) -> Result<Option<String>, LlamaCoreError> {
+    let system_fingerprint = match system_fingerprint.clone() {
+        Some(system_fingerprint) => system_fingerprint,
         None => {
-            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
+            let fingerprint = gen_system_fingerprint(graph)?;
+            *system_fingerprint = Some(fingerprint.clone());
+            fingerprint
+        }
+    };
 
+    // once a stop sequence is hit, the rest of the generation is skipped and the choice is finished as if the end of sequence is reached
+    let result = match choice.stop_sequences.is_hit() {
+        true => Err(wasmedge_wasi_nn::Error::BackendError(
+            wasmedge_wasi_nn::BackendError::EndOfSequence,
+        )),
+        false => graph.compute_single(),
+    };
+
+    // compute
+    match result {
+        Ok(_) => {
             #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
+            info!(target: "stdout", "Compute the chat stream chunk successfully.");
 
-            return Err(LlamaCoreError::Operation(err_msg.into()));
-        }
-    };
+            // Retrieve the output
+            let output_buffer = get_output_buffer_single(graph, OUTPUT_TENSOR)?;
 
-    let mut chat_graphs = chat_graphs.lock().map_err(|e| {
-        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e);
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "retrieved the output buffer");
 
-        #[cfg(feature = "logging")]
-        error!(target: "stdout", "{}", &err_msg);
+            // decode the output buffer to a utf8 string. A utf-8 character may be split into multiple tokens, so the bytes are cached per choice for future decoding.
+            choice.utf8_bytes.extend_from_slice(&output_buffer[..]);
+            let output = match String::from_utf8(choice.utf8_bytes.clone()) {
+                Ok(token) => {
+                    // clear encodings
+                    choice.utf8_bytes.clear();
 
-        LlamaCoreError::Operation(err_msg)
-    })?;
+                    token
+                }
+                Err(e) => {
+                    // TODO This is a temp check. In case, infinite cached encodings happen.
+                    if choice.utf8_bytes.len() > 4 {
+                        let err_msg = format!("Fail to convert a vector of bytes to string. The length of the utf8 bytes exceeds 4. {}", e);
 
-    // get graph
-    let res = match &model_name {
-        Some(model_name) => {
-            match chat_graphs.contains_key(model_name) {
-                true => {
-                    let graph = chat_graphs.get_mut(model_name).unwrap();
-                    // compute
-                    match graph.compute_single() {
-                        Ok(_) => {
-                            #[cfg(feature = "logging")]
-                            info!(target: "stdout", "Compute the chat stream chunk successfully.");
+                        #[cfg(feature = "logging")]
+                        error!(target: "stdout", "{}", &err_msg);
 
-                            // Retrieve the output
-                            let output_buffer = get_output_buffer_single(graph, OUTPUT_TENSOR)?;
+                        return Err(LlamaCoreError::Operation(err_msg));
+                    } else {
+                        let warn_msg =
+                            format!("Fail to convert a vector of bytes to string. {}", e);
 
-                            #[cfg(feature = "logging")]
-                            info!(target: "stdout", "retrieved the output buffer");
-
-                            // decode the output buffer to a utf8 string
-                            let output = match String::from_utf8(output_buffer.clone()) {
-                                Ok(token) => token,
-                                Err(_) => {
-                                    let mutex = CACHED_UTF8_ENCODINGS
-                                        .get_or_init(|| Mutex::new(Vec::new()));
-                                    let mut cached_encodings = mutex.lock().map_err(|e| {
-                                            let err_msg = format!(
-                                                "Fail to acquire the lock of `UTF8_ENCODINGS`. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    // cache the bytes for future decoding
-                                    cached_encodings.extend_from_slice(&output_buffer[..]);
-
-                                    match String::from_utf8(cached_encodings.to_vec()) {
-                                        Ok(token) => {
-                                            // clear encodings
-                                            cached_encodings.clear();
-
-                                            token
-                                        }
-                                        Err(e) => {
-                                            // TODO This is a temp check. In case, infinite cached encodings happen.
-                                            if cached_encodings.len() > 4 {
-                                                let err_msg = format!("Fail to convert a vector of bytes to string. The length of the utf8 bytes exceeds 4. {}", e);
-
-                                                #[cfg(feature = "logging")]
-                                                error!(target: "stdout", "{}", &err_msg);
-
-                                                return Err(LlamaCoreError::Operation(err_msg));
-                                            } else {
-                                                let warn_msg = format!("Fail to convert a vector of bytes to string. {}", e);
-
-                                                #[cfg(feature = "logging")]
-                                                warn!(target: "stdout", "{}", &warn_msg);
-
-                                                String::from(" ")
-                                            }
-                                        }
-                                    }
-                                }
-                            };
+                        #[cfg(feature = "logging")]
+                        warn!(target: "stdout", "{}", &warn_msg);
 
-                            #[cfg(feature = "logging")]
-                            info!(target: "stdout", "decoded the output buffer");
+                        String::from(" ")
+                    }
+                }
+            };
 
-                            let created = SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .map_err(|e| {
-                                let err_msg =
-                                    format!("Failed to get the current time. Reason: {}", e);
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "decoded the output buffer");
 
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
+            // retrieve the log probabilities of the generated token
+            if let (Some(top_logprobs), Some(logprobs)) = (top_logprobs, choice.logprobs.as_mut()) {
+                logprobs.extend(get_logprobs_by_graph(graph, top_logprobs, true)?);
+            }
 
-                                LlamaCoreError::Operation(err_msg)
-                            })?;
-
-                            let chat_completion_chunk = ChatCompletionChunk {
-                                id,
-                                object: "chat.completion.chunk".to_string(),
-                                created: created.as_secs(),
-                                model: graph.name().to_owned(),
-                                system_fingerprint: "fp_44709d6fcb".to_string(),
-                                choices: vec![ChatCompletionChunkChoice {
-                                    index: 0,
-                                    delta: ChatCompletionChunkChoiceDelta {
-                                        role: ChatCompletionRole::Assistant,
-                                        content: Some(output),
-                                        tool_calls: vec![],
-                                    },
-                                    logprobs: None,
-                                    finish_reason: None,
-                                }],
-                                usage: None,
-                            };
+            // check the stop sequences
+            let (output, finish_reason) = match choice.stop_sequences.push(&output) {
+                (output, true) => {
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "Hit a stop sequence. choice: {}", choice.index);
 
-                            #[cfg(feature = "logging")]
-                            info!(target: "stdout", "created chat completion chunk");
+                    (output, Some(FinishReason::stop))
+                }
+                (output, false) => (output, None),
+            };
 
-                            // serialize chat completion chunk
-                            let chunk_str =
-                                serde_json::to_string(&chat_completion_chunk).map_err(|e| {
-                                    let err_msg = format!(
-                                        "Failed to serialize chat completion chunk. Reason: {}",
-                                        e
-                                    );
+            // the log probabilities are sent along with the text released by the stop sequences check
+            let logprobs = match output.is_empty() && finish_reason.is_none() {
+                true => None,
+                false => choice.take_logprobs(),
+            };
 
-                                    #[cfg(feature = "logging")]
-                                    error!(target: "stdout", "{}", &err_msg);
+            // detect the tool calls
+            let (content, tool_calls) = match choice.tool_calls.as_mut() {
+                Some(tool_calls) => tool_call_deltas_to_chunk(tool_calls.push(&output)),
+                None => (Some(output), vec![]),
+            };
 
-                                    LlamaCoreError::Operation(err_msg)
-                                })?;
+            let chunk_choice = ChatCompletionChunkChoice {
+                index: choice.index,
+                delta: ChatCompletionChunkChoiceDelta {
+                    role: ChatCompletionRole::Assistant,
+                    content,
+                    tool_calls,
+                },
+                logprobs,
+                finish_reason,
+            };
 
-                            Ok(format!("data: {}\n\n", chunk_str))
-                        }
-                        Err(wasmedge_wasi_nn::Error::BackendError(
-                            wasmedge_wasi_nn::BackendError::EndOfSequence,
-                        )) => {
-                            match stream_state {
-                                StreamState::Usage => {
-                                    *stream_state = StreamState::Done;
-
-                                    // retrieve the number of prompt and completion tokens
-                                    let token_info = get_token_info_by_graph(graph)?;
-
-                                    let usage = Some(Usage {
-                                        prompt_tokens: token_info.prompt_tokens,
-                                        completion_tokens: token_info.completion_tokens,
-                                        total_tokens: token_info.prompt_tokens
-                                            + token_info.completion_tokens,
-                                    });
-
-                                    #[cfg(feature = "logging")]
-                                    info!(target: "stdout", "token_info: {} prompt tokens, {} completion tokens", token_info.prompt_tokens, token_info.completion_tokens);
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![],
-                                        usage,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                StreamState::Done => {
-                                    *stream_state = StreamState::EndOfSequence;
-
-                                    Ok("data: [DONE]\n\n".to_string())
-                                }
-                                StreamState::EndOfSequence => {
-                                    Ok("[GGML] End of sequence".to_string())
-                                }
-                            }
-                        }
-                        Err(wasmedge_wasi_nn::Error::BackendError(
-                            wasmedge_wasi_nn::BackendError::ContextFull,
-                        )) => {
-                            match context_full_state {
-                                ContextFullState::Message => {
-                                    match include_usage {
-                                        true => *context_full_state = ContextFullState::Usage,
-                                        false => *context_full_state = ContextFullState::Done,
-                                    }
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![ChatCompletionChunkChoice {
-                                            index: 0,
-                                            delta: ChatCompletionChunkChoiceDelta {
-                                                role: ChatCompletionRole::Assistant,
-                                                content: Some(
-                                                    "<|WASMEDGE-GGML-CONTEXT-FULL|>".to_string(),
-                                                ),
-                                                tool_calls: vec![],
-                                            },
-                                            logprobs: None,
-                                            finish_reason: Some(FinishReason::length),
-                                        }],
-                                        usage: None,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                ContextFullState::Usage => {
-                                    *context_full_state = ContextFullState::Done;
-
-                                    // retrieve the number of prompt and completion tokens
-                                    let token_info = get_token_info_by_graph(graph)?;
-
-                                    let usage = Some(Usage {
-                                        prompt_tokens: token_info.prompt_tokens,
-                                        completion_tokens: token_info.completion_tokens,
-                                        total_tokens: token_info.prompt_tokens
-                                            + token_info.completion_tokens,
-                                    });
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![],
-                                        usage,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                ContextFullState::Done => {
-                                    *context_full_state = ContextFullState::EndOfSequence;
-
-                                    Ok("data: [DONE]\n\n".to_string())
-                                }
-                                ContextFullState::EndOfSequence => {
-                                    Ok("[GGML] End of sequence".to_string())
-                                }
-                            }
-                        }
-                        Err(wasmedge_wasi_nn::Error::BackendError(
-                            wasmedge_wasi_nn::BackendError::PromptTooLong,
-                        )) => {
-                            match prompt_too_long_state {
-                                PromptTooLongState::Message => {
-                                    match include_usage {
-                                        true => *prompt_too_long_state = PromptTooLongState::Usage,
-                                        false => *prompt_too_long_state = PromptTooLongState::Done,
-                                    }
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![ChatCompletionChunkChoice {
-                                            index: 0,
-                                            delta: ChatCompletionChunkChoiceDelta {
-                                                role: ChatCompletionRole::Assistant,
-                                                content: None,
-                                                tool_calls: vec![],
-                                            },
-                                            logprobs: None,
-                                            finish_reason: Some(FinishReason::length),
-                                        }],
-                                        usage: None,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                PromptTooLongState::Usage => {
-                                    *prompt_too_long_state = PromptTooLongState::Done;
-
-                                    // retrieve the number of prompt and completion tokens
-                                    let token_info = get_token_info_by_graph(graph)?;
-
-                                    let usage = Some(Usage {
-                                        prompt_tokens: token_info.prompt_tokens,
-                                        completion_tokens: token_info.completion_tokens,
-                                        total_tokens: token_info.prompt_tokens
-                                            + token_info.completion_tokens,
-                                    });
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![],
-                                        usage,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                PromptTooLongState::Done => {
-                                    *prompt_too_long_state = PromptTooLongState::EndOfSequence;
-
-                                    Ok("data: [DONE]\n\n".to_string())
-                                }
-                                PromptTooLongState::EndOfSequence => {
-                                    Ok("[GGML] End of sequence".to_string())
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            let err_msg =
-                                format!("Failed to compute the chat completion. Reason: {}", e);
+            serialize_chat_chunk(
+                id,
+                graph.name(),
+                &system_fingerprint,
+                vec![chunk_choice],
+                None,
+            )
+            .map(Some)
+        }
+        Err(wasmedge_wasi_nn::Error::BackendError(
+            wasmedge_wasi_nn::BackendError::EndOfSequence
+            | wasmedge_wasi_nn::BackendError::ContextFull,
+        )) if choice.stop_sequences.has_pending()
+            || choice.tool_calls.as_ref().is_some_and(|t| !t.is_finished()) =>
+        {
+            // flush the text held back while checking the stop sequences
+            let output = choice.stop_sequences.flush();
+            let logprobs = choice.take_logprobs();
+
+            // flush the text held back while detecting the tool calls
+            let (content, tool_calls, finish_reason) = match choice.tool_calls.as_mut() {
+                Some(tool_calls) => {
+                    let mut deltas = tool_calls.push(&output);
+                    deltas.extend(tool_calls.finish());
+
+                    let finish_reason =
+                        match tool_calls.has_tool_calls() && !choice.stop_sequences.is_hit() {
+                            true => Some(FinishReason::tool_calls),
+                            false => None,
+                        };
 
-                            #[cfg(feature = "logging")]
-                            error!(target: "stdout", "{}", &err_msg);
+                    let (content, tool_calls) = tool_call_deltas_to_chunk(deltas);
 
-                            Err(LlamaCoreError::Backend(BackendError::ComputeSingle(
-                                err_msg,
-                            )))
-                        }
-                    }
+                    (content, tool_calls, finish_reason)
                 }
-                false => {
-                    match chat_graphs.iter_mut().next() {
-                        Some((_, graph)) => {
-                            // compute
-                            match graph.compute_single() {
-                                Ok(_) => {
-                                    #[cfg(feature = "logging")]
-                                    info!(target: "stdout", "Compute the chat stream chunk successfully.");
-
-                                    // Retrieve the output
-                                    let output_buffer =
-                                        get_output_buffer_single(graph, OUTPUT_TENSOR)?;
-
-                                    #[cfg(feature = "logging")]
-                                    info!(target: "stdout", "retrieved the output buffer");
-
-                                    // decode the output buffer to a utf8 string
-                                    let output = match String::from_utf8(output_buffer.clone()) {
-                                        Ok(token) => token,
-                                        Err(_) => {
-                                            let mutex = CACHED_UTF8_ENCODINGS
-                                                .get_or_init(|| Mutex::new(Vec::new()));
-                                            let mut cached_encodings = mutex.lock().map_err(|e| {
-                                            let err_msg = format!(
-                                                "Fail to acquire the lock of `UTF8_ENCODINGS`. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                            // cache the bytes for future decoding
-                                            cached_encodings.extend_from_slice(&output_buffer[..]);
-
-                                            match String::from_utf8(cached_encodings.to_vec()) {
-                                                Ok(token) => {
-                                                    // clear encodings
-                                                    cached_encodings.clear();
-
-                                                    token
-                                                }
-                                                Err(e) => {
-                                                    // TODO This is a temp check. In case, infinite cached encodings happen.
-                                                    if cached_encodings.len() > 4 {
-                                                        let err_msg = format!("Fail to convert a vector of bytes to string. The length of the utf8 bytes exceeds 4. {}", e);
-
-                                                        #[cfg(feature = "logging")]
-                                                        error!(target: "stdout", "{}", &err_msg);
-
-                                                        return Err(LlamaCoreError::Operation(
-                                                            err_msg,
-                                                        ));
-                                                    } else {
-                                                        let warn_msg = format!("Fail to convert a vector of bytes to string. {}", e);
-
-                                                        #[cfg(feature = "logging")]
-                                                        warn!(target: "stdout", "{}", &warn_msg);
-
-                                                        String::from(" ")
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    };
-
-                                    #[cfg(feature = "logging")]
-                                    info!(target: "stdout", "decoded the output buffer");
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![ChatCompletionChunkChoice {
-                                            index: 0,
-                                            delta: ChatCompletionChunkChoiceDelta {
-                                                role: ChatCompletionRole::Assistant,
-                                                content: Some(output),
-                                                tool_calls: vec![],
-                                            },
-                                            logprobs: None,
-                                            finish_reason: None,
-                                        }],
-                                        usage: None,
-                                    };
-
-                                    #[cfg(feature = "logging")]
-                                    info!(target: "stdout", "created chat completion chunk");
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                Err(wasmedge_wasi_nn::Error::BackendError(
-                                    wasmedge_wasi_nn::BackendError::EndOfSequence,
-                                )) => {
-                                    match stream_state {
-                                        StreamState::Usage => {
-                                            *stream_state = StreamState::Done;
-
-                                            // retrieve the number of prompt and completion tokens
-                                            let token_info = get_token_info_by_graph(graph)?;
-
-                                            let usage = Some(Usage {
-                                                prompt_tokens: token_info.prompt_tokens,
-                                                completion_tokens: token_info.completion_tokens,
-                                                total_tokens: token_info.prompt_tokens
-                                                    + token_info.completion_tokens,
-                                            });
-
-                                            #[cfg(feature = "logging")]
-                                            info!(target: "stdout", "token_info: {} prompt tokens, {} completion tokens", token_info.prompt_tokens, token_info.completion_tokens);
-
-                                            let created = SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .map_err(|e| {
-                                                    let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                                    #[cfg(feature = "logging")]
-                                                    error!(target: "stdout", "{}", &err_msg);
-
-                                                    LlamaCoreError::Operation(err_msg)
-                                                })?;
-
-                                            let chat_completion_chunk = ChatCompletionChunk {
-                                                id,
-                                                object: "chat.completion.chunk".to_string(),
-                                                created: created.as_secs(),
-                                                model: graph.name().to_owned(),
-                                                system_fingerprint: "fp_44709d6fcb".to_string(),
-                                                choices: vec![],
-                                                usage,
-                                            };
-
-                                            // serialize chat completion chunk
-                                            let chunk_str =
-                                                serde_json::to_string(&chat_completion_chunk)
-                                                    .map_err(|e| {
-                                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                                        #[cfg(feature = "logging")]
-                                                        error!(target: "stdout", "{}", &err_msg);
-
-                                                        LlamaCoreError::Operation(err_msg)
-                                                    })?;
-
-                                            Ok(format!("data: {}\n\n", chunk_str))
-                                        }
-                                        StreamState::Done => {
-                                            *stream_state = StreamState::EndOfSequence;
-
-                                            Ok("data: [DONE]\n\n".to_string())
-                                        }
-                                        StreamState::EndOfSequence => {
-                                            Ok("[GGML] End of sequence".to_string())
-                                        }
-                                    }
-                                }
-                                Err(wasmedge_wasi_nn::Error::BackendError(
-                                    wasmedge_wasi_nn::BackendError::ContextFull,
-                                )) => {
-                                    match context_full_state {
-                                        ContextFullState::Message => {
-                                            match include_usage {
-                                                true => {
-                                                    *context_full_state = ContextFullState::Usage
-                                                }
-                                                false => {
-                                                    *context_full_state = ContextFullState::Done
-                                                }
-                                            }
-
-                                            let created = SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .map_err(|e| {
-                                                    let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                                    #[cfg(feature = "logging")]
-                                                    error!(target: "stdout", "{}", &err_msg);
-
-                                                    LlamaCoreError::Operation(err_msg)
-                                                })?;
-
-                                            let chat_completion_chunk = ChatCompletionChunk {
-                                                id,
-                                                object: "chat.completion.chunk".to_string(),
-                                                created: created.as_secs(),
-                                                model: graph.name().to_owned(),
-                                                system_fingerprint: "fp_44709d6fcb".to_string(),
-                                                choices: vec![ChatCompletionChunkChoice {
-                                                    index: 0,
-                                                    delta: ChatCompletionChunkChoiceDelta {
-                                                        role: ChatCompletionRole::Assistant,
-                                                        content: Some(
-                                                            "<|WASMEDGE-GGML-CONTEXT-FULL|>"
-                                                                .to_string(),
-                                                        ),
-                                                        tool_calls: vec![],
-                                                    },
-                                                    logprobs: None,
-                                                    finish_reason: Some(FinishReason::length),
-                                                }],
-                                                usage: None,
-                                            };
-
-                                            // serialize chat completion chunk
-                                            let chunk_str =
-                                                serde_json::to_string(&chat_completion_chunk)
-                                                    .map_err(|e| {
-                                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                                        #[cfg(feature = "logging")]
-                                                        error!(target: "stdout", "{}", &err_msg);
-
-                                                        LlamaCoreError::Operation(err_msg)
-                                                    })?;
-
-                                            Ok(format!("data: {}\n\n", chunk_str))
-                                        }
-                                        ContextFullState::Usage => {
-                                            *context_full_state = ContextFullState::Done;
-
-                                            // retrieve the number of prompt and completion tokens
-                                            let token_info = get_token_info_by_graph(graph)?;
-
-                                            let usage = Some(Usage {
-                                                prompt_tokens: token_info.prompt_tokens,
-                                                completion_tokens: token_info.completion_tokens,
-                                                total_tokens: token_info.prompt_tokens
-                                                    + token_info.completion_tokens,
-                                            });
-
-                                            let created = SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .map_err(|e| {
-                                                    let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                                    #[cfg(feature = "logging")]
-                                                    error!(target: "stdout", "{}", &err_msg);
-
-                                                    LlamaCoreError::Operation(err_msg)
-                                                })?;
-
-                                            let chat_completion_chunk = ChatCompletionChunk {
-                                                id,
-                                                object: "chat.completion.chunk".to_string(),
-                                                created: created.as_secs(),
-                                                model: graph.name().to_owned(),
-                                                system_fingerprint: "fp_44709d6fcb".to_string(),
-                                                choices: vec![],
-                                                usage,
-                                            };
-
-                                            // serialize chat completion chunk
-                                            let chunk_str =
-                                                serde_json::to_string(&chat_completion_chunk)
-                                                    .map_err(|e| {
-                                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                                        #[cfg(feature = "logging")]
-                                                        error!(target: "stdout", "{}", &err_msg);
-
-                                                        LlamaCoreError::Operation(err_msg)
-                                                    })?;
-
-                                            Ok(format!("data: {}\n\n", chunk_str))
-                                        }
-                                        ContextFullState::Done => {
-                                            *context_full_state = ContextFullState::EndOfSequence;
-
-                                            Ok("data: [DONE]\n\n".to_string())
-                                        }
-                                        ContextFullState::EndOfSequence => {
-                                            Ok("[GGML] End of sequence".to_string())
-                                        }
-                                    }
-                                }
-                                Err(wasmedge_wasi_nn::Error::BackendError(
-                                    wasmedge_wasi_nn::BackendError::PromptTooLong,
-                                )) => {
-                                    match prompt_too_long_state {
-                                        PromptTooLongState::Message => {
-                                            match include_usage {
-                                                true => {
-                                                    *prompt_too_long_state =
-                                                        PromptTooLongState::Usage
-                                                }
-                                                false => {
-                                                    *prompt_too_long_state =
-                                                        PromptTooLongState::Done
-                                                }
-                                            }
-
-                                            let created = SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .map_err(|e| {
-                                                    let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                                    #[cfg(feature = "logging")]
-                                                    error!(target: "stdout", "{}", &err_msg);
-
-                                                    LlamaCoreError::Operation(err_msg)
-                                                })?;
-
-                                            let chat_completion_chunk = ChatCompletionChunk {
-                                                id,
-                                                object: "chat.completion.chunk".to_string(),
-                                                created: created.as_secs(),
-                                                model: graph.name().to_owned(),
-                                                system_fingerprint: "fp_44709d6fcb".to_string(),
-                                                choices: vec![ChatCompletionChunkChoice {
-                                                    index: 0,
-                                                    delta: ChatCompletionChunkChoiceDelta {
-                                                        role: ChatCompletionRole::Assistant,
-                                                        content: None,
-                                                        tool_calls: vec![],
-                                                    },
-                                                    logprobs: None,
-                                                    finish_reason: Some(FinishReason::length),
-                                                }],
-                                                usage: None,
-                                            };
-
-                                            // serialize chat completion chunk
-                                            let chunk_str =
-                                                serde_json::to_string(&chat_completion_chunk)
-                                                    .map_err(|e| {
-                                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                                        #[cfg(feature = "logging")]
-                                                        error!(target: "stdout", "{}", &err_msg);
-
-                                                        LlamaCoreError::Operation(err_msg)
-                                                    })?;
-
-                                            Ok(format!("data: {}\n\n", chunk_str))
-                                        }
-                                        PromptTooLongState::Usage => {
-                                            *prompt_too_long_state = PromptTooLongState::Done;
-
-                                            // retrieve the number of prompt and completion tokens
-                                            let token_info = get_token_info_by_graph(graph)?;
-
-                                            let usage = Some(Usage {
-                                                prompt_tokens: token_info.prompt_tokens,
-                                                completion_tokens: token_info.completion_tokens,
-                                                total_tokens: token_info.prompt_tokens
-                                                    + token_info.completion_tokens,
-                                            });
-
-                                            let created = SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .map_err(|e| {
-                                                    let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                                    #[cfg(feature = "logging")]
-                                                    error!(target: "stdout", "{}", &err_msg);
-
-                                                    LlamaCoreError::Operation(err_msg)
-                                                })?;
-
-                                            let chat_completion_chunk = ChatCompletionChunk {
-                                                id,
-                                                object: "chat.completion.chunk".to_string(),
-                                                created: created.as_secs(),
-                                                model: graph.name().to_owned(),
-                                                system_fingerprint: "fp_44709d6fcb".to_string(),
-                                                choices: vec![],
-                                                usage,
-                                            };
-
-                                            // serialize chat completion chunk
-                                            let chunk_str =
-                                                serde_json::to_string(&chat_completion_chunk)
-                                                    .map_err(|e| {
-                                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                                        #[cfg(feature = "logging")]
-                                                        error!(target: "stdout", "{}", &err_msg);
-
-                                                        LlamaCoreError::Operation(err_msg)
-                                                    })?;
-
-                                            Ok(format!("data: {}\n\n", chunk_str))
-                                        }
-                                        PromptTooLongState::Done => {
-                                            *prompt_too_long_state =
-                                                PromptTooLongState::EndOfSequence;
-
-                                            Ok("data: [DONE]\n\n".to_string())
-                                        }
-                                        PromptTooLongState::EndOfSequence => {
-                                            Ok("[GGML] End of sequence".to_string())
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    let err_msg = format!(
-                                        "Failed to compute the chat completion. Reason: {}",
-                                        e
-                                    );
-
-                                    #[cfg(feature = "logging")]
-                                    error!(target: "stdout", "{}", &err_msg);
-
-                                    Err(LlamaCoreError::Backend(BackendError::ComputeSingle(
-                                        err_msg,
-                                    )))
-                                }
-                            }
-                        }
-                        None => {
-                            let err_msg = "There is no model available in the chat graphs.";
+                None => (Some(output), vec![], None),
+            };
 
-                            #[cfg(feature = "logging")]
-                            error!(target: "stdout", "{}", &err_msg);
+            let chunk_choice = ChatCompletionChunkChoice {
+                index: choice.index,
+                delta: ChatCompletionChunkChoiceDelta {
+                    role: ChatCompletionRole::Assistant,
+                    content,
+                    tool_calls,
+                },
+                logprobs,
+                finish_reason,
+            };
 
-                            Err(LlamaCoreError::Operation(err_msg.into()))
-                        }
-                    }
-                }
-            }
+            serialize_chat_chunk(
+                id,
+                graph.name(),
+                &system_fingerprint,
+                vec![chunk_choice],
+                None,
+            )
+            .map(Some)
         }
-        None => {
-            match chat_graphs.iter_mut().next() {
-                Some((_, graph)) => {
-                    // compute
-                    match graph.compute_single() {
-                        Ok(_) => {
-                            #[cfg(feature = "logging")]
-                            info!(target: "stdout", "Compute the chat stream chunk successfully.");
+        Err(wasmedge_wasi_nn::Error::BackendError(
+            backend_error @ (wasmedge_wasi_nn::BackendError::EndOfSequence
+            | wasmedge_wasi_nn::BackendError::ContextFull
+            | wasmedge_wasi_nn::BackendError::PromptTooLong),
+        )) => {
+            choice.finished = true;
 
-                            // Retrieve the output
-                            let output_buffer = get_output_buffer_single(graph, OUTPUT_TENSOR)?;
+            // retrieve the number of prompt and completion tokens of the finished choice. The choices share the prompt, so the prompt is counted once.
+            let token_info = get_token_info_by_graph(graph)?;
+            usage.prompt_tokens = token_info.prompt_tokens;
+            usage.completion_tokens += token_info.completion_tokens;
 
-                            #[cfg(feature = "logging")]
-                            info!(target: "stdout", "retrieved the output buffer");
-
-                            // decode the output buffer to a utf8 string
-                            let output = match String::from_utf8(output_buffer.clone()) {
-                                Ok(token) => token,
-                                Err(_) => {
-                                    let mutex = CACHED_UTF8_ENCODINGS
-                                        .get_or_init(|| Mutex::new(Vec::new()));
-                                    let mut cached_encodings = mutex.lock().map_err(|e| {
-                                            let err_msg = format!(
-                                                "Fail to acquire the lock of `UTF8_ENCODINGS`. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    cached_encodings.extend_from_slice(&output_buffer[..]);
-
-                                    match String::from_utf8(cached_encodings.to_vec()) {
-                                        Ok(token) => {
-                                            // clear encodings
-                                            cached_encodings.clear();
-
-                                            token
-                                        }
-                                        Err(e) => {
-                                            // TODO This is a temp check. In case, infinite cached encodings happen.
-                                            if cached_encodings.len() > 4 {
-                                                let err_msg = format!("Fail to convert a vector of bytes to string. The length of the utf8 bytes exceeds 4. {}", e);
-
-                                                #[cfg(feature = "logging")]
-                                                error!(target: "stdout", "{}", &err_msg);
-
-                                                return Err(LlamaCoreError::Operation(err_msg));
-                                            } else {
-                                                let warn_msg = format!("Fail to convert a vector of bytes to string. {}", e);
-
-                                                #[cfg(feature = "logging")]
-                                                warn!(target: "stdout", "{}", &warn_msg);
-
-                                                String::from(" ")
-                                            }
-                                        }
-                                    }
-                                }
-                            };
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Finish the choice {}. {} completion tokens", choice.index, token_info.completion_tokens);
 
-                            #[cfg(feature = "logging")]
-                            info!(target: "stdout", "decoded the output buffer");
+            let (content, finish_reason) = match backend_error {
+                wasmedge_wasi_nn::BackendError::PromptTooLong => {
+                    #[cfg(feature = "logging")]
+                    warn!(target: "stdout", "The prompt is too long. Please reduce the length of your input and try again.");
+
+                    (None, FinishReason::length)
+                }
+                // the finish reason is already sent if a stop sequence is hit or any tool call is detected
+                _ if choice.stop_sequences.is_hit()
+                    || choice
+                        .tool_calls
+                        .as_ref()
+                        .is_some_and(|t| t.has_tool_calls()) =>
+                {
+                    return Ok(None)
+                }
+                wasmedge_wasi_nn::BackendError::ContextFull => (
+                    Some("<|WASMEDGE-GGML-CONTEXT-FULL|>".to_string()),
+                    FinishReason::length,
+                ),
+                _ => (None, FinishReason::stop),
+            };
 
-                            let created = SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .map_err(|e| {
-                                let err_msg =
-                                    format!("Failed to get the current time. Reason: {}", e);
+            let chunk_choice = ChatCompletionChunkChoice {
+                index: choice.index,
+                delta: ChatCompletionChunkChoiceDelta {
+                    role: ChatCompletionRole::Assistant,
+                    content,
+                    tool_calls: vec![],
+                },
+                logprobs: None,
+                finish_reason: Some(finish_reason),
+            };
 
-                                #[cfg(feature = "logging")]
-                                error!(target: "stdout", "{}", &err_msg);
+            serialize_chat_chunk(
+                id,
+                graph.name(),
+                &system_fingerprint,
+                vec![chunk_choice],
+                None,
+            )
+            .map(Some)
+        }
+        Err(e) => {
+            choice.finished = true;
 
-                                LlamaCoreError::Operation(err_msg)
-                            })?;
-
-                            let chat_completion_chunk = ChatCompletionChunk {
-                                id,
-                                object: "chat.completion.chunk".to_string(),
-                                created: created.as_secs(),
-                                model: graph.name().to_owned(),
-                                system_fingerprint: "fp_44709d6fcb".to_string(),
-                                choices: vec![ChatCompletionChunkChoice {
-                                    index: 0,
-                                    delta: ChatCompletionChunkChoiceDelta {
-                                        role: ChatCompletionRole::Assistant,
-                                        content: Some(output),
-                                        tool_calls: vec![],
-                                    },
-                                    logprobs: None,
-                                    finish_reason: None,
-                                }],
-                                usage: None,
-                            };
+            let err_msg = format!("Failed to compute the chat completion. Reason: {}", e);
 
-                            #[cfg(feature = "logging")]
-                            info!(target: "stdout", "created chat completion chunk");
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
 
-                            // serialize chat completion chunk
-                            let chunk_str =
-                                serde_json::to_string(&chat_completion_chunk).map_err(|e| {
-                                    let err_msg = format!(
-                                        "Failed to serialize chat completion chunk. Reason: {}",
-                                        e
-                                    );
+            Err(LlamaCoreError::Backend(BackendError::ComputeSingle(
+                err_msg,
+            )))
+        }
+    }
+}
 
-                                    #[cfg(feature = "logging")]
-                                    error!(target: "stdout", "{}", &err_msg);
+/// Serialize a chat completion chunk to a server-sent event.
+fn serialize_chat_chunk(
+    id: &str,
+    model: &str,
+    system_fingerprint: &str,
+    choices: Vec<ChatCompletionChunkChoice>,
+    usage: Option<Usage>,
+) -> Result<String, LlamaCoreError> {
+    let created = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| {
+            let err_msg = format!("Failed to get the current time. Reason: {}", e);
 
-                                    LlamaCoreError::Operation(err_msg)
-                                })?;
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
 
-                            Ok(format!("data: {}\n\n", chunk_str))
-                        }
-                        Err(wasmedge_wasi_nn::Error::BackendError(
-                            wasmedge_wasi_nn::BackendError::EndOfSequence,
-                        )) => {
-                            match stream_state {
-                                StreamState::Usage => {
-                                    *stream_state = StreamState::Done;
-
-                                    // retrieve the number of prompt and completion tokens
-                                    let token_info = get_token_info_by_graph(graph)?;
-
-                                    let usage = Some(Usage {
-                                        prompt_tokens: token_info.prompt_tokens,
-                                        completion_tokens: token_info.completion_tokens,
-                                        total_tokens: token_info.prompt_tokens
-                                            + token_info.completion_tokens,
-                                    });
-
-                                    #[cfg(feature = "logging")]
-                                    info!(target: "stdout", "token_info: {} prompt tokens, {} completion tokens", token_info.prompt_tokens, token_info.completion_tokens);
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![],
-                                        usage,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                StreamState::Done => {
-                                    *stream_state = StreamState::EndOfSequence;
-
-                                    Ok("data: [DONE]\n\n".to_string())
-                                }
-                                StreamState::EndOfSequence => {
-                                    Ok("[GGML] End of sequence".to_string())
-                                }
-                            }
-                        }
-                        Err(wasmedge_wasi_nn::Error::BackendError(
-                            wasmedge_wasi_nn::BackendError::ContextFull,
-                        )) => {
-                            match context_full_state {
-                                ContextFullState::Message => {
-                                    match include_usage {
-                                        true => *context_full_state = ContextFullState::Usage,
-                                        false => *context_full_state = ContextFullState::Done,
-                                    }
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![ChatCompletionChunkChoice {
-                                            index: 0,
-                                            delta: ChatCompletionChunkChoiceDelta {
-                                                role: ChatCompletionRole::Assistant,
-                                                content: Some(
-                                                    "<|WASMEDGE-GGML-CONTEXT-FULL|>".to_string(),
-                                                ),
-                                                tool_calls: vec![],
-                                            },
-                                            logprobs: None,
-                                            finish_reason: Some(FinishReason::length),
-                                        }],
-                                        usage: None,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                ContextFullState::Usage => {
-                                    *context_full_state = ContextFullState::Done;
-
-                                    // retrieve the number of prompt and completion tokens
-                                    let token_info = get_token_info_by_graph(graph)?;
-
-                                    let usage = Some(Usage {
-                                        prompt_tokens: token_info.prompt_tokens,
-                                        completion_tokens: token_info.completion_tokens,
-                                        total_tokens: token_info.prompt_tokens
-                                            + token_info.completion_tokens,
-                                    });
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![],
-                                        usage,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                ContextFullState::Done => {
-                                    *context_full_state = ContextFullState::EndOfSequence;
-
-                                    Ok("data: [DONE]\n\n".to_string())
-                                }
-                                ContextFullState::EndOfSequence => {
-                                    Ok("[GGML] End of sequence".to_string())
-                                }
-                            }
-                        }
-                        Err(wasmedge_wasi_nn::Error::BackendError(
-                            wasmedge_wasi_nn::BackendError::PromptTooLong,
-                        )) => {
-                            match prompt_too_long_state {
-                                PromptTooLongState::Message => {
-                                    match include_usage {
-                                        true => *prompt_too_long_state = PromptTooLongState::Usage,
-                                        false => *prompt_too_long_state = PromptTooLongState::Done,
-                                    }
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![ChatCompletionChunkChoice {
-                                            index: 0,
-                                            delta: ChatCompletionChunkChoiceDelta {
-                                                role: ChatCompletionRole::Assistant,
-                                                content: None,
-                                                tool_calls: vec![],
-                                            },
-                                            logprobs: None,
-                                            finish_reason: Some(FinishReason::length),
-                                        }],
-                                        usage: None,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                PromptTooLongState::Usage => {
-                                    *prompt_too_long_state = PromptTooLongState::Done;
-
-                                    // retrieve the number of prompt and completion tokens
-                                    let token_info = get_token_info_by_graph(graph)?;
-
-                                    let usage = Some(Usage {
-                                        prompt_tokens: token_info.prompt_tokens,
-                                        completion_tokens: token_info.completion_tokens,
-                                        total_tokens: token_info.prompt_tokens
-                                            + token_info.completion_tokens,
-                                    });
-
-                                    let created = SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .map_err(|e| {
-                                            let err_msg = format!(
-                                                "Failed to get the current time. Reason: {}",
-                                                e
-                                            );
-
-                                            #[cfg(feature = "logging")]
-                                            error!(target: "stdout", "{}", &err_msg);
-
-                                            LlamaCoreError::Operation(err_msg)
-                                        })?;
-
-                                    let chat_completion_chunk = ChatCompletionChunk {
-                                        id,
-                                        object: "chat.completion.chunk".to_string(),
-                                        created: created.as_secs(),
-                                        model: graph.name().to_owned(),
-                                        system_fingerprint: "fp_44709d6fcb".to_string(),
-                                        choices: vec![],
-                                        usage,
-                                    };
-
-                                    // serialize chat completion chunk
-                                    let chunk_str = serde_json::to_string(&chat_completion_chunk)
-                                        .map_err(|e| {
-                                        let err_msg = format!(
-                                            "Failed to serialize chat completion chunk. Reason: {}",
-                                            e
-                                        );
-
-                                        #[cfg(feature = "logging")]
-                                        error!(target: "stdout", "{}", &err_msg);
-
-                                        LlamaCoreError::Operation(err_msg)
-                                    })?;
-
-                                    Ok(format!("data: {}\n\n", chunk_str))
-                                }
-                                PromptTooLongState::Done => {
-                                    *prompt_too_long_state = PromptTooLongState::EndOfSequence;
-
-                                    Ok("data: [DONE]\n\n".to_string())
-                                }
-                                PromptTooLongState::EndOfSequence => {
-                                    Ok("[GGML] End of sequence".to_string())
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            let err_msg =
-                                format!("Failed to compute the chat completion. Reason: {}", e);
+            LlamaCoreError::Operation(err_msg)
+        })?;
 
-                            #[cfg(feature = "logging")]
-                            error!(target: "stdout", "{}", &err_msg);
+    let chat_completion_chunk = ChatCompletionChunk {
+        id: id.to_string(),
+        object: "chat.completion.chunk".to_string(),
+        created: created.as_secs(),
+        model: model.to_owned(),
+        system_fingerprint: system_fingerprint.to_owned(),
+        choices,
+        usage,
+    };
 
-                            Err(LlamaCoreError::Backend(BackendError::ComputeSingle(
-                                err_msg,
-                            )))
-                        }
-                    }
-                }
-                None => {
-                    let err_msg = "There is no model available in the chat graphs.";
+    // serialize chat completion chunk
+    let chunk_str = serde_json::to_string(&chat_completion_chunk).map_err(|e| {
+        let err_msg = format!("Failed to serialize chat completion chunk. Reason: {}", e);
 
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
 
-                    Err(LlamaCoreError::Operation(err_msg.into()))
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    Ok(format!("data: {}\n\n", chunk_str))
+}
+
+/// Convert the deltas detected in the generated tokens to the content and the tool calls of a chat completion chunk.
+fn tool_call_deltas_to_chunk(
+    deltas: Vec<ToolCallDelta>,
+) -> (Option<String>, Vec<ToolCallForChunk>) {
+    let mut content = String::new();
+    let mut tool_calls: Vec<ToolCallForChunk> = vec![];
+    for delta in deltas {
+        match delta {
+            ToolCallDelta::Content(text) => content.push_str(&text),
+            ToolCallDelta::Start { index, name } => tool_calls.push(ToolCallForChunk {
+                index,
+                id: gen_tool_call_id(),
+                ty: "function".to_string(),
+                function: Function {
+                    name,
+                    arguments: String::new(),
+                },
+            }),
+            ToolCallDelta::Arguments { index, fragment } => match tool_calls.last_mut() {
+                Some(tool_call) if tool_call.index == index => {
+                    tool_call.function.arguments.push_str(&fragment)
                 }
-            }
+                // the id, type and name are only sent in the first delta of a tool call
+                _ => tool_calls.push(ToolCallForChunk {
+                    index,
+                    id: String::new(),
+                    ty: "function".to_string(),
+                    function: Function {
+                        name: String::new(),
+                        arguments: fragment,
+                    },
+                }),
+            },
         }
-    };
+    }
 
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Return the chat stream chunk!");
+    let content = match content.is_empty() && !tool_calls.is_empty() {
+        true => None,
+        false => Some(content),
+    };
 
-    res
+    (content, tool_calls)
 }
 
 #[derive(Debug)]
@@ -4332,3 +2321,33 @@ struct ParseResult {
     content: Option<String>,
     tool_calls: Vec<ToolCall>,
 }
+
+#[test]
+fn test_stream_choices_take_turns() {
+    let mut choices = StreamChoices::new(3, None, None, None);
+
+    // the chunks of the choices are interleaved
+    let mut indices = vec![];
+    while let Some(position) = choices.next_choice() {
+        indices.push(choices.choices[position].index);
+        if indices.len() == 6 {
+            break;
+        }
+    }
+    assert_eq!(indices, vec![0, 1, 2, 0, 1, 2]);
+
+    // the finished choices are skipped
+    choices.choices[1].finished = true;
+    let mut indices = vec![];
+    while let Some(position) = choices.next_choice() {
+        indices.push(choices.choices[position].index);
+        if indices.len() == 4 {
+            break;
+        }
+    }
+    assert_eq!(indices, vec![0, 2, 0, 2]);
+
+    choices.choices[0].finished = true;
+    choices.choices[2].finished = true;
+    assert_eq!(choices.next_choice(), None);
+}
@@ -4,17 +4,40 @@ use crate::{
     error::{BackendError, LlamaCoreError},
     metadata::ggml::GgmlMetadata,
     running_mode,
-    utils::{get_output_buffer, get_token_info_by_graph},
-    Graph, RunningMode, CHAT_GRAPHS, OUTPUT_TENSOR,
+    utils::{
+        get_logprobs_by_graph, get_output_buffer, get_output_buffer_single,
+        get_token_info_by_graph, lease_chat_slot, parse_logit_bias, set_tensor_data_u8,
+        truncate_at_stop_sequence, with_chat_slot, StopSequences,
+    },
+    Graph, RunningMode, SlotLease, OUTPUT_TENSOR,
 };
+use chat_prompts::PromptTemplateType;
+use either::{Either, Left, Right};
 use endpoints::{
+    chat::TokenLogProb,
     common::{FinishReason, Usage},
-    completions::{CompletionChoice, CompletionObject, CompletionPrompt, CompletionRequest},
+    completions::{
+        CompletionChoice, CompletionChunk, CompletionChunkChoice, CompletionObject,
+        CompletionPrompt, CompletionRequest, LogprobResult,
+    },
+};
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
 };
-use std::time::SystemTime;
 
 /// Given a prompt, the model will return one or more predicted completions along with the probabilities of alternative tokens at each position.
-pub async fn completions(request: &CompletionRequest) -> Result<CompletionObject, LlamaCoreError> {
+///
+/// Each prompt in a batch is completed separately. If `stream` is enabled, the completions are returned as a stream of server-sent events; otherwise, a [CompletionObject](endpoints::completions::CompletionObject) is returned.
+pub async fn completions(
+    request: &CompletionRequest,
+) -> Result<
+    Either<impl futures::TryStream<Ok = String, Error = LlamaCoreError>, CompletionObject>,
+    LlamaCoreError,
+> {
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Generate completions");
 
@@ -31,35 +54,241 @@ pub async fn completions(request: &CompletionRequest) -> Result<CompletionObject
         return Err(LlamaCoreError::Operation(err_msg));
     }
 
-    let prompt = match &request.prompt {
-        CompletionPrompt::SingleText(prompt) => prompt.to_owned(),
-        CompletionPrompt::MultiText(prompts) => prompts.join(" "),
+    let prompts = match &request.prompt {
+        CompletionPrompt::SingleText(prompt) => vec![prompt.to_owned()],
+        CompletionPrompt::MultiText(prompts) => prompts.to_owned(),
     };
+    if prompts.is_empty() {
+        let err_msg = "The prompt must not be empty.";
 
-    compute(prompt.trim(), request.model.as_ref())
-}
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", err_msg);
+
+        return Err(LlamaCoreError::Operation(err_msg.into()));
+    }
+
+    // the number of completions to return for each prompt
+    let n = request.n.unwrap_or(1).max(1);
+
+    // the number of candidate completions generated for each prompt
+    let best_of = request.best_of.unwrap_or(n);
+    if best_of < n {
+        let err_msg = format!(
+            "`best_of` must be greater than or equal to `n`, but got best_of: {}, n: {}",
+            best_of, n
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::Operation(err_msg));
+    }
+
+    let stream = request.stream.unwrap_or_default();
+    if stream && best_of > n {
+        let err_msg = "`best_of` cannot be used in the stream mode.";
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", err_msg);
+
+        return Err(LlamaCoreError::Operation(err_msg.into()));
+    }
 
-fn compute(
-    prompt: impl AsRef<str>,
-    model_name: Option<&String>,
-) -> std::result::Result<CompletionObject, LlamaCoreError> {
     #[cfg(feature = "logging")]
-    info!(target: "stdout", "Compute completions");
+    info!(target: "stdout", "prompts: {}, n: {}, best_of: {}, stream: {}", prompts.len(), n, best_of, stream);
+
+    // the number of the most likely tokens to return at each token position. The log probabilities are also required to rank the candidates if `best_of` is greater than `n`.
+    let top_logprobs = match request.logprobs {
+        Some(logprobs) => Some(logprobs.min(u8::MAX as u32) as u8),
+        None if best_of > n => Some(0),
+        None => None,
+    };
+
+    // lease a free execution slot of the model, which is released when the request is done
+    let slot = lease_chat_slot(request.model.as_deref())?;
+
+    // update the model metadata with the options of the request
+    let metadata = update_model_metadata(request, &slot, top_logprobs)?;
+    let prompt_template = metadata.prompt_template();
 
-    let chat_graphs = match CHAT_GRAPHS.get() {
-        Some(chat_graphs) => chat_graphs,
-        None => {
-            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
+    // the metadata is pushed again with the seed of each candidate if a seed is given
+    let seeded_metadata = request.seed.map(|_| metadata);
 
+    // fill in the middle if the suffix is provided
+    let inputs: Vec<String> = match request.suffix.as_deref() {
+        Some(suffix) if !suffix.is_empty() => {
             #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", err_msg);
+            info!(target: "stdout", "Fill in the middle with the suffix. prompt template: {}", prompt_template);
 
-            return Err(LlamaCoreError::Operation(err_msg.into()));
+            prompts
+                .iter()
+                .map(|prompt| build_fim_prompt(prompt_template, prompt, suffix))
+                .collect()
         }
+        _ => prompts.clone(),
     };
 
-    let mut chat_graphs = chat_graphs.lock().map_err(|e| {
-        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e);
+    let id = format!("cmpl-{}", uuid::Uuid::new_v4());
+    let echo = request.echo.unwrap_or_default();
+
+    // send the usage of the request in the last chunk of the stream if it is requested
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .and_then(|stream_options| stream_options.include_usage)
+        .unwrap_or_default();
+
+    match stream {
+        true => {
+            let stream = CompletionStream::new(
+                slot,
+                id,
+                prompts,
+                inputs,
+                n,
+                echo,
+                request.logprobs.map(|_| top_logprobs.unwrap_or_default()),
+                request.max_tokens,
+                request.stop.clone(),
+                seeded_metadata,
+                include_usage,
+            );
+
+            Ok(Left(stream))
+        }
+        false => {
+            let result = compute(
+                &slot,
+                id,
+                &prompts,
+                &inputs,
+                n,
+                best_of,
+                echo,
+                top_logprobs,
+                request.logprobs.is_some(),
+                request.max_tokens,
+                request.stop.as_deref().unwrap_or_default(),
+                seeded_metadata.as_ref(),
+            );
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Reset the model metadata");
+
+            // reset the model metadata
+            reset_model_metadata(&slot)?;
+
+            result.map(Right)
+        }
+    }
+}
+
+/// Build the fill-in-the-middle prompt with the special tokens of the model family, which is inferred from the prompt template of the model. The tokens of Qwen2.5-Coder and StarCoder2 are used by default.
+fn build_fim_prompt(template: PromptTemplateType, prefix: &str, suffix: &str) -> String {
+    match template {
+        PromptTemplateType::CodeLlama | PromptTemplateType::CodeLlamaSuper => {
+            format!("<PRE> {} <SUF>{} <MID>", prefix, suffix)
+        }
+        PromptTemplateType::DeepseekCoder => {
+            format!(
+                "<｜fim▁begin｜>{}<｜fim▁hole｜>{}<｜fim▁end｜>",
+                prefix, suffix
+            )
+        }
+        _ => format!(
+            "<|fim_prefix|>{}<|fim_suffix|>{}<|fim_middle|>",
+            prefix, suffix
+        ),
+    }
+}
+
+/// Update the model metadata with the sampling parameters of the request. The metadata is reset by [reset_model_metadata] after the request is done. Returns the metadata of the request.
+fn update_model_metadata(
+    request: &CompletionRequest,
+    slot: &SlotLease,
+    top_logprobs: Option<u8>,
+) -> Result<GgmlMetadata, LlamaCoreError> {
+    let logit_bias = match &request.logit_bias {
+        Some(logit_bias) => {
+            let logit_bias: HashMap<String, f64> = logit_bias
+                .iter()
+                .map(|(token, &bias)| (token.clone(), bias as f64))
+                .collect();
+
+            Some(parse_logit_bias(&logit_bias)?)
+        }
+        None => None,
+    };
+
+    with_chat_slot(slot, |graph| {
+        // check if the `embedding` model is disabled or not
+        if graph.metadata.embeddings {
+            graph.metadata.embeddings = false;
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "The `embedding` field of metadata sets to false.");
+
+            graph.update_metadata()?;
+        }
+
+        let mut metadata = graph.metadata.clone();
+
+        if let Some(max_tokens) = request.max_tokens {
+            metadata.n_predict = max_tokens as i32;
+        }
+        if let Some(temperature) = request.temperature {
+            metadata.temperature = temperature as f64;
+        }
+        if let Some(top_p) = request.top_p {
+            metadata.top_p = top_p as f64;
+        }
+        if let Some(presence_penalty) = request.presence_penalty {
+            metadata.presence_penalty = presence_penalty as f64;
+        }
+        if let Some(frequency_penalty) = request.frequency_penalty {
+            metadata.frequency_penalty = frequency_penalty as f64;
+        }
+        if let Some(seed) = request.seed {
+            metadata.seed = seed;
+        }
+        if let Some(logit_bias) = logit_bias {
+            metadata.logit_bias = logit_bias;
+        }
+        if let Some(top_logprobs) = top_logprobs {
+            metadata.logprobs = true;
+            metadata.top_logprobs = top_logprobs as u64;
+        }
+
+        let config = serde_json::to_string(&metadata).map_err(|e| {
+            let err_msg = format!("Fail to serialize metadata to a JSON string. {}", e);
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })?;
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Update the model metadata: {}", &config);
+
+        // the original metadata is kept in the graph for resetting
+        set_tensor_data_u8(graph, 1, config.as_bytes())?;
+
+        Ok(metadata)
+    })
+}
+
+/// Push the metadata of the request with the seed of the candidate of a prompt. The candidates are sampled with `seed`, `seed + 1`, ..., so that they differ from each other but are still reproducible.
+fn set_candidate_seed(
+    graph: &mut Graph<GgmlMetadata>,
+    metadata: &GgmlMetadata,
+    candidate: u32,
+) -> Result<(), LlamaCoreError> {
+    let mut metadata = metadata.clone();
+    metadata.seed = metadata.seed.wrapping_add(candidate as u64);
+
+    let config = serde_json::to_string(&metadata).map_err(|e| {
+        let err_msg = format!("Fail to serialize metadata to a JSON string. {}", e);
 
         #[cfg(feature = "logging")]
         error!(target: "stdout", "{}", &err_msg);
@@ -67,84 +296,174 @@ fn compute(
         LlamaCoreError::Operation(err_msg)
     })?;
 
-    match model_name {
-        Some(model_name) => match chat_graphs.contains_key(model_name) {
-            true => {
-                let graph = chat_graphs.get_mut(model_name).unwrap();
-                compute_by_graph(graph, prompt)
+    set_tensor_data_u8(graph, 1, config.as_bytes())
+}
+
+/// Reset the metadata of the slot to the original metadata of the model.
+fn reset_model_metadata(slot: &SlotLease) -> Result<(), LlamaCoreError> {
+    with_chat_slot(slot, |graph| graph.update_metadata())
+}
+
+/// A completion generated by the model for a single prompt.
+struct Completion {
+    text: String,
+    finish_reason: FinishReason,
+    logprobs: Vec<TokenLogProb>,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+impl Completion {
+    /// The mean log probability of the generated tokens, which is used to pick the best candidates.
+    fn mean_logprob(&self) -> f32 {
+        match self.logprobs.is_empty() {
+            true => f32::NEG_INFINITY,
+            false => {
+                self.logprobs.iter().map(|l| l.logprob).sum::<f32>() / self.logprobs.len() as f32
             }
-            false => match chat_graphs.iter_mut().next() {
-                Some((_, graph)) => compute_by_graph(graph, prompt),
-                None => {
-                    let err_msg = "There is no model available in the chat graphs.";
+        }
+    }
+}
 
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
+#[allow(clippy::too_many_arguments)]
+fn compute(
+    slot: &SlotLease,
+    id: String,
+    prompts: &[String],
+    inputs: &[String],
+    n: u32,
+    best_of: u32,
+    echo: bool,
+    top_logprobs: Option<u8>,
+    return_logprobs: bool,
+    max_tokens: Option<u32>,
+    stop: &[String],
+    seeded_metadata: Option<&GgmlMetadata>,
+) -> Result<CompletionObject, LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Compute completions");
 
-                    Err(LlamaCoreError::Operation(err_msg.into()))
+    with_chat_slot(slot, |graph| {
+        let mut choices = vec![];
+        let mut usage = Usage::default();
+
+        for (prompt_index, (prompt, input)) in prompts.iter().zip(inputs).enumerate() {
+            // generate the candidates of the prompt
+            let mut candidates = vec![];
+            for candidate in 0..best_of {
+                if let Some(metadata) = seeded_metadata {
+                    set_candidate_seed(graph, metadata, candidate)?;
                 }
-            },
-        },
-        None => match chat_graphs.iter_mut().next() {
-            Some((_, graph)) => compute_by_graph(graph, prompt),
-            None => {
-                let err_msg = "There is no model available in the chat graphs.";
+
+                let completion = compute_by_graph(graph, input, top_logprobs, max_tokens, stop)?;
+
+                // the prompt is processed once for each prompt in the batch
+                if candidates.is_empty() {
+                    usage.prompt_tokens += completion.prompt_tokens;
+                }
+                usage.completion_tokens += completion.completion_tokens;
+
+                candidates.push(completion);
+            }
+
+            // pick the best `n` candidates by the mean log probability of the generated tokens
+            if best_of > n {
+                candidates.sort_by(|a, b| b.mean_logprob().total_cmp(&a.mean_logprob()));
+                candidates.truncate(n as usize);
+            }
+
+            for (i, completion) in candidates.into_iter().enumerate() {
+                let (text, offset) = match echo {
+                    true => (
+                        format!("{}{}", prompt, completion.text),
+                        prompt.chars().count() as i32,
+                    ),
+                    false => (completion.text, 0),
+                };
+
+                let logprobs = match return_logprobs {
+                    true => Some(to_logprob_result(completion.logprobs, offset)),
+                    false => None,
+                };
+
+                choices.push(CompletionChoice {
+                    index: prompt_index as u32 * n + i as u32,
+                    text,
+                    finish_reason: completion.finish_reason,
+                    logprobs,
+                });
+            }
+        }
+        usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Prompt tokens: {}, Completion tokens: {}", usage.prompt_tokens, usage.completion_tokens);
+
+        let created = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| {
+                let err_msg = format!("Failed to get the current time. {}", e);
 
                 #[cfg(feature = "logging")]
                 error!(target: "stdout", "{}", &err_msg);
 
-                Err(LlamaCoreError::Operation(err_msg.into()))
-            }
-        },
-    }
+                LlamaCoreError::Operation(err_msg)
+            })?;
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Completions generated successfully.");
+
+        Ok(CompletionObject {
+            id,
+            object: String::from("text_completion"),
+            created: created.as_secs(),
+            model: graph.name().to_string(),
+            choices,
+            usage,
+        })
+    })
 }
 
-/// Runs inference on the model with the given name and returns the output.
+/// Runs inference on the model with the given input and returns the completion.
 fn compute_by_graph(
     graph: &mut Graph<GgmlMetadata>,
-    prompt: impl AsRef<str>,
-) -> std::result::Result<CompletionObject, LlamaCoreError> {
+    input: &str,
+    top_logprobs: Option<u8>,
+    max_tokens: Option<u32>,
+    stop: &[String],
+) -> Result<Completion, LlamaCoreError> {
     #[cfg(feature = "logging")]
     info!(target: "stdout", "Compute completions by graph");
 
-    // check if the `embedding` model is disabled or not
-    if graph.metadata.embeddings {
-        graph.metadata.embeddings = false;
-
-        #[cfg(feature = "logging")]
-        info!(target: "stdout", "The `embedding` field of metadata sets to false.");
-
-        graph.update_metadata()?;
-    }
-
     // set input
-    let tensor_data = prompt.as_ref().as_bytes().to_vec();
-    graph
-        .set_input(0, wasmedge_wasi_nn::TensorType::U8, &[1], &tensor_data)
-        .map_err(|e| {
-            let err_msg = format!("Failed to set the input tensor. {}", e);
+    set_tensor_data_u8(graph, 0, input.as_bytes())?;
 
+    // execute the inference
+    let finish_reason = match graph.compute() {
+        Ok(_) => FinishReason::stop,
+        Err(wasmedge_wasi_nn::Error::BackendError(
+            wasmedge_wasi_nn::BackendError::ContextFull
+            | wasmedge_wasi_nn::BackendError::PromptTooLong,
+        )) => {
             #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
-
-            LlamaCoreError::Backend(BackendError::SetInput(err_msg))
-        })?;
+            warn!(target: "stdout", "The context is full or the prompt is too long.");
 
-    // execute the inference
-    graph.compute().map_err(|e| {
-        let err_msg = format!("Failed to execute the inference. {}", e);
+            FinishReason::length
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to execute the inference. {}", e);
 
-        #[cfg(feature = "logging")]
-        error!(target: "stdout", "{}", &err_msg);
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
 
-        LlamaCoreError::Backend(BackendError::Compute(err_msg))
-    })?;
+            return Err(LlamaCoreError::Backend(BackendError::Compute(err_msg)));
+        }
+    };
 
     // Retrieve the output
     let buffer = get_output_buffer(graph, OUTPUT_TENSOR)?;
 
     // convert inference result to string
-    let model_answer = String::from_utf8(buffer).map_err(|e| {
+    let mut text = String::from_utf8(buffer).map_err(|e| {
         let err_msg = format!(
             "Failed to decode the buffer of the inference result to a utf-8 string. {}",
             e
@@ -155,18 +474,384 @@ fn compute_by_graph(
 
         LlamaCoreError::Operation(err_msg)
     })?;
-    let answer = model_answer.trim();
 
     // retrieve the number of prompt and completion tokens
     let token_info = get_token_info_by_graph(graph)?;
 
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Prompt tokens: {}, Completion tokens: {}", token_info.prompt_tokens, token_info.completion_tokens);
+    // truncate the generation at the first stop sequence
+    let finish_reason = match truncate_at_stop_sequence(&mut text, stop) {
+        true => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Hit a stop sequence. The generation is truncated.");
+
+            FinishReason::stop
+        }
+        false => match max_tokens {
+            Some(max_tokens) if token_info.completion_tokens >= max_tokens as u64 => {
+                FinishReason::length
+            }
+            _ => finish_reason,
+        },
+    };
+
+    // retrieve the log probabilities of the generated tokens
+    let logprobs = match top_logprobs {
+        Some(top_logprobs) => get_logprobs_by_graph(graph, top_logprobs, false)?,
+        None => vec![],
+    };
+
+    Ok(Completion {
+        text,
+        finish_reason,
+        logprobs,
+        prompt_tokens: token_info.prompt_tokens,
+        completion_tokens: token_info.completion_tokens,
+    })
+}
+
+/// Convert the log probabilities of the generated tokens to the legacy format. `offset` is the position of the first token in the returned text.
+fn to_logprob_result(logprobs: Vec<TokenLogProb>, offset: i32) -> LogprobResult {
+    let mut result = LogprobResult {
+        tokens: Vec::with_capacity(logprobs.len()),
+        token_logprobs: Vec::with_capacity(logprobs.len()),
+        top_logprobs: Vec::with_capacity(logprobs.len()),
+        text_offset: Vec::with_capacity(logprobs.len()),
+    };
+
+    let mut offset = offset;
+    for logprob in logprobs {
+        result.text_offset.push(offset);
+        offset += logprob.token.chars().count() as i32;
+
+        result.top_logprobs.push(
+            logprob
+                .top_logprobs
+                .into_iter()
+                .map(|top| (top.token, top.logprob))
+                .collect::<HashMap<String, f32>>(),
+        );
+        result.token_logprobs.push(logprob.logprob);
+        result.tokens.push(logprob.token);
+    }
+
+    result
+}
 
-    let created = SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map_err(|e| {
-            let err_msg = format!("Failed to get the current time. {}", e);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompletionStreamState {
+    /// Feed the prompt of the next choice to the model.
+    Prompt,
+    /// Generate the tokens of the current choice.
+    Generate,
+    /// All choices are generated. Send the usage of the request.
+    Usage,
+    /// Send the `[DONE]` message.
+    Done,
+    /// The stream is finished.
+    EndOfSequence,
+}
+
+/// Stream of the completions in the server-sent events format. The choices are generated one after another, and the chunks of each choice are tagged with its index.
+struct CompletionStream {
+    /// The execution slot held until the stream is dropped.
+    slot: Arc<SlotLease>,
+    id: String,
+    prompts: Vec<String>,
+    inputs: Vec<String>,
+    n: u32,
+    echo: bool,
+    top_logprobs: Option<u8>,
+    max_tokens: Option<u32>,
+    stop_sequences: StopSequences,
+    state: CompletionStreamState,
+    /// The index of the choice being generated.
+    index: u32,
+    /// The number of tokens generated for the current choice.
+    completion_tokens: u32,
+    /// The position of the next token in the text of the current choice.
+    text_offset: i32,
+    /// The bytes of an incomplete utf-8 character held back.
+    utf8_bytes: Vec<u8>,
+    /// The log probabilities of the tokens held back while checking the stop sequences.
+    logprobs: Vec<TokenLogProb>,
+    /// The metadata of the request if a seed is given, which is pushed again with the seed of each choice.
+    seeded_metadata: Option<GgmlMetadata>,
+    /// Whether to send the usage of the request before the `[DONE]` message.
+    include_usage: bool,
+    /// The number of prompt and completion tokens of the choices generated so far.
+    usage: Usage,
+}
+impl CompletionStream {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        slot: SlotLease,
+        id: String,
+        prompts: Vec<String>,
+        inputs: Vec<String>,
+        n: u32,
+        echo: bool,
+        top_logprobs: Option<u8>,
+        max_tokens: Option<u32>,
+        stop: Option<Vec<String>>,
+        seeded_metadata: Option<GgmlMetadata>,
+        include_usage: bool,
+    ) -> Self {
+        Self {
+            slot: Arc::new(slot),
+            id,
+            prompts,
+            inputs,
+            n,
+            echo,
+            top_logprobs,
+            max_tokens,
+            stop_sequences: StopSequences::new(stop),
+            state: CompletionStreamState::Prompt,
+            index: 0,
+            completion_tokens: 0,
+            text_offset: 0,
+            utf8_bytes: vec![],
+            logprobs: vec![],
+            seeded_metadata,
+            include_usage,
+            usage: Usage::default(),
+        }
+    }
+
+    /// Generate the next chunk. Returns `None` if nothing is ready to send yet.
+    fn next_chunk(
+        &mut self,
+        graph: &mut Graph<GgmlMetadata>,
+    ) -> Result<Option<String>, LlamaCoreError> {
+        match self.state {
+            CompletionStreamState::Prompt => {
+                let prompt_index = (self.index / self.n) as usize;
+
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "Feed the prompt {} for the choice {}", prompt_index, self.index);
+
+                if let Some(metadata) = &self.seeded_metadata {
+                    set_candidate_seed(graph, metadata, self.index % self.n)?;
+                }
+                set_tensor_data_u8(graph, 0, self.inputs[prompt_index].as_bytes())?;
+                self.state = CompletionStreamState::Generate;
+                self.completion_tokens = 0;
+                self.text_offset = 0;
+
+                match self.echo {
+                    true => {
+                        let prompt = self.prompts[prompt_index].clone();
+                        self.text_offset = prompt.chars().count() as i32;
+
+                        self.gen_chunk(graph, prompt, None, None).map(Some)
+                    }
+                    false => Ok(None),
+                }
+            }
+            CompletionStreamState::Generate => {
+                // once a stop sequence is hit, the rest of the generation is skipped
+                let result = match self.stop_sequences.is_hit() {
+                    true => Err(wasmedge_wasi_nn::Error::BackendError(
+                        wasmedge_wasi_nn::BackendError::EndOfSequence,
+                    )),
+                    false => graph.compute_single(),
+                };
+
+                match result {
+                    Ok(_) => {
+                        self.completion_tokens += 1;
+
+                        // retrieve the output
+                        let output_buffer = get_output_buffer_single(graph, OUTPUT_TENSOR)?;
+                        self.utf8_bytes.extend_from_slice(&output_buffer[..]);
+
+                        // a utf-8 character may be split into multiple tokens
+                        let token = match String::from_utf8(self.utf8_bytes.clone()) {
+                            Ok(token) => {
+                                self.utf8_bytes.clear();
+                                token
+                            }
+                            Err(e) if self.utf8_bytes.len() > 4 => {
+                                let err_msg = format!("Fail to convert a vector of bytes to string. The length of the utf8 bytes exceeds 4. {}", e);
+
+                                #[cfg(feature = "logging")]
+                                error!(target: "stdout", "{}", &err_msg);
+
+                                return Err(LlamaCoreError::Operation(err_msg));
+                            }
+                            Err(_) => String::new(),
+                        };
+
+                        // retrieve the log probabilities of the generated token
+                        if let Some(top_logprobs) = self.top_logprobs {
+                            let logprobs = get_logprobs_by_graph(graph, top_logprobs, true)?;
+                            self.logprobs.extend(logprobs);
+                        }
+
+                        // check the stop sequences
+                        let (text, hit) = self.stop_sequences.push(&token);
+                        if hit {
+                            #[cfg(feature = "logging")]
+                            info!(target: "stdout", "Hit a stop sequence.");
+                        }
+
+                        match text.is_empty() {
+                            true => Ok(None),
+                            false => {
+                                let logprobs = self.take_logprobs();
+                                self.gen_chunk(graph, text, logprobs, None).map(Some)
+                            }
+                        }
+                    }
+                    Err(wasmedge_wasi_nn::Error::BackendError(
+                        backend_error @ (wasmedge_wasi_nn::BackendError::EndOfSequence
+                        | wasmedge_wasi_nn::BackendError::ContextFull),
+                    )) => {
+                        let context_full =
+                            matches!(backend_error, wasmedge_wasi_nn::BackendError::ContextFull);
+                        let finish_reason = match self.max_tokens {
+                            _ if self.stop_sequences.is_hit() => FinishReason::stop,
+                            _ if context_full => FinishReason::length,
+                            Some(max_tokens) if self.completion_tokens >= max_tokens => {
+                                FinishReason::length
+                            }
+                            _ => FinishReason::stop,
+                        };
+
+                        #[cfg(feature = "logging")]
+                        info!(target: "stdout", "Finish the choice {}. finish reason: {:?}", self.index, finish_reason);
+
+                        // flush the text held back while checking the stop sequences
+                        let text = self.stop_sequences.flush();
+                        let logprobs = self.take_logprobs();
+                        let chunk = self.gen_chunk(graph, text, logprobs, Some(finish_reason))?;
+
+                        // retrieve the number of prompt and completion tokens. The prompt is counted once for each prompt in the batch.
+                        let token_info = get_token_info_by_graph(graph)?;
+                        if self.index % self.n == 0 {
+                            self.usage.prompt_tokens += token_info.prompt_tokens;
+                        }
+                        self.usage.completion_tokens += token_info.completion_tokens;
+
+                        // clean up the context for the next choice
+                        graph.finish_single().map_err(|e| {
+                            let err_msg = format!("Failed to clean up the context. Reason: {}", e);
+
+                            #[cfg(feature = "logging")]
+                            error!(target: "stdout", "{}", &err_msg);
+
+                            LlamaCoreError::Backend(BackendError::FinishSingle(err_msg))
+                        })?;
+
+                        self.index += 1;
+                        self.stop_sequences.reset();
+                        self.utf8_bytes.clear();
+                        self.logprobs.clear();
+                        self.state =
+                            match (self.index as usize) < self.prompts.len() * self.n as usize {
+                                true => CompletionStreamState::Prompt,
+                                false if self.include_usage => CompletionStreamState::Usage,
+                                false => CompletionStreamState::Done,
+                            };
+
+                        Ok(Some(chunk))
+                    }
+                    Err(e) => {
+                        let err_msg = format!("Failed to compute the completion. Reason: {}", e);
+
+                        #[cfg(feature = "logging")]
+                        error!(target: "stdout", "{}", &err_msg);
+
+                        Err(LlamaCoreError::Backend(BackendError::ComputeSingle(
+                            err_msg,
+                        )))
+                    }
+                }
+            }
+            CompletionStreamState::Usage => {
+                self.state = CompletionStreamState::Done;
+
+                let usage = Usage {
+                    prompt_tokens: self.usage.prompt_tokens,
+                    completion_tokens: self.usage.completion_tokens,
+                    total_tokens: self.usage.prompt_tokens + self.usage.completion_tokens,
+                };
+
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "Prompt tokens: {}, Completion tokens: {}", usage.prompt_tokens, usage.completion_tokens);
+
+                self.serialize_chunk(graph, vec![], Some(usage)).map(Some)
+            }
+            CompletionStreamState::Done => {
+                self.state = CompletionStreamState::EndOfSequence;
+
+                Ok(Some("data: [DONE]\n\n".to_string()))
+            }
+            CompletionStreamState::EndOfSequence => Ok(None),
+        }
+    }
+
+    /// Take the log probabilities of the tokens held back so far.
+    fn take_logprobs(&mut self) -> Option<LogprobResult> {
+        self.top_logprobs.map(|_| {
+            let logprobs = std::mem::take(&mut self.logprobs);
+            let result = to_logprob_result(logprobs, self.text_offset);
+            self.text_offset += result
+                .tokens
+                .iter()
+                .map(|token| token.chars().count() as i32)
+                .sum::<i32>();
+
+            result
+        })
+    }
+
+    fn gen_chunk(
+        &self,
+        graph: &Graph<GgmlMetadata>,
+        text: String,
+        logprobs: Option<LogprobResult>,
+        finish_reason: Option<FinishReason>,
+    ) -> Result<String, LlamaCoreError> {
+        let choices = vec![CompletionChunkChoice {
+            finish_reason,
+            index: self.index,
+            logprobs,
+            text,
+        }];
+
+        self.serialize_chunk(graph, choices, None)
+    }
+
+    fn serialize_chunk(
+        &self,
+        graph: &Graph<GgmlMetadata>,
+        choices: Vec<CompletionChunkChoice>,
+        usage: Option<Usage>,
+    ) -> Result<String, LlamaCoreError> {
+        let created = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| {
+                let err_msg = format!("Failed to get the current time. Reason: {}", e);
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                LlamaCoreError::Operation(err_msg)
+            })?;
+
+        let chunk = CompletionChunk {
+            id: self.id.clone(),
+            choices,
+            created: created.as_secs(),
+            model: graph.name().to_owned(),
+            object: "text_completion".to_string(),
+            usage,
+        };
+
+        // serialize completion chunk
+        let chunk_str = serde_json::to_string(&chunk).map_err(|e| {
+            let err_msg = format!("Failed to serialize completion chunk. Reason: {}", e);
 
             #[cfg(feature = "logging")]
             error!(target: "stdout", "{}", &err_msg);
@@ -174,24 +859,68 @@ fn compute_by_graph(
             LlamaCoreError::Operation(err_msg)
         })?;
 
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Completions generated successfully.");
-
-    Ok(CompletionObject {
-        id: uuid::Uuid::new_v4().to_string(),
-        object: String::from("text_completion"),
-        created: created.as_secs(),
-        model: graph.name().to_string(),
-        choices: vec![CompletionChoice {
-            index: 0,
-            text: String::from(answer),
-            finish_reason: FinishReason::stop,
-            logprobs: None,
-        }],
-        usage: Usage {
-            prompt_tokens: token_info.prompt_tokens,
-            completion_tokens: token_info.completion_tokens,
-            total_tokens: token_info.prompt_tokens + token_info.completion_tokens,
-        },
-    })
+        Ok(format!("data: {}\n\n", chunk_str))
+    }
+}
+impl Drop for CompletionStream {
+    fn drop(&mut self) {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Clean up the context of the completion stream.");
+
+        let state = self.state;
+        let result = with_chat_slot(&self.slot, |graph| {
+            // the generation is interrupted, e.g. the client is disconnected
+            if state == CompletionStreamState::Generate {
+                graph.finish_single().map_err(|e| {
+                    let err_msg = format!("Failed to clean up the context. Reason: {}", e);
+
+                    LlamaCoreError::Backend(BackendError::FinishSingle(err_msg))
+                })?;
+            }
+
+            // reset the model metadata
+            graph.update_metadata()
+        });
+
+        if let Err(e) = result {
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", e);
+
+            #[cfg(not(feature = "logging"))]
+            println!(
+                "[ERROR][llama_core] Failed to clean up the context. Reason: {}",
+                e
+            );
+        }
+    }
+}
+impl futures::Stream for CompletionStream {
+    type Item = Result<String, LlamaCoreError>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.state == CompletionStreamState::EndOfSequence {
+                return Poll::Ready(None);
+            }
+
+            let slot = this.slot.clone();
+            match with_chat_slot(&slot, |graph| this.next_chunk(graph)) {
+                Ok(Some(chunk)) => {
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "next item: {}", &chunk);
+
+                    return Poll::Ready(Some(Ok(chunk)));
+                }
+                // nothing to send yet, e.g. the text is held back while checking the stop sequences
+                Ok(None) => continue,
+                Err(e) => {
+                    this.state = CompletionStreamState::EndOfSequence;
+
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
 }
@@ -45,6 +45,9 @@ pub async fn embeddings(
 
     let model_name = &embedding_request.model;
 
+    // load the embedding model if it is registered to be loaded on demand
+    crate::models::load_on_demand(model_name.as_deref(), true)?;
+
     // For general embedding scenario, the embedding model is the same as the chat model.
     // For RAG scenario, the embedding model is different from the chat model.
     let embedding_graphs = match EMBEDDING_GRAPHS.get() {
@@ -8,6 +8,9 @@ pub enum LlamaCoreError {
     /// Errors in General operation.
     #[error("{0}")]
     Operation(String),
+    /// Errors in the request sent by the client, e.g. invalid messages or parameters.
+    #[error("{0}")]
+    BadRequest(String),
     /// Errors in Context initialization.
     #[error("Failed to initialize computation context. Reason: {0}")]
     InitContext(String),
@@ -22,11 +25,33 @@ pub enum LlamaCoreError {
     /// Errors in file not found.
     #[error("File not found.")]
     FileNotFound,
+    /// Errors in scheduling the request, e.g. the queue of the model is full.
+    #[error("{0}")]
+    QueueFull(String),
+    /// Errors in model not found.
+    #[error("{0}")]
+    ModelNotFound(String),
+    /// Errors in loading or unloading a model, e.g. the model has already been loaded or is serving requests.
+    #[error("{0}")]
+    ModelConflict(String),
+    /// Errors in serving a request on a model which is still being loaded on demand.
+    #[error("{0}")]
+    ModelLoading(String),
     /// Errors in Qdrant.
     #[cfg(feature = "rag")]
     #[cfg_attr(docsrs, doc(cfg(feature = "rag")))]
     #[error("Qdrant error:{0}")]
     Qdrant(String),
+    /// Errors in the embedded vector store.
+    #[cfg(feature = "rag")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "rag")))]
+    #[error("Vector store error:{0}")]
+    VectorStore(String),
+    /// Errors in the keyword search.
+    #[cfg(feature = "index")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "index")))]
+    #[error("Keyword search error:{0}")]
+    KeywordSearch(String),
 }
 
 /// Error types for wasi-nn errors.
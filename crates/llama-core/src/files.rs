@@ -27,6 +27,10 @@ pub fn remove_file(id: impl AsRef<str>) -> Result<DeleteFileStatus, LlamaCoreErr
             #[cfg(feature = "logging")]
             info!(target: "stdout", "Successfully deleted the target file with id {}.", id.as_ref());
 
+            // the BM25 index of the file is deleted together with the file
+            #[cfg(feature = "index")]
+            crate::keyword_search::remove_bm25_index(id.as_ref());
+
             DeleteFileStatus {
                 id: id.as_ref().into(),
                 object: "file".to_string(),
@@ -1,6 +1,10 @@
 //! Define Graph and GraphBuilder APIs for creating a new computation graph.
 
 use crate::{error::LlamaCoreError, utils::set_tensor_data_u8, BaseMetadata};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex, MutexGuard, PoisonError,
+};
 use wasmedge_wasi_nn::{
     Error as WasiNnError, Graph as WasiNnGraph, GraphExecutionContext, TensorType,
 };
@@ -9,23 +13,22 @@ use wasmedge_wasi_nn::{
 #[derive(Debug)]
 pub struct GraphBuilder<M: BaseMetadata + serde::Serialize + Clone + Default> {
     metadata: Option<M>,
-    wasi_nn_graph_builder: wasmedge_wasi_nn::GraphBuilder,
+    slots: usize,
+    ty: EngineType,
+    /// The config passed to the wasi-nn graph builder.
+    config: Option<String>,
+    /// Select the execution target of the wasi-nn graph builder.
+    target: fn(wasmedge_wasi_nn::GraphBuilder) -> wasmedge_wasi_nn::GraphBuilder,
 }
 impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
     /// Create a new computation graph builder.
     pub fn new(ty: EngineType) -> Result<Self, LlamaCoreError> {
-        let encoding = match ty {
-            EngineType::Ggml => wasmedge_wasi_nn::GraphEncoding::Ggml,
-            EngineType::Whisper => wasmedge_wasi_nn::GraphEncoding::Whisper,
-            EngineType::Piper => wasmedge_wasi_nn::GraphEncoding::Piper,
-        };
-
-        let wasi_nn_graph_builder =
-            wasmedge_wasi_nn::GraphBuilder::new(encoding, wasmedge_wasi_nn::ExecutionTarget::AUTO);
-
         Ok(Self {
             metadata: None,
-            wasi_nn_graph_builder,
+            slots: 1,
+            ty,
+            config: None,
+            target: |builder| builder,
         })
     }
 
@@ -38,24 +41,30 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
 
             LlamaCoreError::Operation(err_msg)
         })?;
-        self.wasi_nn_graph_builder = self.wasi_nn_graph_builder.config(config);
+        self.config = Some(config);
         self.metadata = Some(metadata.clone());
 
         Ok(self)
     }
 
+    /// Set the number of execution slots, each of which owns an execution context sharing the loaded model. Defaults to 1.
+    pub fn with_slots(mut self, slots: usize) -> Self {
+        self.slots = slots.max(1);
+        self
+    }
+
     pub fn use_cpu(mut self) -> Self {
-        self.wasi_nn_graph_builder = self.wasi_nn_graph_builder.cpu();
+        self.target = wasmedge_wasi_nn::GraphBuilder::cpu;
         self
     }
 
     pub fn use_gpu(mut self) -> Self {
-        self.wasi_nn_graph_builder = self.wasi_nn_graph_builder.gpu();
+        self.target = wasmedge_wasi_nn::GraphBuilder::gpu;
         self
     }
 
     pub fn use_tpu(mut self) -> Self {
-        self.wasi_nn_graph_builder = self.wasi_nn_graph_builder.tpu();
+        self.target = wasmedge_wasi_nn::GraphBuilder::tpu;
         self
     }
 
@@ -67,27 +76,7 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
         B: AsRef<[u8]>,
     {
         // load the model
-        let graph = self
-            .wasi_nn_graph_builder
-            .build_from_bytes(bytes_array)
-            .map_err(|e| {
-                let err_msg = e.to_string();
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                LlamaCoreError::Operation(err_msg)
-            })?;
-
-        // initialize the execution context
-        let context = graph.init_execution_context().map_err(|e| {
-            let err_msg = e.to_string();
-
-            #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
-
-            LlamaCoreError::Operation(err_msg)
-        })?;
+        let slots = self.load_slots(|builder| builder.build_from_bytes(bytes_array.as_ref()))?;
 
         let created = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -103,8 +92,11 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
         Ok(Graph {
             created,
             metadata: self.metadata.clone().unwrap_or_default(),
-            graph,
-            context,
+            leased: (0..slots.len())
+                .map(|_| Arc::new(AtomicBool::new(false)))
+                .collect(),
+            slots,
+            slot: 0,
         })
     }
 
@@ -113,27 +105,7 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
         P: AsRef<std::path::Path>,
     {
         // load the model
-        let graph = self
-            .wasi_nn_graph_builder
-            .build_from_files(files)
-            .map_err(|e| {
-                let err_msg = e.to_string();
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
-
-                LlamaCoreError::Operation(err_msg)
-            })?;
-
-        // initialize the execution context
-        let context = graph.init_execution_context().map_err(|e| {
-            let err_msg = e.to_string();
-
-            #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
-
-            LlamaCoreError::Operation(err_msg)
-        })?;
+        let slots = self.load_slots(|builder| builder.build_from_files(files.as_ref()))?;
 
         let created = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -149,8 +121,11 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
         Ok(Graph {
             created,
             metadata: self.metadata.clone().unwrap_or_default(),
-            graph,
-            context,
+            leased: (0..slots.len())
+                .map(|_| Arc::new(AtomicBool::new(false)))
+                .collect(),
+            slots,
+            slot: 0,
         })
     }
 
@@ -158,27 +133,8 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
         match &self.metadata {
             Some(metadata) => {
                 // load the model
-                let graph = self
-                    .wasi_nn_graph_builder
-                    .build_from_cache(metadata.model_alias())
-                    .map_err(|e| {
-                        let err_msg = e.to_string();
-
-                        #[cfg(feature = "logging")]
-                        error!(target: "stdout", "{}", &err_msg);
-
-                        LlamaCoreError::Operation(err_msg)
-                    })?;
-
-                // initialize the execution context
-                let context = graph.init_execution_context().map_err(|e| {
-                    let err_msg = e.to_string();
-
-                    #[cfg(feature = "logging")]
-                    error!(target: "stdout", "{}", &err_msg);
-
-                    LlamaCoreError::Operation(err_msg)
-                })?;
+                let slots =
+                    self.load_slots(|builder| builder.build_from_cache(metadata.model_alias()))?;
 
                 let created = std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
@@ -194,8 +150,11 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
                 Ok(Graph {
                     created,
                     metadata: metadata.clone(),
-                    graph,
-                    context,
+                    leased: (0..slots.len())
+                        .map(|_| Arc::new(AtomicBool::new(false)))
+                        .collect(),
+                    slots,
+                    slot: 0,
                 })
             }
             None => {
@@ -210,6 +169,45 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
             }
         }
     }
+
+    /// Create a wasi-nn graph builder with the config and the execution target of this builder.
+    fn wasi_nn_graph_builder(&self) -> wasmedge_wasi_nn::GraphBuilder {
+        let encoding = match self.ty {
+            EngineType::Ggml => wasmedge_wasi_nn::GraphEncoding::Ggml,
+            EngineType::Whisper => wasmedge_wasi_nn::GraphEncoding::Whisper,
+            EngineType::Piper => wasmedge_wasi_nn::GraphEncoding::Piper,
+        };
+
+        let builder = (self.target)(wasmedge_wasi_nn::GraphBuilder::new(
+            encoding,
+            wasmedge_wasi_nn::ExecutionTarget::AUTO,
+        ));
+
+        match &self.config {
+            Some(config) => builder.config(config.clone()),
+            None => builder,
+        }
+    }
+
+    /// Load the model once and initialize an execution context on it for each execution slot.
+    fn load_slots(
+        &self,
+        load: impl FnOnce(wasmedge_wasi_nn::GraphBuilder) -> Result<WasiNnGraph, WasiNnError>,
+    ) -> Result<Vec<Arc<Mutex<Slot>>>, LlamaCoreError> {
+        let graph = load(self.wasi_nn_graph_builder()).map_err(|e| {
+            let err_msg = e.to_string();
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })?;
+        let graph = Arc::new(LoadedGraph(graph));
+
+        (0..self.slots)
+            .map(|_| Slot::new(&graph).map(|slot| Arc::new(Mutex::new(slot))))
+            .collect()
+    }
 }
 
 /// Wrapper of the `wasmedge_wasi_nn::Graph` struct
@@ -217,8 +215,12 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> GraphBuilder<M> {
 pub struct Graph<M: BaseMetadata + serde::Serialize + Clone + Default> {
     pub created: std::time::Duration,
     pub metadata: M,
-    graph: WasiNnGraph,
-    context: GraphExecutionContext,
+    /// The execution slots, each of which owns an execution context sharing the loaded model.
+    slots: Vec<Arc<Mutex<Slot>>>,
+    /// Whether the slots are leased.
+    leased: Vec<Arc<AtomicBool>>,
+    /// The slot the inference runs on.
+    slot: usize,
 }
 impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
     /// Create a new computation graph from the given metadata.
@@ -249,14 +251,7 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         })?;
 
         // initialize the execution context
-        let context = graph.init_execution_context().map_err(|e| {
-            let err_msg = e.to_string();
-
-            #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
-
-            LlamaCoreError::Operation(err_msg)
-        })?;
+        let slot = Slot::new(&Arc::new(LoadedGraph(graph)))?;
 
         let created = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -272,8 +267,9 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         Ok(Self {
             created,
             metadata: metadata.clone(),
-            graph,
-            context,
+            slots: vec![Arc::new(Mutex::new(slot))],
+            leased: vec![Arc::new(AtomicBool::new(false))],
+            slot: 0,
         })
     }
 
@@ -287,6 +283,93 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         self.metadata.model_alias()
     }
 
+    /// Get the number of the execution slots
+    pub fn slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Get the number of the execution slots which are not leased
+    pub fn free_slots(&self) -> usize {
+        self.leased
+            .iter()
+            .filter(|leased| !leased.load(Ordering::Acquire))
+            .count()
+    }
+
+    /// Lease a free execution slot. Returns `None` if all the slots are leased.
+    pub fn lease_slot(&self) -> Option<SlotLease> {
+        self.leased.iter().enumerate().find_map(|(index, leased)| {
+            leased
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+                .ok()
+                .map(|_| SlotLease {
+                    model_name: self.name().to_string(),
+                    index,
+                    leased: leased.clone(),
+                })
+        })
+    }
+
+    /// Run the subsequent operations on the execution slot of the given lease.
+    pub fn use_slot(&mut self, lease: &SlotLease) -> Result<(), LlamaCoreError> {
+        self.check_lease(lease)?;
+        self.slot = lease.index;
+
+        Ok(())
+    }
+
+    /// Get a handle of the graph which runs on the execution slot of the given lease. The handle does not borrow the graph, so the inference on the slot runs without holding the lock of the graphs.
+    pub(crate) fn detach_slot(&self, lease: &SlotLease) -> Result<Self, LlamaCoreError> {
+        self.check_lease(lease)?;
+
+        Ok(Self {
+            created: self.created,
+            metadata: self.metadata.clone(),
+            slots: self.slots.clone(),
+            leased: self.leased.clone(),
+            slot: lease.index,
+        })
+    }
+
+    /// Get a handle of the graph which runs on a new execution context of the loaded model. The context is not one of the slots and is released when the handle is dropped, so that a request can sample several sequences side by side.
+    pub(crate) fn fork_context(&self) -> Result<Self, LlamaCoreError> {
+        let graph = self.lock_slot().graph.clone();
+        let slot = Slot::new(&graph)?;
+
+        Ok(Self {
+            created: self.created,
+            metadata: self.metadata.clone(),
+            slots: vec![Arc::new(Mutex::new(slot))],
+            leased: vec![Arc::new(AtomicBool::new(true))],
+            slot: 0,
+        })
+    }
+
+    fn check_lease(&self, lease: &SlotLease) -> Result<(), LlamaCoreError> {
+        match self.leased.get(lease.index) {
+            Some(leased) if Arc::ptr_eq(leased, &lease.leased) => Ok(()),
+            _ => {
+                let err_msg = format!(
+                    "The slot {} is not leased from the model named {}.",
+                    lease.index,
+                    self.name()
+                );
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                Err(LlamaCoreError::Operation(err_msg))
+            }
+        }
+    }
+
+    /// Lock the execution slot the inference runs on. The lease of the slot keeps the other requests away, so the lock is not contended.
+    fn lock_slot(&self) -> MutexGuard<'_, Slot> {
+        self.slots[self.slot]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
     /// Update metadata
     pub fn update_metadata(&mut self) -> Result<(), LlamaCoreError> {
         #[cfg(feature = "logging")]
@@ -321,19 +404,21 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         dimensions: &[usize],
         data: impl AsRef<[T]>,
     ) -> Result<(), WasiNnError> {
-        self.context.set_input(index, tensor_type, dimensions, data)
+        self.lock_slot()
+            .context
+            .set_input(index, tensor_type, dimensions, data)
     }
 
     /// Compute the inference on the given inputs.
     pub fn compute(&mut self) -> Result<(), WasiNnError> {
-        self.context.compute()
+        self.lock_slot().context.compute()
     }
 
     /// Compute the inference on the given inputs.
     ///
     /// Note that this method is used for the stream mode. It generates one token at a time.
     pub fn compute_single(&mut self) -> Result<(), WasiNnError> {
-        self.context.compute_single()
+        self.lock_slot().context.compute_single()
     }
 
     /// Copy output tensor to out_buffer, return the output’s **size in bytes**.
@@ -342,7 +427,7 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         index: usize,
         out_buffer: &mut [T],
     ) -> Result<usize, WasiNnError> {
-        self.context.get_output(index, out_buffer)
+        self.lock_slot().context.get_output(index, out_buffer)
     }
 
     /// Copy output tensor to out_buffer, return the output’s **size in bytes**.
@@ -353,20 +438,52 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Graph<M> {
         index: usize,
         out_buffer: &mut [T],
     ) -> Result<usize, WasiNnError> {
-        self.context.get_output_single(index, out_buffer)
+        self.lock_slot()
+            .context
+            .get_output_single(index, out_buffer)
     }
 
     /// Clear the computation context.
     ///
     /// Note that this method is used for the stream mode. It clears the context after the stream mode is finished.
     pub fn finish_single(&mut self) -> Result<(), WasiNnError> {
-        self.context.fini_single()
+        self.lock_slot().context.fini_single()
     }
 }
-impl<M: BaseMetadata + serde::Serialize + Clone + Default> Drop for Graph<M> {
+
+/// An execution slot, which owns an execution context on the loaded model.
+#[derive(Debug)]
+struct Slot {
+    context: GraphExecutionContext,
+    /// The loaded model, which is unloaded after the execution contexts of all the slots are dropped.
+    graph: Arc<LoadedGraph>,
+}
+impl Slot {
+    fn new(graph: &Arc<LoadedGraph>) -> Result<Self, LlamaCoreError> {
+        // initialize the execution context
+        let context = graph.0.init_execution_context().map_err(|e| {
+            let err_msg = e.to_string();
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })?;
+
+        Ok(Self {
+            context,
+            graph: graph.clone(),
+        })
+    }
+}
+
+/// A wasi-nn graph shared by the execution slots, which is unloaded when it is dropped.
+#[derive(Debug)]
+struct LoadedGraph(WasiNnGraph);
+impl Drop for LoadedGraph {
     fn drop(&mut self) {
         // unload the wasi-nn graph
-        if let Err(e) = self.graph.unload() {
+        if let Err(e) = self.0.unload() {
             let err_msg = format!("Failed to unload the wasi-nn graph. Reason: {}", e);
 
             #[cfg(feature = "logging")]
@@ -378,6 +495,30 @@ impl<M: BaseMetadata + serde::Serialize + Clone + Default> Drop for Graph<M> {
     }
 }
 
+/// Lease of an execution slot of a graph, which is released when the lease is dropped.
+#[derive(Debug)]
+pub struct SlotLease {
+    model_name: String,
+    index: usize,
+    leased: Arc<AtomicBool>,
+}
+impl SlotLease {
+    /// Get the name of the model the slot belongs to
+    pub fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    /// Get the index of the slot
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+impl Drop for SlotLease {
+    fn drop(&mut self) {
+        self.leased.store(false, Ordering::Release);
+    }
+}
+
 /// Engine type
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum EngineType {
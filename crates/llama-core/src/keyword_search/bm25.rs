@@ -0,0 +1,479 @@
+//! Define the built-in BM25 index, which indexes the chunks of the archived files for the keyword search without a keyword search server.
+
+use crate::{embeddings::chunk_text, error::LlamaCoreError, ARCHIVES_DIR};
+use endpoints::keyword_search::{
+    DocumentResult, IndexResponse, QueryRequest, QueryResponse, SearchHit,
+};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+/// The name of the index file in the directory of the archive. It starts with `.`, so that it is not listed as an archived file.
+const INDEX_FILE_NAME: &str = ".bm25_index.json";
+
+/// The saturation of the term frequency.
+const K1: f32 = 1.2;
+
+/// The normalization of the length of the chunks.
+const B: f32 = 0.75;
+
+/// The characters joining the parts of a term, e.g., `SKU-1024`, `user_id` or `v1.2`.
+const CONNECTORS: [char; 3] = ['-', '_', '.'];
+
+// key: path of the index file, value: the index loaded from the file
+static INDEXES: OnceCell<Mutex<HashMap<PathBuf, Bm25Index>>> = OnceCell::new();
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Document {
+    /// The name of the file of the chunk.
+    title: String,
+    /// The text of the chunk.
+    content: String,
+    /// The number of the terms of the chunk.
+    len: usize,
+}
+
+/// The BM25 inverted index of the chunks of documents.
+///
+/// The text is split into lowercase terms at the whitespaces and the punctuations. The terms joined by `-`, `_` or `.`, such as product codes and IDs, are indexed both as a whole and by parts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Bm25Index {
+    documents: Vec<Document>,
+    // key: term, value: the positions of the chunks containing the term, and the frequencies of the term in the chunks
+    postings: HashMap<String, Vec<(usize, usize)>>,
+}
+impl Bm25Index {
+    /// Create an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the chunks of a document to the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `title` - The title of the document, which is the `title` of the hits of its chunks.
+    ///
+    /// * `chunks` - The chunks of the document, e.g., the ones produced by [crate::embeddings::chunk_text].
+    pub fn add_chunks(&mut self, title: impl Into<String>, chunks: Vec<String>) {
+        let title = title.into();
+        for chunk in chunks {
+            let terms = tokenize(&chunk);
+
+            let mut frequencies: HashMap<&str, usize> = HashMap::new();
+            for term in terms.iter() {
+                *frequencies.entry(term.as_str()).or_default() += 1;
+            }
+
+            let position = self.documents.len();
+            for (term, frequency) in frequencies {
+                self.postings
+                    .entry(term.to_string())
+                    .or_default()
+                    .push((position, frequency));
+            }
+
+            self.documents.push(Document {
+                title: title.clone(),
+                content: chunk,
+                len: terms.len(),
+            });
+        }
+    }
+
+    /// The number of the chunks in the index.
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Check if the index has no chunks.
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Search the chunks matching the terms of the query.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query text.
+    ///
+    /// * `top_k` - The maximum number of the hits to return.
+    ///
+    /// # Returns
+    ///
+    /// The chunks containing any term of the query, in descending order of the BM25 score.
+    pub fn search(&self, query: &str, top_k: usize) -> Vec<SearchHit> {
+        if self.documents.is_empty() || top_k == 0 {
+            return vec![];
+        }
+
+        let n = self.documents.len() as f32;
+        let avg_len = self.documents.iter().map(|d| d.len).sum::<usize>() as f32 / n;
+
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+
+        // key: position of the chunk, value: score of the chunk
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        for term in terms.iter() {
+            if let Some(postings) = self.postings.get(term) {
+                let df = postings.len() as f32;
+                let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+
+                for &(position, frequency) in postings.iter() {
+                    let tf = frequency as f32;
+                    let len = self.documents[position].len as f32;
+                    let score = idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / avg_len));
+                    *scores.entry(position).or_default() += score;
+                }
+            }
+        }
+
+        let mut scores: Vec<(usize, f32)> = scores.into_iter().collect();
+        // the ties keep the order of the chunks
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores.truncate(top_k);
+
+        scores
+            .into_iter()
+            .map(|(position, score)| {
+                let document = &self.documents[position];
+                SearchHit {
+                    title: document.title.clone(),
+                    content: document.content.clone(),
+                    score,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Split the text into lowercase terms.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = vec![];
+    for word in text.split(|c: char| !c.is_alphanumeric() && !CONNECTORS.contains(&c)) {
+        let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+        if word.is_empty() {
+            continue;
+        }
+
+        let word = word.to_lowercase();
+        if word.contains(CONNECTORS) {
+            terms.extend(
+                word.split(CONNECTORS)
+                    .filter(|part| !part.is_empty())
+                    .map(|part| part.to_string()),
+            );
+        }
+        terms.push(word);
+    }
+
+    terms
+}
+
+/// Build the BM25 index of the text and markdown files of the archive, and persist it in the directory of the archive.
+///
+/// The files are split into chunks by [crate::embeddings::chunk_text], which are the same chunks as the ones of the embeddings. The index is named by the archive id, and replaces the previous index of the archive.
+///
+/// # Arguments
+///
+/// * `id` - The id of the archive, i.e., the id of the uploaded file.
+///
+/// * `chunk_capacity` - The max tokens each chunk contains.
+///
+/// # Returns
+///
+/// The indexing result of each file of the archive. `index_name` is set if any file is indexed.
+pub fn create_bm25_index(
+    id: impl AsRef<str>,
+    chunk_capacity: usize,
+) -> Result<IndexResponse, LlamaCoreError> {
+    let id = id.as_ref();
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Create the BM25 index of the archive {}.", id);
+
+    let path = index_path(id)?;
+    let archive_dir = path.parent().unwrap();
+    let entries = fs::read_dir(archive_dir).map_err(|e| {
+        let err_msg = format!("Not found archive id: {}. {}", id, e);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::BadRequest(err_msg)
+    })?;
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && !path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| name.starts_with('.'))
+                    .unwrap_or(true)
+        })
+        .collect();
+    files.sort();
+
+    let mut index = Bm25Index::new();
+    let mut results = vec![];
+    for file in files.iter() {
+        let filename = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let extension = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+
+        let chunks = fs::read_to_string(file)
+            .map_err(|e| format!("Failed to read `{}`. {}", &filename, e))
+            .and_then(|contents| {
+                chunk_text(contents, extension, chunk_capacity).map_err(|e| e.to_string())
+            });
+
+        match chunks {
+            Ok(chunks) => {
+                #[cfg(feature = "logging")]
+                info!(target: "stdout", "Index {} chunks of {}.", chunks.len(), &filename);
+
+                index.add_chunks(filename.as_str(), chunks);
+                results.push(DocumentResult {
+                    filename,
+                    status: "indexed".to_string(),
+                    error: None,
+                });
+            }
+            Err(err_msg) => {
+                #[cfg(feature = "logging")]
+                warn!(target: "stdout", "Skip {} of the archive {}. {}", &filename, id, &err_msg);
+
+                results.push(DocumentResult {
+                    filename,
+                    status: "failed".to_string(),
+                    error: Some(err_msg),
+                });
+            }
+        }
+    }
+
+    let index_name = match results.iter().any(|result| result.error.is_none()) {
+        true => {
+            save_index(&path, &index)?;
+            lock_indexes()?.insert(path, index);
+
+            Some(id.to_string())
+        }
+        false => None,
+    };
+
+    Ok(IndexResponse {
+        results,
+        index_name,
+        download_url: None,
+    })
+}
+
+/// Search the BM25 index named by `index` of the query request, which is created by [create_bm25_index].
+///
+/// # Arguments
+///
+/// * `query_request` - The query, the name of the index and the number of the hits to return.
+///
+/// # Returns
+///
+/// The hits of the query in descending order of score.
+pub fn search_bm25_index(query_request: &QueryRequest) -> Result<QueryResponse, LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Search the BM25 index {}.", &query_request.index);
+
+    let path = index_path(&query_request.index)?;
+
+    let mut indexes = lock_indexes()?;
+
+    // the index is deleted together with its archive
+    if !path.exists() {
+        indexes.remove(&path);
+
+        let err_msg = format!(
+            "The BM25 index {} does not exist. Create it from the uploaded file first.",
+            &query_request.index
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::BadRequest(err_msg));
+    }
+
+    if !indexes.contains_key(&path) {
+        let index: Bm25Index = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                let err_msg = format!(
+                    "Fail to load the BM25 index {}. {}",
+                    &query_request.index, e
+                );
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                LlamaCoreError::KeywordSearch(err_msg)
+            })?;
+
+        indexes.insert(path.clone(), index);
+    }
+
+    let hits = indexes[&path].search(&query_request.query, query_request.top_k);
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Number of hits found in the BM25 index: {}", hits.len());
+
+    Ok(QueryResponse { hits, error: None })
+}
+
+/// Drop the cached index of the archive, which is called when the archive is deleted.
+pub(crate) fn remove_bm25_index(id: &str) {
+    // the archives whose ids are not valid index names have no index
+    if let (Ok(path), Ok(mut indexes)) = (index_path(id), lock_indexes()) {
+        indexes.remove(&path);
+    }
+}
+
+/// The path of the index file of the archive. The ids which could escape the archives directory are rejected.
+fn index_path(id: &str) -> Result<PathBuf, LlamaCoreError> {
+    let is_valid = !id.is_empty()
+        && !id.starts_with('.')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !is_valid {
+        let err_msg = format!("Invalid index name: {}.", id);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::BadRequest(err_msg));
+    }
+
+    Ok(Path::new(ARCHIVES_DIR).join(id).join(INDEX_FILE_NAME))
+}
+
+fn lock_indexes() -> Result<MutexGuard<'static, HashMap<PathBuf, Bm25Index>>, LlamaCoreError> {
+    INDEXES
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|e| {
+            let err_msg = format!("Fail to acquire the lock of the BM25 indexes. {}", e);
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })
+}
+
+/// Write the index to a temporary file first, and then rename it, so that a failed write never leaves a broken index file.
+fn save_index(path: &Path, index: &Bm25Index) -> Result<(), LlamaCoreError> {
+    let result = (|| -> Result<(), String> {
+        let bytes = serde_json::to_vec(index).map_err(|e| e.to_string())?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, bytes).map_err(|e| e.to_string())?;
+        fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+    })();
+
+    result.map_err(|e| {
+        let err_msg = format!("Fail to save the BM25 index to {}. {}", path.display(), e);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::KeywordSearch(err_msg)
+    })
+}
+
+#[test]
+fn test_bm25_rare_terms_rank_higher() {
+    let mut index = Bm25Index::new();
+    index.add_chunks(
+        "a.md",
+        vec![
+            "common words here".to_string(),
+            "rare words here".to_string(),
+            "common words there".to_string(),
+        ],
+    );
+
+    // `rare` is in fewer chunks than `common`, so its inverse document frequency is higher
+    let hits = index.search("common rare", 10);
+    let contents: Vec<&str> = hits.iter().map(|hit| hit.content.as_str()).collect();
+    assert_eq!(
+        contents,
+        ["rare words here", "common words here", "common words there"]
+    );
+    assert!(hits[0].score > hits[1].score);
+    assert_eq!(hits[1].score, hits[2].score);
+    assert!(hits.iter().all(|hit| hit.title == "a.md"));
+}
+
+#[test]
+fn test_bm25_index_terms_with_connectors() {
+    assert_eq!(tokenize("SKU-1024."), ["sku", "1024", "sku-1024"]);
+    assert_eq!(
+        tokenize("user_id v1.2"),
+        ["user", "id", "user_id", "v1", "2", "v1.2"]
+    );
+
+    let mut index = Bm25Index::new();
+    index.add_chunks(
+        "products.md",
+        vec![
+            "The widget SKU-1024 ships in blue.".to_string(),
+            "The gadget SKU-2048 ships in red.".to_string(),
+            "Order 1024 units at least.".to_string(),
+        ],
+    );
+
+    // the whole code matches exactly one chunk, and ranks it above the chunks matching its parts
+    let hits = index.search("Where is sku-1024?", 10);
+    assert_eq!(hits[0].content, "The widget SKU-1024 ships in blue.");
+    assert_eq!(hits.len(), 3);
+
+    // the parts of the code are searchable, too
+    let hits = index.search("1024", 10);
+    let contents: Vec<&str> = hits.iter().map(|hit| hit.content.as_str()).collect();
+    assert_eq!(contents.len(), 2);
+    assert!(contents.contains(&"The widget SKU-1024 ships in blue."));
+    assert!(contents.contains(&"Order 1024 units at least."));
+}
+
+#[test]
+fn test_bm25_search_top_k() {
+    let mut index = Bm25Index::new();
+    index.add_chunks(
+        "a.txt",
+        (0..5).map(|i| format!("term chunk {}", i)).collect(),
+    );
+    index.add_chunks("b.txt", vec!["unrelated".to_string()]);
+    assert_eq!(index.len(), 6);
+
+    let hits = index.search("term", 2);
+    let contents: Vec<&str> = hits.iter().map(|hit| hit.content.as_str()).collect();
+    assert_eq!(contents, ["term chunk 0", "term chunk 1"]);
+
+    assert_eq!(index.search("term", 10).len(), 5);
+    assert!(index.search("term", 0).is_empty());
+    assert!(index.search("missing", 10).is_empty());
+}
@@ -0,0 +1,307 @@
+//! Define the keyword search, which searches the indexes of the documents by keywords.
+//!
+//! The index is searched by the keyword search server at the given URL, or by the built-in BM25 index if the URL is `local://`. See [Bm25Index] for the built-in index.
+
+mod bm25;
+
+pub(crate) use self::bm25::remove_bm25_index;
+pub use self::bm25::{create_bm25_index, search_bm25_index, Bm25Index};
+
+use crate::error::LlamaCoreError;
+use endpoints::keyword_search::{QueryRequest, QueryResponse};
+use reqwest::{Client, StatusCode, Url};
+
+/// The URL of the built-in BM25 index.
+pub const LOCAL_SCHEME: &str = "local://";
+
+/// The number of the hits returned by the keyword search if `kw_top_k` is not set.
+pub const DEFAULT_KW_TOP_K: u64 = 5;
+
+/// The path of the query endpoint of the keyword search server.
+const QUERY_PATH: &str = "v1/search";
+
+/// Search the index of the keyword search server, or the built-in BM25 index.
+///
+/// # Arguments
+///
+/// * `kw_search_url` - The URL of the keyword search server, e.g., `http://localhost:12306`, whose `/v1/search` endpoint is queried, or `local://` for the built-in BM25 index.
+///
+/// * `query_request` - The query, the name of the index and the number of the hits to return.
+///
+/// # Returns
+///
+/// The hits of the query in descending order of score.
+pub async fn search_keywords(
+    kw_search_url: impl AsRef<str>,
+    query_request: &QueryRequest,
+) -> Result<QueryResponse, LlamaCoreError> {
+    if kw_search_url.as_ref().starts_with(LOCAL_SCHEME) {
+        return search_bm25_index(query_request);
+    }
+
+    let url = query_url(kw_search_url.as_ref())?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Search the index {} of the keyword search server: {}", &query_request.index, &url);
+
+    let response = match Client::new().post(url).json(query_request).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            let err_msg = format!("Fail to send the query to the keyword search server. {}", e);
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::KeywordSearch(err_msg));
+        }
+    };
+
+    let status = response.status();
+    let body = match response.bytes().await {
+        Ok(body) => body,
+        Err(e) => {
+            let err_msg = format!(
+                "Fail to read the response of the keyword search server. {}",
+                e
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::KeywordSearch(err_msg));
+        }
+    };
+
+    // the server reports the failures in the `error` field of the response
+    let query_response: Option<QueryResponse> = serde_json::from_slice(&body).ok();
+
+    if let Some(err) = query_response.as_ref().and_then(|r| r.error.as_ref()) {
+        let err_msg = format!("The keyword search server failed to search. {}", err);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::KeywordSearch(err_msg));
+    }
+
+    if status != StatusCode::OK {
+        let err_msg = format!(
+            "The keyword search server responded with {}. {}",
+            status,
+            String::from_utf8_lossy(&body)
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::KeywordSearch(err_msg));
+    }
+
+    match query_response {
+        Some(mut query_response) => {
+            query_response
+                .hits
+                .sort_by(|a, b| b.score.total_cmp(&a.score));
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Number of hits found by the keyword search: {}", query_response.hits.len());
+
+            Ok(query_response)
+        }
+        None => {
+            let err_msg = format!(
+                "Fail to parse the response of the keyword search server: {}",
+                String::from_utf8_lossy(&body)
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::KeywordSearch(err_msg))
+        }
+    }
+}
+
+/// Join the URL of the keyword search server with the path of the query endpoint.
+fn query_url(kw_search_url: &str) -> Result<Url, LlamaCoreError> {
+    let base = format!("{}/", kw_search_url.trim_end_matches('/'));
+
+    Url::parse(&base)
+        .and_then(|base| base.join(QUERY_PATH))
+        .map_err(|e| {
+            let err_msg = format!(
+                "Invalid URL of the keyword search server: {}. {}",
+                kw_search_url, e
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::KeywordSearch(err_msg)
+        })
+}
+
+#[cfg(test)]
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_search_keywords() {
+    // a stand-in keyword search server, which answers one request and yields the received request
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+
+        // read the head and the body of the request before answering it
+        let mut request = String::new();
+        while stream.read_line(&mut request).await.unwrap() > 0
+            && !request.ends_with("\r\n\r\n")
+        {}
+        let content_length = request
+            .lines()
+            .find_map(|line| {
+                line.to_lowercase()
+                    .strip_prefix("content-length:")
+                    .and_then(|value| value.trim().parse().ok())
+            })
+            .unwrap_or_default();
+        let mut body = vec![0u8; content_length];
+        stream.read_exact(&mut body).await.unwrap();
+        request.push_str(&String::from_utf8_lossy(&body));
+
+        let body = r#"{"hits":[{"title":"a.md","content":"low","score":1.0},{"title":"b.md","content":"high","score":3.5}]}"#;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+
+        request
+    });
+
+    let query_request = QueryRequest {
+        query: "SKU-1024".to_string(),
+        top_k: 2,
+        index: "products".to_string(),
+    };
+
+    let query_response = search_keywords(format!("{}/", url), &query_request)
+        .await
+        .unwrap();
+    let contents: Vec<&str> = query_response
+        .hits
+        .iter()
+        .map(|hit| hit.content.as_str())
+        .collect();
+    assert_eq!(contents, ["high", "low"]);
+
+    let request = server.await.unwrap();
+    assert!(request.starts_with("POST /v1/search HTTP/1.1"));
+    assert!(request.contains(r#""query":"SKU-1024""#));
+    assert!(request.contains(r#""index":"products""#));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_search_keywords_error_in_body() {
+    // a stand-in keyword search server, which answers one request and yields the received request
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+
+        // read the head and the body of the request before answering it
+        let mut request = String::new();
+        while stream.read_line(&mut request).await.unwrap() > 0
+            && !request.ends_with("\r\n\r\n")
+        {}
+        let content_length = request
+            .lines()
+            .find_map(|line| {
+                line.to_lowercase()
+                    .strip_prefix("content-length:")
+                    .and_then(|value| value.trim().parse().ok())
+            })
+            .unwrap_or_default();
+        let mut body = vec![0u8; content_length];
+        stream.read_exact(&mut body).await.unwrap();
+        request.push_str(&String::from_utf8_lossy(&body));
+
+        let body = r#"{"hits":[],"error":"Index products not found."}"#;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+
+        request
+    });
+
+    let query_request = QueryRequest {
+        query: "SKU-1024".to_string(),
+        top_k: 2,
+        index: "products".to_string(),
+    };
+
+    let err = search_keywords(url, &query_request).await.unwrap_err();
+    assert!(
+        matches!(&err, LlamaCoreError::KeywordSearch(err_msg) if err_msg.contains("Index products not found."))
+    );
+    server.await.unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_search_keywords_non_200_status() {
+    // a stand-in keyword search server, which answers one request and yields the received request
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+
+        // read the head and the body of the request before answering it
+        let mut request = String::new();
+        while stream.read_line(&mut request).await.unwrap() > 0
+            && !request.ends_with("\r\n\r\n")
+        {}
+        let content_length = request
+            .lines()
+            .find_map(|line| {
+                line.to_lowercase()
+                    .strip_prefix("content-length:")
+                    .and_then(|value| value.trim().parse().ok())
+            })
+            .unwrap_or_default();
+        let mut body = vec![0u8; content_length];
+        stream.read_exact(&mut body).await.unwrap();
+        request.push_str(&String::from_utf8_lossy(&body));
+
+        let body = "overloaded";
+        let response = format!(
+            "HTTP/1.1 503 Service Unavailable\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+
+        request
+    });
+
+    let query_request = QueryRequest {
+        query: "SKU-1024".to_string(),
+        top_k: 2,
+        index: "products".to_string(),
+    };
+
+    let err = search_keywords(url, &query_request).await.unwrap_err();
+    assert!(
+        matches!(&err, LlamaCoreError::KeywordSearch(err_msg) if err_msg.contains("503") && err_msg.contains("overloaded"))
+    );
+    server.await.unwrap();
+}
@@ -14,18 +14,25 @@ pub mod error;
 pub mod files;
 pub mod graph;
 pub mod images;
+#[cfg(feature = "index")]
+#[cfg_attr(docsrs, doc(cfg(feature = "index")))]
+pub mod keyword_search;
 pub mod metadata;
 pub mod models;
 #[cfg(feature = "rag")]
 #[cfg_attr(docsrs, doc(cfg(feature = "rag")))]
 pub mod rag;
+pub mod scheduler;
 #[cfg(feature = "search")]
 #[cfg_attr(docsrs, doc(cfg(feature = "search")))]
 pub mod search;
 pub mod utils;
+#[cfg(feature = "rag")]
+#[cfg_attr(docsrs, doc(cfg(feature = "rag")))]
+pub mod vector_store;
 
 pub use error::LlamaCoreError;
-pub use graph::{EngineType, Graph, GraphBuilder};
+pub use graph::{EngineType, Graph, GraphBuilder, SlotLease};
 #[cfg(feature = "whisper")]
 use metadata::whisper::WhisperMetadata;
 pub use metadata::{ggml::GgmlMetadata, piper::PiperMetadata, BaseMetadata};
@@ -45,8 +52,6 @@ pub(crate) static CHAT_GRAPHS: OnceCell<Mutex<HashMap<String, Graph<GgmlMetadata
 // key: model_name, value: Graph
 pub(crate) static EMBEDDING_GRAPHS: OnceCell<Mutex<HashMap<String, Graph<GgmlMetadata>>>> =
     OnceCell::new();
-// cache bytes for decoding utf8
-pub(crate) static CACHED_UTF8_ENCODINGS: OnceCell<Mutex<Vec<u8>>> = OnceCell::new();
 // running mode
 pub(crate) static RUNNING_MODE: OnceCell<RwLock<RunningMode>> = OnceCell::new();
 // stable diffusion context for the text-to-image task
@@ -61,6 +66,7 @@ pub(crate) static PIPER_GRAPH: OnceCell<Mutex<Graph<PiperMetadata>>> = OnceCell:
 
 pub(crate) const MAX_BUFFER_SIZE: usize = 2usize.pow(14) * 15 + 128;
 pub(crate) const OUTPUT_TENSOR: usize = 0;
+pub(crate) const LOGPROBS_TENSOR: usize = 2;
 const PLUGIN_VERSION: usize = 1;
 
 /// The directory for storing the archives in wasm virtual file system.
@@ -88,7 +94,10 @@ pub fn init_ggml_context(
     if let Some(metadata_chats) = metadata_for_chats {
         let mut chat_graphs = HashMap::new();
         for metadata in metadata_chats {
-            let graph = Graph::new(metadata.clone())?;
+            let graph = GraphBuilder::new(EngineType::Ggml)?
+                .with_config(metadata.clone())?
+                .with_slots(metadata.slots)
+                .build_from_cache()?;
 
             chat_graphs.insert(graph.name().to_string(), graph);
         }
@@ -165,7 +174,10 @@ pub fn init_ggml_rag_context(
     }
     let mut chat_graphs = HashMap::new();
     for metadata in metadata_for_chats {
-        let graph = Graph::new(metadata.clone())?;
+        let graph = GraphBuilder::new(EngineType::Ggml)?
+            .with_config(metadata.clone())?
+            .with_slots(metadata.slots)
+            .build_from_cache()?;
 
         chat_graphs.insert(graph.name().to_string(), graph);
     }
@@ -308,7 +320,7 @@ pub fn get_plugin_info() -> Result<PluginInfo, LlamaCoreError> {
     }
 }
 
-fn get_plugin_info_by_graph<M: BaseMetadata + serde::Serialize + Clone + Default>(
+pub(crate) fn get_plugin_info_by_graph<M: BaseMetadata + serde::Serialize + Clone + Default>(
     graph: &Graph<M>,
 ) -> Result<PluginInfo, LlamaCoreError> {
     #[cfg(feature = "logging")]
@@ -3,6 +3,10 @@
 use super::BaseMetadata;
 use chat_prompts::PromptTemplateType;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The seed which makes the backend sample with a random seed.
+pub const DEFAULT_SEED: u64 = 0xFFFFFFFF;
 
 /// Builder for creating a ggml metadata
 #[derive(Debug)]
@@ -26,6 +30,11 @@ impl GgmlMetadataBuilder {
         self
     }
 
+    pub fn with_slots(mut self, slots: usize) -> Self {
+        self.metadata.slots = slots.max(1);
+        self
+    }
+
     pub fn enable_plugin_log(mut self, enable: bool) -> Self {
         self.metadata.log_enable = enable;
         self
@@ -51,6 +60,16 @@ impl GgmlMetadataBuilder {
         self
     }
 
+    pub fn enable_logprobs(mut self, enable: bool) -> Self {
+        self.metadata.logprobs = enable;
+        self
+    }
+
+    pub fn with_top_logprobs(mut self, n: u64) -> Self {
+        self.metadata.top_logprobs = n;
+        self
+    }
+
     pub fn with_main_gpu(mut self, gpu: Option<u64>) -> Self {
         self.metadata.main_gpu = gpu;
         self
@@ -136,6 +155,11 @@ impl GgmlMetadataBuilder {
         self
     }
 
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.metadata.seed = seed;
+        self
+    }
+
     pub fn with_grammar(mut self, grammar: impl Into<String>) -> Self {
         self.metadata.grammar = grammar.into();
         self
@@ -146,6 +170,11 @@ impl GgmlMetadataBuilder {
         self
     }
 
+    pub fn with_logit_bias(mut self, logit_bias: LogitBias) -> Self {
+        self.metadata.logit_bias = logit_bias;
+        self
+    }
+
     pub fn build(self) -> GgmlMetadata {
         self.metadata
     }
@@ -153,6 +182,7 @@ impl GgmlMetadataBuilder {
 
 /// Metadata for chat and embeddings models
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct GgmlMetadata {
     // this field not defined for the beckend plugin
     #[serde(skip_serializing)]
@@ -166,6 +196,10 @@ pub struct GgmlMetadata {
     // this field not defined for the beckend plugin
     #[serde(skip_serializing)]
     pub prompt_template: PromptTemplateType,
+    // this field not defined for the beckend plugin
+    /// The number of execution slots, i.e., the number of requests served concurrently by the model.
+    #[serde(skip_serializing)]
+    pub slots: usize,
 
     // * Plugin parameters (used by this plugin):
     #[serde(rename = "enable-log")]
@@ -179,6 +213,11 @@ pub struct GgmlMetadata {
     /// Number of tokens to predict, -1 = infinity, -2 = until context filled. Defaults to -1.
     #[serde(rename = "n-predict")]
     pub n_predict: i32,
+    /// Whether to output the log probabilities of the generated tokens. Defaults to false.
+    pub logprobs: bool,
+    /// Number of the most likely tokens to output at each token position, each with an associated log probability. Only used if `logprobs` is enabled. Defaults to 0.
+    #[serde(rename = "top-logprobs")]
+    pub top_logprobs: u64,
     /// Halt generation at PROMPT, return control in interactive mode.
     #[serde(skip_serializing_if = "Option::is_none", rename = "reverse-prompt")]
     pub reverse_prompt: Option<String>,
@@ -240,6 +279,11 @@ pub struct GgmlMetadata {
     /// Repeat alpha frequency penalty. Defaults to 0.0.
     #[serde(rename = "frequency-penalty")]
     pub frequency_penalty: f64,
+    /// Modify the likelihood of the specified tokens appearing in the completion. Defaults to empty.
+    #[serde(rename = "logit-bias")]
+    pub logit_bias: LogitBias,
+    /// RNG seed used in sampling. `4294967295` (`0xFFFFFFFF`) means a random seed. Defaults to `4294967295`.
+    pub seed: u64,
 
     // * grammar parameters
     /// BNF-like grammar to constrain generations (see samples in grammars/ dir). Defaults to empty string.
@@ -256,9 +300,12 @@ impl Default for GgmlMetadata {
             log_prompts: false,
             debug_log: false,
             prompt_template: PromptTemplateType::Llama2Chat,
+            slots: 1,
             log_enable: false,
             embeddings: false,
             n_predict: -1,
+            logprobs: false,
+            top_logprobs: 0,
             reverse_prompt: None,
             mmproj: None,
             image: None,
@@ -276,6 +323,8 @@ impl Default for GgmlMetadata {
             repeat_penalty: 1.0,
             presence_penalty: 0.0,
             frequency_penalty: 0.0,
+            logit_bias: LogitBias::default(),
+            seed: DEFAULT_SEED,
             grammar: String::new(),
             json_schema: None,
         }
@@ -295,3 +344,12 @@ impl GgmlMetadata {
         self.prompt_template
     }
 }
+
+/// Logit biases added to the logits generated by the model prior to sampling.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct LogitBias {
+    /// The biases keyed by the token ids in the tokenizer of the model.
+    pub input_ids: BTreeMap<u32, f64>,
+    /// The biases keyed by the strings which the backend tokenizes against the loaded model. The bias applies to each token of the string.
+    pub tokens: BTreeMap<String, f64>,
+}
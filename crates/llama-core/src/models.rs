@@ -1,7 +1,66 @@
-//! Define APIs for querying models.
+//! Define APIs for querying, loading and unloading models.
+//!
+//! Besides the models loaded at startup or by [load_model], the models registered by [register_model] are loaded on the first request to them, and are evicted in the least-recently-used order according to the [EvictionPolicy].
 
-use crate::{error::LlamaCoreError, CHAT_GRAPHS, EMBEDDING_GRAPHS};
-use endpoints::models::{ListModelsResponse, Model};
+use crate::{
+    error::LlamaCoreError,
+    graph::{EngineType, Graph, GraphBuilder},
+    metadata::ggml::GgmlMetadata,
+    scheduler, RunningMode, CHAT_GRAPHS, EMBEDDING_GRAPHS, RUNNING_MODE,
+};
+use chat_prompts::PromptTemplateType;
+use endpoints::models::{DeleteModelStatus, ListModelsResponse, Model, ModelState};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, RwLock},
+    time::{Duration, Instant},
+};
+
+type Graphs = Mutex<HashMap<String, Graph<GgmlMetadata>>>;
+
+// the models loaded on demand and the policy of evicting them
+static MODEL_REGISTRY: OnceCell<Mutex<ModelRegistry>> = OnceCell::new();
+
+#[derive(Debug, Default)]
+struct ModelRegistry {
+    /// The registered models in the registration order.
+    models: Vec<RegisteredModel>,
+    policy: EvictionPolicy,
+}
+
+#[derive(Debug)]
+struct RegisteredModel {
+    metadata: GgmlMetadata,
+    /// Path to the model file. If `None`, the model is loaded from the model preloaded with the alias of the model.
+    path: Option<PathBuf>,
+    /// Size (in bytes) of the model file, which is used to estimate the memory used by the model.
+    size: u64,
+    /// The Unix timestamp (in seconds) of when the model was registered.
+    created: u64,
+    last_used: Instant,
+    loading: bool,
+}
+impl RegisteredModel {
+    fn name(&self) -> &str {
+        &self.metadata.model_name
+    }
+
+    fn is_embedding(&self) -> bool {
+        self.metadata.prompt_template == PromptTemplateType::Embedding
+    }
+}
+
+/// Policy of evicting the models loaded on demand. The least recently used models are evicted first, and the models serving or queuing requests are never evicted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvictionPolicy {
+    /// Evict the models which have not been used for the given duration. `None` means the idle models are kept.
+    pub idle_timeout: Option<Duration>,
+    /// Maximum total size (in bytes) of the model files loaded on demand. `None` means no limit.
+    pub memory_budget: Option<u64>,
+}
 
 /// Lists models available
 pub async fn models() -> Result<ListModelsResponse, LlamaCoreError> {
@@ -27,6 +86,7 @@ pub async fn models() -> Result<ListModelsResponse, LlamaCoreError> {
                     created: graph.created.as_secs(),
                     object: String::from("model"),
                     owned_by: String::from("Not specified"),
+                    state: Some(ModelState::Loaded),
                 });
             }
         }
@@ -48,14 +108,694 @@ pub async fn models() -> Result<ListModelsResponse, LlamaCoreError> {
                         created: graph.created.as_secs(),
                         object: String::from("model"),
                         owned_by: String::from("Not specified"),
+                        state: Some(ModelState::Loaded),
                     });
                 }
             }
         }
     }
 
+    // the registered models which are not loaded
+    if let Some(registry) = MODEL_REGISTRY.get() {
+        let registry = lock_registry(registry)?;
+
+        for registered in registry.models.iter() {
+            if models.iter().any(|model| model.id == registered.name()) {
+                continue;
+            }
+
+            models.push(Model {
+                id: registered.name().to_string(),
+                created: registered.created,
+                object: String::from("model"),
+                owned_by: String::from("Not specified"),
+                state: match registered.loading {
+                    true => Some(ModelState::Loading),
+                    false => Some(ModelState::Unloaded),
+                },
+            });
+        }
+    }
+
     Ok(ListModelsResponse {
         object: String::from("list"),
         data: models,
     })
 }
+
+/// Request of loading a chat or embedding model at runtime.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LoadModelRequest {
+    /// Path to the model file, which should be accessible in the wasm virtual file system.
+    pub path: String,
+    /// Metadata of the model. The model is loaded as an embedding model if the prompt template is `embedding`; otherwise, it is loaded as a chat model.
+    pub metadata: GgmlMetadata,
+}
+
+/// Load a chat or embedding model from the given model file.
+///
+/// The model is loaded without holding the lock of the graphs, so the inference running on the other threads is not blocked. Note that the loading blocks the calling thread until the model is loaded, so the requests served on the same thread, e.g., by a single-threaded runtime, wait for the loading. Returns [LlamaCoreError::ModelConflict] if a model with the same name has been loaded.
+pub fn load_model(
+    path: impl AsRef<Path>,
+    mut metadata: GgmlMetadata,
+) -> Result<Model, LlamaCoreError> {
+    let path = path.as_ref();
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Load the model {} from {}", &metadata.model_name, path.display());
+
+    if metadata.model_name.is_empty() {
+        let err_msg = "The name of the model is empty.";
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", err_msg);
+
+        return Err(LlamaCoreError::Operation(err_msg.into()));
+    }
+
+    if metadata.model_alias.is_empty() {
+        metadata.model_alias = metadata.model_name.clone();
+    }
+
+    if !path.exists() {
+        let err_msg = format!("The model file {} is not found.", path.display());
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::Operation(err_msg));
+    }
+
+    // check the name before loading the model, which takes a while
+    ensure_model_not_loaded(&metadata.model_name)?;
+
+    let is_embedding = metadata.prompt_template == PromptTemplateType::Embedding;
+    let graph = build_graph(Some(path), &metadata)?;
+    let model = insert_graph(graph, is_embedding)?;
+
+    update_running_mode()?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "The model {} has been loaded", &model.id);
+
+    Ok(model)
+}
+
+/// Unload the chat or embedding model with the given name.
+///
+/// Returns [LlamaCoreError::ModelNotFound] if the model is not loaded, or [LlamaCoreError::ModelConflict] if the model is serving or queuing requests.
+pub fn unload_model(model_name: impl AsRef<str>) -> Result<DeleteModelStatus, LlamaCoreError> {
+    let model_name = model_name.as_ref();
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Unload the model {}", model_name);
+
+    let mut graph = None;
+    for (graphs, is_embedding) in [(CHAT_GRAPHS.get(), false), (EMBEDDING_GRAPHS.get(), true)] {
+        if let Some(graphs) = graphs {
+            let mut graphs = lock_graphs(graphs, is_embedding)?;
+
+            if let Some(g) = graphs.get(model_name) {
+                if !is_idle(g) {
+                    let err_msg = format!(
+                        "The model {} is serving or queuing requests and cannot be unloaded.",
+                        model_name
+                    );
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return Err(LlamaCoreError::ModelConflict(err_msg));
+                }
+
+                graph = graphs.remove(model_name);
+                break;
+            }
+        }
+    }
+
+    match graph {
+        Some(graph) => {
+            // unload the wasi-nn graph outside of the lock of the graphs
+            drop(graph);
+
+            update_running_mode()?;
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "The model {} has been unloaded", model_name);
+
+            Ok(DeleteModelStatus {
+                id: model_name.to_string(),
+                object: String::from("model"),
+                deleted: true,
+            })
+        }
+        None => {
+            let err_msg = format!("The model {} is not loaded.", model_name);
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::ModelNotFound(err_msg))
+        }
+    }
+}
+
+/// Build the graph of the chat or embedding model. The model is loaded from the model file if `path` is given; otherwise, from the model preloaded with the alias of the model.
+fn build_graph(
+    path: Option<&Path>,
+    metadata: &GgmlMetadata,
+) -> Result<Graph<GgmlMetadata>, LlamaCoreError> {
+    let slots = match metadata.prompt_template == PromptTemplateType::Embedding {
+        true => 1,
+        false => metadata.slots,
+    };
+    let builder = GraphBuilder::new(EngineType::Ggml)?
+        .with_config(metadata.clone())?
+        .with_slots(slots);
+
+    match path {
+        Some(path) => builder.build_from_files([path]),
+        None => builder.build_from_cache(),
+    }
+}
+
+/// Insert the graph into the chat or embedding graphs. Returns [LlamaCoreError::ModelConflict] if a model with the same name has been loaded.
+fn insert_graph(graph: Graph<GgmlMetadata>, is_embedding: bool) -> Result<Model, LlamaCoreError> {
+    let model = Model {
+        id: graph.name().to_string(),
+        created: graph.created.as_secs(),
+        object: String::from("model"),
+        owned_by: String::from("Not specified"),
+        state: Some(ModelState::Loaded),
+    };
+
+    let graphs = match is_embedding {
+        true => EMBEDDING_GRAPHS.get_or_init(|| Mutex::new(HashMap::new())),
+        false => CHAT_GRAPHS.get_or_init(|| Mutex::new(HashMap::new())),
+    };
+
+    let mut graphs = lock_graphs(graphs, is_embedding)?;
+
+    // another model with the same name may be loaded in the meantime
+    if graphs.contains_key(&model.id) {
+        let err_msg = format!("The model {} has already been loaded.", &model.id);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::ModelConflict(err_msg));
+    }
+
+    graphs.insert(model.id.clone(), graph);
+
+    Ok(model)
+}
+
+fn lock_graphs(
+    graphs: &Graphs,
+    is_embedding: bool,
+) -> Result<MutexGuard<'_, HashMap<String, Graph<GgmlMetadata>>>, LlamaCoreError> {
+    graphs.lock().map_err(|e| {
+        let err_msg = match is_embedding {
+            true => format!("Fail to acquire the lock of `EMBEDDING_GRAPHS`. {}", e),
+            false => format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e),
+        };
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })
+}
+
+/// Return [LlamaCoreError::ModelConflict] if a chat or embedding model with the given name has been loaded.
+fn ensure_model_not_loaded(model_name: &str) -> Result<(), LlamaCoreError> {
+    for (graphs, is_embedding) in [(CHAT_GRAPHS.get(), false), (EMBEDDING_GRAPHS.get(), true)] {
+        if let Some(graphs) = graphs {
+            if lock_graphs(graphs, is_embedding)?.contains_key(model_name) {
+                let err_msg = format!("The model {} has already been loaded.", model_name);
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::ModelConflict(err_msg));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Update the running mode according to the loaded chat and embedding models. The RAG mode is kept as it is.
+fn update_running_mode() -> Result<(), LlamaCoreError> {
+    let has_models = |graphs: Option<&Graphs>, is_embedding: bool| match graphs {
+        Some(graphs) => lock_graphs(graphs, is_embedding).map(|graphs| !graphs.is_empty()),
+        None => Ok(false),
+    };
+    let mut has_chat_models = has_models(CHAT_GRAPHS.get(), false)?;
+    let mut has_embedding_models = has_models(EMBEDDING_GRAPHS.get(), true)?;
+
+    // the registered models are available even if they are not loaded
+    if let Some(registry) = MODEL_REGISTRY.get() {
+        let registry = lock_registry(registry)?;
+        has_chat_models |= registry.models.iter().any(|m| !m.is_embedding());
+        has_embedding_models |= registry.models.iter().any(|m| m.is_embedding());
+    }
+
+    let mode = match (has_chat_models, has_embedding_models) {
+        (true, true) => RunningMode::ChatEmbedding,
+        (true, false) => RunningMode::Chat,
+        _ => RunningMode::Embeddings,
+    };
+
+    let running_mode = RUNNING_MODE.get_or_init(|| RwLock::new(mode.clone()));
+    let mut running_mode = running_mode.write().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of `RUNNING_MODE`. {}", e);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    if *running_mode != RunningMode::Rag && *running_mode != mode {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "running mode: {}", &mode);
+
+        *running_mode = mode;
+    }
+
+    Ok(())
+}
+
+/// Set the policy of evicting the models loaded on demand.
+pub fn set_eviction_policy(policy: EvictionPolicy) -> Result<(), LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Set the eviction policy: {:?}", &policy);
+
+    lock_registry(registry())?.policy = policy;
+
+    Ok(())
+}
+
+/// Register a chat or embedding model, which is loaded on the first request to it rather than immediately.
+///
+/// The model is loaded from the model file if `path` is given; otherwise, from the model preloaded with the alias of the model. The size of the model file is counted against the memory budget of the [EvictionPolicy].
+pub fn register_model(
+    mut metadata: GgmlMetadata,
+    path: Option<PathBuf>,
+) -> Result<(), LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Register the model {}", &metadata.model_name);
+
+    if metadata.model_alias.is_empty() {
+        metadata.model_alias = metadata.model_name.clone();
+    }
+
+    ensure_model_not_loaded(&metadata.model_name)?;
+
+    let size = match &path {
+        Some(path) => std::fs::metadata(path).map(|m| m.len()).map_err(|e| {
+            let err_msg = format!(
+                "Fail to get the metadata of the model file {}. {}",
+                path.display(),
+                e
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })?,
+        None => 0,
+    };
+
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    {
+        let mut registry = lock_registry(registry())?;
+
+        if registry
+            .models
+            .iter()
+            .any(|m| m.name() == metadata.model_name)
+        {
+            let err_msg = format!(
+                "The model {} has already been registered.",
+                &metadata.model_name
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::ModelConflict(err_msg));
+        }
+
+        registry.models.push(RegisteredModel {
+            metadata,
+            path,
+            size,
+            created,
+            last_used: Instant::now(),
+            loading: false,
+        });
+    }
+
+    update_running_mode()
+}
+
+/// Load the registered model targeted by the request if it is not loaded, and mark it as used.
+///
+/// If the model name is not given or not registered, the first registered model of the kind is loaded only if no model of the kind is loaded, which keeps the fallback of the requests without a valid `model` field working.
+///
+/// Loading the model blocks the calling thread until the model is loaded. A request to a model which is still being loaded gets `LlamaCoreError::ModelLoading`.
+pub(crate) fn load_on_demand(
+    model_name: Option<&str>,
+    is_embedding: bool,
+) -> Result<(), LlamaCoreError> {
+    let registry = match MODEL_REGISTRY.get() {
+        Some(registry) => registry,
+        None => return Ok(()),
+    };
+
+    let loaded = loaded_model_names()?;
+    let has_loaded_models = match is_embedding {
+        true => !loaded.embeddings.is_empty(),
+        false => !loaded.chats.is_empty(),
+    };
+
+    let (metadata, path, victims) = {
+        let mut registry = lock_registry(registry)?;
+
+        let index = match model_name.and_then(|name| {
+            registry
+                .models
+                .iter()
+                .position(|m| m.name() == name && m.is_embedding() == is_embedding)
+        }) {
+            Some(index) => index,
+            None if !has_loaded_models => {
+                match registry
+                    .models
+                    .iter()
+                    .position(|m| m.is_embedding() == is_embedding)
+                {
+                    Some(index) => index,
+                    None => return Ok(()),
+                }
+            }
+            None => return Ok(()),
+        };
+
+        registry.models[index].last_used = Instant::now();
+
+        if loaded.contains(registry.models[index].name()) {
+            return Ok(());
+        }
+
+        if registry.models[index].loading {
+            let err_msg = format!(
+                "The model {} is being loaded.",
+                registry.models[index].name()
+            );
+
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::ModelLoading(err_msg));
+        }
+
+        // the loaded models which could be evicted for the memory budget in the least-recently-used order, and the memory to free
+        let mut victims = vec![];
+        let mut to_free = 0;
+        if let Some(memory_budget) = registry.policy.memory_budget {
+            let target = &registry.models[index];
+            let mut candidates: Vec<&RegisteredModel> = registry
+                .models
+                .iter()
+                .filter(|m| m.name() != target.name() && loaded.contains(m.name()))
+                .collect();
+            candidates.sort_by_key(|m| m.last_used);
+
+            let used: u64 = candidates.iter().map(|m| m.size).sum();
+            if used + target.size > memory_budget {
+                to_free = used + target.size - memory_budget;
+                victims = candidates
+                    .into_iter()
+                    .map(|m| (m.name().to_string(), m.is_embedding(), m.size))
+                    .collect();
+            }
+        }
+
+        registry.models[index].loading = true;
+
+        (
+            registry.models[index].metadata.clone(),
+            registry.models[index].path.clone(),
+            victims,
+        )
+    };
+
+    let result = evict_and_load(&metadata, path.as_deref(), victims, to_free);
+
+    // the model is either loaded or failed to load
+    if let Some(model) = lock_registry(registry)?
+        .models
+        .iter_mut()
+        .find(|m| m.name() == metadata.model_name)
+    {
+        model.loading = false;
+        model.last_used = Instant::now();
+    }
+
+    result
+}
+
+/// Evict the given models in order until the given memory is freed, and load the model.
+///
+/// The models in use are skipped. If the idle models cannot free enough memory, none of them is evicted and the loading fails.
+fn evict_and_load(
+    metadata: &GgmlMetadata,
+    path: Option<&Path>,
+    victims: Vec<(String, bool, u64)>,
+    to_free: u64,
+) -> Result<(), LlamaCoreError> {
+    let budget_exhausted = || {
+        let err_msg = format!(
+            "Fail to load the model {}. The memory budget is exhausted, and the models in use cannot be evicted.",
+            &metadata.model_name
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    };
+
+    let mut victims_in_idle = vec![];
+    for victim in victims {
+        if is_evictable(&victim.0, victim.1)? {
+            victims_in_idle.push(victim);
+        }
+    }
+
+    let evictable: u64 = victims_in_idle.iter().map(|(_, _, size)| size).sum();
+    if evictable < to_free {
+        return Err(budget_exhausted());
+    }
+
+    let mut to_free = to_free;
+    for (model_name, is_embedding, size) in victims_in_idle.iter() {
+        if to_free == 0 {
+            break;
+        }
+
+        // the model may be used since it was checked
+        if evict_model(model_name, *is_embedding)? {
+            to_free = to_free.saturating_sub(*size);
+        }
+    }
+
+    if to_free > 0 {
+        return Err(budget_exhausted());
+    }
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Load the model {} on demand", &metadata.model_name);
+
+    let is_embedding = metadata.prompt_template == PromptTemplateType::Embedding;
+    let graph = build_graph(path, metadata)?;
+    insert_graph(graph, is_embedding)?;
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "The model {} has been loaded", &metadata.model_name);
+
+    Ok(())
+}
+
+/// Evict the registered models which have been idle longer than the idle timeout of the [EvictionPolicy]. Returns the names of the evicted models.
+pub fn evict_idle_models() -> Result<Vec<String>, LlamaCoreError> {
+    let registry = match MODEL_REGISTRY.get() {
+        Some(registry) => registry,
+        None => return Ok(vec![]),
+    };
+
+    let loaded = loaded_model_names()?;
+
+    let candidates: Vec<(String, bool)> = {
+        let registry = lock_registry(registry)?;
+
+        let idle_timeout = match registry.policy.idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return Ok(vec![]),
+        };
+
+        registry
+            .models
+            .iter()
+            .filter(|m| {
+                !m.loading && loaded.contains(m.name()) && m.last_used.elapsed() >= idle_timeout
+            })
+            .map(|m| (m.name().to_string(), m.is_embedding()))
+            .collect()
+    };
+
+    let mut evicted = vec![];
+    for (model_name, is_embedding) in candidates {
+        if evict_model(&model_name, is_embedding)? {
+            evicted.push(model_name);
+        }
+    }
+
+    Ok(evicted)
+}
+
+/// Unload the model if it is neither serving nor queuing requests. Returns `false` if the model is in use.
+fn evict_model(model_name: &str, is_embedding: bool) -> Result<bool, LlamaCoreError> {
+    let graphs = match is_embedding {
+        true => EMBEDDING_GRAPHS.get(),
+        false => CHAT_GRAPHS.get(),
+    };
+
+    let graph = match graphs {
+        Some(graphs) => {
+            let mut graphs = lock_graphs(graphs, is_embedding)?;
+
+            match graphs.get(model_name) {
+                Some(graph) if !is_idle(graph) => return Ok(false),
+                Some(_) => graphs.remove(model_name),
+                None => None,
+            }
+        }
+        None => None,
+    };
+
+    // unload the wasi-nn graph outside of the lock of the graphs
+    if graph.is_some() {
+        drop(graph);
+
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "The model {} has been evicted", model_name);
+    }
+
+    Ok(true)
+}
+
+/// Check if the model is neither serving nor queuing requests, so that it can be evicted. Returns `true` if the model is not loaded.
+fn is_evictable(model_name: &str, is_embedding: bool) -> Result<bool, LlamaCoreError> {
+    let graphs = match is_embedding {
+        true => EMBEDDING_GRAPHS.get(),
+        false => CHAT_GRAPHS.get(),
+    };
+
+    match graphs {
+        Some(graphs) => Ok(lock_graphs(graphs, is_embedding)?
+            .get(model_name)
+            .map(is_idle)
+            .unwrap_or(true)),
+        None => Ok(true),
+    }
+}
+
+fn is_idle(graph: &Graph<GgmlMetadata>) -> bool {
+    is_model_idle(graph.name(), graph.free_slots(), graph.slots())
+}
+
+/// Check if none of the execution slots of the model is leased, and no request is running on or waiting for the model. A request holding the permit of the model may not have leased a slot yet.
+fn is_model_idle(model_name: &str, free_slots: usize, slots: usize) -> bool {
+    free_slots == slots && scheduler::is_idle(model_name)
+}
+
+/// The names of the loaded chat and embedding models.
+struct LoadedModels {
+    chats: HashSet<String>,
+    embeddings: HashSet<String>,
+}
+impl LoadedModels {
+    fn contains(&self, model_name: &str) -> bool {
+        self.chats.contains(model_name) || self.embeddings.contains(model_name)
+    }
+}
+
+fn loaded_model_names() -> Result<LoadedModels, LlamaCoreError> {
+    let names = |graphs: Option<&Graphs>, is_embedding: bool| match graphs {
+        Some(graphs) => lock_graphs(graphs, is_embedding)
+            .map(|graphs| graphs.keys().cloned().collect::<HashSet<String>>()),
+        None => Ok(HashSet::new()),
+    };
+
+    Ok(LoadedModels {
+        chats: names(CHAT_GRAPHS.get(), false)?,
+        embeddings: names(EMBEDDING_GRAPHS.get(), true)?,
+    })
+}
+
+fn registry() -> &'static Mutex<ModelRegistry> {
+    MODEL_REGISTRY.get_or_init(|| Mutex::new(ModelRegistry::default()))
+}
+
+fn lock_registry(
+    registry: &Mutex<ModelRegistry>,
+) -> Result<MutexGuard<'_, ModelRegistry>, LlamaCoreError> {
+    registry.lock().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of the model registry. {}", e);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })
+}
+
+#[test]
+fn test_model_with_queued_request_cannot_be_unloaded() {
+    use futures::FutureExt;
+
+    let model_name = "test-model-with-queued-request";
+    assert!(is_model_idle(model_name, 1, 1));
+
+    // a request holds the permit of the only slot before leasing the slot, and another request waits in the queue
+    let permit = scheduler::join_queue(model_name.to_string(), 1)
+        .unwrap()
+        .now_or_never()
+        .unwrap()
+        .unwrap();
+    let mut queued = scheduler::join_queue(model_name.to_string(), 1).unwrap();
+    assert!((&mut queued).now_or_never().is_none());
+    assert!(!is_model_idle(model_name, 1, 1));
+
+    // the queued request takes over the permit
+    drop(permit);
+    assert!(!is_model_idle(model_name, 1, 1));
+
+    let permit = queued.now_or_never().unwrap().unwrap();
+    drop(permit);
+    assert!(is_model_idle(model_name, 1, 1));
+
+    // a leased slot keeps the model busy as well
+    assert!(!is_model_idle(model_name, 0, 1));
+}
@@ -1,14 +1,42 @@
 //! Define APIs for RAG operations.
 
-use crate::{embeddings::embeddings, error::LlamaCoreError, running_mode, RunningMode};
+#[cfg(feature = "index")]
+use crate::keyword_search::{search_keywords, DEFAULT_KW_TOP_K};
+use crate::{
+    embeddings::embeddings,
+    error::LlamaCoreError,
+    running_mode,
+    utils::chat_prompt_template,
+    vector_store::{vector_store, Point, ScoredPoint},
+    RunningMode,
+};
+use chat_prompts::{MergeRagContext, MergeRagContextPolicy, DEFAULT_RAG_PROMPT};
+#[cfg(feature = "index")]
+use endpoints::keyword_search::{QueryRequest, SearchHit};
 use endpoints::{
-    embeddings::{EmbeddingObject, EmbeddingRequest, EmbeddingsResponse, InputText},
+    chat::{
+        ChatCompletionRequest, ChatCompletionRequestMessage, ChatCompletionUserMessageContent,
+        ContentPart,
+    },
+    embeddings::{EmbeddingRequest, EmbeddingsResponse, InputText},
     rag::{RagScoredPoint, RetrieveObject},
 };
-use qdrant::*;
 use serde_json::Value;
+#[cfg(feature = "index")]
+use std::collections::HashMap;
 use std::collections::HashSet;
 
+/// The number of the points retrieved from each collection if the chat request does not set `limit`.
+pub const DEFAULT_RAG_LIMIT: u64 = 5;
+
+/// The constant `k` of the reciprocal rank fusion, which damps the lead of the top ranks of each list.
+#[cfg(feature = "index")]
+pub const RRF_K: f32 = 60.0;
+
+/// Merge the RAG context into the chat messages in the way of [MergeRagContext].
+pub struct RagPromptBuilder;
+impl MergeRagContext for RagPromptBuilder {}
+
 /// Convert document chunks to embeddings.
 ///
 /// # Arguments
@@ -17,7 +45,7 @@ use std::collections::HashSet;
 ///
 /// # Returns
 ///
-/// Name of the Qdrant collection if successful.
+/// The embeddings of the document chunks if successful. The embeddings are persisted to the collection of the vector store selected by `vdb_server_url`; see [crate::vector_store] for the supported URLs.
 pub async fn rag_doc_chunks_to_embeddings(
     embedding_request: &EmbeddingRequest,
 ) -> Result<EmbeddingsResponse, LlamaCoreError> {
@@ -37,7 +65,7 @@ pub async fn rag_doc_chunks_to_embeddings(
         return Err(LlamaCoreError::Operation(err_msg));
     }
 
-    let qdrant_url = match embedding_request.vdb_server_url.as_deref() {
+    let vdb_server_url = match embedding_request.vdb_server_url.as_deref() {
         Some(url) => url.to_string(),
         None => {
             let err_msg = "The VectorDB server URL is not provided.";
@@ -48,7 +76,7 @@ pub async fn rag_doc_chunks_to_embeddings(
             return Err(LlamaCoreError::Operation(err_msg.into()));
         }
     };
-    let qdrant_collection_name = match embedding_request.vdb_collection_name.as_deref() {
+    let vdb_collection_name = match embedding_request.vdb_collection_name.as_deref() {
         Some(name) => name.to_string(),
         None => {
             let err_msg = "The VectorDB collection name is not provided.";
@@ -73,21 +101,11 @@ pub async fn rag_doc_chunks_to_embeddings(
     let embeddings = embeddings_response.data.as_slice();
     let dim = embeddings[0].embedding.len();
 
-    // create a Qdrant client
-    let mut qdrant_client = qdrant::Qdrant::new_with_url(qdrant_url);
-
-    // set the API key if provided
-    if let Some(key) = embedding_request.vdb_api_key.as_deref() {
-        if !key.is_empty() {
-            #[cfg(feature = "logging")]
-            debug!(target: "stdout", "Set the API key for the VectorDB server.");
-
-            qdrant_client.set_api_key(key);
-        }
-    }
+    // open the vector store
+    let store = vector_store(&vdb_server_url, embedding_request.vdb_api_key.as_deref())?;
 
     // create a collection
-    qdrant_create_collection(&qdrant_client, &qdrant_collection_name, dim).await?;
+    store.create_collection(&vdb_collection_name, dim).await?;
 
     let chunks = match &embedding_request.input {
         InputText::String(text) => vec![text.clone()],
@@ -100,13 +118,17 @@ pub async fn rag_doc_chunks_to_embeddings(
     };
 
     // create and upsert points
-    qdrant_persist_embeddings(
-        &qdrant_client,
-        &qdrant_collection_name,
-        embeddings,
-        chunks.as_slice(),
-    )
-    .await?;
+    let points: Vec<Point> = embeddings
+        .iter()
+        .map(|embedding| Point {
+            id: embedding.index,
+            vector: embedding.embedding.iter().map(|x| *x as f32).collect(),
+            payload: serde_json::json!({"source": chunks[embedding.index as usize]})
+                .as_object()
+                .map(|m| m.to_owned()),
+        })
+        .collect();
+    store.upsert_points(&vdb_collection_name, points).await?;
 
     Ok(embeddings_response)
 }
@@ -135,15 +157,15 @@ pub async fn rag_query_to_embeddings(
     embeddings(embedding_request).await
 }
 
-/// Retrieve similar points from the Qdrant server using the query embedding
+/// Retrieve similar points from the vector store using the query embedding
 ///
 /// # Arguments
 ///
 /// * `query_embedding` - A reference to a query embedding.
 ///
-/// * `qdrant_url` - URL of the Qdrant server.
+/// * `vdb_server_url` - URL of the VectorDB server, or `local://[name]` for the embedded store.
 ///
-/// * `qdrant_collection_name` - Name of the Qdrant collection to be created.
+/// * `vdb_collection_name` - Name of the collection to search.
 ///
 /// * `limit` - Number of retrieved results.
 ///
@@ -160,7 +182,7 @@ pub async fn rag_retrieve_context(
     {
         info!(target: "stdout", "Retrieve context.");
 
-        info!(target: "stdout", "qdrant_url: {}, qdrant_collection_name: {}, limit: {}, score_threshold: {}", vdb_server_url.as_ref(), vdb_collection_name.as_ref(), limit, score_threshold.unwrap_or_default());
+        info!(target: "stdout", "vdb_server_url: {}, vdb_collection_name: {}, limit: {}, score_threshold: {}", vdb_server_url.as_ref(), vdb_collection_name.as_ref(), limit, score_threshold.unwrap_or_default());
     }
 
     let running_mode = running_mode()?;
@@ -176,28 +198,33 @@ pub async fn rag_retrieve_context(
         return Err(LlamaCoreError::Operation(err_msg));
     }
 
-    // create a Qdrant client
-    let mut qdrant_client = qdrant::Qdrant::new_with_url(vdb_server_url.as_ref().to_string());
-
-    // set the API key if provided
-    if let Some(key) = vdb_api_key.as_deref() {
-        if !key.is_empty() {
-            #[cfg(feature = "logging")]
-            debug!(target: "stdout", "Set the API key for the VectorDB server.");
-
-            qdrant_client.set_api_key(key);
-        }
-    }
-
-    // search for similar points
-    let scored_points = match qdrant_search_similar_points(
-        &qdrant_client,
-        vdb_collection_name.as_ref(),
+    retrieve_context(
         query_embedding,
+        vdb_server_url.as_ref(),
+        vdb_collection_name.as_ref(),
         limit,
         score_threshold,
+        vdb_api_key.as_deref(),
     )
     .await
+}
+
+/// Search the collection for the points similar to the query embedding, and remove the duplicates which have the same source.
+async fn retrieve_context(
+    query_embedding: &[f32],
+    vdb_server_url: &str,
+    vdb_collection_name: &str,
+    limit: usize,
+    score_threshold: Option<f32>,
+    vdb_api_key: Option<&str>,
+) -> Result<RetrieveObject, LlamaCoreError> {
+    // open the vector store
+    let store = vector_store(vdb_server_url, vdb_api_key)?;
+
+    // search for similar points
+    let scored_points = match store
+        .search_points(vdb_collection_name, query_embedding, limit, score_threshold)
+        .await
     {
         Ok(points) => points,
         Err(e) => {
@@ -267,108 +294,430 @@ pub async fn rag_retrieve_context(
     Ok(ro)
 }
 
-async fn qdrant_create_collection(
-    qdrant_client: &qdrant::Qdrant,
-    collection_name: impl AsRef<str>,
-    dim: usize,
-) -> Result<(), LlamaCoreError> {
+/// Retrieve the context of the chat request from the vector store, and merge it into the messages of the request.
+///
+/// The retrieval is performed only if the request sets `vdb_server_url` and `vdb_collection_name`:
+///
+/// 1. The text of the last `context_window` user messages, one by default, is embedded by the embedding model.
+///
+/// 2. Each collection of `vdb_collection_name` is searched with the `limit` and `score_threshold` at the same position. `limit` defaults to [DEFAULT_RAG_LIMIT], and no score threshold is applied by default.
+///
+/// 3. If the request also sets `kw_search_url` and `kw_index_name`, the index is searched by the keyword search server for the top `kw_top_k` hits of the same query, which are fused with the points by [rag_fuse_context].
+///
+/// 4. The points of all the collections are deduplicated by source, sorted by score, and merged into the messages according to `policy`.
+///
+/// # Arguments
+///
+/// * `chat_request` - The chat request, whose messages are updated with the retrieved context.
+///
+/// * `policy` - The policy for merging the context into the messages.
+///
+/// * `rag_prompt` - The instruction of the context, in which `{context}` is replaced by the numbered pieces of context. Defaults to [chat_prompts::DEFAULT_RAG_PROMPT].
+///
+/// # Returns
+///
+/// The points retrieved from each collection in the order of `vdb_collection_name`, followed by the hits of the keyword search if it is performed, or `None` if the request does not ask for the retrieval. [LlamaCoreError::BadRequest] is returned if the request cannot be served, e.g., the numbers of `limit` or `score_threshold` values do not match the number of collections, or there is no user message.
+pub async fn rag_augment_chat_request(
+    chat_request: &mut ChatCompletionRequest,
+    policy: MergeRagContextPolicy,
+    rag_prompt: Option<&str>,
+) -> Result<Option<Vec<RetrieveObject>>, LlamaCoreError> {
+    let (vdb_server_url, collection_names) = match (
+        chat_request.vdb_server_url.as_deref(),
+        chat_request.vdb_collection_name.as_deref(),
+    ) {
+        (Some(url), Some(names)) if !url.is_empty() && !names.is_empty() => {
+            (url.to_string(), names.to_vec())
+        }
+        _ => return Ok(None),
+    };
+
     #[cfg(feature = "logging")]
-    info!(target: "stdout", "Create a Qdrant collection named {} of {} dimensions.", collection_name.as_ref(), dim);
+    info!(target: "stdout", "Retrieve the context for the chat request from {} collections.", collection_names.len());
 
-    if let Err(e) = qdrant_client
-        .create_collection(collection_name.as_ref(), dim as u32)
-        .await
-    {
-        let err_msg = e.to_string();
+    let running_mode = running_mode()?;
+    if running_mode != RunningMode::Rag && running_mode != RunningMode::ChatEmbedding {
+        let err_msg = format!(
+            "The RAG chat completion requires both chat and embedding models, which is not supported in the {} mode.",
+            running_mode
+        );
 
         #[cfg(feature = "logging")]
         error!(target: "stdout", "{}", &err_msg);
 
-        return Err(LlamaCoreError::Qdrant(err_msg));
+        return Err(LlamaCoreError::BadRequest(err_msg));
     }
 
-    Ok(())
-}
+    // the limit and the score threshold of each collection
+    let limits = match chat_request.limit.as_ref() {
+        Some(limits) if limits.len() != collection_names.len() => {
+            let err_msg = format!(
+                "The number of `limit` values ({}) does not match the number of collections ({}).",
+                limits.len(),
+                collection_names.len()
+            );
 
-async fn qdrant_persist_embeddings(
-    qdrant_client: &qdrant::Qdrant,
-    collection_name: impl AsRef<str>,
-    embeddings: &[EmbeddingObject],
-    chunks: &[String],
-) -> Result<(), LlamaCoreError> {
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Persist embeddings to the Qdrant instance.");
-
-    let mut points = Vec::<Point>::new();
-    for embedding in embeddings {
-        // convert the embedding to a vector
-        let vector: Vec<_> = embedding.embedding.iter().map(|x| *x as f32).collect();
-
-        // create a payload
-        let payload = serde_json::json!({"source": chunks[embedding.index as usize]})
-            .as_object()
-            .map(|m| m.to_owned());
-
-        // create a point
-        let p = Point {
-            id: PointId::Num(embedding.index),
-            vector,
-            payload,
-        };
-
-        points.push(p);
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::BadRequest(err_msg));
+        }
+        Some(limits) => limits.clone(),
+        None => vec![DEFAULT_RAG_LIMIT; collection_names.len()],
+    };
+    let score_thresholds: Vec<Option<f32>> = match chat_request.score_threshold.as_ref() {
+        Some(score_thresholds) if score_thresholds.len() != collection_names.len() => {
+            let err_msg = format!(
+                "The number of `score_threshold` values ({}) does not match the number of collections ({}).",
+                score_thresholds.len(),
+                collection_names.len()
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::BadRequest(err_msg));
+        }
+        Some(score_thresholds) => score_thresholds.iter().map(|t| Some(*t)).collect(),
+        None => vec![None; collection_names.len()],
+    };
+
+    // compute the embedding of the query
+    let context_window = chat_request.context_window.unwrap_or(1) as usize;
+    let query = rag_query_text(&chat_request.messages, context_window);
+    if query.is_empty() {
+        let err_msg = "No user message to retrieve the context for.";
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", err_msg);
+
+        return Err(LlamaCoreError::BadRequest(err_msg.into()));
     }
 
     #[cfg(feature = "logging")]
-    info!(target: "stdout", "Number of points to be upserted: {}", points.len());
+    info!(target: "stdout", "Compute embeddings for the query of the last {} user messages.", context_window);
+
+    let embedding_request = EmbeddingRequest {
+        model: None,
+        input: InputText::String(query.clone()),
+        encoding_format: None,
+        user: chat_request.user.clone(),
+        vdb_server_url: None,
+        vdb_collection_name: None,
+        vdb_api_key: None,
+    };
+    let embeddings_response = embeddings(&embedding_request).await?;
+    let query_embedding: Vec<f32> = match embeddings_response.data.first() {
+        Some(embedding) => embedding.embedding.iter().map(|x| *x as f32).collect(),
+        None => {
+            let err_msg = "No embedding is computed for the query.";
 
-    if let Err(e) = qdrant_client
-        .upsert_points(collection_name.as_ref(), points)
-        .await
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg.into()));
+        }
+    };
+
+    // retrieve the points from each collection
+    let mut retrieved = Vec::with_capacity(collection_names.len());
+    for ((collection_name, limit), score_threshold) in collection_names
+        .iter()
+        .zip(limits.iter())
+        .zip(score_thresholds.into_iter())
     {
-        let err_msg = format!("{}", e);
+        let ro = retrieve_context(
+            &query_embedding,
+            &vdb_server_url,
+            collection_name,
+            *limit as usize,
+            score_threshold,
+            chat_request.vdb_api_key.as_deref(),
+        )
+        .await?;
+        retrieved.push(ro);
+    }
+
+    // merge the points of all the collections
+    let mut points: Vec<RagScoredPoint> = retrieved
+        .iter()
+        .filter_map(|ro| ro.points.as_ref())
+        .flatten()
+        .cloned()
+        .collect();
+    points.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    // search the index by keywords, and fuse the hits with the points
+    #[cfg(feature = "index")]
+    if let Some((top_k, hits)) = rag_keyword_search(chat_request, &query).await? {
+        points = rag_fuse_context(&points, &hits);
+
+        let hits: Vec<RagScoredPoint> = hits
+            .into_iter()
+            .map(|hit| RagScoredPoint {
+                source: hit.content,
+                score: hit.score,
+            })
+            .collect();
+        retrieved.push(RetrieveObject {
+            points: match hits.is_empty() {
+                true => None,
+                false => Some(hits),
+            },
+            limit: top_k,
+            score_threshold: 0.0,
+        });
+    }
+
+    let mut seen = HashSet::new();
+    let context: Vec<String> = points
+        .iter()
+        .filter(|point| seen.insert(point.source.as_str()))
+        .map(|point| point.source.clone())
+        .collect();
+
+    if context.is_empty() {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "No context is retrieved for the chat request.");
+
+        return Ok(Some(retrieved));
+    }
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Merge {} pieces of context into the chat messages. policy: {}", context.len(), policy);
+
+    let prompt_template = chat_prompt_template(chat_request.model.as_deref())?;
+    if let Err(e) = RagPromptBuilder::build_with_prompt(
+        &mut chat_request.messages,
+        &context,
+        prompt_template.has_system_prompt(),
+        policy,
+        rag_prompt.unwrap_or(DEFAULT_RAG_PROMPT),
+    ) {
+        let err_msg = format!(
+            "Fail to merge the RAG context into the chat messages. {}",
+            e
+        );
 
         #[cfg(feature = "logging")]
         error!(target: "stdout", "{}", &err_msg);
 
-        return Err(LlamaCoreError::Qdrant(err_msg));
+        return Err(LlamaCoreError::Operation(err_msg));
     }
 
-    Ok(())
+    Ok(Some(retrieved))
 }
 
-async fn qdrant_search_similar_points(
-    qdrant_client: &qdrant::Qdrant,
-    collection_name: impl AsRef<str>,
-    query_vector: &[f32],
-    limit: usize,
-    score_threshold: Option<f32>,
-) -> Result<Vec<ScoredPoint>, LlamaCoreError> {
-    #[cfg(feature = "logging")]
-    info!(target: "stdout", "Search similar points from the qdrant instance.");
+/// Search the index of the keyword search server for the query, if the chat request sets `kw_search_url` and `kw_index_name`.
+///
+/// Returns the number of the hits asked for, which is `kw_top_k` or [DEFAULT_KW_TOP_K], and the hits.
+#[cfg(feature = "index")]
+async fn rag_keyword_search(
+    chat_request: &ChatCompletionRequest,
+    query: &str,
+) -> Result<Option<(usize, Vec<SearchHit>)>, LlamaCoreError> {
+    let (kw_search_url, kw_index_name) = match (
+        chat_request.kw_search_url.as_deref(),
+        chat_request.kw_index_name.as_deref(),
+    ) {
+        (Some(url), Some(name)) if !url.is_empty() && !name.is_empty() => (url, name),
+        _ => return Ok(None),
+    };
 
-    match qdrant_client
-        .search_points(
-            collection_name.as_ref(),
-            query_vector.to_vec(),
-            limit as u64,
-            score_threshold,
-        )
-        .await
-    {
-        Ok(search_result) => {
-            #[cfg(feature = "logging")]
-            info!(target: "stdout", "Number of similar points found: {}", search_result.len());
+    let top_k = chat_request.kw_top_k.unwrap_or(DEFAULT_KW_TOP_K) as usize;
+    let query_request = QueryRequest {
+        query: query.to_string(),
+        top_k,
+        index: kw_index_name.to_string(),
+    };
+    let mut query_response = search_keywords(kw_search_url, &query_request).await?;
+    query_response.hits.truncate(top_k);
 
-            Ok(search_result)
-        }
-        Err(e) => {
-            let err_msg = e.to_string();
+    Ok(Some((top_k, query_response.hits)))
+}
 
-            #[cfg(feature = "logging")]
-            error!(target: "stdout", "{}", &err_msg);
+/// Fuse the points retrieved from the vector store and the hits of the keyword search into one ranked list by reciprocal rank fusion.
+///
+/// Each list is ranked by its own scores, and the piece of context at rank `r`, starting from 1, of a list scores `1 / (RRF_K + r)`. The pieces of context with the same text, ignoring the leading and trailing whitespaces, are merged by summing their scores, so that the ones found by both searches rank higher.
+///
+/// # Arguments
+///
+/// * `points` - The points retrieved from the vector store.
+///
+/// * `hits` - The hits of the keyword search.
+///
+/// # Returns
+///
+/// The deduplicated pieces of context in descending order of the fused score, which is the `score` of each point.
+#[cfg(feature = "index")]
+pub fn rag_fuse_context(points: &[RagScoredPoint], hits: &[SearchHit]) -> Vec<RagScoredPoint> {
+    let mut points: Vec<&RagScoredPoint> = points.iter().collect();
+    points.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let mut hits: Vec<&SearchHit> = hits.iter().collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let rankings: [Vec<&str>; 2] = [
+        points.iter().map(|point| point.source.as_str()).collect(),
+        hits.iter().map(|hit| hit.content.as_str()).collect(),
+    ];
+
+    // key: the trimmed text of the context, value: the position of the context in `fused`
+    let mut positions: HashMap<&str, usize> = HashMap::new();
+    let mut fused: Vec<RagScoredPoint> = vec![];
+    for ranking in rankings.iter() {
+        // the duplicates in a list only count at their best rank
+        let mut seen = HashSet::new();
+        for source in ranking.iter() {
+            let key = source.trim();
+            if key.is_empty() || !seen.insert(key) {
+                continue;
+            }
 
-            Err(LlamaCoreError::Qdrant(err_msg))
+            let score = 1.0 / (RRF_K + seen.len() as f32);
+            match positions.get(key) {
+                Some(&i) => fused[i].score += score,
+                None => {
+                    positions.insert(key, fused.len());
+                    fused.push(RagScoredPoint {
+                        source: source.to_string(),
+                        score,
+                    });
+                }
+            }
         }
     }
+
+    // the sort is stable, so the ties keep the order of the vector search
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    fused
+}
+
+/// Join the text of the last `context_window` user messages in their original order, which is the query of the retrieval.
+fn rag_query_text(messages: &[ChatCompletionRequestMessage], context_window: usize) -> String {
+    let mut texts: Vec<String> = messages
+        .iter()
+        .rev()
+        .filter_map(|message| match message {
+            ChatCompletionRequestMessage::User(message) => Some(message),
+            _ => None,
+        })
+        .take(context_window.max(1))
+        .map(|message| match message.content() {
+            ChatCompletionUserMessageContent::Text(text) => text.trim().to_string(),
+            ChatCompletionUserMessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text(text_part) => Some(text_part.text().trim()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        })
+        .filter(|text| !text.is_empty())
+        .collect();
+    texts.reverse();
+
+    texts.join("\n")
+}
+
+#[cfg(feature = "index")]
+#[test]
+fn test_rag_fuse_context_scores_by_rank() {
+    let point = |source: &str, score| RagScoredPoint {
+        source: source.to_string(),
+        score,
+    };
+    let hit = |content: &str, score| SearchHit {
+        title: String::new(),
+        content: content.to_string(),
+        score,
+    };
+
+    // the lists are ranked by their own scores, whose scales do not matter
+    let fused = rag_fuse_context(
+        &[point("b", 0.2), point("a", 0.9)],
+        &[hit("d", 3.0), hit("c", 42.0)],
+    );
+    let sources: Vec<&str> = fused.iter().map(|p| p.source.as_str()).collect();
+    assert_eq!(sources, ["a", "c", "b", "d"]);
+
+    let scores: Vec<f32> = fused.iter().map(|p| p.score).collect();
+    let rank_1 = 1.0 / (RRF_K + 1.0);
+    let rank_2 = 1.0 / (RRF_K + 2.0);
+    assert_eq!(scores, [rank_1, rank_1, rank_2, rank_2]);
+}
+
+#[cfg(feature = "index")]
+#[test]
+fn test_rag_fuse_context_sums_scores_found_by_both() {
+    let point = |source: &str, score| RagScoredPoint {
+        source: source.to_string(),
+        score,
+    };
+    let hit = |content: &str, score| SearchHit {
+        title: String::new(),
+        content: content.to_string(),
+        score,
+    };
+
+    let fused = rag_fuse_context(
+        &[point("x", 0.9), point("y", 0.8)],
+        &[hit("y", 5.0), hit("z", 4.0)],
+    );
+    let sources: Vec<&str> = fused.iter().map(|p| p.source.as_str()).collect();
+    assert_eq!(sources, ["y", "x", "z"]);
+    assert_eq!(fused[0].score, 1.0 / (RRF_K + 2.0) + 1.0 / (RRF_K + 1.0));
+}
+
+#[cfg(feature = "index")]
+#[test]
+fn test_rag_fuse_context_dedups_trimmed_text() {
+    let point = |source: &str, score| RagScoredPoint {
+        source: source.to_string(),
+        score,
+    };
+    let hit = |content: &str, score| SearchHit {
+        title: String::new(),
+        content: content.to_string(),
+        score,
+    };
+
+    let fused = rag_fuse_context(
+        &[
+            point("  same text\n", 0.9),
+            point("same text", 0.8),
+            point(" ", 0.7),
+            point("other", 0.6),
+        ],
+        &[hit("same text ", 1.0)],
+    );
+
+    // the first text found is kept, and the duplicates in a list only count at their best rank
+    let sources: Vec<&str> = fused.iter().map(|p| p.source.as_str()).collect();
+    assert_eq!(sources, ["  same text\n", "other"]);
+    assert_eq!(fused[0].score, 1.0 / (RRF_K + 1.0) + 1.0 / (RRF_K + 1.0));
+    assert_eq!(fused[1].score, 1.0 / (RRF_K + 2.0));
+}
+
+#[cfg(feature = "index")]
+#[test]
+fn test_rag_fuse_context_keeps_order_of_ties() {
+    let point = |source: &str, score| RagScoredPoint {
+        source: source.to_string(),
+        score,
+    };
+    let hit = |content: &str, score| SearchHit {
+        title: String::new(),
+        content: content.to_string(),
+        score,
+    };
+
+    // the ties in a list keep their order, and the ties across the lists put the vector search first
+    let fused = rag_fuse_context(
+        &[point("p1", 0.5), point("p2", 0.5), point("p3", 0.1)],
+        &[hit("h1", 2.0), hit("h2", 2.0)],
+    );
+    let sources: Vec<&str> = fused.iter().map(|p| p.source.as_str()).collect();
+    assert_eq!(sources, ["p1", "h1", "p2", "h2", "p3"]);
 }
@@ -0,0 +1,355 @@
+//! Define the scheduler that queues the concurrent requests on the same chat model.
+//!
+//! A chat model serves as many requests at a time as its execution slots. The other requests wait in a bounded FIFO queue of the model until a running request releases its [Permit]. The queue-wait timeout is left to the caller, for example, by wrapping the [Acquire] future with a timer; dropping the future removes the request from the queue.
+
+use crate::{error::LlamaCoreError, utils::resolve_chat_model_name, CHAT_GRAPHS};
+use once_cell::sync::OnceCell;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll, Waker},
+};
+
+/// The default number of requests that can wait in the queue of a model.
+pub const DEFAULT_QUEUE_SIZE: usize = 16;
+
+static SCHEDULER: OnceCell<Mutex<Scheduler>> = OnceCell::new();
+
+#[derive(Debug)]
+struct Scheduler {
+    /// The queue size used by the models without a specific one.
+    default_queue_size: usize,
+    /// The queue sizes of the specific models.
+    queue_sizes: HashMap<String, usize>,
+    /// key: model name, value: queue of the model
+    queues: HashMap<String, ModelQueue>,
+    next_ticket: u64,
+}
+impl Scheduler {
+    fn queue_mut(&mut self, model_name: &str) -> &mut ModelQueue {
+        self.queues.entry(model_name.to_string()).or_default()
+    }
+
+    fn queue_size(&self, model_name: &str) -> usize {
+        self.queue_sizes
+            .get(model_name)
+            .copied()
+            .unwrap_or(self.default_queue_size)
+    }
+}
+
+#[derive(Debug)]
+struct ModelQueue {
+    /// The number of the execution slots of the model.
+    slots: usize,
+    /// The number of the requests running on the model.
+    running: usize,
+    /// The waiting requests in arrival order.
+    waiting: VecDeque<Waiter>,
+}
+impl Default for ModelQueue {
+    fn default() -> Self {
+        Self {
+            slots: 1,
+            running: 0,
+            waiting: VecDeque::new(),
+        }
+    }
+}
+impl ModelQueue {
+    /// Whether all the execution slots of the model are busy.
+    fn is_busy(&self) -> bool {
+        self.running >= self.slots
+    }
+
+    /// Wake up the first waiting request if a slot of the model is free.
+    fn wake_next(&mut self) {
+        if !self.is_busy() {
+            if let Some(waker) = self.waiting.front_mut().and_then(|w| w.waker.take()) {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Waiter {
+    ticket: u64,
+    waker: Option<Waker>,
+}
+
+fn scheduler() -> &'static Mutex<Scheduler> {
+    SCHEDULER.get_or_init(|| {
+        Mutex::new(Scheduler {
+            default_queue_size: DEFAULT_QUEUE_SIZE,
+            queue_sizes: HashMap::new(),
+            queues: HashMap::new(),
+            next_ticket: 0,
+        })
+    })
+}
+
+fn lock_scheduler() -> Result<std::sync::MutexGuard<'static, Scheduler>, LlamaCoreError> {
+    scheduler().lock().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of the scheduler. {}", e);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })
+}
+
+/// Set the number of requests that can wait in the queue of each model. Defaults to [DEFAULT_QUEUE_SIZE].
+pub fn set_default_queue_size(queue_size: usize) -> Result<(), LlamaCoreError> {
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Set the default queue size of the scheduler to {}", queue_size);
+
+    lock_scheduler()?.default_queue_size = queue_size;
+
+    Ok(())
+}
+
+/// Set the number of requests that can wait in the queue of the given model, which overrides the default queue size.
+pub fn set_queue_size(
+    model_name: impl Into<String>,
+    queue_size: usize,
+) -> Result<(), LlamaCoreError> {
+    let model_name = model_name.into();
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Set the queue size of the model {} to {}", &model_name, queue_size);
+
+    lock_scheduler()?.queue_sizes.insert(model_name, queue_size);
+
+    Ok(())
+}
+
+/// Return the number of requests waiting in the queue of the given model.
+pub fn queue_depth(model_name: &str) -> Result<usize, LlamaCoreError> {
+    Ok(lock_scheduler()?
+        .queues
+        .get(model_name)
+        .map(|queue| queue.waiting.len())
+        .unwrap_or_default())
+}
+
+/// Whether no request is running on or waiting for the given model.
+pub(crate) fn is_idle(model_name: &str) -> bool {
+    match scheduler().lock() {
+        Ok(scheduler) => scheduler
+            .queues
+            .get(model_name)
+            .map(|queue| queue.running == 0 && queue.waiting.is_empty())
+            .unwrap_or(true),
+        Err(_) => false,
+    }
+}
+
+/// Join the queue of the given chat model. The first chat model is used if the model is not specified or not found.
+///
+/// Returns [LlamaCoreError::QueueFull] immediately if the queue of the model is full; otherwise, returns an [Acquire] future that resolves to a [Permit] once the requests ahead have been served and a slot of the model is free.
+pub fn acquire(model_name: Option<&str>) -> Result<Acquire, LlamaCoreError> {
+    // the model registered to be loaded on demand is loaded before joining its queue
+    crate::models::load_on_demand(model_name, false)?;
+
+    let (model_name, slots) = resolve_chat_model(model_name)?;
+
+    join_queue(model_name, slots)
+}
+
+/// Join the queue of the model with the given name and number of execution slots.
+pub(crate) fn join_queue(model_name: String, slots: usize) -> Result<Acquire, LlamaCoreError> {
+    let mut scheduler = lock_scheduler()?;
+    let queue_size = scheduler.queue_size(&model_name);
+    let ticket = scheduler.next_ticket;
+    scheduler.next_ticket += 1;
+
+    let queue = scheduler.queue_mut(&model_name);
+    queue.slots = slots;
+    if queue.is_busy() && queue.waiting.len() >= queue_size {
+        let err_msg = format!(
+            "The queue of the model {} is full. {} requests are waiting.",
+            &model_name,
+            queue.waiting.len()
+        );
+
+        #[cfg(feature = "logging")]
+        warn!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::QueueFull(err_msg));
+    }
+
+    let position = match queue.is_busy() || !queue.waiting.is_empty() {
+        true => queue.waiting.len() + 1,
+        false => 0,
+    };
+    queue.waiting.push_back(Waiter {
+        ticket,
+        waker: None,
+    });
+
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Queue the request on the model {}. position: {}", &model_name, position);
+
+    Ok(Acquire {
+        model_name,
+        ticket,
+        position,
+        acquired: false,
+    })
+}
+
+/// Resolve the name of the chat model in the same way as the chat and completion requests do. Returns the name and the number of the execution slots of the model.
+fn resolve_chat_model(model_name: Option<&str>) -> Result<(String, usize), LlamaCoreError> {
+    let chat_graphs = match CHAT_GRAPHS.get() {
+        Some(chat_graphs) => chat_graphs,
+        None => {
+            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg.into()));
+        }
+    };
+
+    let chat_graphs = chat_graphs.lock().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    let model_name = resolve_chat_model_name(&chat_graphs, model_name)?;
+    let slots = chat_graphs[&model_name].slots();
+
+    Ok((model_name, slots))
+}
+
+/// Future that resolves to a [Permit] when the request reaches the head of the queue and a slot of the model is free. Dropping it before completion removes the request from the queue.
+#[derive(Debug)]
+pub struct Acquire {
+    model_name: String,
+    ticket: u64,
+    position: usize,
+    acquired: bool,
+}
+impl Acquire {
+    /// The name of the model the request is queued on.
+    pub fn model_name(&self) -> &str {
+        &self.model_name
+    }
+
+    /// The position of the request in the queue when it joined the queue. `0` means a slot of the model is free and the request is served immediately; `1` means the request is the next to be served.
+    pub fn queue_position(&self) -> usize {
+        self.position
+    }
+}
+impl Future for Acquire {
+    type Output = Result<Permit, LlamaCoreError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        let mut scheduler = match lock_scheduler() {
+            Ok(scheduler) => scheduler,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
+        let queue = scheduler.queue_mut(&this.model_name);
+
+        let is_first = queue
+            .waiting
+            .front()
+            .map(|w| w.ticket == this.ticket)
+            .unwrap_or_default();
+        if !queue.is_busy() && is_first {
+            queue.waiting.pop_front();
+            queue.running += 1;
+            this.acquired = true;
+
+            // the next request may be served by another free slot
+            queue.wake_next();
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "The request acquires the model {}", &this.model_name);
+
+            return Poll::Ready(Ok(Permit {
+                model_name: this.model_name.clone(),
+            }));
+        }
+
+        if let Some(waiter) = queue.waiting.iter_mut().find(|w| w.ticket == this.ticket) {
+            waiter.waker = Some(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if self.acquired {
+            return;
+        }
+
+        // the request leaves the queue, e.g. the queue-wait timeout is reached or the client is disconnected
+        if let Ok(mut scheduler) = scheduler().lock() {
+            let queue = scheduler.queue_mut(&self.model_name);
+            queue.waiting.retain(|w| w.ticket != self.ticket);
+            queue.wake_next();
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "The request leaves the queue of the model {}", &self.model_name);
+        }
+    }
+}
+
+/// Grants the use of an execution slot of a chat model. The next request in the queue is woken up when the permit is dropped.
+#[derive(Debug)]
+pub struct Permit {
+    model_name: String,
+}
+impl Permit {
+    /// The name of the model the permit is granted on.
+    pub fn model_name(&self) -> &str {
+        &self.model_name
+    }
+}
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Ok(mut scheduler) = scheduler().lock() {
+            let queue = scheduler.queue_mut(&self.model_name);
+            queue.running = queue.running.saturating_sub(1);
+            queue.wake_next();
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "The request releases the model {}", &self.model_name);
+        }
+    }
+}
+
+/// Stream that holds a [Permit] until it is dropped, so that the model is released only after the whole stream is consumed or the client is disconnected.
+pub struct PermitStream<S> {
+    inner: S,
+    _permit: Permit,
+}
+impl<S> PermitStream<S> {
+    /// Attach the permit to the stream.
+    pub fn new(inner: S, permit: Permit) -> Self {
+        Self {
+            inner,
+            _permit: permit,
+        }
+    }
+}
+impl<S: futures::Stream + Unpin> futures::Stream for PermitStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.get_mut().inner).poll_next(cx)
+    }
+}
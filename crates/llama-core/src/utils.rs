@@ -2,15 +2,55 @@
 
 use crate::{
     error::{BackendError, LlamaCoreError},
-    BaseMetadata, Graph, CHAT_GRAPHS, EMBEDDING_GRAPHS, MAX_BUFFER_SIZE,
+    get_plugin_info_by_graph,
+    metadata::ggml::{GgmlMetadata, LogitBias},
+    BaseMetadata, Graph, SlotLease, CHAT_GRAPHS, EMBEDDING_GRAPHS, LOGPROBS_TENSOR,
+    MAX_BUFFER_SIZE,
 };
 use chat_prompts::PromptTemplateType;
+use endpoints::{
+    chat::{TokenLogProb, TopLogProb},
+    common::LlamaCppLogitBiasType,
+};
+use serde::Deserialize;
 use serde_json::Value;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
 
 pub(crate) fn gen_chat_id() -> String {
     format!("chatcmpl-{}", uuid::Uuid::new_v4())
 }
 
+/// Generate a unique id for a tool call, e.g. `call_4c1f0b9d2e7a4d5c9b8e3f2a1d0c6b7e`.
+pub(crate) fn gen_tool_call_id() -> String {
+    format!("call_{}", uuid::Uuid::new_v4().simple())
+}
+
+/// Generate the fingerprint of the backend configuration that the model runs with, including the version of the plugin, the model and the context parameters. The requests with the same `seed` and parameters return the same result only if the fingerprints are the same.
+pub(crate) fn gen_system_fingerprint(
+    graph: &Graph<GgmlMetadata>,
+) -> Result<String, LlamaCoreError> {
+    let plugin_info = get_plugin_info_by_graph(graph)?;
+
+    let mut hasher = DefaultHasher::new();
+    plugin_info.build_number.hash(&mut hasher);
+    plugin_info.commit_id.hash(&mut hasher);
+    graph.name().hash(&mut hasher);
+    graph.metadata.prompt_template.to_string().hash(&mut hasher);
+    graph.metadata.n_gpu_layers.hash(&mut hasher);
+    graph.metadata.main_gpu.hash(&mut hasher);
+    graph.metadata.tensor_split.hash(&mut hasher);
+    graph.metadata.split_mode.hash(&mut hasher);
+    graph.metadata.ctx_size.hash(&mut hasher);
+    graph.metadata.batch_size.hash(&mut hasher);
+    graph.metadata.ubatch_size.hash(&mut hasher);
+    graph.metadata.threads.hash(&mut hasher);
+
+    Ok(format!("fp_{:010x}", hasher.finish() & 0xFF_FFFF_FFFF))
+}
+
 /// Return the names of the chat models.
 pub fn chat_model_names() -> Result<Vec<String>, LlamaCoreError> {
     #[cfg(feature = "logging")]
@@ -293,10 +333,90 @@ where
     })
 }
 
-/// Get the token information from the graph by the model name.
-pub(crate) fn get_token_info_by_graph_name(
-    name: Option<&String>,
-) -> Result<TokenInfo, LlamaCoreError> {
+/// Resolve the name of the chat model serving a request. The first chat model is used if the model is not specified or not found.
+///
+/// Both the scheduler and [lease_chat_slot] resolve the model by this function, so that a request runs on the model whose queue it waits in.
+pub(crate) fn resolve_chat_model_name(
+    chat_graphs: &HashMap<String, Graph<GgmlMetadata>>,
+    model_name: Option<&str>,
+) -> Result<String, LlamaCoreError> {
+    match model_name {
+        Some(model_name) if chat_graphs.contains_key(model_name) => Ok(model_name.to_string()),
+        _ => match chat_graphs.keys().next() {
+            Some(model_name) => Ok(model_name.clone()),
+            None => {
+                let err_msg = "There is no model available in the chat graphs.";
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", err_msg);
+
+                Err(LlamaCoreError::Operation(err_msg.into()))
+            }
+        },
+    }
+}
+
+/// Lease a free execution slot of the given chat model. The first chat model is used if the model is not specified or not found.
+pub(crate) fn lease_chat_slot(model_name: Option<&str>) -> Result<SlotLease, LlamaCoreError> {
+    crate::models::load_on_demand(model_name, false)?;
+
+    let chat_graphs = match CHAT_GRAPHS.get() {
+        Some(chat_graphs) => chat_graphs,
+        None => {
+            let err_msg = "Fail to get the underlying value of `CHAT_GRAPHS`.";
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg.into()));
+        }
+    };
+
+    let mut chat_graphs = chat_graphs.lock().map_err(|e| {
+        let err_msg = format!("Fail to acquire the lock of `CHAT_GRAPHS`. {}", e);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::Operation(err_msg)
+    })?;
+
+    let model_name = resolve_chat_model_name(&chat_graphs, model_name)?;
+    let graph = chat_graphs.get_mut(&model_name).unwrap();
+
+    match graph.lease_slot() {
+        Some(slot) => {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Lease the slot {} of the model named {}", slot.index(), graph.name());
+
+            // the slot may keep the metadata of a failed request, so start with the original metadata of the model
+            graph.use_slot(&slot)?;
+            graph.update_metadata()?;
+
+            Ok(slot)
+        }
+        None => {
+            let err_msg = format!(
+                "All the {} slots of the model named {} are busy.",
+                graph.slots(),
+                graph.name()
+            );
+
+            #[cfg(feature = "logging")]
+            warn!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::QueueFull(err_msg))
+        }
+    }
+}
+
+/// Run the given function on the execution slot of the given lease.
+///
+/// The lock of `CHAT_GRAPHS` is only held while the slot is looked up, so the inference on a slot does not block the requests on the other slots and models.
+pub(crate) fn with_chat_slot<T>(
+    slot: &SlotLease,
+    f: impl FnOnce(&mut Graph<GgmlMetadata>) -> Result<T, LlamaCoreError>,
+) -> Result<T, LlamaCoreError> {
     let chat_graphs = match CHAT_GRAPHS.get() {
         Some(chat_graphs) => chat_graphs,
         None => {
@@ -318,38 +438,192 @@ pub(crate) fn get_token_info_by_graph_name(
         LlamaCoreError::Operation(err_msg)
     })?;
 
-    match name {
-        Some(model_name) => match chat_graphs.contains_key(model_name) {
-            true => {
-                let graph = chat_graphs.get(model_name).unwrap();
-                get_token_info_by_graph(graph)
-            }
-            false => match chat_graphs.iter().next() {
-                Some((_, graph)) => get_token_info_by_graph(graph),
-                None => {
-                    let err_msg = "There is no model available in the chat graphs.";
+    match chat_graphs.get(slot.model_name()) {
+        Some(graph) => {
+            let mut graph = graph.detach_slot(slot)?;
+            drop(chat_graphs);
+
+            f(&mut graph)
+        }
+        None => {
+            let err_msg = format!(
+                "There is no model named {} in the chat graphs.",
+                slot.model_name()
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(LlamaCoreError::Operation(err_msg))
+        }
+    }
+}
+
+/// Convert the `logit_bias` of a request to the logit biases pushed to the backend.
+///
+/// A key consisting of digits is taken as a token id in the tokenizer of the model; otherwise, it is taken as a string. The wasi-nn interface does not expose the tokenizer of the model, so the strings are pushed to the backend, which tokenizes them against the loaded model and applies the bias to each of their tokens.
+pub(crate) fn parse_logit_bias(
+    logit_bias: &HashMap<String, f64>,
+) -> Result<LogitBias, LlamaCoreError> {
+    let mut parsed = LogitBias::default();
+
+    for (key, &bias) in logit_bias {
+        if !(-100.0..=100.0).contains(&bias) {
+            let err_msg = format!(
+                "Invalid logit bias for '{}': {}. The bias value must be between -100 and 100.",
+                key, bias
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::BadRequest(err_msg));
+        }
+
+        if key.is_empty() {
+            let err_msg = "Invalid logit bias. The token must not be empty.";
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", err_msg);
+
+            return Err(LlamaCoreError::BadRequest(err_msg.into()));
+        }
+
+        match logit_bias_type(key) {
+            LlamaCppLogitBiasType::input_ids => {
+                let token_id = key.parse::<u32>().map_err(|e| {
+                    let err_msg = format!("Invalid logit bias key '{}'. {}", key, e);
 
                     #[cfg(feature = "logging")]
                     error!(target: "stdout", "{}", &err_msg);
 
-                    Err(LlamaCoreError::Operation(err_msg.into()))
-                }
-            },
-        },
-        None => match chat_graphs.iter().next() {
-            Some((_, graph)) => get_token_info_by_graph(graph),
-            None => {
-                let err_msg = "There is no model available in the chat graphs.";
-
-                #[cfg(feature = "logging")]
-                error!(target: "stdout", "{}", &err_msg);
+                    LlamaCoreError::BadRequest(err_msg)
+                })?;
 
-                Err(LlamaCoreError::Operation(err_msg.into()))
+                parsed.input_ids.insert(token_id, bias);
             }
-        },
+            LlamaCppLogitBiasType::tokens => {
+                parsed.tokens.insert(key.clone(), bias);
+            }
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Tell whether a key of `logit_bias` is a token id or a string to be tokenized.
+fn logit_bias_type(key: &str) -> LlamaCppLogitBiasType {
+    match key.bytes().all(|b| b.is_ascii_digit()) {
+        true => LlamaCppLogitBiasType::input_ids,
+        false => LlamaCppLogitBiasType::tokens,
     }
 }
 
+/// Get the log probabilities of the generated tokens from the graph.
+///
+/// If the `logprobs` option of the metadata is enabled, the backend outputs the log probabilities in JSON to the output tensor at index 2. In the stream mode, only the tokens generated by the last `compute_single` call are output.
+///
+/// The versions of the wasi-nn ggml plugin without the `logprobs` option ignore it and output nothing at index 2, which is reported as an error instead of empty log probabilities.
+pub(crate) fn get_logprobs_by_graph<M>(
+    graph: &Graph<M>,
+    top_logprobs: u8,
+    stream: bool,
+) -> Result<Vec<TokenLogProb>, LlamaCoreError>
+where
+    M: BaseMetadata + serde::Serialize + Clone + Default,
+{
+    #[cfg(feature = "logging")]
+    info!(target: "stdout", "Get log probabilities from the model named {}", graph.name());
+
+    let output_buffer = match stream {
+        true => get_output_buffer_single(graph, LOGPROBS_TENSOR),
+        false => get_output_buffer(graph, LOGPROBS_TENSOR),
+    };
+
+    // the backend outputs nothing at index 2 if no token is generated, so a missing or empty output of the generated tokens means that the plugin does not support the `logprobs` option
+    let output_buffer = match output_buffer {
+        Ok(output_buffer) if output_buffer.is_empty() => {
+            match stream || get_token_info_by_graph(graph)?.completion_tokens > 0 {
+                true => None,
+                false => return Ok(vec![]),
+            }
+        }
+        Ok(output_buffer) => Some(output_buffer),
+        Err(_) => None,
+    };
+    let output_buffer = match output_buffer {
+        Some(output_buffer) => output_buffer,
+        None => {
+            let plugin = match get_plugin_info_by_graph(graph) {
+                Ok(plugin_info) => plugin_info.to_string(),
+                Err(_) => "wasi-nn ggml plugin".to_string(),
+            };
+            let err_msg = format!(
+                "No log probabilities are output by the backend. The {} may not support the `logprobs` option.",
+                plugin
+            );
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::Operation(err_msg));
+        }
+    };
+
+    let outputs: Vec<TokenLogProbOutput> =
+        serde_json::from_slice(&output_buffer[..]).map_err(|e| {
+            let err_msg = format!("Fail to deserialize the log probabilities: {msg}", msg = e);
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })?;
+
+    let logprobs = outputs
+        .into_iter()
+        .map(|output| TokenLogProb {
+            bytes: output
+                .bytes
+                .or_else(|| Some(output.token.as_bytes().to_vec())),
+            token: output.token,
+            logprob: output.logprob,
+            top_logprobs: output
+                .top_logprobs
+                .into_iter()
+                .take(top_logprobs as usize)
+                .map(|top| TopLogProb {
+                    bytes: top.bytes.or_else(|| Some(top.token.as_bytes().to_vec())),
+                    token: top.token,
+                    logprob: top.logprob,
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(logprobs)
+}
+
+/// The log probability of a generated token output by the backend.
+#[derive(Debug, Deserialize)]
+struct TokenLogProbOutput {
+    token: String,
+    logprob: f32,
+    #[serde(default)]
+    bytes: Option<Vec<u8>>,
+    #[serde(default)]
+    top_logprobs: Vec<TopLogProbOutput>,
+}
+
+/// One of the most likely tokens output by the backend at a token position.
+#[derive(Debug, Deserialize)]
+struct TopLogProbOutput {
+    token: String,
+    logprob: f32,
+    #[serde(default)]
+    bytes: Option<Vec<u8>>,
+}
+
 #[derive(Debug)]
 pub(crate) struct TokenInfo {
     pub(crate) prompt_tokens: u64,
@@ -399,3 +673,231 @@ where
 
     Ok(())
 }
+
+/// Find the byte offset of the earliest occurrence of any of the stop sequences in the given text.
+pub(crate) fn find_stop_sequence(text: &str, stop: &[String]) -> Option<usize> {
+    stop.iter()
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| text.find(s.as_str()))
+        .min()
+}
+
+/// Truncate the given text at the earliest occurrence of any of the stop sequences. Returns `true` if a stop sequence is found.
+pub(crate) fn truncate_at_stop_sequence(text: &mut String, stop: &[String]) -> bool {
+    match find_stop_sequence(text, stop) {
+        Some(pos) => {
+            text.truncate(pos);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Drop the log probabilities of the tokens after the earliest stop sequence, so that they cover the same text as the truncated generation. The token in which the stop sequence starts is kept if part of its text is emitted.
+pub(crate) fn truncate_logprobs_at_stop_sequence(
+    logprobs: &mut Vec<TokenLogProb>,
+    stop: &[String],
+) {
+    let text: String = logprobs
+        .iter()
+        .map(|logprob| logprob.token.as_str())
+        .collect();
+
+    if let Some(pos) = find_stop_sequence(&text, stop) {
+        let mut start = 0;
+        logprobs.retain(|logprob| {
+            let keep = start < pos;
+            start += logprob.token.len();
+            keep
+        });
+    }
+}
+
+/// Track the stop sequences of a request while the tokens are generated one by one in the stream mode.
+///
+/// A stop sequence may be split across several tokens, so the tail of the generated text which is a prefix of any stop sequence is held back until it can be decided whether the stop sequence is hit or not.
+#[derive(Debug, Default)]
+pub(crate) struct StopSequences {
+    stop: Vec<String>,
+    pending: String,
+    hit: bool,
+}
+impl StopSequences {
+    pub(crate) fn new(stop: Option<Vec<String>>) -> Self {
+        let stop = stop
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        Self {
+            stop,
+            pending: String::new(),
+            hit: false,
+        }
+    }
+
+    /// Feed a newly generated token. Returns the text which is safe to send out, and `true` if a stop sequence is hit. The stop sequence and the text after it are dropped.
+    pub(crate) fn push(&mut self, token: &str) -> (String, bool) {
+        if self.stop.is_empty() {
+            return (token.to_string(), false);
+        }
+
+        self.pending.push_str(token);
+
+        if let Some(pos) = find_stop_sequence(&self.pending, &self.stop) {
+            let text = self.pending[..pos].to_string();
+            self.pending.clear();
+            self.hit = true;
+
+            return (text, true);
+        }
+
+        // hold back the longest tail which is a prefix of any stop sequence
+        let held = self
+            .pending
+            .char_indices()
+            .map(|(idx, _)| idx)
+            .find(|&idx| {
+                self.stop
+                    .iter()
+                    .any(|s| s.starts_with(&self.pending[idx..]))
+            })
+            .unwrap_or(self.pending.len());
+
+        let text = self.pending[..held].to_string();
+        self.pending.drain(..held);
+
+        (text, false)
+    }
+
+    /// Take out the text held back so far.
+    pub(crate) fn flush(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Check if there is any text held back.
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Check if a stop sequence has been hit.
+    pub(crate) fn is_hit(&self) -> bool {
+        self.hit
+    }
+
+    /// Clear the state for generating a new sequence with the same stop sequences.
+    pub(crate) fn reset(&mut self) {
+        self.pending.clear();
+        self.hit = false;
+    }
+}
+
+#[test]
+fn test_stop_sequences_split_across_tokens() {
+    let mut stop_sequences = StopSequences::new(Some(vec!["</end>".to_string()]));
+
+    assert_eq!(stop_sequences.push("Hello</"), ("Hello".to_string(), false));
+    assert!(stop_sequences.has_pending());
+    assert_eq!(stop_sequences.push("en"), (String::new(), false));
+    assert_eq!(stop_sequences.push("d> world"), (String::new(), true));
+    assert!(stop_sequences.is_hit());
+    assert!(!stop_sequences.has_pending());
+}
+
+#[test]
+fn test_stop_sequences_multiple() {
+    let mut stop_sequences = StopSequences::new(Some(vec!["STOP".to_string(), "\n\n".to_string()]));
+
+    assert_eq!(stop_sequences.push("one\n"), ("one".to_string(), false));
+    assert_eq!(stop_sequences.push("two"), ("\ntwo".to_string(), false));
+    assert_eq!(stop_sequences.push(" ST"), (" ".to_string(), false));
+    assert_eq!(stop_sequences.push("OP three"), (String::new(), true));
+    assert!(stop_sequences.is_hit());
+
+    // the earliest stop sequence wins
+    stop_sequences.reset();
+    assert!(!stop_sequences.is_hit());
+    assert_eq!(stop_sequences.push("a\n\nb STOP"), ("a".to_string(), true));
+}
+
+#[test]
+fn test_stop_sequences_abandoned_partial_match() {
+    let mut stop_sequences = StopSequences::new(Some(vec!["<|end|>".to_string()]));
+
+    assert_eq!(stop_sequences.push("1 <"), ("1 ".to_string(), false));
+    assert_eq!(stop_sequences.push("|e"), (String::new(), false));
+    assert_eq!(stop_sequences.push("x"), ("<|ex".to_string(), false));
+    assert!(!stop_sequences.has_pending());
+
+    // the text held back at the end of the generation is flushed
+    assert_eq!(stop_sequences.push(" <|"), (" ".to_string(), false));
+    assert!(stop_sequences.has_pending());
+    assert_eq!(stop_sequences.flush(), "<|");
+    assert!(!stop_sequences.has_pending());
+    assert!(!stop_sequences.is_hit());
+}
+
+#[test]
+fn test_stop_sequences_hit_in_first_token() {
+    let mut stop_sequences = StopSequences::new(Some(vec!["###".to_string()]));
+
+    assert_eq!(stop_sequences.push("###"), (String::new(), true));
+    assert!(stop_sequences.is_hit());
+
+    let mut stop_sequences = StopSequences::new(None);
+    assert_eq!(stop_sequences.push("###"), ("###".to_string(), false));
+    assert!(!stop_sequences.is_hit());
+}
+
+#[test]
+fn test_truncate_logprobs_at_stop_sequence() {
+    let mut logprobs: Vec<TokenLogProb> = ["Hello", " wor", "ld<", "/end>", " more"]
+        .iter()
+        .map(|token| TokenLogProb {
+            token: token.to_string(),
+            logprob: -0.5,
+            bytes: Some(token.as_bytes().to_vec()),
+            top_logprobs: vec![],
+        })
+        .collect();
+
+    truncate_logprobs_at_stop_sequence(&mut logprobs, &["</end>".to_string()]);
+
+    let tokens: Vec<&str> = logprobs.iter().map(|l| l.token.as_str()).collect();
+    assert_eq!(tokens, vec!["Hello", " wor", "ld<"]);
+
+    // nothing is dropped if no stop sequence is hit
+    truncate_logprobs_at_stop_sequence(&mut logprobs, &["STOP".to_string()]);
+    assert_eq!(logprobs.len(), 3);
+}
+
+#[test]
+fn test_parse_logit_bias() {
+    let logit_bias = HashMap::from([
+        ("15043".to_string(), -100.0),
+        ("Hello".to_string(), 5.0),
+        (" world".to_string(), -1.5),
+    ]);
+    let parsed = parse_logit_bias(&logit_bias).unwrap();
+    assert_eq!(parsed.input_ids.get(&15043), Some(&-100.0));
+    assert_eq!(parsed.tokens.get("Hello"), Some(&5.0));
+    assert_eq!(parsed.tokens.get(" world"), Some(&-1.5));
+    assert_eq!(parsed.input_ids.len() + parsed.tokens.len(), 3);
+
+    // the bias must be between -100 and 100
+    let logit_bias = HashMap::from([("Hello".to_string(), 101.0)]);
+    assert!(matches!(
+        parse_logit_bias(&logit_bias),
+        Err(LlamaCoreError::BadRequest(_))
+    ));
+
+    // the token must not be empty, and the token id must fit in `u32`
+    for key in ["", "99999999999"] {
+        let logit_bias = HashMap::from([(key.to_string(), 1.0)]);
+        assert!(matches!(
+            parse_logit_bias(&logit_bias),
+            Err(LlamaCoreError::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,459 @@
+//! Define the embedded vector store, which keeps each collection in a JSON file under `vector_store/` and searches it with a flat index.
+
+use super::{Point, ScoredPoint, VectorStore};
+use crate::error::LlamaCoreError;
+use futures::future::BoxFuture;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+/// The directory of the embedded vector stores, which is kept apart from the archives of the uploaded files.
+const ROOT_DIR: &str = "vector_store";
+
+/// The name of the store used if the URL does not name one.
+const DEFAULT_STORE_NAME: &str = "default";
+
+// key: path of the collection file, value: the collection loaded from the file
+static COLLECTIONS: OnceCell<Mutex<HashMap<PathBuf, Collection>>> = OnceCell::new();
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Collection {
+    /// The dimension of the vectors.
+    dim: usize,
+    points: Vec<Point>,
+}
+
+/// The embedded vector store. Each collection is persisted to `vector_store/<store name>/<collection name>.json`, and is searched by comparing the query vector with all the points of the collection.
+#[derive(Debug, Clone)]
+pub struct LocalVectorStore {
+    dir: PathBuf,
+}
+impl LocalVectorStore {
+    /// Open the store of the given name. The store named `default` is opened if the name is empty.
+    pub fn new(name: impl AsRef<str>) -> Result<Self, LlamaCoreError> {
+        let name = name.as_ref().trim_matches('/');
+        let name = match name.is_empty() {
+            true => DEFAULT_STORE_NAME,
+            false => name,
+        };
+        validate_name(name, "store")?;
+
+        Ok(Self {
+            dir: Path::new(ROOT_DIR).join(name),
+        })
+    }
+
+    fn collection_path(&self, collection_name: &str) -> Result<PathBuf, LlamaCoreError> {
+        validate_name(collection_name, "collection")?;
+
+        Ok(self.dir.join(format!("{}.json", collection_name)))
+    }
+}
+impl VectorStore for LocalVectorStore {
+    fn create_collection<'a>(
+        &'a self,
+        collection_name: &'a str,
+        dim: usize,
+    ) -> BoxFuture<'a, Result<(), LlamaCoreError>> {
+        Box::pin(async move {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Create a local collection named {} of {} dimensions.", collection_name, dim);
+
+            let path = self.collection_path(collection_name)?;
+
+            let mut collections = lock_collections()?;
+            if collections.contains_key(&path) || path.exists() {
+                let err_msg = format!("The collection {} already exists.", collection_name);
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::VectorStore(err_msg));
+            }
+
+            let collection = Collection {
+                dim,
+                points: vec![],
+            };
+            save_collection(&path, &collection)?;
+            collections.insert(path, collection);
+
+            Ok(())
+        })
+    }
+
+    fn upsert_points<'a>(
+        &'a self,
+        collection_name: &'a str,
+        points: Vec<Point>,
+    ) -> BoxFuture<'a, Result<(), LlamaCoreError>> {
+        Box::pin(async move {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Persist {} embeddings to the local collection {}.", points.len(), collection_name);
+
+            let path = self.collection_path(collection_name)?;
+
+            let mut collections = lock_collections()?;
+            let collection = load_collection(&mut collections, &path, collection_name)?;
+
+            if let Some(point) = points.iter().find(|p| p.vector.len() != collection.dim) {
+                let err_msg = format!(
+                    "The dimension of the point {} is {}, but the collection {} requires {}.",
+                    point.id,
+                    point.vector.len(),
+                    collection_name,
+                    collection.dim
+                );
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::VectorStore(err_msg));
+            }
+
+            // save a copy first, so that the cached collection is unchanged if the file cannot be written
+            let mut updated = collection.clone();
+            for point in points {
+                match updated.points.iter_mut().find(|p| p.id == point.id) {
+                    Some(existing) => *existing = point,
+                    None => updated.points.push(point),
+                }
+            }
+            save_collection(&path, &updated)?;
+            *collection = updated;
+
+            Ok(())
+        })
+    }
+
+    fn search_points<'a>(
+        &'a self,
+        collection_name: &'a str,
+        query_vector: &'a [f32],
+        limit: usize,
+        score_threshold: Option<f32>,
+    ) -> BoxFuture<'a, Result<Vec<ScoredPoint>, LlamaCoreError>> {
+        Box::pin(async move {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Search similar points from the local collection {}.", collection_name);
+
+            let path = self.collection_path(collection_name)?;
+
+            let mut collections = lock_collections()?;
+            let collection = load_collection(&mut collections, &path, collection_name)?;
+
+            if query_vector.len() != collection.dim {
+                let err_msg = format!(
+                    "The dimension of the query vector is {}, but the collection {} requires {}.",
+                    query_vector.len(),
+                    collection_name,
+                    collection.dim
+                );
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::VectorStore(err_msg));
+            }
+
+            let mut scored_points: Vec<ScoredPoint> = collection
+                .points
+                .iter()
+                .map(|point| ScoredPoint {
+                    payload: point.payload.clone(),
+                    score: cosine_similarity(query_vector, &point.vector),
+                })
+                .filter(|point| match score_threshold {
+                    Some(score_threshold) => point.score >= score_threshold,
+                    None => true,
+                })
+                .collect();
+            scored_points.sort_by(|a, b| b.score.total_cmp(&a.score));
+            scored_points.truncate(limit);
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Number of similar points found: {}", scored_points.len());
+
+            Ok(scored_points)
+        })
+    }
+}
+
+/// Reject the names which could escape the directory of the stores.
+fn validate_name(name: &str, kind: &str) -> Result<(), LlamaCoreError> {
+    let is_valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !is_valid {
+        let err_msg = format!(
+            "Invalid {} name: {}. Only ASCII letters, digits, `-`, `_` and `.` are allowed.",
+            kind, name
+        );
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(LlamaCoreError::VectorStore(err_msg));
+    }
+
+    Ok(())
+}
+
+fn lock_collections() -> Result<MutexGuard<'static, HashMap<PathBuf, Collection>>, LlamaCoreError> {
+    COLLECTIONS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|e| {
+            let err_msg = format!("Fail to acquire the lock of the local collections. {}", e);
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            LlamaCoreError::Operation(err_msg)
+        })
+}
+
+/// Get the cached collection, or load it from its file.
+fn load_collection<'a>(
+    collections: &'a mut HashMap<PathBuf, Collection>,
+    path: &Path,
+    collection_name: &str,
+) -> Result<&'a mut Collection, LlamaCoreError> {
+    if !collections.contains_key(path) {
+        if !path.exists() {
+            let err_msg = format!("The collection {} does not exist.", collection_name);
+
+            #[cfg(feature = "logging")]
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(LlamaCoreError::VectorStore(err_msg));
+        }
+
+        let collection: Collection = fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                let err_msg = format!(
+                    "Fail to load the collection {} from {}. {}",
+                    collection_name,
+                    path.display(),
+                    e
+                );
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                LlamaCoreError::VectorStore(err_msg)
+            })?;
+
+        collections.insert(path.to_path_buf(), collection);
+    }
+
+    Ok(collections.get_mut(path).unwrap())
+}
+
+/// Write the collection to a temporary file first, and then rename it, so that a failed write never leaves a broken collection file.
+fn save_collection(path: &Path, collection: &Collection) -> Result<(), LlamaCoreError> {
+    let result = (|| -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+
+        let bytes = serde_json::to_vec(collection).map_err(|e| e.to_string())?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, bytes).map_err(|e| e.to_string())?;
+        fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+    })();
+
+    result.map_err(|e| {
+        let err_msg = format!("Fail to save the collection to {}. {}", path.display(), e);
+
+        #[cfg(feature = "logging")]
+        error!(target: "stdout", "{}", &err_msg);
+
+        LlamaCoreError::VectorStore(err_msg)
+    })
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    match norm_a == 0.0 || norm_b == 0.0 {
+        true => 0.0,
+        false => dot / (norm_a * norm_b),
+    }
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_search_points_by_cosine_similarity() {
+    // the store lives in a temporary directory, so that the test never touches the stores of the server
+    let store = LocalVectorStore {
+        dir: std::env::temp_dir().join(format!("llama-core-test-{}", uuid::Uuid::new_v4())),
+    };
+    let point = |id, vector, source: &str| Point {
+        id,
+        vector,
+        payload: serde_json::json!({ "source": source }).as_object().cloned(),
+    };
+    let sources = |scored_points: &[ScoredPoint]| {
+        scored_points
+            .iter()
+            .map(|point| {
+                point.payload.as_ref().unwrap()["source"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    store.create_collection("docs", 2).await.unwrap();
+    store
+        .upsert_points(
+            "docs",
+            vec![
+                point(1, vec![0.0, 1.0], "orthogonal"),
+                point(2, vec![2.0, 0.0], "same direction"),
+                point(3, vec![1.0, 1.0], "diagonal"),
+                point(4, vec![-1.0, 0.0], "opposite"),
+            ],
+        )
+        .await
+        .unwrap();
+
+    // the length of the vectors does not matter
+    let scored_points = store
+        .search_points("docs", &[0.5, 0.0], 10, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        sources(&scored_points),
+        ["same direction", "diagonal", "orthogonal", "opposite"]
+    );
+    assert!((scored_points[0].score - 1.0).abs() < 1e-6);
+    assert!((scored_points[1].score - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+    assert!(scored_points[2].score.abs() < 1e-6);
+    assert!((scored_points[3].score + 1.0).abs() < 1e-6);
+
+    let scored_points = store
+        .search_points("docs", &[1.0, 0.0], 2, None)
+        .await
+        .unwrap();
+    assert_eq!(sources(&scored_points), ["same direction", "diagonal"]);
+
+    let scored_points = store
+        .search_points("docs", &[1.0, 0.0], 10, Some(0.5))
+        .await
+        .unwrap();
+    assert_eq!(sources(&scored_points), ["same direction", "diagonal"]);
+
+    fs::remove_dir_all(&store.dir).unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_upsert_points_replaces_point_of_same_id() {
+    // the store lives in a temporary directory, so that the test never touches the stores of the server
+    let store = LocalVectorStore {
+        dir: std::env::temp_dir().join(format!("llama-core-test-{}", uuid::Uuid::new_v4())),
+    };
+    let point = |id, vector, source: &str| Point {
+        id,
+        vector,
+        payload: serde_json::json!({ "source": source }).as_object().cloned(),
+    };
+    let sources = |scored_points: &[ScoredPoint]| {
+        scored_points
+            .iter()
+            .map(|point| {
+                point.payload.as_ref().unwrap()["source"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    store.create_collection("docs", 2).await.unwrap();
+    store
+        .upsert_points("docs", vec![point(1, vec![1.0, 0.0], "old")])
+        .await
+        .unwrap();
+    store
+        .upsert_points(
+            "docs",
+            vec![
+                point(1, vec![0.0, 1.0], "new"),
+                point(2, vec![1.0, 1.0], "other"),
+            ],
+        )
+        .await
+        .unwrap();
+
+    let scored_points = store
+        .search_points("docs", &[0.0, 1.0], 10, None)
+        .await
+        .unwrap();
+    assert_eq!(sources(&scored_points), ["new", "other"]);
+
+    // the replaced point is persisted, too
+    let path = store.collection_path("docs").unwrap();
+    lock_collections().unwrap().remove(&path);
+    let scored_points = store
+        .search_points("docs", &[0.0, 1.0], 10, None)
+        .await
+        .unwrap();
+    assert_eq!(sources(&scored_points), ["new", "other"]);
+
+    fs::remove_dir_all(&store.dir).unwrap();
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_reject_vectors_of_wrong_dimension() {
+    // the store lives in a temporary directory, so that the test never touches the stores of the server
+    let store = LocalVectorStore {
+        dir: std::env::temp_dir().join(format!("llama-core-test-{}", uuid::Uuid::new_v4())),
+    };
+    let point = |id, vector, source: &str| Point {
+        id,
+        vector,
+        payload: serde_json::json!({ "source": source }).as_object().cloned(),
+    };
+
+    store.create_collection("docs", 3).await.unwrap();
+
+    let result = store
+        .upsert_points(
+            "docs",
+            vec![
+                point(1, vec![1.0, 0.0, 0.0], "valid"),
+                point(2, vec![1.0, 0.0], "invalid"),
+            ],
+        )
+        .await;
+    assert!(matches!(result, Err(LlamaCoreError::VectorStore(_))));
+
+    // none of the points is inserted
+    let scored_points = store
+        .search_points("docs", &[1.0, 0.0, 0.0], 10, None)
+        .await
+        .unwrap();
+    assert!(scored_points.is_empty());
+
+    let result = store.search_points("docs", &[1.0, 0.0], 10, None).await;
+    assert!(matches!(result, Err(LlamaCoreError::VectorStore(_))));
+
+    fs::remove_dir_all(&store.dir).unwrap();
+}
@@ -0,0 +1,104 @@
+//! Define the vector stores which keep the embeddings of the document chunks for RAG.
+//!
+//! The vector store is selected by the scheme of the VectorDB server URL:
+//!
+//! - `http://` or `https://`: a Qdrant server, e.g., `http://localhost:6333`.
+//! - `local://`: the embedded store persisted under `vector_store/`, e.g., `local://` or `local://kb`, where `kb` names a separate store. It needs no external service.
+
+mod local;
+mod qdrant;
+
+pub use self::{local::LocalVectorStore, qdrant::QdrantVectorStore};
+
+use crate::error::LlamaCoreError;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// The scheme of the URL of the embedded vector store.
+pub const LOCAL_SCHEME: &str = "local://";
+
+/// A vector with its id and payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Point {
+    /// Id of the point, which is unique in the collection.
+    pub id: u64,
+    /// The vector of the point.
+    pub vector: Vec<f32>,
+    /// The payload of the point, e.g., the source text of the vector in the `source` field.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Map<String, Value>>,
+}
+
+/// A point found by the similarity search.
+#[derive(Debug, Clone)]
+pub struct ScoredPoint {
+    /// The payload of the point.
+    pub payload: Option<Map<String, Value>>,
+    /// The cosine similarity between the point and the query vector.
+    pub score: f32,
+}
+
+/// The operations of a vector store used by RAG.
+pub trait VectorStore: Send + Sync {
+    /// Create a collection of the vectors of the given dimension.
+    fn create_collection<'a>(
+        &'a self,
+        collection_name: &'a str,
+        dim: usize,
+    ) -> BoxFuture<'a, Result<(), LlamaCoreError>>;
+
+    /// Insert the points into the collection. The points with existing ids replace the old ones.
+    fn upsert_points<'a>(
+        &'a self,
+        collection_name: &'a str,
+        points: Vec<Point>,
+    ) -> BoxFuture<'a, Result<(), LlamaCoreError>>;
+
+    /// Search the points most similar to the query vector, in descending order of score.
+    fn search_points<'a>(
+        &'a self,
+        collection_name: &'a str,
+        query_vector: &'a [f32],
+        limit: usize,
+        score_threshold: Option<f32>,
+    ) -> BoxFuture<'a, Result<Vec<ScoredPoint>, LlamaCoreError>>;
+}
+
+/// Create the vector store selected by the scheme of the VectorDB server URL.
+///
+/// # Arguments
+///
+/// * `vdb_server_url` - URL of the VectorDB server, or `local://[name]` for the embedded store.
+///
+/// * `vdb_api_key` - API key of the VectorDB server, which is ignored by the embedded store.
+pub fn vector_store(
+    vdb_server_url: impl AsRef<str>,
+    vdb_api_key: Option<&str>,
+) -> Result<Box<dyn VectorStore>, LlamaCoreError> {
+    let url = vdb_server_url.as_ref();
+
+    if let Some(name) = url.strip_prefix(LOCAL_SCHEME) {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Use the embedded vector store: {}", url);
+
+        return Ok(Box::new(LocalVectorStore::new(name)?));
+    }
+
+    if url.starts_with("http://") || url.starts_with("https://") {
+        #[cfg(feature = "logging")]
+        info!(target: "stdout", "Use the Qdrant server: {}", url);
+
+        return Ok(Box::new(QdrantVectorStore::new(url, vdb_api_key)));
+    }
+
+    let err_msg = format!(
+        "Unsupported VectorDB server URL: {}. The URL should start with `http://`, `https://` or `{}`.",
+        url, LOCAL_SCHEME
+    );
+
+    #[cfg(feature = "logging")]
+    error!(target: "stdout", "{}", &err_msg);
+
+    Err(LlamaCoreError::Operation(err_msg))
+}
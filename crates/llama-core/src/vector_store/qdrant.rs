@@ -0,0 +1,140 @@
+//! Define the vector store backed by a Qdrant server.
+
+use super::{Point, ScoredPoint, VectorStore};
+use crate::error::LlamaCoreError;
+use futures::future::BoxFuture;
+
+/// The vector store backed by a Qdrant server.
+pub struct QdrantVectorStore {
+    client: ::qdrant::Qdrant,
+}
+impl QdrantVectorStore {
+    /// Create a client of the Qdrant server.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - URL of the Qdrant server.
+    ///
+    /// * `api_key` - API key of the Qdrant server, which is not set if empty.
+    pub fn new(url: impl Into<String>, api_key: Option<&str>) -> Self {
+        let mut client = ::qdrant::Qdrant::new_with_url(url.into());
+
+        // set the API key if provided
+        if let Some(key) = api_key {
+            if !key.is_empty() {
+                #[cfg(feature = "logging")]
+                debug!(target: "stdout", "Set the API key for the VectorDB server.");
+
+                client.set_api_key(key);
+            }
+        }
+
+        Self { client }
+    }
+}
+impl VectorStore for QdrantVectorStore {
+    fn create_collection<'a>(
+        &'a self,
+        collection_name: &'a str,
+        dim: usize,
+    ) -> BoxFuture<'a, Result<(), LlamaCoreError>> {
+        Box::pin(async move {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Create a Qdrant collection named {} of {} dimensions.", collection_name, dim);
+
+            if let Err(e) = self
+                .client
+                .create_collection(collection_name, dim as u32)
+                .await
+            {
+                let err_msg = e.to_string();
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::Qdrant(err_msg));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn upsert_points<'a>(
+        &'a self,
+        collection_name: &'a str,
+        points: Vec<Point>,
+    ) -> BoxFuture<'a, Result<(), LlamaCoreError>> {
+        Box::pin(async move {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Persist embeddings to the Qdrant instance.");
+
+            let points: Vec<::qdrant::Point> = points
+                .into_iter()
+                .map(|point| ::qdrant::Point {
+                    id: ::qdrant::PointId::Num(point.id),
+                    vector: point.vector,
+                    payload: point.payload,
+                })
+                .collect();
+
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Number of points to be upserted: {}", points.len());
+
+            if let Err(e) = self.client.upsert_points(collection_name, points).await {
+                let err_msg = format!("{}", e);
+
+                #[cfg(feature = "logging")]
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(LlamaCoreError::Qdrant(err_msg));
+            }
+
+            Ok(())
+        })
+    }
+
+    fn search_points<'a>(
+        &'a self,
+        collection_name: &'a str,
+        query_vector: &'a [f32],
+        limit: usize,
+        score_threshold: Option<f32>,
+    ) -> BoxFuture<'a, Result<Vec<ScoredPoint>, LlamaCoreError>> {
+        Box::pin(async move {
+            #[cfg(feature = "logging")]
+            info!(target: "stdout", "Search similar points from the qdrant instance.");
+
+            match self
+                .client
+                .search_points(
+                    collection_name,
+                    query_vector.to_vec(),
+                    limit as u64,
+                    score_threshold,
+                )
+                .await
+            {
+                Ok(search_result) => {
+                    #[cfg(feature = "logging")]
+                    info!(target: "stdout", "Number of similar points found: {}", search_result.len());
+
+                    Ok(search_result
+                        .into_iter()
+                        .map(|point| ScoredPoint {
+                            payload: point.payload,
+                            score: point.score,
+                        })
+                        .collect())
+                }
+                Err(e) => {
+                    let err_msg = e.to_string();
+
+                    #[cfg(feature = "logging")]
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    Err(LlamaCoreError::Qdrant(err_msg))
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,365 @@
+//! Authorize the requests with the API keys, and count the token usage of each key.
+
+use crate::{
+    error,
+    ratelimit::{self, RateLimit},
+    utils::usage_of_chunk,
+};
+use endpoints::common::Usage;
+use futures_util::Stream;
+use hyper::{body::to_bytes, Body, Request, Response};
+use multipart::server::{Multipart, ReadEntryResult};
+use multipart_2021 as multipart;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    net::IpAddr,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+// key: API key, value: the config of the key
+static API_KEYS: OnceCell<HashMap<String, ApiKey>> = OnceCell::new();
+
+// key: name of the API key, value: the usage of the key
+static KEY_USAGE: OnceCell<Mutex<HashMap<String, KeyUsage>>> = OnceCell::new();
+
+/// The endpoints whose requests specify the model in the `model` field.
+const MODEL_ENDPOINTS: &[&str] = &[
+    "/v1/chat/completions",
+    "/v1/completions",
+    "/v1/embeddings",
+    "/v1/images/generations",
+    "/v1/images/edits",
+    "/v1/audio/transcriptions",
+    "/v1/audio/translations",
+    "/v1/audio/speech",
+];
+
+/// An API key and its scopes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct ApiKey {
+    /// The secret of the key, which is sent as the bearer token of the requests
+    pub(crate) key: String,
+    /// Name of the key, which is used in the logs and the usage counters instead of the secret. Defaults to `key-<n>`, where `n` is the position of the key
+    #[serde(default)]
+    pub(crate) name: Option<String>,
+    /// The endpoints the key is allowed to access, e.g., `/v1/chat/completions`. An endpoint also allows its sub-paths, e.g., `/v1/files` allows `/v1/files/{file_id}`. Empty means all endpoints
+    #[serde(default)]
+    pub(crate) endpoints: Vec<String>,
+    /// The models the key is allowed to use. Empty means all models
+    #[serde(default)]
+    pub(crate) models: Vec<String>,
+    /// Maximum number of requests per minute of the key, which overrides `rate-limit.per-key.rpm` of the config file
+    #[serde(default)]
+    pub(crate) rpm: Option<u64>,
+    /// Maximum number of tokens per minute of the key, which overrides `rate-limit.per-key.tpm` of the config file
+    #[serde(default)]
+    pub(crate) tpm: Option<u64>,
+}
+impl ApiKey {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or_default()
+    }
+
+    fn allows_endpoint(&self, path: &str) -> bool {
+        self.endpoints.is_empty()
+            || self.endpoints.iter().any(|endpoint| {
+                let endpoint = endpoint.trim_end_matches('/');
+                path == endpoint
+                    || path
+                        .strip_prefix(endpoint)
+                        .map(|rest| rest.starts_with('/'))
+                        .unwrap_or_default()
+            })
+    }
+
+    fn allows_model(&self, model: &str) -> bool {
+        self.models.is_empty() || self.models.iter().any(|m| m == model)
+    }
+}
+
+/// The caller of the request, which is stored in the extensions of the request once the request is authorized.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Caller {
+    /// Name of the API key of the request. `None` if the authorization is disabled.
+    pub(crate) key_name: Option<String>,
+    /// IP address of the client.
+    pub(crate) ip: Option<IpAddr>,
+    /// The rate limits set by the API key itself.
+    pub(crate) rate_limit: RateLimit,
+}
+
+/// The number of the requests and tokens of an API key.
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct KeyUsage {
+    pub(crate) name: String,
+    pub(crate) requests: u64,
+    pub(crate) prompt_tokens: u64,
+    pub(crate) completion_tokens: u64,
+    pub(crate) total_tokens: u64,
+}
+
+/// Set the API keys. The requests to the `/v1` endpoints require one of the keys if any key is set.
+pub(crate) fn init_api_keys(keys: Vec<ApiKey>) -> Result<(), String> {
+    let mut api_keys = HashMap::new();
+    for (i, mut api_key) in keys.into_iter().enumerate() {
+        if api_key.key.is_empty() {
+            return Err(format!("The API key at position {} is empty.", i));
+        }
+
+        if api_key.name.is_none() {
+            api_key.name = Some(format!("key-{}", i));
+        }
+
+        if let Some(duplicate) = api_keys.insert(api_key.key.clone(), api_key) {
+            return Err(format!(
+                "The API key named {} is duplicated.",
+                duplicate.name()
+            ));
+        }
+    }
+
+    API_KEYS
+        .set(api_keys)
+        .map_err(|_| "The API keys have already been set.".to_string())
+}
+
+/// Parse the API keys in the YAML key file, which is a list of the keys.
+pub(crate) fn read_api_keys_file(path: impl AsRef<std::path::Path>) -> Result<Vec<ApiKey>, String> {
+    let path = path.as_ref();
+
+    let yaml_content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read the API keys file {}. {}", path.display(), e))?;
+
+    serde_yaml::from_str(&yaml_content).map_err(|e| {
+        format!(
+            "Failed to parse the API keys file {}. {}",
+            path.display(),
+            e
+        )
+    })
+}
+
+/// Authorize the request with its bearer token, and check the endpoint and the model of the request against the scopes of the key. The [Caller] of the request is stored in the extensions of the authorized request.
+pub(crate) async fn authorize(
+    req: Request<Body>,
+    ip: IpAddr,
+) -> Result<Request<Body>, Response<Body>> {
+    let api_keys = match API_KEYS.get() {
+        Some(api_keys) if !api_keys.is_empty() => api_keys,
+        _ => {
+            let mut req = req;
+            req.extensions_mut().insert(Caller {
+                ip: Some(ip),
+                ..Default::default()
+            });
+            return Ok(req);
+        }
+    };
+
+    let token = req
+        .headers()
+        .get("authorization")
+        .and_then(|auth_header| auth_header.to_str().ok())
+        .and_then(|auth_header| auth_header.strip_prefix("Bearer "))
+        .map(|token| token.trim())
+        .unwrap_or_default();
+    if token.is_empty() {
+        return Err(error::unauthorized(
+            "An API key is required. Please set the `Authorization: Bearer <api-key>` header.",
+        ));
+    }
+
+    let api_key = match api_keys.get(token) {
+        Some(api_key) => api_key,
+        None => return Err(error::unauthorized("Invalid API key.")),
+    };
+
+    // log the name of the key rather than the key itself
+    info!(target: "stdout", "api_key: {}", api_key.name());
+
+    let path = req.uri().path().to_string();
+    if !api_key.allows_endpoint(&path) {
+        let err_msg = format!(
+            "The API key named {} is not allowed to access {}.",
+            api_key.name(),
+            path
+        );
+        return Err(error::forbidden(err_msg));
+    }
+
+    let mut req = match !api_key.models.is_empty() && MODEL_ENDPOINTS.contains(&path.as_str()) {
+        true => {
+            let (parts, body) = req.into_parts();
+            let body_bytes = match to_bytes(body).await {
+                Ok(body_bytes) => body_bytes,
+                Err(e) => {
+                    let err_msg = format!("Fail to read buffer from request body. {}", e);
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return Err(error::internal_server_error(err_msg));
+                }
+            };
+
+            let content_type = parts
+                .headers
+                .get("content-type")
+                .and_then(|ct| ct.to_str().ok())
+                .unwrap_or_default();
+            let model = match content_type.starts_with("multipart/form-data") {
+                true => model_of_multipart_form(content_type, &body_bytes),
+                false => serde_json::from_slice::<serde_json::Value>(&body_bytes)
+                    .ok()
+                    .and_then(|v| v.get("model").and_then(|m| m.as_str()).map(String::from)),
+            };
+
+            match model {
+                Some(model) if api_key.allows_model(&model) => {}
+                Some(model) => {
+                    let err_msg = format!(
+                        "The API key named {} is not allowed to use the model {}.",
+                        api_key.name(),
+                        model
+                    );
+                    return Err(error::forbidden(err_msg));
+                }
+                None => {
+                    let err_msg = format!(
+                        "The API key named {} is restricted to specific models. Please set the `model` field of the request.",
+                        api_key.name()
+                    );
+                    return Err(error::forbidden(err_msg));
+                }
+            }
+
+            Request::from_parts(parts, Body::from(body_bytes))
+        }
+        false => req,
+    };
+
+    if let Ok(mut key_usage) = key_usage().lock() {
+        key_usage
+            .entry(api_key.name().to_string())
+            .or_insert_with(|| KeyUsage {
+                name: api_key.name().to_string(),
+                ..Default::default()
+            })
+            .requests += 1;
+    }
+
+    req.extensions_mut().insert(Caller {
+        key_name: Some(api_key.name().to_string()),
+        ip: Some(ip),
+        rate_limit: RateLimit {
+            rpm: api_key.rpm,
+            tpm: api_key.tpm,
+        },
+    });
+
+    Ok(req)
+}
+
+/// Get the value of the `model` field of the multipart form.
+fn model_of_multipart_form(content_type: &str, body_bytes: &[u8]) -> Option<String> {
+    let boundary = content_type
+        .split(';')
+        .find_map(|param| param.trim().strip_prefix("boundary="))?;
+
+    let mut multipart = Multipart::with_body(Cursor::new(body_bytes), boundary);
+    while let ReadEntryResult::Entry(mut field) = multipart.read_entry_mut() {
+        if &*field.headers.name == "model" {
+            let mut model = String::new();
+            field.data.read_to_string(&mut model).ok()?;
+            return Some(model);
+        }
+    }
+
+    None
+}
+
+fn key_usage() -> &'static Mutex<HashMap<String, KeyUsage>> {
+    KEY_USAGE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Add the token usage of a completed request to the counters of its API key, and count the tokens against the rate limits of the caller.
+pub(crate) fn record_usage(caller: &Caller, usage: &Usage) {
+    ratelimit::record_tokens(caller, usage.total_tokens);
+
+    let key_name = match &caller.key_name {
+        Some(key_name) => key_name,
+        None => return,
+    };
+
+    if let Ok(mut key_usage) = key_usage().lock() {
+        let key_usage = key_usage
+            .entry(key_name.clone())
+            .or_insert_with(|| KeyUsage {
+                name: key_name.clone(),
+                ..Default::default()
+            });
+        key_usage.prompt_tokens += usage.prompt_tokens;
+        key_usage.completion_tokens += usage.completion_tokens;
+        key_usage.total_tokens += usage.total_tokens;
+    }
+}
+
+/// Return the usage of all the API keys, sorted by name.
+pub(crate) fn usage_of_keys() -> Vec<KeyUsage> {
+    let mut usage: Vec<KeyUsage> = match key_usage().lock() {
+        Ok(key_usage) => key_usage.values().cloned().collect(),
+        Err(_) => vec![],
+    };
+    usage.sort_by(|a, b| a.name.cmp(&b.name));
+
+    usage
+}
+
+/// Stream of chat completion or completion chunks which records the token usage in the last chunk. The usage chunk is dropped if the client does not ask for it.
+pub(crate) struct UsageStream<S> {
+    inner: S,
+    caller: Caller,
+    forward_usage: bool,
+}
+impl<S> UsageStream<S> {
+    /// `forward_usage` tells whether the client sets `stream_options.include_usage`.
+    pub(crate) fn new(inner: S, caller: Caller, forward_usage: bool) -> Self {
+        Self {
+            inner,
+            caller,
+            forward_usage,
+        }
+    }
+}
+impl<S, E> Stream for UsageStream<S>
+where
+    S: Stream<Item = Result<String, E>> + Unpin,
+{
+    type Item = Result<String, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            match Pin::new(&mut this.inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => match usage_of_chunk(&chunk) {
+                    Some(usage) => {
+                        record_usage(&this.caller, &usage);
+
+                        if this.forward_usage {
+                            return Poll::Ready(Some(Ok(chunk)));
+                        }
+                    }
+                    None => return Poll::Ready(Some(Ok(chunk))),
+                },
+                other => return other,
+            }
+        }
+    }
+}
@@ -1,19 +1,31 @@
-use crate::{error, utils::gen_chat_id, SERVER_INFO};
+use crate::{auth, error, metrics, utils::gen_chat_id, QUEUE_TIMEOUT, SERVER_INFO};
+#[cfg(feature = "whisper")]
+use endpoints::audio::{
+    transcription::{TimestampGranularity, TranscriptionRequest},
+    translation::TranslationRequest,
+};
+#[cfg(feature = "index")]
+use endpoints::keyword_search::Bm25IndexRequest;
 use endpoints::{
-    chat::ChatCompletionRequest,
+    audio::speech::SpeechRequest,
+    chat::{ChatCompletionRequest, StreamOptions},
     completions::CompletionRequest,
     embeddings::{ChunksRequest, ChunksResponse, EmbeddingRequest},
     files::{DeleteFileStatus, FileObject},
+    images::{ImageCreateRequest, ImageEditRequest, ImageEditRequestBuilder, ResponseFormat},
+    models::{DeleteModelStatus, Model, ModelState},
 };
 use futures_util::TryStreamExt;
 use hyper::{body::to_bytes, Body, Method, Request, Response};
+use llama_core::LlamaCoreError;
 use multipart::server::{Multipart, ReadEntry, ReadEntryResult};
 use multipart_2021 as multipart;
 use std::{
+    collections::HashMap,
     fs::{self, File},
     io::{Cursor, Read, Write},
     path::Path,
-    time::SystemTime,
+    time::{Instant, SystemTime},
 };
 
 /// List all models available.
@@ -21,7 +33,7 @@ pub(crate) async fn models_handler() -> Response<Body> {
     // log
     info!(target: "stdout", "Handling the coming model list request.");
 
-    let list_models_response = match llama_core::models::models().await {
+    let mut list_models_response = match llama_core::models::models().await {
         Ok(list_models_response) => list_models_response,
         Err(e) => {
             let err_msg = format!("Failed to get model list. Reason: {}", e);
@@ -33,6 +45,32 @@ pub(crate) async fn models_handler() -> Response<Body> {
         }
     };
 
+    // append the image and audio models if they are loaded
+    if let Some(server_info) = SERVER_INFO.get() {
+        if let Some(image_model) = &server_info.image_model {
+            list_models_response.data.push(Model {
+                id: image_model.name.clone(),
+                created: image_model.created,
+                object: String::from("model"),
+                owned_by: String::from("Not specified"),
+                state: Some(ModelState::Loaded),
+            });
+        }
+
+        for audio_model in [&server_info.whisper_model, &server_info.piper_model]
+            .into_iter()
+            .flatten()
+        {
+            list_models_response.data.push(Model {
+                id: audio_model.name.clone(),
+                created: audio_model.created,
+                object: String::from("model"),
+                owned_by: String::from("Not specified"),
+                state: Some(ModelState::Loaded),
+            });
+        }
+    }
+
     // serialize response
     let s = match serde_json::to_string(&list_models_response) {
         Ok(s) => s,
@@ -97,6 +135,12 @@ pub(crate) async fn embeddings_handler(mut req: Request<Body>) -> Response<Body>
         }
     }
 
+    let caller = req
+        .extensions()
+        .get::<auth::Caller>()
+        .cloned()
+        .unwrap_or_default();
+
     // parse request
     let body_bytes = match to_bytes(req.body_mut()).await {
         Ok(body_bytes) => body_bytes,
@@ -135,6 +179,8 @@ pub(crate) async fn embeddings_handler(mut req: Request<Body>) -> Response<Body>
 
     let res = match llama_core::embeddings::embeddings(&embedding_request).await {
         Ok(embedding_response) => {
+            auth::record_usage(&caller, &embedding_response.usage);
+
             // serialize embedding object
             match serde_json::to_string(&embedding_response) {
                 Ok(s) => {
@@ -174,7 +220,10 @@ pub(crate) async fn embeddings_handler(mut req: Request<Body>) -> Response<Body>
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            error::internal_server_error(err_msg)
+            match e {
+                LlamaCoreError::ModelLoading(_) => error::service_unavailable(err_msg),
+                _ => error::internal_server_error(err_msg),
+            }
         }
     };
 
@@ -188,6 +237,8 @@ pub(crate) async fn completions_handler(mut req: Request<Body>) -> Response<Body
     // log
     info!(target: "stdout", "Handling the coming completions request.");
 
+    let start = Instant::now();
+
     if req.method().eq(&hyper::http::Method::OPTIONS) {
         let result = Response::builder()
             .header("Access-Control-Allow-Origin", "*")
@@ -209,6 +260,12 @@ pub(crate) async fn completions_handler(mut req: Request<Body>) -> Response<Body
         }
     }
 
+    let caller = req
+        .extensions()
+        .get::<auth::Caller>()
+        .cloned()
+        .unwrap_or_default();
+
     // parse request
     let body_bytes = match to_bytes(req.body_mut()).await {
         Ok(body_bytes) => body_bytes,
@@ -245,48 +302,121 @@ pub(crate) async fn completions_handler(mut req: Request<Body>) -> Response<Body
     // log user id
     info!(target: "stdout", "user: {}", &id);
 
+    // the usage of the stream is always requested for the token metrics and the usage counters of the API key, but it is sent to the client only if the client asks for it
+    let include_usage = completion_request
+        .stream_options
+        .as_ref()
+        .and_then(|stream_options| stream_options.include_usage)
+        .unwrap_or_default();
+    if completion_request.stream == Some(true) && !include_usage {
+        completion_request.stream_options = Some(StreamOptions {
+            include_usage: Some(true),
+        });
+    }
+
+    // wait for the turn of the request in the queue of the model
+    let (permit, queue_position) = match wait_in_queue(completion_request.model.as_deref()).await {
+        Ok(queued) => queued,
+        Err(response) => return response,
+    };
+    let model_name = permit.model_name().to_string();
+
+    // run the request on the model whose queue it waited in
+    completion_request.model = Some(model_name.clone());
+
+    let generation_start = Instant::now();
+
     let res = match llama_core::completions::completions(&completion_request).await {
-        Ok(completion_object) => {
-            // serialize completion object
-            let s = match serde_json::to_string(&completion_object) {
-                Ok(s) => s,
-                Err(e) => {
-                    let err_msg = format!("Fail to serialize completion object. {}", e);
+        Ok(result) => match result {
+            either::Left(stream) => {
+                let stream = llama_core::scheduler::PermitStream::new(stream, permit);
+                let stream = metrics::MetricsStream::new(stream, model_name, start);
+                let stream = auth::UsageStream::new(stream, caller, include_usage);
+                let stream = stream.map_err(|e| e.to_string());
 
-                    // log
-                    error!(target: "stdout", "{}", &err_msg);
+                let result = Response::builder()
+                    .header("Access-Control-Allow-Origin", "*")
+                    .header("Access-Control-Allow-Methods", "*")
+                    .header("Access-Control-Allow-Headers", "*")
+                    .header("Content-Type", "text/event-stream")
+                    .header("Cache-Control", "no-cache")
+                    .header("Connection", "keep-alive")
+                    .header("user", id)
+                    .header("x-queue-position", queue_position)
+                    .body(Body::wrap_stream(stream));
 
-                    return error::internal_server_error(err_msg);
+                match result {
+                    Ok(response) => {
+                        // log
+                        info!(target: "stdout", "finish completions in stream mode");
+
+                        response
+                    }
+                    Err(e) => {
+                        let err_msg = format!("Failed completions in stream mode. Reason: {}", e);
+
+                        // log
+                        error!(target: "stdout", "{}", &err_msg);
+
+                        error::internal_server_error(err_msg)
+                    }
                 }
-            };
+            }
+            either::Right(completion_object) => {
+                auth::record_usage(&caller, &completion_object.usage);
+                metrics::observe_generation(
+                    &model_name,
+                    &completion_object.usage,
+                    generation_start.elapsed(),
+                );
 
-            // return response
-            let result = Response::builder()
-                .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Methods", "*")
-                .header("Access-Control-Allow-Headers", "*")
-                .header("Content-Type", "application/json")
-                .header("user", id)
-                .body(Body::from(s));
-            match result {
-                Ok(response) => response,
-                Err(e) => {
-                    let err_msg = e.to_string();
+                // serialize completion object
+                let s = match serde_json::to_string(&completion_object) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        let err_msg = format!("Fail to serialize completion object. {}", e);
 
-                    // log
-                    error!(target: "stdout", "{}", &err_msg);
+                        // log
+                        error!(target: "stdout", "{}", &err_msg);
 
-                    error::internal_server_error(err_msg)
+                        return error::internal_server_error(err_msg);
+                    }
+                };
+
+                // return response
+                let result = Response::builder()
+                    .header("Access-Control-Allow-Origin", "*")
+                    .header("Access-Control-Allow-Methods", "*")
+                    .header("Access-Control-Allow-Headers", "*")
+                    .header("Content-Type", "application/json")
+                    .header("user", id)
+                    .header("x-queue-position", queue_position)
+                    .body(Body::from(s));
+                match result {
+                    Ok(response) => response,
+                    Err(e) => {
+                        let err_msg = e.to_string();
+
+                        // log
+                        error!(target: "stdout", "{}", &err_msg);
+
+                        error::internal_server_error(err_msg)
+                    }
                 }
             }
-        }
+        },
         Err(e) => {
             let err_msg = e.to_string();
 
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            error::internal_server_error(err_msg)
+            match e {
+                LlamaCoreError::BadRequest(_) => error::bad_request(err_msg),
+                LlamaCoreError::QueueFull(_) => error::too_many_requests(err_msg),
+                LlamaCoreError::ModelLoading(_) => error::service_unavailable(err_msg),
+                _ => error::internal_server_error(err_msg),
+            }
         }
     };
 
@@ -299,6 +429,8 @@ pub(crate) async fn completions_handler(mut req: Request<Body>) -> Response<Body
 pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response<Body> {
     info!(target: "stdout", "Handling the coming chat completion request");
 
+    let start = Instant::now();
+
     if req.method().eq(&hyper::http::Method::OPTIONS) {
         let result = Response::builder()
             .header("Access-Control-Allow-Origin", "*")
@@ -322,6 +454,12 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
 
     info!(target: "stdout", "Prepare the chat completion request");
 
+    let caller = req
+        .extensions()
+        .get::<auth::Caller>()
+        .cloned()
+        .unwrap_or_default();
+
     // parse request
     let body_bytes = match to_bytes(req.body_mut()).await {
         Ok(body_bytes) => body_bytes,
@@ -359,9 +497,79 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
     // log user id
     info!(target: "stdout", "user: {}", chat_request.user.clone().unwrap());
 
+    // the usage of the stream is always requested for the token metrics and the usage counters of the API key, but it is sent to the client only if the client asks for it
+    let include_usage = chat_request
+        .stream_options
+        .as_ref()
+        .and_then(|stream_options| stream_options.include_usage)
+        .unwrap_or_default();
+    if chat_request.stream == Some(true) && !include_usage {
+        chat_request.stream_options = Some(StreamOptions {
+            include_usage: Some(true),
+        });
+    }
+
+    // wait for the turn of the request in the queue of the model
+    let (permit, queue_position) = match wait_in_queue(chat_request.model.as_deref()).await {
+        Ok(queued) => queued,
+        Err(response) => return response,
+    };
+    let model_name = permit.model_name().to_string();
+
+    // run the request on the model whose queue it waited in
+    chat_request.model = Some(model_name.clone());
+
+    // retrieve the context from the vector store and merge it into the messages if the request asks for it. The retrieval runs after the turn of the request comes, so that the embeddings and the searches are bounded by the queue of the model as well.
+    #[cfg(feature = "rag")]
+    let sources = match llama_core::rag::rag_augment_chat_request(
+        &mut chat_request,
+        crate::RAG_POLICY.get().copied().unwrap_or_default(),
+        crate::RAG_PROMPT.get().map(|prompt| prompt.as_str()),
+    )
+    .await
+    {
+        Ok(sources) => sources,
+        Err(LlamaCoreError::BadRequest(err_msg)) => {
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::bad_request(err_msg);
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to retrieve the context. Reason: {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    };
+
+    let generation_start = Instant::now();
+
     let res = match llama_core::chat::chat(&mut chat_request).await {
         Ok(result) => match result {
             either::Left(stream) => {
+                let stream = llama_core::scheduler::PermitStream::new(stream, permit);
+                let stream = metrics::MetricsStream::new(stream, model_name, start);
+                let stream = auth::UsageStream::new(stream, caller, include_usage);
+
+                // the retrieved sources are sent in the first chunk
+                #[cfg(feature = "rag")]
+                let stream = {
+                    let mut sources = sources;
+                    futures_util::StreamExt::map(stream, move |chunk| {
+                        match (chunk, sources.take()) {
+                            (Ok(chunk), Some(sources)) => Ok(chunk
+                                .strip_prefix("data: ")
+                                .and_then(|data| add_rag_sources(data.trim(), &sources))
+                                .map(|data| format!("data: {}\n\n", data))
+                                .unwrap_or(chunk)),
+                            (chunk, _) => chunk,
+                        }
+                    })
+                };
+
                 let stream = stream.map_err(|e| e.to_string());
 
                 let result = Response::builder()
@@ -372,6 +580,7 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
                     .header("Cache-Control", "no-cache")
                     .header("Connection", "keep-alive")
                     .header("user", id)
+                    .header("x-queue-position", queue_position)
                     .body(Body::wrap_stream(stream));
 
                 match result {
@@ -393,6 +602,13 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
                 }
             }
             either::Right(chat_completion_object) => {
+                auth::record_usage(&caller, &chat_completion_object.usage);
+                metrics::observe_generation(
+                    &model_name,
+                    &chat_completion_object.usage,
+                    generation_start.elapsed(),
+                );
+
                 // serialize chat completion object
                 let s = match serde_json::to_string(&chat_completion_object) {
                     Ok(s) => s,
@@ -405,6 +621,11 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
                         return error::internal_server_error(err_msg);
                     }
                 };
+                #[cfg(feature = "rag")]
+                let s = match &sources {
+                    Some(sources) => add_rag_sources(&s, sources).unwrap_or(s),
+                    None => s,
+                };
 
                 // return response
                 let result = Response::builder()
@@ -413,6 +634,7 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
                     .header("Access-Control-Allow-Headers", "*")
                     .header("Content-Type", "application/json")
                     .header("user", id)
+                    .header("x-queue-position", queue_position)
                     .body(Body::from(s));
 
                 match result {
@@ -440,7 +662,12 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            error::internal_server_error(err_msg)
+            match e {
+                LlamaCoreError::BadRequest(_) => error::bad_request(err_msg),
+                LlamaCoreError::QueueFull(_) => error::too_many_requests(err_msg),
+                LlamaCoreError::ModelLoading(_) => error::service_unavailable(err_msg),
+                _ => error::internal_server_error(err_msg),
+            }
         }
     };
 
@@ -450,6 +677,74 @@ pub(crate) async fn chat_completions_handler(mut req: Request<Body>) -> Response
     res
 }
 
+/// Add the sources retrieved for the RAG chat completion to the `sources` field of the JSON object of the response.
+#[cfg(feature = "rag")]
+fn add_rag_sources(json: &str, sources: &[endpoints::rag::RetrieveObject]) -> Option<String> {
+    let mut value: serde_json::Value = serde_json::from_str(json).ok()?;
+    value
+        .as_object_mut()?
+        .insert("sources".to_string(), serde_json::to_value(sources).ok()?);
+
+    serde_json::to_string(&value).ok()
+}
+
+/// Wait in the queue of the chat model until the request is allowed to run on the model.
+///
+/// Returns the permit of the model and the position of the request when it joined the queue. A 429 response is returned if the queue is full, and a 503 response is returned if the request waits longer than the queue timeout.
+async fn wait_in_queue(
+    model_name: Option<&str>,
+) -> Result<(llama_core::scheduler::Permit, usize), Response<Body>> {
+    let acquire = match llama_core::scheduler::acquire(model_name) {
+        Ok(acquire) => acquire,
+        Err(LlamaCoreError::QueueFull(err_msg)) => return Err(error::too_many_requests(err_msg)),
+        Err(LlamaCoreError::ModelLoading(err_msg)) => {
+            return Err(error::service_unavailable(err_msg))
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to join the queue of the model. Reason: {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+    let queue_position = acquire.queue_position();
+    let model_name = acquire.model_name().to_string();
+
+    // log
+    info!(target: "stdout", "queue position: {}", queue_position);
+
+    let result = match QUEUE_TIMEOUT.get().copied().unwrap_or_default() {
+        0 => acquire.await,
+        timeout => {
+            match tokio::time::timeout(std::time::Duration::from_secs(timeout), acquire).await {
+                Ok(result) => result,
+                Err(_) => {
+                    let err_msg = format!(
+                        "The request waited more than {} seconds in the queue of the model {}.",
+                        timeout, model_name
+                    );
+
+                    return Err(error::service_unavailable(err_msg));
+                }
+            }
+        }
+    };
+
+    match result {
+        Ok(permit) => Ok((permit, queue_position)),
+        Err(e) => {
+            let err_msg = format!("Failed to acquire the model. Reason: {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(error::internal_server_error(err_msg))
+        }
+    }
+}
+
 /// Upload, download, retrieve and delete a file, or list all files.
 ///
 /// - `POST /v1/files`: Upload a file.
@@ -1155,46 +1450,102 @@ pub(crate) async fn chunks_handler(mut req: Request<Body>) -> Response<Body> {
     res
 }
 
-/// Return the server info.
-pub(crate) async fn server_info_handler() -> Response<Body> {
+/// Build the built-in BM25 index of an uploaded file, which is searched by the RAG chat requests setting `kw_search_url` to `local://` and `kw_index_name` to the id of the file.
+#[cfg(feature = "index")]
+pub(crate) async fn bm25_index_handler(mut req: Request<Body>) -> Response<Body> {
     // log
-    info!(target: "stdout", "Handling the coming server info request.");
+    info!(target: "stdout", "Handling the coming BM25 index request");
 
-    // get the server info
-    let server_info = match SERVER_INFO.get() {
-        Some(server_info) => server_info,
-        None => {
-            let err_msg = "The server info is not set.";
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        let result = Response::builder()
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "*")
+            .header("Access-Control-Allow-Headers", "*")
+            .header("Content-Type", "application/json")
+            .body(Body::empty());
+
+        match result {
+            Ok(response) => return response,
+            Err(e) => {
+                let err_msg = e.to_string();
+
+                // log
+                error!(target: "bm25_index_handler", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    }
+
+    // parse request
+    let body_bytes = match to_bytes(req.body_mut()).await {
+        Ok(body_bytes) => body_bytes,
+        Err(e) => {
+            let err_msg = format!("Fail to read buffer from request body. {}", e);
 
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            return error::internal_server_error("The server info is not set.");
+            return error::internal_server_error(err_msg);
         }
     };
 
-    // serialize server info
-    let s = match serde_json::to_string(&server_info) {
-        Ok(s) => s,
+    let index_request: Bm25IndexRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(index_request) => index_request,
         Err(e) => {
-            let err_msg = format!("Fail to serialize server info. {}", e);
+            let mut err_msg = format!("Fail to deserialize BM25 index request: {}.", e);
+
+            if let Ok(json_value) = serde_json::from_slice::<serde_json::Value>(&body_bytes) {
+                err_msg = format!("{}\njson_value: {}", err_msg, json_value);
+            }
 
             // log
             error!(target: "stdout", "{}", &err_msg);
 
-            return error::internal_server_error(err_msg);
+            return error::bad_request(err_msg);
         }
     };
 
-    // return response
-    let result = Response::builder()
-        .header("Access-Control-Allow-Origin", "*")
-        .header("Access-Control-Allow-Methods", "*")
-        .header("Access-Control-Allow-Headers", "*")
-        .header("Content-Type", "application/json")
-        .body(Body::from(s));
-    let res = match result {
-        Ok(response) => response,
+    let res = match llama_core::keyword_search::create_bm25_index(
+        &index_request.id,
+        index_request.chunk_capacity,
+    ) {
+        Ok(index_response) => match serde_json::to_string(&index_response) {
+            Ok(s) => {
+                // return response
+                let result = Response::builder()
+                    .header("Access-Control-Allow-Origin", "*")
+                    .header("Access-Control-Allow-Methods", "*")
+                    .header("Access-Control-Allow-Headers", "*")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(s));
+                match result {
+                    Ok(response) => response,
+                    Err(e) => {
+                        let err_msg = e.to_string();
+
+                        // log
+                        error!(target: "stdout", "{}", &err_msg);
+
+                        error::internal_server_error(err_msg)
+                    }
+                }
+            }
+            Err(e) => {
+                let err_msg = format!("Fail to serialize BM25 index response. {}", e);
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                error::internal_server_error(err_msg)
+            }
+        },
+        Err(LlamaCoreError::BadRequest(err_msg)) => {
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::bad_request(err_msg)
+        }
         Err(e) => {
             let err_msg = e.to_string();
 
@@ -1205,7 +1556,1149 @@ pub(crate) async fn server_info_handler() -> Response<Body> {
         }
     };
 
-    info!(target: "stdout", "Send the server info response.");
+    info!(target: "stdout", "Send the BM25 index response.");
 
     res
 }
+
+/// Load or unload a chat or embedding model at runtime. The requests are authorized by the admin key.
+///
+/// - `POST /v1/admin/models`: Load a model from the model file with the given metadata.
+/// - `DELETE /v1/admin/models/{model_name}`: Unload a model by name.
+///
+pub(crate) async fn admin_models_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming admin models request");
+
+    if req.method() == Method::OPTIONS {
+        let result = Response::builder()
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "*")
+            .header("Access-Control-Allow-Headers", "*")
+            .header("Content-Type", "application/json")
+            .body(Body::empty());
+
+        match result {
+            Ok(response) => return response,
+            Err(e) => {
+                let err_msg = e.to_string();
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    }
+
+    let s = if req.method() == Method::POST && req.uri().path() == "/v1/admin/models" {
+        // parse request
+        let body_bytes = match to_bytes(req.into_body()).await {
+            Ok(body_bytes) => body_bytes,
+            Err(e) => {
+                let err_msg = format!("Fail to read buffer from request body. {}", e);
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        };
+        let load_request: llama_core::models::LoadModelRequest =
+            match serde_json::from_slice(&body_bytes) {
+                Ok(load_request) => load_request,
+                Err(e) => {
+                    let err_msg = format!("Fail to deserialize load model request: {}", e);
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return error::bad_request(err_msg);
+                }
+            };
+
+        let model = match llama_core::models::load_model(&load_request.path, load_request.metadata)
+        {
+            Ok(model) => model,
+            Err(e) => return model_admin_error(e),
+        };
+
+        match serde_json::to_string(&model) {
+            Ok(s) => s,
+            Err(e) => {
+                let err_msg = format!("Fail to serialize the loaded model. {}", e);
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    } else if req.method() == Method::DELETE {
+        let model_name = req.uri().path().trim_start_matches("/v1/admin/models/");
+        let status: DeleteModelStatus = match llama_core::models::unload_model(model_name) {
+            Ok(status) => status,
+            Err(e) => return model_admin_error(e),
+        };
+
+        match serde_json::to_string(&status) {
+            Ok(s) => s,
+            Err(e) => {
+                let err_msg = format!(
+                    "Fail to serialize the status of the model unloading operation. {}",
+                    e
+                );
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    } else {
+        let err_msg = "Invalid HTTP Method.";
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        return error::bad_request(err_msg);
+    };
+
+    // return response
+    let result = Response::builder()
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "*")
+        .header("Access-Control-Allow-Headers", "*")
+        .header("Content-Type", "application/json")
+        .body(Body::from(s));
+    let res = match result {
+        Ok(response) => response,
+        Err(e) => {
+            let err_msg = e.to_string();
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the admin models response");
+
+    res
+}
+
+/// Map the error of loading or unloading a model to the response.
+fn model_admin_error(e: LlamaCoreError) -> Response<Body> {
+    match e {
+        LlamaCoreError::ModelNotFound(err_msg) => error::not_found(err_msg),
+        LlamaCoreError::ModelConflict(err_msg) => error::conflict(err_msg),
+        e => error::internal_server_error(e.to_string()),
+    }
+}
+
+/// Return the number of the requests and tokens of each API key.
+pub(crate) async fn admin_usage_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming admin usage request.");
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        let result = Response::builder()
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "*")
+            .header("Access-Control-Allow-Headers", "*")
+            .header("Content-Type", "application/json")
+            .body(Body::empty());
+
+        match result {
+            Ok(response) => return response,
+            Err(e) => {
+                let err_msg = e.to_string();
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    }
+
+    let usage = serde_json::json!({
+        "object": "list",
+        "data": auth::usage_of_keys(),
+    });
+
+    // return response
+    let result = Response::builder()
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "*")
+        .header("Access-Control-Allow-Headers", "*")
+        .header("Content-Type", "application/json")
+        .body(Body::from(usage.to_string()));
+    let res = match result {
+        Ok(response) => response,
+        Err(e) => {
+            let err_msg = e.to_string();
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the admin usage response");
+
+    res
+}
+
+/// Return the server info.
+pub(crate) async fn server_info_handler() -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming server info request.");
+
+    // get the server info
+    let server_info = match SERVER_INFO.get() {
+        Some(server_info) => server_info,
+        None => {
+            let err_msg = "The server info is not set.";
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error("The server info is not set.");
+        }
+    };
+
+    // serialize server info
+    let s = match serde_json::to_string(&server_info) {
+        Ok(s) => s,
+        Err(e) => {
+            let err_msg = format!("Fail to serialize server info. {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    };
+
+    // return response
+    let result = Response::builder()
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "*")
+        .header("Access-Control-Allow-Headers", "*")
+        .header("Content-Type", "application/json")
+        .body(Body::from(s));
+    let res = match result {
+        Ok(response) => response,
+        Err(e) => {
+            let err_msg = e.to_string();
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the server info response.");
+
+    res
+}
+
+/// Create images given a prompt.
+pub(crate) async fn image_generation_handler(mut req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming image generation request.");
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        let result = Response::builder()
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "*")
+            .header("Access-Control-Allow-Headers", "*")
+            .header("Content-Type", "application/json")
+            .body(Body::empty());
+
+        match result {
+            Ok(response) => return response,
+            Err(e) => {
+                let err_msg = e.to_string();
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    }
+
+    // parse request
+    let body_bytes = match to_bytes(req.body_mut()).await {
+        Ok(body_bytes) => body_bytes,
+        Err(e) => {
+            let err_msg = format!("Fail to read buffer from request body. {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    };
+    let mut image_request: ImageCreateRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(image_request) => image_request,
+        Err(e) => {
+            let err_msg = format!("Fail to deserialize image create request: {}.", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::bad_request(err_msg);
+        }
+    };
+
+    if let Err(response) = check_image_model(&image_request.model) {
+        return response;
+    }
+
+    let res = match llama_core::images::image_generation(&mut image_request).await {
+        Ok(images_response) => {
+            // serialize images response
+            let s = match serde_json::to_string(&images_response) {
+                Ok(s) => s,
+                Err(e) => {
+                    let err_msg = format!("Fail to serialize the images response. {}", e);
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return error::internal_server_error(err_msg);
+                }
+            };
+
+            // return response
+            let result = Response::builder()
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Access-Control-Allow-Methods", "*")
+                .header("Access-Control-Allow-Headers", "*")
+                .header("Content-Type", "application/json")
+                .body(Body::from(s));
+            match result {
+                Ok(response) => response,
+                Err(e) => {
+                    let err_msg = e.to_string();
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    error::internal_server_error(err_msg)
+                }
+            }
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to generate images. Reason: {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the image generation response.");
+
+    res
+}
+
+/// Create edited or extended images given an original image and a prompt. The request body is a multipart form.
+pub(crate) async fn image_edit_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming image edit request.");
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        let result = Response::builder()
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "*")
+            .header("Access-Control-Allow-Headers", "*")
+            .header("Content-Type", "application/json")
+            .body(Body::empty());
+
+        match result {
+            Ok(response) => return response,
+            Err(e) => {
+                let err_msg = e.to_string();
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    }
+
+    let mut form = match parse_multipart_form(
+        req,
+        &["image", "mask", "control_image"],
+        &["png", "jpg", "jpeg"],
+    )
+    .await
+    {
+        Ok(form) => form,
+        Err(response) => return response,
+    };
+
+    let mut image_request = match build_image_edit_request(
+        form.files.remove("image"),
+        form.files.remove("mask"),
+        form.files.remove("control_image"),
+        form.fields,
+    ) {
+        Ok(image_request) => image_request,
+        Err(err_msg) => {
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::bad_request(err_msg);
+        }
+    };
+
+    if let Err(response) = check_image_model(&image_request.model) {
+        return response;
+    }
+
+    let res = match llama_core::images::image_edit(&mut image_request).await {
+        Ok(images_response) => {
+            // serialize images response
+            let s = match serde_json::to_string(&images_response) {
+                Ok(s) => s,
+                Err(e) => {
+                    let err_msg = format!("Fail to serialize the images response. {}", e);
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return error::internal_server_error(err_msg);
+                }
+            };
+
+            // return response
+            let result = Response::builder()
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Access-Control-Allow-Methods", "*")
+                .header("Access-Control-Allow-Headers", "*")
+                .header("Content-Type", "application/json")
+                .body(Body::from(s));
+            match result {
+                Ok(response) => response,
+                Err(e) => {
+                    let err_msg = e.to_string();
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    error::internal_server_error(err_msg)
+                }
+            }
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to edit the image. Reason: {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the image edit response.");
+
+    res
+}
+
+/// The files and text fields of a multipart form.
+struct MultipartForm {
+    /// The uploaded files, keyed by the field name.
+    files: HashMap<String, FileObject>,
+    /// The text fields in the order they appear in the form.
+    fields: Vec<(String, String)>,
+}
+
+/// Parse the multipart form of the request. The files of `file_fields` are saved to the `archives` directory and must have one of the given `extensions`.
+async fn parse_multipart_form(
+    req: Request<Body>,
+    file_fields: &[&str],
+    extensions: &[&str],
+) -> Result<MultipartForm, Response<Body>> {
+    let boundary = "boundary=";
+    let boundary = match req.headers().get("content-type").and_then(|ct| {
+        let ct = ct.to_str().ok()?;
+        let idx = ct.find(boundary)?;
+        Some(ct[idx + boundary.len()..].to_string())
+    }) {
+        Some(boundary) => boundary,
+        None => {
+            let err_msg = "The request body must be a multipart form.";
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::bad_request(err_msg));
+        }
+    };
+
+    let body_bytes = match to_bytes(req.into_body()).await {
+        Ok(body_bytes) => body_bytes,
+        Err(e) => {
+            let err_msg = format!("Fail to read buffer from request body. {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+
+    let cursor = Cursor::new(body_bytes.to_vec());
+    let mut multipart = Multipart::with_body(cursor, boundary);
+
+    let mut form = MultipartForm {
+        files: HashMap::new(),
+        fields: vec![],
+    };
+    while let ReadEntryResult::Entry(mut field) = multipart.read_entry_mut() {
+        let name = field.headers.name.to_string();
+        if file_fields.contains(&name.as_str()) {
+            let filename = match field.headers.filename.clone() {
+                Some(filename) => filename,
+                None => {
+                    let err_msg = format!("The filename of `{}` is not provided.", &name);
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    return Err(error::bad_request(err_msg));
+                }
+            };
+
+            let mut buffer = Vec::new();
+            if let Err(e) = field.data.read_to_end(&mut buffer) {
+                let err_msg = format!("Failed to read the `{}` file. {}", &name, e);
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(error::internal_server_error(err_msg));
+            }
+
+            let file_object = save_uploaded_file(filename, &buffer, extensions)?;
+            form.files.insert(name, file_object);
+        } else {
+            let mut value = String::new();
+            if let Err(e) = field.data.read_to_string(&mut value) {
+                let err_msg = format!("Failed to read the `{}` field. {}", &name, e);
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return Err(error::bad_request(err_msg));
+            }
+
+            form.fields.push((name, value));
+        }
+    }
+
+    Ok(form)
+}
+
+/// Check if the image model is loaded and matches the `model` field of the request.
+fn check_image_model(model: &str) -> Result<(), Response<Body>> {
+    let image_model = match SERVER_INFO
+        .get()
+        .and_then(|server_info| server_info.image_model.as_ref())
+    {
+        Some(image_model) => image_model,
+        None => {
+            let err_msg = "No image model is loaded. Please start the server with `--sd-model` or `--sd-diffusion-model`.";
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::bad_request(err_msg));
+        }
+    };
+
+    if !model.is_empty() && model != image_model.name {
+        let err_msg = format!(
+            "The image model `{}` is not found. The available image model is `{}`.",
+            model, image_model.name
+        );
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(error::bad_request(err_msg));
+    }
+
+    Ok(())
+}
+
+/// Save the uploaded file to the `archives` directory, which is the same location as the files uploaded via `/v1/files`.
+fn save_uploaded_file(
+    filename: String,
+    buffer: &[u8],
+    extensions: &[&str],
+) -> Result<FileObject, Response<Body>> {
+    if !extensions
+        .iter()
+        .any(|ext| filename.to_lowercase().ends_with(&format!(".{}", ext)))
+    {
+        let err_msg = format!(
+            "Failed to upload the target file. Only files with {} extensions are supported. The file to be uploaded is {}.",
+            extensions
+                .iter()
+                .map(|ext| format!("'{}'", ext))
+                .collect::<Vec<String>>()
+                .join(", "),
+            &filename
+        );
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(error::bad_request(err_msg));
+    }
+
+    // create a unique file id
+    let id = format!("file_{}", uuid::Uuid::new_v4());
+
+    // save the file
+    let file_path = Path::new("archives").join(&id);
+    if let Err(e) = fs::create_dir_all(&file_path) {
+        let err_msg = format!("Failed to create the archive directory {}. {}", &id, e);
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(error::internal_server_error(err_msg));
+    }
+    let mut file = match File::create(file_path.join(&filename)) {
+        Ok(file) => file,
+        Err(e) => {
+            let err_msg = format!("Failed to create archive document {}. {}", &filename, e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+    if let Err(e) = file.write_all(buffer) {
+        let err_msg = format!("Failed to write archive document {}. {}", &filename, e);
+
+        // log
+        error!(target: "stdout", "{}", &err_msg);
+
+        return Err(error::internal_server_error(err_msg));
+    }
+
+    // log
+    info!(target: "stdout", "file_id: {}, file_name: {}", &id, &filename);
+
+    let created_at = match SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(n) => n.as_secs(),
+        Err(_) => {
+            let err_msg = "Failed to get the current time.";
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+
+    Ok(FileObject {
+        id,
+        bytes: buffer.len() as u64,
+        created_at,
+        filename,
+        object: "file".to_string(),
+        purpose: "assistants".to_string(),
+    })
+}
+
+/// Build an image edit request from the fields of the multipart form.
+fn build_image_edit_request(
+    image: Option<FileObject>,
+    mask: Option<FileObject>,
+    control_image: Option<FileObject>,
+    fields: Vec<(String, String)>,
+) -> Result<ImageEditRequest, String> {
+    let image = image.ok_or("The `image` field is required.")?;
+
+    let prompt = fields
+        .iter()
+        .find(|(name, _)| name == "prompt")
+        .map(|(_, value)| value.clone())
+        .ok_or("The `prompt` field is required.")?;
+
+    let model = fields
+        .iter()
+        .find(|(name, _)| name == "model")
+        .map(|(_, value)| value.clone())
+        .unwrap_or_default();
+
+    let mut builder = ImageEditRequestBuilder::new(model, image, prompt);
+    if let Some(mask) = mask {
+        builder = builder.with_mask(mask);
+    }
+    if let Some(control_image) = control_image {
+        builder = builder.with_control_image(control_image);
+    }
+
+    let mut height: Option<usize> = None;
+    let mut width: Option<usize> = None;
+    for (name, value) in fields {
+        builder = match name.as_str() {
+            "prompt" | "model" => builder,
+            "negative_prompt" => builder.with_negative_prompt(value),
+            "n" => builder.with_number_of_images(parse_field(&name, &value)?),
+            "size" => builder.with_size(value),
+            "response_format" => {
+                let response_format: ResponseFormat = parse_field(&name, &value)?;
+                builder.with_response_format(response_format)
+            }
+            "user" => builder.with_user(value),
+            "cfg_scale" => builder.with_cfg_scale(parse_field(&name, &value)?),
+            "sample_method" => builder.with_sample_method(value.as_str().into()),
+            "steps" => builder.with_steps(parse_field(&name, &value)?),
+            "height" => {
+                height = Some(parse_field(&name, &value)?);
+                builder
+            }
+            "width" => {
+                width = Some(parse_field(&name, &value)?);
+                builder
+            }
+            "control_strength" => builder.with_control_strength(parse_field(&name, &value)?),
+            "seed" => builder.with_seed(parse_field(&name, &value)?),
+            "strength" => builder.with_strength(parse_field(&name, &value)?),
+            "scheduler" => builder.with_scheduler(value.as_str().into()),
+            "apply_canny_preprocessor" => {
+                builder.with_apply_canny_preprocessor(parse_field(&name, &value)?)
+            }
+            "style_ratio" => builder.with_style_ratio(parse_field(&name, &value)?),
+            _ => {
+                // log
+                warn!(target: "stdout", "Ignore the unsupported field `{}` of the image edit request.", name);
+
+                builder
+            }
+        };
+    }
+
+    let mut image_request = builder.build();
+    image_request.height = height.or(image_request.height);
+    image_request.width = width.or(image_request.width);
+
+    Ok(image_request)
+}
+
+/// Parse the value of a field of the multipart form.
+fn parse_field<T>(name: &str, value: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid value of the `{}` field: {}", name, e))
+}
+
+/// Transcribe audio into the input language. The request body is a multipart form.
+#[cfg(feature = "whisper")]
+pub(crate) async fn audio_transcriptions_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming audio transcription request.");
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        let result = Response::builder()
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "*")
+            .header("Access-Control-Allow-Headers", "*")
+            .header("Content-Type", "application/json")
+            .body(Body::empty());
+
+        match result {
+            Ok(response) => return response,
+            Err(e) => {
+                let err_msg = e.to_string();
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    }
+
+    let mut form = match parse_multipart_form(req, &["file"], &["wav"]).await {
+        Ok(form) => form,
+        Err(response) => return response,
+    };
+
+    let request = match build_transcription_request(form.files.remove("file"), form.fields) {
+        Ok(request) => request,
+        Err(err_msg) => {
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::bad_request(err_msg);
+        }
+    };
+
+    if let Err(response) =
+        check_audio_model(request.model.as_deref(), AudioModelKind::Transcription)
+    {
+        return response;
+    }
+
+    let response_format = request.response_format.clone();
+    let res = match llama_core::audio::audio_transcriptions(request).await {
+        Ok(transcription_object) => {
+            transcription_response(&transcription_object.text, response_format.as_deref())
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to transcribe the audio. Reason: {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the audio transcription response.");
+
+    res
+}
+
+/// Translate audio into English. The request body is a multipart form.
+#[cfg(feature = "whisper")]
+pub(crate) async fn audio_translations_handler(req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming audio translation request.");
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        let result = Response::builder()
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "*")
+            .header("Access-Control-Allow-Headers", "*")
+            .header("Content-Type", "application/json")
+            .body(Body::empty());
+
+        match result {
+            Ok(response) => return response,
+            Err(e) => {
+                let err_msg = e.to_string();
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    }
+
+    let mut form = match parse_multipart_form(req, &["file"], &["wav"]).await {
+        Ok(form) => form,
+        Err(response) => return response,
+    };
+
+    let request = match build_translation_request(form.files.remove("file"), form.fields) {
+        Ok(request) => request,
+        Err(err_msg) => {
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::bad_request(err_msg);
+        }
+    };
+
+    if let Err(response) =
+        check_audio_model(request.model.as_deref(), AudioModelKind::Transcription)
+    {
+        return response;
+    }
+
+    let response_format = request.response_format.clone();
+    let res = match llama_core::audio::audio_translations(request).await {
+        Ok(translation_object) => {
+            transcription_response(&translation_object.text, response_format.as_deref())
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to translate the audio. Reason: {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the audio translation response.");
+
+    res
+}
+
+/// Generate audio from the input text.
+pub(crate) async fn audio_speech_handler(mut req: Request<Body>) -> Response<Body> {
+    // log
+    info!(target: "stdout", "Handling the coming audio speech request.");
+
+    if req.method().eq(&hyper::http::Method::OPTIONS) {
+        let result = Response::builder()
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "*")
+            .header("Access-Control-Allow-Headers", "*")
+            .header("Content-Type", "application/json")
+            .body(Body::empty());
+
+        match result {
+            Ok(response) => return response,
+            Err(e) => {
+                let err_msg = e.to_string();
+
+                // log
+                error!(target: "stdout", "{}", &err_msg);
+
+                return error::internal_server_error(err_msg);
+            }
+        }
+    }
+
+    // parse request
+    let body_bytes = match to_bytes(req.body_mut()).await {
+        Ok(body_bytes) => body_bytes,
+        Err(e) => {
+            let err_msg = format!("Fail to read buffer from request body. {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::internal_server_error(err_msg);
+        }
+    };
+    let speech_request: SpeechRequest = match serde_json::from_slice(&body_bytes) {
+        Ok(speech_request) => speech_request,
+        Err(e) => {
+            let err_msg = format!("Fail to deserialize speech request: {}.", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return error::bad_request(err_msg);
+        }
+    };
+
+    if let Err(response) = check_audio_model(Some(&speech_request.model), AudioModelKind::Speech) {
+        return response;
+    }
+
+    let res = match llama_core::audio::create_speech(speech_request).await {
+        Ok(audio) => {
+            // return response
+            let result = Response::builder()
+                .header("Access-Control-Allow-Origin", "*")
+                .header("Access-Control-Allow-Methods", "*")
+                .header("Access-Control-Allow-Headers", "*")
+                .header("Content-Type", "audio/wav")
+                .header("Content-Disposition", "attachment; filename=audio.wav")
+                .body(Body::from(audio));
+            match result {
+                Ok(response) => response,
+                Err(e) => {
+                    let err_msg = e.to_string();
+
+                    // log
+                    error!(target: "stdout", "{}", &err_msg);
+
+                    error::internal_server_error(err_msg)
+                }
+            }
+        }
+        Err(e) => {
+            let err_msg = format!("Failed to generate the speech. Reason: {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    };
+
+    info!(target: "stdout", "Send the audio speech response.");
+
+    res
+}
+
+/// The kind of the audio model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AudioModelKind {
+    /// The whisper model for transcription and translation.
+    Transcription,
+    /// The piper model for speech generation.
+    Speech,
+}
+
+/// Check if the audio model of the given kind is loaded and matches the `model` field of the request.
+fn check_audio_model(model: Option<&str>, kind: AudioModelKind) -> Result<(), Response<Body>> {
+    let audio_model = SERVER_INFO.get().and_then(|server_info| match kind {
+        AudioModelKind::Transcription => server_info.whisper_model.as_ref(),
+        AudioModelKind::Speech => server_info.piper_model.as_ref(),
+    });
+
+    let audio_model = match audio_model {
+        Some(audio_model) => audio_model,
+        None => {
+            let err_msg = match kind {
+                AudioModelKind::Transcription => {
+                    "No whisper model is loaded. Please start the server with `--whisper-model`."
+                }
+                AudioModelKind::Speech => {
+                    "No piper model is loaded. Please start the server with `--piper-voice-model`."
+                }
+            };
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::bad_request(err_msg));
+        }
+    };
+
+    match model {
+        Some(model) if !model.is_empty() && model != audio_model.name => {
+            let err_msg = format!(
+                "The audio model `{}` is not found. The available audio model is `{}`.",
+                model, audio_model.name
+            );
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            Err(error::bad_request(err_msg))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Build the response of the audio transcription or translation in the requested format. `text` returns the plain text; the other formats return a JSON object.
+#[cfg(feature = "whisper")]
+fn transcription_response(text: &str, response_format: Option<&str>) -> Response<Body> {
+    let (content_type, body) = match response_format {
+        Some("text") => ("text/plain", text.to_string()),
+        _ => (
+            "application/json",
+            serde_json::json!({ "text": text }).to_string(),
+        ),
+    };
+
+    // return response
+    let result = Response::builder()
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "*")
+        .header("Access-Control-Allow-Headers", "*")
+        .header("Content-Type", content_type)
+        .body(Body::from(body));
+    match result {
+        Ok(response) => response,
+        Err(e) => {
+            let err_msg = e.to_string();
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    }
+}
+
+/// Build an audio transcription request from the fields of the multipart form.
+#[cfg(feature = "whisper")]
+fn build_transcription_request(
+    file: Option<FileObject>,
+    fields: Vec<(String, String)>,
+) -> Result<TranscriptionRequest, String> {
+    let mut request = TranscriptionRequest {
+        file: file.ok_or("The `file` field is required.")?,
+        ..Default::default()
+    };
+
+    for (name, value) in fields {
+        match name.as_str() {
+            "model" => request.model = Some(value),
+            "language" => request.language = Some(value),
+            "prompt" => request.prompt = Some(value),
+            "response_format" => request.response_format = Some(value),
+            "temperature" => request.temperature = Some(parse_field(&name, &value)?),
+            "timestamp_granularities" | "timestamp_granularities[]" => {
+                let granularity: TimestampGranularity =
+                    serde_json::from_value(serde_json::Value::String(value.trim().to_string()))
+                        .map_err(|e| format!("Invalid value of the `{}` field: {}", name, e))?;
+                request
+                    .timestamp_granularities
+                    .get_or_insert_with(Vec::new)
+                    .push(granularity);
+            }
+            "detect_language" => request.detect_language = Some(parse_field(&name, &value)?),
+            "offset_time" => request.offset_time = Some(parse_field(&name, &value)?),
+            "duration" => request.duration = Some(parse_field(&name, &value)?),
+            "max_context" => request.max_context = Some(parse_field(&name, &value)?),
+            "max_len" => request.max_len = Some(parse_field(&name, &value)?),
+            "split_on_word" => request.split_on_word = Some(parse_field(&name, &value)?),
+            "use_new_context" => request.use_new_context = parse_field(&name, &value)?,
+            _ => {
+                // log
+                warn!(target: "stdout", "Ignore the unsupported field `{}` of the audio transcription request.", name);
+            }
+        }
+    }
+
+    Ok(request)
+}
+
+/// Build an audio translation request from the fields of the multipart form.
+#[cfg(feature = "whisper")]
+fn build_translation_request(
+    file: Option<FileObject>,
+    fields: Vec<(String, String)>,
+) -> Result<TranslationRequest, String> {
+    let mut request = TranslationRequest {
+        file: file.ok_or("The `file` field is required.")?,
+        ..Default::default()
+    };
+
+    for (name, value) in fields {
+        match name.as_str() {
+            "model" => request.model = Some(value),
+            "language" => request.language = Some(value),
+            "prompt" => request.prompt = Some(value),
+            "response_format" => request.response_format = Some(value),
+            "temperature" => request.temperature = Some(parse_field(&name, &value)?),
+            "detect_language" => request.detect_language = Some(parse_field(&name, &value)?),
+            "offset_time" => request.offset_time = Some(parse_field(&name, &value)?),
+            "duration" => request.duration = Some(parse_field(&name, &value)?),
+            "max_context" => request.max_context = Some(parse_field(&name, &value)?),
+            "max_len" => request.max_len = Some(parse_field(&name, &value)?),
+            "split_on_word" => request.split_on_word = Some(parse_field(&name, &value)?),
+            "use_new_context" => request.use_new_context = parse_field(&name, &value)?,
+            _ => {
+                // log
+                warn!(target: "stdout", "Ignore the unsupported field `{}` of the audio translation request.", name);
+            }
+        }
+    }
+
+    Ok(request)
+}
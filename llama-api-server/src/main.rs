@@ -1,11 +1,16 @@
 #[macro_use]
 extern crate log;
 
+mod auth;
 mod backend;
 mod error;
+mod metrics;
+mod ratelimit;
 mod utils;
 
 use anyhow::Result;
+#[cfg(feature = "rag")]
+use chat_prompts::MergeRagContextPolicy;
 use chat_prompts::PromptTemplateType;
 use clap::{ArgGroup, Parser};
 use error::ServerError;
@@ -19,7 +24,13 @@ use hyper::{
 use llama_core::metadata::ggml::GgmlMetadataBuilder;
 use once_cell::sync::OnceCell;
 use serde::{de, Deserialize, Serialize};
-use std::{collections::HashMap, fs, net::SocketAddr, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 use tokio::net::TcpListener;
 use utils::LogLevel;
 
@@ -28,8 +39,19 @@ type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 // server info
 pub(crate) static SERVER_INFO: OnceCell<ServerInfo> = OnceCell::new();
 
-// API key
-pub(crate) static LLAMA_API_KEY: OnceCell<String> = OnceCell::new();
+// admin key, which authorizes the requests to the admin endpoints
+pub(crate) static LLAMA_ADMIN_API_KEY: OnceCell<String> = OnceCell::new();
+
+// the maximum time (in seconds) a request waits in the queue of a model. 0 = no limit
+pub(crate) static QUEUE_TIMEOUT: OnceCell<u64> = OnceCell::new();
+
+// the policy for merging the RAG context into the chat messages
+#[cfg(feature = "rag")]
+pub(crate) static RAG_POLICY: OnceCell<MergeRagContextPolicy> = OnceCell::new();
+
+// the instruction of the RAG context merged into the chat messages
+#[cfg(feature = "rag")]
+pub(crate) static RAG_PROMPT: OnceCell<String> = OnceCell::new();
 
 // default port
 const DEFAULT_PORT: &str = "8080";
@@ -38,6 +60,7 @@ const DEFAULT_PORT: &str = "8080";
 #[command(name = "LlamaEdge API Server", version = env!("CARGO_PKG_VERSION"), author = env!("CARGO_PKG_AUTHORS"), about = "LlamaEdge API Server")]
 #[command(group = ArgGroup::new("socket_address_group").multiple(false).args(&["socket_addr", "port"]))]
 #[command(group = ArgGroup::new("config_group").multiple(false).args(&["config", "prompt_template"]))]
+#[command(group = ArgGroup::new("sd_model_group").multiple(false).args(&["sd_model", "sd_diffusion_model"]))]
 struct Cli {
     /// Sets names for chat and/or embedding models. To run both chat and embedding models, the names should be separated by comma without space, for example, '--model-name Llama-2-7b,all-minilm'. The first value is for the chat model, and the second is for the embedding model.
     #[arg(short, long, value_delimiter = ',', default_value = "default")]
@@ -74,6 +97,9 @@ struct Cli {
     /// Number of tokens to predict, -1 = infinity, -2 = until context filled.
     #[arg(short, long, default_value = "-1")]
     n_predict: i32,
+    /// Number of execution slots of the chat model, i.e., the number of requests served concurrently. The slots share the loaded model, and each of them owns a context of `ctx-size` tokens
+    #[arg(long, default_value = "1")]
+    slots: usize,
     /// Number of layers to run on the GPU
     #[arg(short = 'g', long, default_value = "100")]
     n_gpu_layers: u64,
@@ -116,6 +142,77 @@ struct Cli {
     /// Path to the multimodal projector file
     #[arg(long)]
     llava_mmproj: Option<String>,
+    /// Name of the stable diffusion model, which is used as the `model` field of the image requests
+    #[arg(long, default_value = "default-image")]
+    sd_model_name: String,
+    /// Path to the full stable diffusion model file
+    #[arg(long, group = "sd_model_group")]
+    sd_model: Option<String>,
+    /// Path to the standalone diffusion model file
+    #[arg(long, group = "sd_model_group")]
+    sd_diffusion_model: Option<String>,
+    /// Path to the VAE model file. Used with the standalone diffusion model
+    #[arg(long)]
+    sd_vae: Option<String>,
+    /// Path to the CLIP-L model file. Used with the standalone diffusion model
+    #[arg(long)]
+    sd_clip_l: Option<String>,
+    /// Path to the T5-XXL model file. Used with the standalone diffusion model
+    #[arg(long)]
+    sd_t5xxl: Option<String>,
+    /// Path to the Lora model directory
+    #[arg(long)]
+    sd_lora_model_dir: Option<String>,
+    /// Path to the controlnet model file
+    #[arg(long)]
+    sd_controlnet: Option<String>,
+    /// Keep the controlnet on CPU
+    #[arg(long)]
+    sd_controlnet_on_cpu: bool,
+    /// Keep the CLIP model on CPU
+    #[arg(long)]
+    sd_clip_on_cpu: bool,
+    /// Keep the VAE model on CPU
+    #[arg(long)]
+    sd_vae_on_cpu: bool,
+    /// Number of threads to use during the image generation. -1 = the number of physical CPU cores
+    #[arg(long, default_value = "-1", allow_negative_numbers = true)]
+    sd_threads: i32,
+    /// Task type of the stable diffusion model. Possible values: `full` (both image generation and editing, default), `text2image` (image generation only), `image2image` (image editing only)
+    #[arg(long, default_value = "full", value_parser = ["full", "text2image", "image2image"])]
+    sd_task: String,
+    /// Name of the whisper model, which is used as the `model` field of the audio transcription and translation requests
+    #[arg(long, default_value = "default-whisper")]
+    whisper_model_name: String,
+    /// Path to the whisper model file. Requires the `whisper` feature
+    #[arg(long)]
+    whisper_model: Option<String>,
+    /// Name of the piper voice model, which is used as the `model` field of the audio speech requests
+    #[arg(long, default_value = "default-piper")]
+    piper_model_name: String,
+    /// Path to the piper voice model file
+    #[arg(long)]
+    piper_voice_model: Option<String>,
+    /// Path to the piper voice config file
+    #[arg(long)]
+    piper_voice_config: Option<String>,
+    /// Path to the espeak-ng data directory used by piper
+    #[arg(long)]
+    piper_espeak_ng_data: Option<String>,
+    /// Maximum number of requests waiting in the queue of each chat model. The requests beyond the limit are rejected with 429
+    #[arg(long, default_value = "16")]
+    queue_size: usize,
+    /// Maximum time (in seconds) a request waits in the queue of a chat model before it is rejected with 503. 0 = no limit
+    #[arg(long, default_value = "300")]
+    queue_timeout: u64,
+    /// Policy for merging the context retrieved for the RAG chat completions into the chat messages
+    #[cfg(feature = "rag")]
+    #[arg(long, value_enum, default_value = "system-message")]
+    rag_policy: MergeRagContextPolicy,
+    /// Instruction of the context retrieved for the RAG chat completions, in which `{context}` is replaced by the numbered pieces of context
+    #[cfg(feature = "rag")]
+    #[arg(long)]
+    rag_prompt: Option<String>,
     /// Socket address of LlamaEdge API Server instance. For example, `0.0.0.0:8080`.
     #[arg(long, default_value = None, value_parser = clap::value_parser!(SocketAddr), group = "socket_address_group")]
     socket_addr: Option<SocketAddr>,
@@ -125,6 +222,9 @@ struct Cli {
     /// Path to the configuration file (*.yaml)
     #[arg(long, group = "config_group")]
     config: Option<PathBuf>,
+    /// Path to the API keys file (*.yaml), which lists the API keys with their names, allowed endpoints and allowed models
+    #[arg(long)]
+    api_keys_file: Option<PathBuf>,
     /// Root path for the Web UI files
     #[arg(long, default_value = "chatbot-ui")]
     web_ui: PathBuf,
@@ -155,6 +255,8 @@ struct CliConfig {
     reverse_prompt: Option<String>,
     /// Number of tokens to predict, -1 = infinity, -2 = until context filled.
     n_predict: i32,
+    /// Number of execution slots of the chat model, i.e., the number of requests served concurrently. The slots share the loaded model, and each of them owns a context of `ctx-size` tokens
+    slots: usize,
     /// Number of layers to run on the GPU
     n_gpu_layers: u64,
     /// Split the model across multiple GPUs. Possible values:
@@ -186,8 +288,64 @@ struct CliConfig {
     json_schema: Option<String>,
     /// Path to the multimodal projector file
     llava_mmproj: Option<String>,
+    /// Name of the stable diffusion model, which is used as the `model` field of the image requests
+    sd_model_name: String,
+    /// Path to the full stable diffusion model file
+    sd_model: Option<String>,
+    /// Path to the standalone diffusion model file
+    sd_diffusion_model: Option<String>,
+    /// Path to the VAE model file. Used with the standalone diffusion model
+    sd_vae: Option<String>,
+    /// Path to the CLIP-L model file. Used with the standalone diffusion model
+    sd_clip_l: Option<String>,
+    /// Path to the T5-XXL model file. Used with the standalone diffusion model
+    sd_t5xxl: Option<String>,
+    /// Path to the Lora model directory
+    sd_lora_model_dir: Option<String>,
+    /// Path to the controlnet model file
+    sd_controlnet: Option<String>,
+    /// Keep the controlnet on CPU
+    sd_controlnet_on_cpu: bool,
+    /// Keep the CLIP model on CPU
+    sd_clip_on_cpu: bool,
+    /// Keep the VAE model on CPU
+    sd_vae_on_cpu: bool,
+    /// Number of threads to use during the image generation. -1 = the number of physical CPU cores
+    sd_threads: i32,
+    /// Task type of the stable diffusion model. Possible values: `full` (both image generation and editing, default), `text2image` (image generation only), `image2image` (image editing only)
+    sd_task: String,
+    /// Name of the whisper model, which is used as the `model` field of the audio transcription and translation requests
+    whisper_model_name: String,
+    /// Path to the whisper model file. Requires the `whisper` feature
+    whisper_model: Option<String>,
+    /// Name of the piper voice model, which is used as the `model` field of the audio speech requests
+    piper_model_name: String,
+    /// Path to the piper voice model file
+    piper_voice_model: Option<String>,
+    /// Path to the piper voice config file
+    piper_voice_config: Option<String>,
+    /// Path to the espeak-ng data directory used by piper
+    piper_espeak_ng_data: Option<String>,
+    /// Maximum number of requests waiting in the queue of each chat model. The requests beyond the limit are rejected with 429
+    queue_size: usize,
+    /// Maximum time (in seconds) a request waits in the queue of a chat model before it is rejected with 503. 0 = no limit
+    queue_timeout: u64,
     /// Socket address of LlamaEdge API Server instance. For example, `0.0.0.0:8080`.
     socket_addr: Option<SocketAddr>,
+    /// Chat and embedding models to serve. If not empty, the models are served instead of the ones set by `model-name` and `prompt-template`
+    models: Vec<ModelEntry>,
+    /// Load the models in `models` on the first request to them rather than at startup
+    lazy_load: bool,
+    /// Evict the models loaded on demand which have not been used for the given time (in seconds). 0 = never
+    idle_timeout: u64,
+    /// Maximum total size (in MiB) of the model files loaded on demand. The least recently used models are evicted to stay within the budget. 0 = no limit
+    memory_budget: u64,
+    /// Path to the API keys file (*.yaml)
+    api_keys_file: Option<PathBuf>,
+    /// API keys with their names, allowed endpoints and allowed models
+    api_keys: Vec<auth::ApiKey>,
+    /// Requests-per-minute and tokens-per-minute limits of each API key and each client IP address
+    rate_limit: ratelimit::RateLimitConfig,
 }
 impl<'de> Deserialize<'de> for CliConfig {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -204,6 +362,7 @@ impl<'de> Deserialize<'de> for CliConfig {
             PromptTemplate,
             ReversePrompt,
             NPredict,
+            Slots,
             NGpuLayers,
             SplitMode,
             MainGpu,
@@ -218,7 +377,35 @@ impl<'de> Deserialize<'de> for CliConfig {
             Grammar,
             JsonSchema,
             LlavaMmproj,
+            SdModelName,
+            SdModel,
+            SdDiffusionModel,
+            SdVae,
+            SdClipL,
+            SdT5xxl,
+            SdLoraModelDir,
+            SdControlnet,
+            SdControlnetOnCpu,
+            SdClipOnCpu,
+            SdVaeOnCpu,
+            SdThreads,
+            SdTask,
+            WhisperModelName,
+            WhisperModel,
+            PiperModelName,
+            PiperVoiceModel,
+            PiperVoiceConfig,
+            PiperEspeakNgData,
+            QueueSize,
+            QueueTimeout,
             SocketAddr,
+            Models,
+            LazyLoad,
+            IdleTimeout,
+            MemoryBudget,
+            ApiKeysFile,
+            ApiKeys,
+            RateLimit,
         }
 
         struct CliConfigVisitor;
@@ -241,6 +428,7 @@ impl<'de> Deserialize<'de> for CliConfig {
                 let mut prompt_template: Option<Vec<String>> = None;
                 let mut reverse_prompt = None;
                 let mut n_predict = None;
+                let mut slots = None;
                 let mut n_gpu_layers = None;
                 let mut split_mode = None;
                 let mut main_gpu = None;
@@ -255,7 +443,35 @@ impl<'de> Deserialize<'de> for CliConfig {
                 let mut grammar: Option<String> = None;
                 let mut json_schema = None;
                 let mut llava_mmproj = None;
+                let mut sd_model_name: Option<String> = None;
+                let mut sd_model = None;
+                let mut sd_diffusion_model = None;
+                let mut sd_vae = None;
+                let mut sd_clip_l = None;
+                let mut sd_t5xxl = None;
+                let mut sd_lora_model_dir = None;
+                let mut sd_controlnet = None;
+                let mut sd_controlnet_on_cpu = None;
+                let mut sd_clip_on_cpu = None;
+                let mut sd_vae_on_cpu = None;
+                let mut sd_threads = None;
+                let mut sd_task: Option<String> = None;
+                let mut whisper_model_name: Option<String> = None;
+                let mut whisper_model = None;
+                let mut piper_model_name: Option<String> = None;
+                let mut piper_voice_model = None;
+                let mut piper_voice_config = None;
+                let mut piper_espeak_ng_data = None;
+                let mut queue_size = None;
+                let mut queue_timeout = None;
                 let mut socket_addr: Option<SocketAddr> = None;
+                let mut models: Option<Vec<ModelEntry>> = None;
+                let mut lazy_load = None;
+                let mut idle_timeout = None;
+                let mut memory_budget = None;
+                let mut api_keys_file = None;
+                let mut api_keys = None;
+                let mut rate_limit = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -308,6 +524,13 @@ impl<'de> Deserialize<'de> for CliConfig {
 
                             n_predict = Some(map.next_value()?)
                         }
+                        Field::Slots => {
+                            if slots.is_some() {
+                                return Err(de::Error::duplicate_field("slots"));
+                            }
+
+                            slots = Some(map.next_value()?)
+                        }
                         Field::NGpuLayers => {
                             if n_gpu_layers.is_some() {
                                 return Err(de::Error::duplicate_field("n-gpu-layers"));
@@ -406,6 +629,153 @@ impl<'de> Deserialize<'de> for CliConfig {
 
                             llava_mmproj = Some(map.next_value()?)
                         }
+                        Field::SdModelName => {
+                            if sd_model_name.is_some() {
+                                return Err(de::Error::duplicate_field("sd-model-name"));
+                            }
+
+                            sd_model_name = Some(map.next_value()?)
+                        }
+                        Field::SdModel => {
+                            if sd_model.is_some() {
+                                return Err(de::Error::duplicate_field("sd-model"));
+                            }
+
+                            sd_model = Some(map.next_value()?)
+                        }
+                        Field::SdDiffusionModel => {
+                            if sd_diffusion_model.is_some() {
+                                return Err(de::Error::duplicate_field("sd-diffusion-model"));
+                            }
+
+                            sd_diffusion_model = Some(map.next_value()?)
+                        }
+                        Field::SdVae => {
+                            if sd_vae.is_some() {
+                                return Err(de::Error::duplicate_field("sd-vae"));
+                            }
+
+                            sd_vae = Some(map.next_value()?)
+                        }
+                        Field::SdClipL => {
+                            if sd_clip_l.is_some() {
+                                return Err(de::Error::duplicate_field("sd-clip-l"));
+                            }
+
+                            sd_clip_l = Some(map.next_value()?)
+                        }
+                        Field::SdT5xxl => {
+                            if sd_t5xxl.is_some() {
+                                return Err(de::Error::duplicate_field("sd-t5xxl"));
+                            }
+
+                            sd_t5xxl = Some(map.next_value()?)
+                        }
+                        Field::SdLoraModelDir => {
+                            if sd_lora_model_dir.is_some() {
+                                return Err(de::Error::duplicate_field("sd-lora-model-dir"));
+                            }
+
+                            sd_lora_model_dir = Some(map.next_value()?)
+                        }
+                        Field::SdControlnet => {
+                            if sd_controlnet.is_some() {
+                                return Err(de::Error::duplicate_field("sd-controlnet"));
+                            }
+
+                            sd_controlnet = Some(map.next_value()?)
+                        }
+                        Field::SdControlnetOnCpu => {
+                            if sd_controlnet_on_cpu.is_some() {
+                                return Err(de::Error::duplicate_field("sd-controlnet-on-cpu"));
+                            }
+
+                            sd_controlnet_on_cpu = Some(map.next_value()?)
+                        }
+                        Field::SdClipOnCpu => {
+                            if sd_clip_on_cpu.is_some() {
+                                return Err(de::Error::duplicate_field("sd-clip-on-cpu"));
+                            }
+
+                            sd_clip_on_cpu = Some(map.next_value()?)
+                        }
+                        Field::SdVaeOnCpu => {
+                            if sd_vae_on_cpu.is_some() {
+                                return Err(de::Error::duplicate_field("sd-vae-on-cpu"));
+                            }
+
+                            sd_vae_on_cpu = Some(map.next_value()?)
+                        }
+                        Field::SdThreads => {
+                            if sd_threads.is_some() {
+                                return Err(de::Error::duplicate_field("sd-threads"));
+                            }
+
+                            sd_threads = Some(map.next_value()?)
+                        }
+                        Field::SdTask => {
+                            if sd_task.is_some() {
+                                return Err(de::Error::duplicate_field("sd-task"));
+                            }
+
+                            sd_task = Some(map.next_value()?)
+                        }
+                        Field::WhisperModelName => {
+                            if whisper_model_name.is_some() {
+                                return Err(de::Error::duplicate_field("whisper-model-name"));
+                            }
+
+                            whisper_model_name = Some(map.next_value()?)
+                        }
+                        Field::WhisperModel => {
+                            if whisper_model.is_some() {
+                                return Err(de::Error::duplicate_field("whisper-model"));
+                            }
+
+                            whisper_model = Some(map.next_value()?)
+                        }
+                        Field::PiperModelName => {
+                            if piper_model_name.is_some() {
+                                return Err(de::Error::duplicate_field("piper-model-name"));
+                            }
+
+                            piper_model_name = Some(map.next_value()?)
+                        }
+                        Field::PiperVoiceModel => {
+                            if piper_voice_model.is_some() {
+                                return Err(de::Error::duplicate_field("piper-voice-model"));
+                            }
+
+                            piper_voice_model = Some(map.next_value()?)
+                        }
+                        Field::PiperVoiceConfig => {
+                            if piper_voice_config.is_some() {
+                                return Err(de::Error::duplicate_field("piper-voice-config"));
+                            }
+
+                            piper_voice_config = Some(map.next_value()?)
+                        }
+                        Field::PiperEspeakNgData => {
+                            if piper_espeak_ng_data.is_some() {
+                                return Err(de::Error::duplicate_field("piper-espeak-ng-data"));
+                            }
+
+                            piper_espeak_ng_data = Some(map.next_value()?)
+                        }
+                        Field::QueueSize => {
+                            if queue_size.is_some() {
+                                return Err(de::Error::duplicate_field("queue-size"));
+                            }
+
+                            queue_size = Some(map.next_value()?)
+                        }
+                        Field::QueueTimeout => {
+                            if queue_timeout.is_some() {
+                                return Err(de::Error::duplicate_field("queue-timeout"));
+                            }
+
+                            queue_timeout = Some(map.next_value()?)
+                        }
                         Field::SocketAddr => {
                             if socket_addr.is_some() {
                                 return Err(de::Error::duplicate_field("socket-addr"));
@@ -413,6 +783,55 @@ impl<'de> Deserialize<'de> for CliConfig {
 
                             socket_addr = Some(map.next_value()?)
                         }
+                        Field::Models => {
+                            if models.is_some() {
+                                return Err(de::Error::duplicate_field("models"));
+                            }
+
+                            models = Some(map.next_value()?)
+                        }
+                        Field::LazyLoad => {
+                            if lazy_load.is_some() {
+                                return Err(de::Error::duplicate_field("lazy-load"));
+                            }
+
+                            lazy_load = Some(map.next_value()?)
+                        }
+                        Field::IdleTimeout => {
+                            if idle_timeout.is_some() {
+                                return Err(de::Error::duplicate_field("idle-timeout"));
+                            }
+
+                            idle_timeout = Some(map.next_value()?)
+                        }
+                        Field::MemoryBudget => {
+                            if memory_budget.is_some() {
+                                return Err(de::Error::duplicate_field("memory-budget"));
+                            }
+
+                            memory_budget = Some(map.next_value()?)
+                        }
+                        Field::ApiKeysFile => {
+                            if api_keys_file.is_some() {
+                                return Err(de::Error::duplicate_field("api-keys-file"));
+                            }
+
+                            api_keys_file = Some(map.next_value()?)
+                        }
+                        Field::ApiKeys => {
+                            if api_keys.is_some() {
+                                return Err(de::Error::duplicate_field("api-keys"));
+                            }
+
+                            api_keys = Some(map.next_value()?)
+                        }
+                        Field::RateLimit => {
+                            if rate_limit.is_some() {
+                                return Err(de::Error::duplicate_field("rate-limit"));
+                            }
+
+                            rate_limit = Some(map.next_value()?)
+                        }
                     }
                 }
 
@@ -426,17 +845,32 @@ impl<'de> Deserialize<'de> for CliConfig {
 
                 let batch_size = batch_size.unwrap_or_else(|| vec![512, 512]);
 
+                let models = models.unwrap_or_default();
+
                 let prompt_template: Vec<PromptTemplateType> = match prompt_template {
                     Some(prompt_template) => {
                         prompt_template.iter().map(|p| p.parse().unwrap()).collect()
                     }
+                    None if !models.is_empty() => vec![],
                     None => return Err(de::Error::missing_field("prompt-template")),
                 };
 
+                let mut model_names = std::collections::HashSet::new();
+                for model in models.iter() {
+                    if !model_names.insert(model.name.as_str()) {
+                        return Err(de::Error::custom(format!(
+                            "duplicate model name `{}` in `models`",
+                            model.name
+                        )));
+                    }
+                }
+
                 let reverse_prompt = reverse_prompt.unwrap_or_default();
 
                 let n_predict = n_predict.unwrap_or(-1);
 
+                let slots = slots.unwrap_or(1);
+
                 let n_gpu_layers = n_gpu_layers.unwrap_or(100);
 
                 let split_mode = split_mode.unwrap_or("layer".to_string());
@@ -463,6 +897,55 @@ impl<'de> Deserialize<'de> for CliConfig {
 
                 let llava_mmproj = llava_mmproj.unwrap();
 
+                let sd_model_name = sd_model_name.unwrap_or("default-image".to_string());
+
+                if sd_model.is_some() && sd_diffusion_model.is_some() {
+                    return Err(de::Error::custom(
+                        "`sd-model` and `sd-diffusion-model` cannot be set at the same time",
+                    ));
+                }
+
+                let sd_controlnet_on_cpu = sd_controlnet_on_cpu.unwrap_or(false);
+
+                let sd_clip_on_cpu = sd_clip_on_cpu.unwrap_or(false);
+
+                let sd_vae_on_cpu = sd_vae_on_cpu.unwrap_or(false);
+
+                let sd_threads = sd_threads.unwrap_or(-1);
+
+                let sd_task = sd_task.unwrap_or("full".to_string());
+
+                let whisper_model_name =
+                    whisper_model_name.unwrap_or("default-whisper".to_string());
+
+                let piper_model_name = piper_model_name.unwrap_or("default-piper".to_string());
+
+                if piper_voice_model.is_some()
+                    && (piper_voice_config.is_none() || piper_espeak_ng_data.is_none())
+                {
+                    return Err(de::Error::custom(
+                        "`piper-voice-model` requires `piper-voice-config` and `piper-espeak-ng-data`",
+                    ));
+                }
+
+                let queue_size = queue_size.unwrap_or(llama_core::scheduler::DEFAULT_QUEUE_SIZE);
+
+                let queue_timeout = queue_timeout.unwrap_or(300);
+
+                let lazy_load = lazy_load.unwrap_or(false);
+
+                let idle_timeout = idle_timeout.unwrap_or(0);
+
+                let memory_budget = memory_budget.unwrap_or(0);
+
+                let api_keys = api_keys.unwrap_or_default();
+
+                let rate_limit = rate_limit.unwrap_or_default();
+
+                if lazy_load && models.is_empty() {
+                    return Err(de::Error::custom("`lazy-load` requires `models`"));
+                }
+
                 Ok(CliConfig {
                     model_name,
                     model_alias,
@@ -471,6 +954,7 @@ impl<'de> Deserialize<'de> for CliConfig {
                     prompt_template,
                     reverse_prompt,
                     n_predict,
+                    slots,
                     n_gpu_layers,
                     split_mode,
                     main_gpu,
@@ -485,7 +969,35 @@ impl<'de> Deserialize<'de> for CliConfig {
                     grammar,
                     json_schema,
                     llava_mmproj,
+                    sd_model_name,
+                    sd_model,
+                    sd_diffusion_model,
+                    sd_vae,
+                    sd_clip_l,
+                    sd_t5xxl,
+                    sd_lora_model_dir,
+                    sd_controlnet,
+                    sd_controlnet_on_cpu,
+                    sd_clip_on_cpu,
+                    sd_vae_on_cpu,
+                    sd_threads,
+                    sd_task,
+                    whisper_model_name,
+                    whisper_model,
+                    piper_model_name,
+                    piper_voice_model,
+                    piper_voice_config,
+                    piper_espeak_ng_data,
+                    queue_size,
+                    queue_timeout,
                     socket_addr,
+                    models,
+                    lazy_load,
+                    idle_timeout,
+                    memory_budget,
+                    api_keys_file,
+                    api_keys,
+                    rate_limit,
                 })
             }
         }
@@ -498,6 +1010,7 @@ impl<'de> Deserialize<'de> for CliConfig {
             "prompt-template",
             "reverse-prompt",
             "n-predict",
+            "slots",
             "n-gpu-layers",
             "split-mode",
             "main-gpu",
@@ -512,13 +1025,101 @@ impl<'de> Deserialize<'de> for CliConfig {
             "grammar",
             "json-schema",
             "llava-mmproj",
+            "sd-model-name",
+            "sd-model",
+            "sd-diffusion-model",
+            "sd-vae",
+            "sd-clip-l",
+            "sd-t5xxl",
+            "sd-lora-model-dir",
+            "sd-controlnet",
+            "sd-controlnet-on-cpu",
+            "sd-clip-on-cpu",
+            "sd-vae-on-cpu",
+            "sd-threads",
+            "sd-task",
+            "whisper-model-name",
+            "whisper-model",
+            "piper-model-name",
+            "piper-voice-model",
+            "piper-voice-config",
+            "piper-espeak-ng-data",
+            "queue-size",
+            "queue-timeout",
             "socket-addr",
+            "models",
+            "lazy-load",
+            "idle-timeout",
+            "memory-budget",
+            "api-keys-file",
+            "api-keys",
+            "rate-limit",
         ];
 
         deserializer.deserialize_struct("CliConfig", FIELDS, CliConfigVisitor)
     }
 }
 
+/// A chat or embedding model declared in the `models` section of the config file. The options not set fall back to the top-level ones of the config file.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ModelEntry {
+    /// Name of the model, which is used as the `model` field of the requests
+    name: String,
+    /// Alias of the model preloaded by `--nn-preload`. Defaults to the name of the model
+    alias: Option<String>,
+    /// Path to the model file. If set, the model is loaded from the file instead of the model preloaded by `--nn-preload`
+    path: Option<String>,
+    /// Prompt template of the model. The model is an embedding model if the prompt template is `embedding`; otherwise, it is a chat model
+    #[serde(deserialize_with = "deserialize_prompt_template")]
+    prompt_template: PromptTemplateType,
+    /// Context size of the model
+    ctx_size: Option<u64>,
+    /// Batch size of the model
+    batch_size: Option<u64>,
+    /// Physical maximum batch size of the model
+    ubatch_size: Option<u64>,
+    /// Number of tokens to predict, -1 = infinity, -2 = until context filled.
+    n_predict: Option<i32>,
+    /// Number of execution slots of the chat model
+    slots: Option<usize>,
+    /// Maximum number of requests waiting in the queue of the chat model
+    queue_size: Option<usize>,
+    /// Halt generation at PROMPT, return control.
+    reverse_prompt: Option<String>,
+    /// Number of layers to run on the GPU
+    n_gpu_layers: Option<u64>,
+    /// Temperature for sampling
+    temp: Option<f64>,
+    /// Nucleus sampling probability mass. 1.0 = disabled
+    top_p: Option<f64>,
+    /// Penalize repeat sequence of tokens
+    repeat_penalty: Option<f64>,
+    /// Repeat alpha presence penalty. 0.0 = disabled
+    presence_penalty: Option<f64>,
+    /// Repeat alpha frequency penalty. 0.0 = disabled
+    frequency_penalty: Option<f64>,
+    /// BNF-like grammar to constrain generations
+    grammar: Option<String>,
+    /// JSON schema to constrain generations
+    json_schema: Option<String>,
+    /// Path to the multimodal projector file
+    llava_mmproj: Option<String>,
+}
+impl ModelEntry {
+    fn is_embedding(&self) -> bool {
+        self.prompt_template == PromptTemplateType::Embedding
+    }
+}
+
+fn deserialize_prompt_template<'de, D>(deserializer: D) -> Result<PromptTemplateType, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let prompt_template = String::deserialize(deserializer)?;
+    prompt_template.parse().map_err(de::Error::custom)
+}
+
 #[allow(clippy::needless_return)]
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), ServerError> {
@@ -537,10 +1138,10 @@ async fn main() -> Result<(), ServerError> {
     wasi_logger::Logger::install().expect("failed to install wasi_logger::Logger");
     log::set_max_level(log_level.into());
 
-    if let Ok(api_key) = std::env::var("API_KEY") {
-        // define a const variable for the API key
-        if let Err(e) = LLAMA_API_KEY.set(api_key) {
-            let err_msg = format!("Failed to set API key. {}", e);
+    if let Ok(admin_api_key) = std::env::var("ADMIN_API_KEY") {
+        // define a const variable for the admin key
+        if let Err(e) = LLAMA_ADMIN_API_KEY.set(admin_api_key) {
+            let err_msg = format!("Failed to set admin key. {}", e);
 
             error!(target: "stdout", "{}", err_msg);
 
@@ -556,6 +1157,13 @@ async fn main() -> Result<(), ServerError> {
     // log the version of the server
     info!(target: "stdout", "server version: {}", env!("CARGO_PKG_VERSION"));
 
+    // the models declared in the `models` section of the config file
+    let mut model_entries: Vec<ModelEntry> = vec![];
+    let mut lazy_load = false;
+    let mut idle_timeout = 0;
+    let mut memory_budget = 0;
+    let mut api_keys = vec![];
+    let mut rate_limit = ratelimit::RateLimitConfig::default();
     if let Some(config_file) = &cli.config {
         match config_file.exists() {
             true => {
@@ -591,6 +1199,7 @@ async fn main() -> Result<(), ServerError> {
                 cli.prompt_template = config.prompt_template;
                 cli.reverse_prompt = config.reverse_prompt;
                 cli.n_predict = config.n_predict;
+                cli.slots = config.slots;
                 cli.n_gpu_layers = config.n_gpu_layers;
                 cli.split_mode = config.split_mode;
                 cli.main_gpu = config.main_gpu;
@@ -605,7 +1214,37 @@ async fn main() -> Result<(), ServerError> {
                 cli.grammar = config.grammar.unwrap_or_default();
                 cli.json_schema = config.json_schema;
                 cli.llava_mmproj = config.llava_mmproj;
+                cli.sd_model_name = config.sd_model_name;
+                cli.sd_model = config.sd_model;
+                cli.sd_diffusion_model = config.sd_diffusion_model;
+                cli.sd_vae = config.sd_vae;
+                cli.sd_clip_l = config.sd_clip_l;
+                cli.sd_t5xxl = config.sd_t5xxl;
+                cli.sd_lora_model_dir = config.sd_lora_model_dir;
+                cli.sd_controlnet = config.sd_controlnet;
+                cli.sd_controlnet_on_cpu = config.sd_controlnet_on_cpu;
+                cli.sd_clip_on_cpu = config.sd_clip_on_cpu;
+                cli.sd_vae_on_cpu = config.sd_vae_on_cpu;
+                cli.sd_threads = config.sd_threads;
+                cli.sd_task = config.sd_task;
+                cli.whisper_model_name = config.whisper_model_name;
+                cli.whisper_model = config.whisper_model;
+                cli.piper_model_name = config.piper_model_name;
+                cli.piper_voice_model = config.piper_voice_model;
+                cli.piper_voice_config = config.piper_voice_config;
+                cli.piper_espeak_ng_data = config.piper_espeak_ng_data;
+                cli.queue_size = config.queue_size;
+                cli.queue_timeout = config.queue_timeout;
                 cli.socket_addr = config.socket_addr;
+                model_entries = config.models;
+                lazy_load = config.lazy_load;
+                idle_timeout = config.idle_timeout;
+                memory_budget = config.memory_budget;
+                if config.api_keys_file.is_some() {
+                    cli.api_keys_file = config.api_keys_file;
+                }
+                api_keys = config.api_keys;
+                rate_limit = config.rate_limit;
             }
             false => {
                 let err_msg = format!("Config file not found: {}", config_file.display());
@@ -617,6 +1256,42 @@ async fn main() -> Result<(), ServerError> {
         }
     }
 
+    // set the API keys
+    if let Some(api_keys_file) = &cli.api_keys_file {
+        let keys = auth::read_api_keys_file(api_keys_file).map_err(|err_msg| {
+            error!(target: "stdout", "{}", &err_msg);
+
+            ServerError::Operation(err_msg)
+        })?;
+        api_keys.extend(keys);
+    }
+    if let Ok(api_key) = std::env::var("API_KEY") {
+        // the key set by the `API_KEY` environment variable is allowed to access all endpoints and models
+        api_keys.push(auth::ApiKey {
+            key: api_key,
+            name: Some("default".to_string()),
+            endpoints: vec![],
+            models: vec![],
+            rpm: None,
+            tpm: None,
+        });
+    }
+    // log the number of the API keys rather than the keys themselves
+    info!(target: "stdout", "api_keys: {}", api_keys.len());
+    auth::init_api_keys(api_keys).map_err(|err_msg| {
+        error!(target: "stdout", "{}", &err_msg);
+
+        ServerError::Operation(err_msg)
+    })?;
+
+    // set the rate limits
+    info!(target: "stdout", "rate_limit: {:?}", &rate_limit);
+    ratelimit::init_rate_limits(rate_limit).map_err(|err_msg| {
+        error!(target: "stdout", "{}", &err_msg);
+
+        ServerError::Operation(err_msg)
+    })?;
+
     // log model names
     if cli.model_name.is_empty() && cli.model_name.len() > 2 {
         return Err(ServerError::ArgumentError(
@@ -699,7 +1374,7 @@ async fn main() -> Result<(), ServerError> {
         .collect::<Vec<String>>()
         .join(",");
     info!(target: "stdout", "prompt_template: {}", prompt_template_str);
-    if cli.model_name.len() != cli.prompt_template.len() {
+    if model_entries.is_empty() && cli.model_name.len() != cli.prompt_template.len() {
         return Err(ServerError::ArgumentError(
             "The number of model names and prompt templates must be the same.".to_owned(),
         ));
@@ -713,6 +1388,9 @@ async fn main() -> Result<(), ServerError> {
     // log n_predict
     info!(target: "stdout", "n_predict: {}", cli.n_predict);
 
+    // log slots
+    info!(target: "stdout", "slots: {}", cli.slots);
+
     // log n_gpu_layers
     info!(target: "stdout", "n_gpu_layers: {}", cli.n_gpu_layers);
 
@@ -770,7 +1448,141 @@ async fn main() -> Result<(), ServerError> {
     // initialize the core context
     let mut chat_model_config = None;
     let mut embedding_model_config = None;
-    if cli.prompt_template.len() == 1 {
+    let mut chat_model_configs = vec![];
+    let mut embedding_model_configs = vec![];
+    if !model_entries.is_empty() {
+        let mut metadata_chats = vec![];
+        let mut metadata_embeddings = vec![];
+        let mut deferred_models = vec![];
+        for entry in model_entries.iter() {
+            // log the model
+            let ty = match entry.is_embedding() {
+                true => "embedding",
+                false => "chat",
+            };
+            info!(target: "stdout", "model: {}, type: {}, prompt_template: {}", &entry.name, ty, entry.prompt_template);
+
+            let metadata = build_model_metadata(entry, &cli, plugin_debug);
+
+            match entry.is_embedding() {
+                true => {
+                    embedding_model_configs.push(ModelConfig {
+                        name: metadata.model_name.clone(),
+                        ty: "embedding".to_string(),
+                        ctx_size: metadata.ctx_size,
+                        batch_size: metadata.batch_size,
+                        ubatch_size: metadata.ubatch_size,
+                        prompt_template: Some(PromptTemplateType::Embedding),
+                        n_predict: Some(metadata.n_predict),
+                        slots: None,
+                        reverse_prompt: metadata.reverse_prompt.clone(),
+                        n_gpu_layers: Some(metadata.n_gpu_layers),
+                        use_mmap: metadata.use_mmap,
+                        temperature: Some(metadata.temperature),
+                        top_p: Some(metadata.top_p),
+                        repeat_penalty: Some(metadata.repeat_penalty),
+                        presence_penalty: Some(metadata.presence_penalty),
+                        frequency_penalty: Some(metadata.frequency_penalty),
+                        split_mode: Some(metadata.split_mode.clone()),
+                        main_gpu: metadata.main_gpu,
+                        tensor_split: metadata.tensor_split.clone(),
+                    });
+                }
+                false => {
+                    if let Some(queue_size) = entry.queue_size {
+                        llama_core::scheduler::set_queue_size(&entry.name, queue_size)
+                            .map_err(|e| ServerError::Operation(format!("{}", e)))?;
+                    }
+
+                    chat_model_configs.push(ModelConfig {
+                        name: metadata.model_name.clone(),
+                        ty: "chat".to_string(),
+                        ctx_size: metadata.ctx_size,
+                        batch_size: metadata.batch_size,
+                        ubatch_size: metadata.ubatch_size,
+                        prompt_template: Some(metadata.prompt_template),
+                        n_predict: Some(metadata.n_predict),
+                        slots: Some(metadata.slots),
+                        reverse_prompt: metadata.reverse_prompt.clone(),
+                        n_gpu_layers: Some(metadata.n_gpu_layers),
+                        use_mmap: metadata.use_mmap,
+                        temperature: Some(metadata.temperature),
+                        top_p: Some(metadata.top_p),
+                        repeat_penalty: Some(metadata.repeat_penalty),
+                        presence_penalty: Some(metadata.presence_penalty),
+                        frequency_penalty: Some(metadata.frequency_penalty),
+                        split_mode: Some(metadata.split_mode.clone()),
+                        main_gpu: metadata.main_gpu,
+                        tensor_split: metadata.tensor_split.clone(),
+                    });
+                }
+            }
+
+            // the models loaded on demand or from the model files are handled after the core context is initialized
+            if lazy_load || entry.path.is_some() {
+                deferred_models.push((metadata, entry.path.as_ref().map(PathBuf::from)));
+            } else if entry.is_embedding() {
+                metadata_embeddings.push(metadata);
+            } else {
+                metadata_chats.push(metadata);
+            }
+        }
+
+        // the first chat and embedding models are the default ones
+        chat_model_config = chat_model_configs.first().cloned();
+        embedding_model_config = embedding_model_configs.first().cloned();
+
+        // initialize the core context
+        if !metadata_chats.is_empty() || !metadata_embeddings.is_empty() {
+            llama_core::init_ggml_context(
+                (!metadata_chats.is_empty()).then_some(metadata_chats.as_slice()),
+                (!metadata_embeddings.is_empty()).then_some(metadata_embeddings.as_slice()),
+            )
+            .map_err(|e| ServerError::Operation(format!("{}", e)))?;
+        }
+
+        // load the models from the model files, or register the models to be loaded on demand
+        for (metadata, path) in deferred_models {
+            let result = match (lazy_load, path) {
+                (false, Some(path)) => llama_core::models::load_model(path, metadata).map(|_| ()),
+                (_, path) => llama_core::models::register_model(metadata, path),
+            };
+            result.map_err(|e| ServerError::Operation(format!("{}", e)))?;
+        }
+
+        if lazy_load {
+            // log eviction policy
+            info!(target: "stdout", "lazy_load: true, idle_timeout: {}, memory_budget: {}", idle_timeout, memory_budget);
+
+            let policy = llama_core::models::EvictionPolicy {
+                idle_timeout: (idle_timeout > 0).then(|| Duration::from_secs(idle_timeout)),
+                memory_budget: (memory_budget > 0).then(|| memory_budget * 1024 * 1024),
+            };
+            llama_core::models::set_eviction_policy(policy)
+                .map_err(|e| ServerError::Operation(format!("{}", e)))?;
+
+            // evict the idle models periodically
+            if idle_timeout > 0 {
+                tokio::spawn(async move {
+                    let mut interval =
+                        tokio::time::interval(Duration::from_secs(idle_timeout.clamp(1, 60)));
+                    loop {
+                        interval.tick().await;
+
+                        match llama_core::models::evict_idle_models() {
+                            Ok(evicted) if !evicted.is_empty() => {
+                                info!(target: "stdout", "evicted idle models: {}", evicted.join(","));
+                            }
+                            Ok(_) => {}
+                            Err(e) => {
+                                error!(target: "stdout", "Failed to evict idle models. {}", e);
+                            }
+                        }
+                    }
+                });
+            }
+        }
+    } else if cli.prompt_template.len() == 1 {
         match cli.prompt_template[0] {
             PromptTemplateType::Embedding => {
                 // create a Metadata instance
@@ -799,6 +1611,7 @@ async fn main() -> Result<(), ServerError> {
                     ubatch_size: metadata_embedding.ubatch_size,
                     prompt_template: Some(PromptTemplateType::Embedding),
                     n_predict: Some(cli.n_predict),
+                    slots: None,
                     reverse_prompt: metadata_embedding.reverse_prompt.clone(),
                     n_gpu_layers: Some(metadata_embedding.n_gpu_layers),
                     use_mmap: metadata_embedding.use_mmap,
@@ -827,6 +1640,7 @@ async fn main() -> Result<(), ServerError> {
                 .with_batch_size(cli.batch_size[0])
                 .with_ubatch_size(cli.ubatch_size[0])
                 .with_n_predict(cli.n_predict)
+                .with_slots(cli.slots)
                 .with_n_gpu_layers(cli.n_gpu_layers)
                 .with_split_mode(cli.split_mode)
                 .with_main_gpu(cli.main_gpu)
@@ -855,6 +1669,7 @@ async fn main() -> Result<(), ServerError> {
                     ubatch_size: metadata_chat.ubatch_size,
                     prompt_template: Some(metadata_chat.prompt_template),
                     n_predict: Some(metadata_chat.n_predict),
+                    slots: Some(metadata_chat.slots),
                     reverse_prompt: metadata_chat.reverse_prompt.clone(),
                     n_gpu_layers: Some(metadata_chat.n_gpu_layers),
                     use_mmap: metadata_chat.use_mmap,
@@ -884,6 +1699,7 @@ async fn main() -> Result<(), ServerError> {
         .with_batch_size(cli.batch_size[0])
         .with_ubatch_size(cli.ubatch_size[0])
         .with_n_predict(cli.n_predict)
+        .with_slots(cli.slots)
         .with_n_gpu_layers(cli.n_gpu_layers)
         .with_split_mode(cli.split_mode.clone())
         .with_main_gpu(cli.main_gpu)
@@ -912,6 +1728,7 @@ async fn main() -> Result<(), ServerError> {
             ubatch_size: metadata_chat.ubatch_size,
             prompt_template: Some(metadata_chat.prompt_template),
             n_predict: Some(metadata_chat.n_predict),
+            slots: Some(metadata_chat.slots),
             reverse_prompt: metadata_chat.reverse_prompt.clone(),
             n_gpu_layers: Some(metadata_chat.n_gpu_layers),
             use_mmap: metadata_chat.use_mmap,
@@ -951,6 +1768,7 @@ async fn main() -> Result<(), ServerError> {
             ubatch_size: metadata_embedding.ubatch_size,
             prompt_template: Some(PromptTemplateType::Embedding),
             n_predict: Some(cli.n_predict),
+            slots: None,
             reverse_prompt: metadata_embedding.reverse_prompt.clone(),
             n_gpu_layers: Some(metadata_embedding.n_gpu_layers),
             use_mmap: metadata_embedding.use_mmap,
@@ -969,14 +1787,221 @@ async fn main() -> Result<(), ServerError> {
             .map_err(|e| ServerError::Operation(format!("{}", e)))?;
     }
 
+    // initialize the stable diffusion context
+    let mut image_model_config = None;
+    if cli.sd_model.is_some() || cli.sd_diffusion_model.is_some() {
+        // log stable diffusion model name
+        info!(target: "stdout", "sd_model_name: {}", &cli.sd_model_name);
+
+        // log stable diffusion task
+        info!(target: "stdout", "sd_task: {}", &cli.sd_task);
+        let task = match cli.sd_task.as_str() {
+            "text2image" => llama_core::StableDiffusionTask::TextToImage,
+            "image2image" => llama_core::StableDiffusionTask::ImageToImage,
+            _ => llama_core::StableDiffusionTask::Full,
+        };
+
+        // log lora model directory
+        if let Some(sd_lora_model_dir) = &cli.sd_lora_model_dir {
+            info!(target: "stdout", "sd_lora_model_dir: {}", sd_lora_model_dir);
+        }
+
+        // log controlnet
+        if let Some(sd_controlnet) = &cli.sd_controlnet {
+            info!(target: "stdout", "sd_controlnet: {}", sd_controlnet);
+            info!(target: "stdout", "sd_controlnet_on_cpu: {}", cli.sd_controlnet_on_cpu);
+        }
+
+        // log clip_on_cpu and vae_on_cpu
+        info!(target: "stdout", "sd_clip_on_cpu: {}", cli.sd_clip_on_cpu);
+        info!(target: "stdout", "sd_vae_on_cpu: {}", cli.sd_vae_on_cpu);
+
+        // log stable diffusion threads
+        info!(target: "stdout", "sd_threads: {}", cli.sd_threads);
+
+        match (&cli.sd_model, &cli.sd_diffusion_model) {
+            (Some(sd_model), _) => {
+                // log full stable diffusion model
+                info!(target: "stdout", "sd_model: {}", sd_model);
+
+                llama_core::init_sd_context_with_full_model(
+                    sd_model,
+                    cli.sd_lora_model_dir.as_deref(),
+                    cli.sd_controlnet.as_deref(),
+                    cli.sd_controlnet_on_cpu,
+                    cli.sd_clip_on_cpu,
+                    cli.sd_vae_on_cpu,
+                    cli.sd_threads,
+                    task,
+                )
+                .map_err(|e| ServerError::Operation(format!("{}", e)))?;
+            }
+            (None, Some(sd_diffusion_model)) => {
+                // log standalone diffusion model
+                info!(target: "stdout", "sd_diffusion_model: {}", sd_diffusion_model);
+
+                let sd_vae = cli.sd_vae.clone().unwrap_or_default();
+                info!(target: "stdout", "sd_vae: {}", &sd_vae);
+
+                let sd_clip_l = cli.sd_clip_l.clone().unwrap_or_default();
+                info!(target: "stdout", "sd_clip_l: {}", &sd_clip_l);
+
+                let sd_t5xxl = cli.sd_t5xxl.clone().unwrap_or_default();
+                info!(target: "stdout", "sd_t5xxl: {}", &sd_t5xxl);
+
+                llama_core::init_sd_context_with_standalone_model(
+                    sd_diffusion_model,
+                    sd_vae,
+                    sd_clip_l,
+                    sd_t5xxl,
+                    cli.sd_lora_model_dir.as_deref(),
+                    cli.sd_controlnet.as_deref(),
+                    cli.sd_controlnet_on_cpu,
+                    cli.sd_clip_on_cpu,
+                    cli.sd_vae_on_cpu,
+                    cli.sd_threads,
+                    task,
+                )
+                .map_err(|e| ServerError::Operation(format!("{}", e)))?;
+            }
+            (None, None) => unreachable!(),
+        }
+
+        // set the image model config
+        image_model_config = Some(ImageModelConfig {
+            name: cli.sd_model_name.clone(),
+            ty: "image".to_string(),
+            task: cli.sd_task.clone(),
+            created: current_timestamp()?,
+        });
+    }
+
+    // initialize the whisper context
+    let whisper_model_config = match &cli.whisper_model {
+        Some(whisper_model) => {
+            // log whisper model
+            info!(target: "stdout", "whisper_model_name: {}", &cli.whisper_model_name);
+            info!(target: "stdout", "whisper_model: {}", whisper_model);
+
+            if cfg!(not(feature = "whisper")) {
+                let err_msg =
+                    "The whisper model requires the server to be built with the `whisper` feature.";
+
+                error!(target: "stdout", "{}", err_msg);
+
+                return Err(ServerError::ArgumentError(err_msg.to_owned()));
+            }
+
+            #[cfg(feature = "whisper")]
+            {
+                let metadata_whisper = llama_core::metadata::whisper::WhisperMetadataBuilder::new(
+                    cli.whisper_model_name.clone(),
+                    cli.whisper_model_name.clone(),
+                )
+                .with_model_path(whisper_model)
+                .with_threads(cli.threads)
+                .enable_plugin_log(true)
+                .enable_debug_log(plugin_debug)
+                .build();
+
+                llama_core::init_whisper_context(&metadata_whisper)
+                    .map_err(|e| ServerError::Operation(format!("{}", e)))?;
+            }
+
+            Some(AudioModelConfig {
+                name: cli.whisper_model_name.clone(),
+                ty: "transcription".to_string(),
+                created: current_timestamp()?,
+            })
+        }
+        None => None,
+    };
+
+    // initialize the piper context
+    let mut piper_model_config = None;
+    if let Some(piper_voice_model) = &cli.piper_voice_model {
+        let (piper_voice_config, piper_espeak_ng_data) = match (
+            &cli.piper_voice_config,
+            &cli.piper_espeak_ng_data,
+        ) {
+            (Some(voice_config), Some(espeak_ng_data)) => (voice_config, espeak_ng_data),
+            _ => {
+                return Err(ServerError::ArgumentError(
+                        "The piper voice model requires `--piper-voice-config` and `--piper-espeak-ng-data`.".to_owned(),
+                    ));
+            }
+        };
+
+        // log piper model
+        info!(target: "stdout", "piper_model_name: {}", &cli.piper_model_name);
+        info!(target: "stdout", "piper_voice_model: {}", piper_voice_model);
+        info!(target: "stdout", "piper_voice_config: {}", piper_voice_config);
+        info!(target: "stdout", "piper_espeak_ng_data: {}", piper_espeak_ng_data);
+
+        let metadata_piper = llama_core::metadata::piper::PiperMetadataBuilder::new(
+            cli.piper_model_name.clone(),
+            cli.piper_model_name.clone(),
+        )
+        .enable_debug(plugin_debug)
+        .build();
+
+        llama_core::init_piper_context(
+            &metadata_piper,
+            piper_voice_model,
+            piper_voice_config,
+            piper_espeak_ng_data,
+        )
+        .map_err(|e| ServerError::Operation(format!("{}", e)))?;
+
+        piper_model_config = Some(AudioModelConfig {
+            name: cli.piper_model_name.clone(),
+            ty: "speech".to_string(),
+            created: current_timestamp()?,
+        });
+    }
+
+    // log queue size
+    info!(target: "stdout", "queue_size: {}", cli.queue_size);
+    llama_core::scheduler::set_default_queue_size(cli.queue_size)
+        .map_err(|e| ServerError::Operation(e.to_string()))?;
+
+    // log queue timeout
+    info!(target: "stdout", "queue_timeout: {}", cli.queue_timeout);
+    QUEUE_TIMEOUT
+        .set(cli.queue_timeout)
+        .map_err(|_| ServerError::Operation("Failed to set `QUEUE_TIMEOUT`.".to_string()))?;
+
+    // log rag policy
+    #[cfg(feature = "rag")]
+    {
+        info!(target: "stdout", "rag_policy: {}", cli.rag_policy);
+        RAG_POLICY
+            .set(cli.rag_policy)
+            .map_err(|_| ServerError::Operation("Failed to set `RAG_POLICY`.".to_string()))?;
+
+        if let Some(rag_prompt) = cli.rag_prompt {
+            info!(target: "stdout", "rag_prompt: {}", &rag_prompt);
+            RAG_PROMPT
+                .set(rag_prompt)
+                .map_err(|_| ServerError::Operation("Failed to set `RAG_PROMPT`.".to_string()))?;
+        }
+    }
+
     // log plugin version
-    let plugin_info =
-        llama_core::get_plugin_info().map_err(|e| ServerError::Operation(e.to_string()))?;
-    let plugin_version = format!(
-        "b{build_number} (commit {commit_id})",
-        build_number = plugin_info.build_number,
-        commit_id = plugin_info.commit_id,
-    );
+    let plugin_version = match llama_core::get_plugin_info() {
+        Ok(plugin_info) => format!(
+            "b{build_number} (commit {commit_id})",
+            build_number = plugin_info.build_number,
+            commit_id = plugin_info.commit_id,
+        ),
+        // no model may be loaded yet if the models are loaded on demand
+        Err(e) if lazy_load => {
+            warn!(target: "stdout", "Failed to get the plugin info. {}", e);
+
+            "unknown".to_string()
+        }
+        Err(e) => return Err(ServerError::Operation(e.to_string())),
+    };
     info!(target: "stdout", "plugin_ggml_version: {}", plugin_version);
 
     // socket address
@@ -1005,6 +2030,11 @@ async fn main() -> Result<(), ServerError> {
         },
         chat_model: chat_model_config,
         embedding_model: embedding_model_config,
+        chat_models: chat_model_configs,
+        embedding_models: embedding_model_configs,
+        image_model: image_model_config,
+        whisper_model: whisper_model_config,
+        piper_model: piper_model_config,
         extras: HashMap::new(),
     };
     SERVER_INFO
@@ -1018,7 +2048,14 @@ async fn main() -> Result<(), ServerError> {
         // web ui
         let web_ui = cli.web_ui.to_string_lossy().to_string();
 
-        async move { Ok::<_, Error>(service_fn(move |req| handle_request(req, web_ui.clone()))) }
+        // client ip address, which is used by the per-IP rate limits
+        let remote_ip = conn.remote_addr().ip();
+
+        async move {
+            Ok::<_, Error>(service_fn(move |req| {
+                handle_request(req, web_ui.clone(), remote_ip)
+            }))
+        }
     });
 
     let tcp_listener = TcpListener::bind(addr).await.unwrap();
@@ -1034,39 +2071,114 @@ async fn main() -> Result<(), ServerError> {
     }
 }
 
+/// Build the metadata of a model declared in the `models` section of the config file. The options not set by the model fall back to the top-level ones.
+fn build_model_metadata(
+    entry: &ModelEntry,
+    cli: &Cli,
+    plugin_debug: bool,
+) -> llama_core::metadata::ggml::GgmlMetadata {
+    // the top-level sizes of the chat model come first, followed by the ones of the embedding model
+    let index = if entry.is_embedding() { 1 } else { 0 };
+    let size_of = |sizes: &[u64]| sizes.get(index).or(sizes.first()).copied();
+
+    let mut builder = GgmlMetadataBuilder::new(
+        entry.name.clone(),
+        entry.alias.clone().unwrap_or_else(|| entry.name.clone()),
+        entry.prompt_template,
+    )
+    .with_split_mode(cli.split_mode.clone())
+    .with_main_gpu(cli.main_gpu)
+    .with_tensor_split(cli.tensor_split.clone())
+    .with_threads(cli.threads)
+    .enable_plugin_log(true)
+    .enable_debug_log(plugin_debug);
+
+    if let Some(ctx_size) = entry.ctx_size.or(size_of(&cli.ctx_size)) {
+        builder = builder.with_ctx_size(ctx_size);
+    }
+    if let Some(batch_size) = entry.batch_size.or(size_of(&cli.batch_size)) {
+        builder = builder.with_batch_size(batch_size);
+    }
+    if let Some(ubatch_size) = entry.ubatch_size.or(size_of(&cli.ubatch_size)) {
+        builder = builder.with_ubatch_size(ubatch_size);
+    }
+
+    if entry.is_embedding() {
+        return builder.build();
+    }
+
+    builder
+        .with_n_predict(entry.n_predict.unwrap_or(cli.n_predict))
+        .with_slots(entry.slots.unwrap_or(cli.slots))
+        .with_n_gpu_layers(entry.n_gpu_layers.unwrap_or(cli.n_gpu_layers))
+        .disable_mmap(cli.no_mmap)
+        .with_temperature(entry.temp.unwrap_or(cli.temp))
+        .with_top_p(entry.top_p.unwrap_or(cli.top_p))
+        .with_repeat_penalty(entry.repeat_penalty.unwrap_or(cli.repeat_penalty))
+        .with_presence_penalty(entry.presence_penalty.unwrap_or(cli.presence_penalty))
+        .with_frequency_penalty(entry.frequency_penalty.unwrap_or(cli.frequency_penalty))
+        .with_grammar(entry.grammar.clone().unwrap_or_else(|| cli.grammar.clone()))
+        .with_json_schema(entry.json_schema.clone().or(cli.json_schema.clone()))
+        .with_reverse_prompt(entry.reverse_prompt.clone().or(cli.reverse_prompt.clone()))
+        .with_mmproj(entry.llava_mmproj.clone().or(cli.llava_mmproj.clone()))
+        .build()
+}
+
 async fn handle_request(
     req: Request<Body>,
     web_ui: String,
+    remote_ip: IpAddr,
 ) -> Result<Response<Body>, hyper::Error> {
-    let path_str = req.uri().path();
-    let path_buf = PathBuf::from(path_str);
+    let start = Instant::now();
+    let endpoint = metrics::endpoint_label(req.uri().path());
+
+    let response = process_request(req, web_ui, remote_ip).await?;
+
+    metrics::observe_request(endpoint, response.status().as_u16(), start.elapsed());
+
+    Ok(response)
+}
+
+async fn process_request(
+    req: Request<Body>,
+    web_ui: String,
+    remote_ip: IpAddr,
+) -> Result<Response<Body>, hyper::Error> {
+    let path_str = req.uri().path().to_string();
+    let path_buf = PathBuf::from(&path_str);
     let mut path_iter = path_buf.iter();
     path_iter.next(); // Must be Some(OsStr::new(&path::MAIN_SEPARATOR.to_string()))
     let root_path = path_iter.next().unwrap_or_default();
     let root_path = "/".to_owned() + root_path.to_str().unwrap_or_default();
 
-    // check if the API key is valid
-    if let Some(auth_header) = req.headers().get("authorization") {
-        if !auth_header.is_empty() {
-            let auth_header = match auth_header.to_str() {
-                Ok(auth_header) => auth_header,
-                Err(e) => {
-                    let err_msg = format!("Failed to get authorization header: {}", e);
-                    return Ok(error::unauthorized(err_msg));
-                }
-            };
-
-            let api_key = auth_header.split(" ").nth(1).unwrap_or_default();
-            info!(target: "stdout", "API Key: {}", api_key);
+    // the admin endpoints are authorized by the admin key instead of the API keys, and are not rate limited
+    let mut rate_limit_status = None;
+    let req = if path_str.starts_with("/v1/admin/") || path_str == "/v1/admin" {
+        if let Err(response) = check_admin_key(&req) {
+            return Ok(response);
+        }
 
-            if let Some(stored_api_key) = LLAMA_API_KEY.get() {
-                if api_key != stored_api_key {
-                    let err_msg = "Invalid API key.";
-                    return Ok(error::unauthorized(err_msg));
-                }
-            }
+        req
+    } else if root_path == "/v1" && req.method() != hyper::http::Method::OPTIONS {
+        let req = match auth::authorize(req, remote_ip).await {
+            Ok(req) => req,
+            Err(response) => return Ok(response),
+        };
+
+        let caller = req
+            .extensions()
+            .get::<auth::Caller>()
+            .cloned()
+            .unwrap_or_default();
+        match ratelimit::admit(&caller) {
+            Ok(status) => rate_limit_status = status,
+            Err(response) => return Ok(response),
         }
-    }
+
+        req
+    } else {
+        req
+    };
 
     // log request
     {
@@ -1088,11 +2200,15 @@ async fn handle_request(
         }
     }
 
-    let response = match root_path.as_str() {
+    let mut response = match root_path.as_str() {
         "/echo" => Response::new(Body::from("echo test")),
+        "/metrics" => metrics::metrics_handler().await,
         "/v1" => backend::handle_llama_request(req).await,
-        _ => static_response(path_str, web_ui),
+        _ => static_response(&path_str, web_ui),
     };
+    if let Some(status) = rate_limit_status {
+        status.set_headers(response.headers_mut());
+    }
 
     // log response
     {
@@ -1126,6 +2242,52 @@ async fn handle_request(
     Ok(response)
 }
 
+/// Check if the request carries the admin key. The admin endpoints are disabled if the admin key is not set.
+fn check_admin_key(req: &Request<Body>) -> Result<(), Response<Body>> {
+    if req.method() == hyper::http::Method::OPTIONS {
+        return Ok(());
+    }
+
+    let stored_admin_key = match LLAMA_ADMIN_API_KEY.get() {
+        Some(stored_admin_key) => stored_admin_key,
+        None => {
+            let err_msg = "The admin endpoints are disabled. Please set the `ADMIN_API_KEY` environment variable to enable them.";
+            return Err(error::forbidden(err_msg));
+        }
+    };
+
+    let admin_key = req
+        .headers()
+        .get("authorization")
+        .and_then(|auth_header| auth_header.to_str().ok())
+        .and_then(|auth_header| auth_header.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    if admin_key.is_empty() || !constant_time_eq(admin_key.as_bytes(), stored_admin_key.as_bytes())
+    {
+        let err_msg = "Invalid admin key.";
+        return Err(error::unauthorized(err_msg));
+    }
+
+    Ok(())
+}
+
+/// Compare two byte strings in a time which does not depend on the position of the first different byte, so that the admin key cannot be guessed byte by byte from the response time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Return the current Unix timestamp in seconds.
+fn current_timestamp() -> Result<u64, ServerError> {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .map_err(|e| ServerError::Operation(format!("Failed to get the current time. {}", e)))
+}
+
 fn static_response(path_str: &str, root: String) -> Response<Body> {
     let path = match path_str {
         "/" => "/index.html",
@@ -1167,6 +2329,18 @@ pub(crate) struct ServerInfo {
     chat_model: Option<ModelConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     embedding_model: Option<ModelConfig>,
+    // all the chat models declared in the `models` section of the config file
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    chat_models: Vec<ModelConfig>,
+    // all the embedding models declared in the `models` section of the config file
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    embedding_models: Vec<ModelConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_model: Option<ImageModelConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    whisper_model: Option<AudioModelConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    piper_model: Option<AudioModelConfig>,
     extras: HashMap<String, String>,
 }
 
@@ -1180,7 +2354,7 @@ pub(crate) struct ApiServer {
     port: String,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub(crate) struct ModelConfig {
     // model name
     name: String,
@@ -1195,6 +2369,8 @@ pub(crate) struct ModelConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub n_predict: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub slots: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub reverse_prompt: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub n_gpu_layers: Option<u64>,
@@ -1217,3 +2393,29 @@ pub(crate) struct ModelConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tensor_split: Option<String>,
 }
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct ImageModelConfig {
+    // model name
+    pub name: String,
+    // type: image
+    #[serde(rename = "type")]
+    ty: String,
+    // task: full, text2image or image2image
+    pub task: String,
+    // the Unix timestamp (in seconds) of when the model was loaded
+    #[serde(skip)]
+    pub created: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct AudioModelConfig {
+    // model name
+    pub name: String,
+    // type: transcription or speech
+    #[serde(rename = "type")]
+    ty: String,
+    // the Unix timestamp (in seconds) of when the model was loaded
+    #[serde(skip)]
+    pub created: u64,
+}
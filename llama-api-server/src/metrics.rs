@@ -0,0 +1,432 @@
+//! Collect the metrics of the server, and expose them in the Prometheus text format on the `/metrics` endpoint.
+
+use crate::{error, utils::usage_of_chunk};
+use endpoints::{common::Usage, models::ModelState};
+use futures_util::Stream;
+use hyper::{Body, Response};
+use once_cell::sync::OnceCell;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// The buckets (in seconds) of the request latency histograms.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+];
+
+/// The buckets (in seconds) of the time-to-first-token histograms.
+const TTFT_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0];
+
+/// The buckets of the tokens-per-second histograms.
+const TOKENS_PER_SECOND_BUCKETS: &[f64] = &[
+    1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 50.0, 75.0, 100.0, 150.0, 200.0, 500.0,
+];
+
+/// The endpoints labelled by their own paths. The other paths are labelled by the endpoints they belong to.
+const ENDPOINTS: &[&str] = &[
+    "/v1/chat/completions",
+    "/v1/completions",
+    "/v1/models",
+    "/v1/embeddings",
+    "/v1/chunks",
+    "/v1/index",
+    "/v1/info",
+    "/v1/images/generations",
+    "/v1/images/edits",
+    "/v1/audio/transcriptions",
+    "/v1/audio/translations",
+    "/v1/audio/speech",
+    "/v1/files",
+    "/v1/admin/models",
+    "/v1/admin/usage",
+    "/echo",
+    "/metrics",
+];
+
+static METRICS: OnceCell<Mutex<Metrics>> = OnceCell::new();
+
+#[derive(Debug, Default)]
+struct Metrics {
+    /// key: (endpoint, status code), value: the number of the requests
+    requests: BTreeMap<(String, u16), u64>,
+    /// key: endpoint
+    request_duration: BTreeMap<String, Histogram>,
+    /// key: model name
+    time_to_first_token: BTreeMap<String, Histogram>,
+    /// key: model name
+    tokens_per_second: BTreeMap<String, Histogram>,
+    /// key: model name
+    prompt_tokens: BTreeMap<String, u64>,
+    /// key: model name
+    completion_tokens: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    buckets: &'static [f64],
+    /// The number of the observations in each bucket, which are not cumulative.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = self.buckets.iter().position(|le| value <= *le) {
+            self.counts[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, label: &str, label_value: &str) {
+        let label_value = escape_label_value(label_value);
+
+        let mut cumulative = 0;
+        for (le, count) in self.buckets.iter().zip(self.counts.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}",
+                name, label, label_value, le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}=\"{}\",le=\"+Inf\"}} {}",
+            name, label, label_value, self.count
+        );
+        let _ = writeln!(
+            out,
+            "{}_sum{{{}=\"{}\"}} {}",
+            name, label, label_value, self.sum
+        );
+        let _ = writeln!(
+            out,
+            "{}_count{{{}=\"{}\"}} {}",
+            name, label, label_value, self.count
+        );
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn with_metrics(f: impl FnOnce(&mut Metrics)) {
+    let metrics = METRICS.get_or_init(|| Mutex::new(Metrics::default()));
+    if let Ok(mut metrics) = metrics.lock() {
+        f(&mut metrics);
+    }
+}
+
+/// The endpoint label of the request path, which keeps the number of the label values bounded, e.g., `/v1/files/{file_id}` is labelled as `/v1/files`. The paths of the Web UI are labelled as `static`.
+pub(crate) fn endpoint_label(path: &str) -> &'static str {
+    for endpoint in ENDPOINTS {
+        let is_sub_path = path
+            .strip_prefix(endpoint)
+            .map(|rest| rest.is_empty() || rest.starts_with('/'))
+            .unwrap_or_default();
+        if is_sub_path {
+            return endpoint;
+        }
+    }
+
+    match path.starts_with("/v1/") || path == "/v1" {
+        true => "unknown",
+        false => "static",
+    }
+}
+
+/// Count a request, and observe the time until its response is ready. The time of a stream response ends when the stream starts.
+pub(crate) fn observe_request(endpoint: &str, status: u16, duration: Duration) {
+    with_metrics(|metrics| {
+        *metrics
+            .requests
+            .entry((endpoint.to_string(), status))
+            .or_default() += 1;
+        metrics
+            .request_duration
+            .entry(endpoint.to_string())
+            .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+            .observe(duration.as_secs_f64());
+    })
+}
+
+/// Observe the time from the arrival of a stream request until its first chunk.
+pub(crate) fn observe_time_to_first_token(model_name: &str, duration: Duration) {
+    with_metrics(|metrics| {
+        metrics
+            .time_to_first_token
+            .entry(model_name.to_string())
+            .or_insert_with(|| Histogram::new(TTFT_BUCKETS))
+            .observe(duration.as_secs_f64());
+    })
+}
+
+/// Count the tokens of a completed generation, and observe its tokens per second over the given generation time.
+pub(crate) fn observe_generation(model_name: &str, usage: &Usage, duration: Duration) {
+    with_metrics(|metrics| {
+        *metrics
+            .prompt_tokens
+            .entry(model_name.to_string())
+            .or_default() += usage.prompt_tokens;
+        *metrics
+            .completion_tokens
+            .entry(model_name.to_string())
+            .or_default() += usage.completion_tokens;
+
+        if usage.completion_tokens > 0 && !duration.is_zero() {
+            metrics
+                .tokens_per_second
+                .entry(model_name.to_string())
+                .or_insert_with(|| Histogram::new(TOKENS_PER_SECOND_BUCKETS))
+                .observe(usage.completion_tokens as f64 / duration.as_secs_f64());
+        }
+    })
+}
+
+/// Render the metrics in the Prometheus text format.
+async fn render() -> String {
+    let mut out = String::new();
+
+    with_metrics(|metrics| {
+        write_header(
+            &mut out,
+            "llamaedge_http_requests_total",
+            "counter",
+            "Number of the HTTP requests by endpoint and status code.",
+        );
+        for ((endpoint, status), count) in metrics.requests.iter() {
+            let _ = writeln!(
+                out,
+                "llamaedge_http_requests_total{{endpoint=\"{}\",status=\"{}\"}} {}",
+                escape_label_value(endpoint),
+                status,
+                count
+            );
+        }
+
+        write_header(
+            &mut out,
+            "llamaedge_http_request_duration_seconds",
+            "histogram",
+            "Time until the response of the HTTP request is ready, by endpoint.",
+        );
+        for (endpoint, histogram) in metrics.request_duration.iter() {
+            histogram.render(
+                &mut out,
+                "llamaedge_http_request_duration_seconds",
+                "endpoint",
+                endpoint,
+            );
+        }
+
+        write_header(
+            &mut out,
+            "llamaedge_time_to_first_token_seconds",
+            "histogram",
+            "Time from the arrival of the stream request until its first chunk, by model.",
+        );
+        for (model, histogram) in metrics.time_to_first_token.iter() {
+            histogram.render(
+                &mut out,
+                "llamaedge_time_to_first_token_seconds",
+                "model",
+                model,
+            );
+        }
+
+        write_header(
+            &mut out,
+            "llamaedge_tokens_per_second",
+            "histogram",
+            "Completion tokens generated per second by each request, by model.",
+        );
+        for (model, histogram) in metrics.tokens_per_second.iter() {
+            histogram.render(&mut out, "llamaedge_tokens_per_second", "model", model);
+        }
+
+        write_header(
+            &mut out,
+            "llamaedge_prompt_tokens_total",
+            "counter",
+            "Number of the prompt tokens, by model.",
+        );
+        for (model, count) in metrics.prompt_tokens.iter() {
+            let _ = writeln!(
+                out,
+                "llamaedge_prompt_tokens_total{{model=\"{}\"}} {}",
+                escape_label_value(model),
+                count
+            );
+        }
+
+        write_header(
+            &mut out,
+            "llamaedge_completion_tokens_total",
+            "counter",
+            "Number of the completion tokens, by model.",
+        );
+        for (model, count) in metrics.completion_tokens.iter() {
+            let _ = writeln!(
+                out,
+                "llamaedge_completion_tokens_total{{model=\"{}\"}} {}",
+                escape_label_value(model),
+                count
+            );
+        }
+    });
+
+    write_header(
+        &mut out,
+        "llamaedge_queue_depth",
+        "gauge",
+        "Number of the requests waiting in the queue of the chat model.",
+    );
+    for model in llama_core::utils::chat_model_names().unwrap_or_default() {
+        if let Ok(depth) = llama_core::scheduler::queue_depth(&model) {
+            let _ = writeln!(
+                out,
+                "llamaedge_queue_depth{{model=\"{}\"}} {}",
+                escape_label_value(&model),
+                depth
+            );
+        }
+    }
+
+    let models = match llama_core::models::models().await {
+        Ok(list_models_response) => list_models_response.data,
+        Err(e) => {
+            // log
+            warn!(target: "stdout", "Failed to get model list for the metrics. Reason: {}", e);
+
+            vec![]
+        }
+    };
+    let is_loaded = |state: &Option<ModelState>| matches!(state, Some(ModelState::Loaded) | None);
+
+    write_header(
+        &mut out,
+        "llamaedge_loaded_models",
+        "gauge",
+        "Number of the loaded chat and embedding models.",
+    );
+    let _ = writeln!(
+        out,
+        "llamaedge_loaded_models {}",
+        models
+            .iter()
+            .filter(|model| is_loaded(&model.state))
+            .count()
+    );
+
+    write_header(
+        &mut out,
+        "llamaedge_model_loaded",
+        "gauge",
+        "Whether the chat or embedding model is loaded (1) or not (0).",
+    );
+    for model in models.iter() {
+        let _ = writeln!(
+            out,
+            "llamaedge_model_loaded{{model=\"{}\"}} {}",
+            escape_label_value(&model.id),
+            u8::from(is_loaded(&model.state))
+        );
+    }
+
+    out
+}
+
+/// Return the metrics in the Prometheus text format.
+pub(crate) async fn metrics_handler() -> Response<Body> {
+    let result = Response::builder()
+        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Methods", "*")
+        .header("Access-Control-Allow-Headers", "*")
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(Body::from(render().await));
+
+    match result {
+        Ok(response) => response,
+        Err(e) => {
+            let err_msg = e.to_string();
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            error::internal_server_error(err_msg)
+        }
+    }
+}
+
+/// Stream of chat completion or completion chunks which observes the time to the first chunk, and the tokens per second from the usage in the last chunk.
+pub(crate) struct MetricsStream<S> {
+    inner: S,
+    model_name: String,
+    /// The time when the request arrives.
+    start: Instant,
+    /// The time when the first chunk is sent.
+    first_chunk: Option<Instant>,
+}
+impl<S> MetricsStream<S> {
+    pub(crate) fn new(inner: S, model_name: impl Into<String>, start: Instant) -> Self {
+        Self {
+            inner,
+            model_name: model_name.into(),
+            start,
+            first_chunk: None,
+        }
+    }
+}
+impl<S, E> Stream for MetricsStream<S>
+where
+    S: Stream<Item = Result<String, E>> + Unpin,
+{
+    type Item = Result<String, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        let poll = Pin::new(&mut this.inner).poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            let now = Instant::now();
+            let first_chunk = match this.first_chunk {
+                Some(first_chunk) => first_chunk,
+                None => {
+                    observe_time_to_first_token(&this.model_name, now - this.start);
+                    this.first_chunk = Some(now);
+                    now
+                }
+            };
+
+            if let Some(usage) = usage_of_chunk(chunk) {
+                observe_generation(&this.model_name, &usage, now - first_chunk);
+            }
+        }
+
+        poll
+    }
+}
@@ -0,0 +1,311 @@
+//! Limit the requests and the tokens per minute of each API key and each client IP address.
+//!
+//! The limits are checked over a sliding window of one minute. The tokens of a request are known only after its response is completed, so a request is rejected once the tokens used in the window reach the limit.
+
+use crate::{auth::Caller, error};
+use hyper::{header::HeaderValue, Body, HeaderMap, Response};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+// the rate limits set by the config file
+static RATE_LIMITS: OnceCell<RateLimitConfig> = OnceCell::new();
+
+// key: the subject of the limits, value: the requests and tokens of the subject in the last minute
+static WINDOWS: OnceCell<Mutex<HashMap<Subject, Window>>> = OnceCell::new();
+
+/// The requests-per-minute and tokens-per-minute limits.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct RateLimit {
+    /// Maximum number of requests per minute. `None` means no limit
+    #[serde(default)]
+    pub(crate) rpm: Option<u64>,
+    /// Maximum number of tokens per minute, counted from the `usage` of the responses. `None` means no limit
+    #[serde(default)]
+    pub(crate) tpm: Option<u64>,
+}
+impl RateLimit {
+    /// Take the limits not set by `self` from `other`.
+    fn or(self, other: RateLimit) -> RateLimit {
+        RateLimit {
+            rpm: self.rpm.or(other.rpm),
+            tpm: self.tpm.or(other.tpm),
+        }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.rpm.is_none() && self.tpm.is_none()
+    }
+}
+
+/// The rate limits of the `rate-limit` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct RateLimitConfig {
+    /// The limits of each API key. The `rpm` and `tpm` of a key override them
+    #[serde(default)]
+    pub(crate) per_key: RateLimit,
+    /// The limits of each client IP address
+    #[serde(default)]
+    pub(crate) per_ip: RateLimit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    Key(String),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    requests: VecDeque<Instant>,
+    tokens: VecDeque<(Instant, u64)>,
+}
+impl Window {
+    /// Remove the requests and the tokens older than one minute.
+    fn prune(&mut self, now: Instant) {
+        while let Some(time) = self.requests.front() {
+            match now.duration_since(*time) >= WINDOW {
+                true => self.requests.pop_front(),
+                false => break,
+            };
+        }
+        while let Some((time, _)) = self.tokens.front() {
+            match now.duration_since(*time) >= WINDOW {
+                true => self.tokens.pop_front(),
+                false => break,
+            };
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.requests.is_empty() && self.tokens.is_empty()
+    }
+
+    fn used_tokens(&self) -> u64 {
+        self.tokens.iter().map(|(_, tokens)| tokens).sum()
+    }
+
+    /// The time until the oldest entry leaves the window.
+    fn reset_after(oldest: Option<&Instant>, now: Instant) -> Duration {
+        oldest
+            .map(|time| WINDOW.saturating_sub(now.duration_since(*time)))
+            .unwrap_or_default()
+    }
+
+    fn status(&self, limit: RateLimit, now: Instant) -> RateLimitStatus {
+        RateLimitStatus {
+            limit_requests: limit.rpm,
+            remaining_requests: limit
+                .rpm
+                .map(|rpm| rpm.saturating_sub(self.requests.len() as u64)),
+            reset_requests: Self::reset_after(self.requests.front(), now),
+            limit_tokens: limit.tpm,
+            remaining_tokens: limit.tpm.map(|tpm| tpm.saturating_sub(self.used_tokens())),
+            reset_tokens: Self::reset_after(self.tokens.front().map(|(time, _)| time), now),
+        }
+    }
+}
+
+/// The state of the rate limits of a request, which is sent in the `x-ratelimit-*` headers of the response.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimitStatus {
+    limit_requests: Option<u64>,
+    remaining_requests: Option<u64>,
+    reset_requests: Duration,
+    limit_tokens: Option<u64>,
+    remaining_tokens: Option<u64>,
+    reset_tokens: Duration,
+}
+impl RateLimitStatus {
+    fn requests_exhausted(&self) -> bool {
+        self.remaining_requests == Some(0)
+    }
+
+    fn tokens_exhausted(&self) -> bool {
+        self.remaining_tokens == Some(0)
+    }
+
+    /// Set the `x-ratelimit-*` headers of the response.
+    pub(crate) fn set_headers(&self, headers: &mut HeaderMap) {
+        let mut set = |name: &'static str, value: String| {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(name, value);
+            }
+        };
+
+        if let (Some(limit), Some(remaining)) = (self.limit_requests, self.remaining_requests) {
+            set("x-ratelimit-limit-requests", limit.to_string());
+            set("x-ratelimit-remaining-requests", remaining.to_string());
+            set(
+                "x-ratelimit-reset-requests",
+                format_duration(self.reset_requests),
+            );
+        }
+        if let (Some(limit), Some(remaining)) = (self.limit_tokens, self.remaining_tokens) {
+            set("x-ratelimit-limit-tokens", limit.to_string());
+            set("x-ratelimit-remaining-tokens", remaining.to_string());
+            set(
+                "x-ratelimit-reset-tokens",
+                format_duration(self.reset_tokens),
+            );
+        }
+    }
+}
+
+/// Format the duration in the way of the `x-ratelimit-reset-*` headers of OpenAI, e.g., `1m0s`, `6s` or `20ms`.
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis < 1000 {
+        return format!("{}ms", millis);
+    }
+
+    let secs = duration.as_secs();
+    match secs >= 60 {
+        true => format!("{}m{}s", secs / 60, secs % 60),
+        false => format!("{}s", secs),
+    }
+}
+
+/// Set the rate limits.
+pub(crate) fn init_rate_limits(config: RateLimitConfig) -> Result<(), String> {
+    RATE_LIMITS
+        .set(config)
+        .map_err(|_| "The rate limits have already been set.".to_string())
+}
+
+fn windows() -> &'static Mutex<HashMap<Subject, Window>> {
+    WINDOWS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// The subjects of the limits of the caller, together with their limits.
+fn subjects(caller: &Caller) -> Vec<(Subject, RateLimit)> {
+    let config = match RATE_LIMITS.get() {
+        Some(config) => config,
+        None => return vec![],
+    };
+
+    let mut subjects = vec![];
+    if let Some(key_name) = &caller.key_name {
+        let limit = caller.rate_limit.or(config.per_key);
+        if !limit.is_unlimited() {
+            subjects.push((Subject::Key(key_name.clone()), limit));
+        }
+    }
+    if let Some(ip) = caller.ip {
+        if !config.per_ip.is_unlimited() {
+            subjects.push((Subject::Ip(ip), config.per_ip));
+        }
+    }
+
+    subjects
+}
+
+/// Check the limits of the API key and the IP address of the caller, and count the request if it is admitted.
+///
+/// Returns the status of the limits of the API key, or the IP address if the key has no limits, for the headers of the response. A 429 response is returned if any limit is reached.
+pub(crate) fn admit(caller: &Caller) -> Result<Option<RateLimitStatus>, Response<Body>> {
+    let subjects = subjects(caller);
+    if subjects.is_empty() {
+        return Ok(None);
+    }
+
+    let mut windows = match windows().lock() {
+        Ok(windows) => windows,
+        Err(e) => {
+            let err_msg = format!("Fail to acquire the lock of the rate limit windows. {}", e);
+
+            // log
+            error!(target: "stdout", "{}", &err_msg);
+
+            return Err(error::internal_server_error(err_msg));
+        }
+    };
+
+    let now = Instant::now();
+    windows.retain(|_, window| {
+        window.prune(now);
+        !window.is_empty()
+    });
+
+    for (subject, limit) in subjects.iter() {
+        let status = windows
+            .get(subject)
+            .map(|window| window.status(*limit, now))
+            .unwrap_or_else(|| Window::default().status(*limit, now));
+
+        let (kind, retry_after) = match status.requests_exhausted() {
+            true => ("requests", status.reset_requests),
+            false => match status.tokens_exhausted() {
+                true => ("tokens", status.reset_tokens),
+                false => continue,
+            },
+        };
+
+        let err_msg = match subject {
+            Subject::Key(key_name) => format!(
+                "Rate limit reached for {} of the API key named {}. Please try again in {}.",
+                kind,
+                key_name,
+                format_duration(retry_after)
+            ),
+            Subject::Ip(ip) => format!(
+                "Rate limit reached for {} of the IP address {}. Please try again in {}.",
+                kind,
+                ip,
+                format_duration(retry_after)
+            ),
+        };
+
+        let mut response = error::too_many_requests(err_msg);
+        status.set_headers(response.headers_mut());
+        let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        response
+            .headers_mut()
+            .insert("retry-after", HeaderValue::from(retry_after.max(1)));
+
+        return Err(response);
+    }
+
+    let mut statuses = vec![];
+    for (subject, limit) in subjects {
+        let window = windows.entry(subject).or_default();
+        window.requests.push_back(now);
+        statuses.push(window.status(limit, now));
+    }
+
+    Ok(statuses.into_iter().next())
+}
+
+/// Count the tokens of a completed request against the limits of its API key and IP address.
+pub(crate) fn record_tokens(caller: &Caller, tokens: u64) {
+    if tokens == 0 {
+        return;
+    }
+
+    let subjects = subjects(caller);
+    if subjects.is_empty() {
+        return;
+    }
+
+    if let Ok(mut windows) = windows().lock() {
+        let now = Instant::now();
+        for (subject, limit) in subjects {
+            if limit.tpm.is_some() {
+                windows
+                    .entry(subject)
+                    .or_default()
+                    .tokens
+                    .push_back((now, tokens));
+            }
+        }
+    }
+}
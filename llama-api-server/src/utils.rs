@@ -1,9 +1,25 @@
+use endpoints::common::Usage;
 use serde::{Deserialize, Serialize};
 
 pub(crate) fn gen_chat_id() -> String {
     format!("chatcmpl-{}", uuid::Uuid::new_v4())
 }
 
+/// The `usage` field shared by the chunks of the chat completion and completion streams.
+#[derive(Deserialize)]
+struct ChunkUsage {
+    usage: Option<Usage>,
+}
+
+/// Parse the token usage of a chunk of a chat completion or completion stream. Returns `None` if the chunk carries no usage.
+pub(crate) fn usage_of_chunk(chunk: &str) -> Option<Usage> {
+    chunk
+        .strip_prefix("data: ")
+        .filter(|data| data.contains("\"usage\""))
+        .and_then(|data| serde_json::from_str::<ChunkUsage>(data.trim()).ok())
+        .and_then(|chunk| chunk.usage)
+}
+
 #[derive(
     Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum, Serialize, Deserialize,
 )]